@@ -0,0 +1,81 @@
+//! cargo bench --bench http
+
+use std::{convert::Infallible, net::SocketAddr, time::Duration};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use http_manager::{create_get, create_json_post, join_uri, Manager};
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Response, Server,
+};
+use tokio::runtime::Runtime;
+
+fn bench_request_construction(c: &mut Criterion) {
+    c.bench_function("create_get", |b| {
+        b.iter(|| create_get("http://localhost:9650", "/ext/health").unwrap())
+    });
+    c.bench_function("create_json_post", |b| {
+        b.iter(|| {
+            create_json_post(
+                "http://localhost:9650",
+                "/ext/info",
+                r#"{"jsonrpc":"2.0","id":1,"method":"info.getNodeID"}"#,
+            )
+            .unwrap()
+        })
+    });
+}
+
+fn bench_join_uri(c: &mut Criterion) {
+    c.bench_function("join_uri", |b| {
+        b.iter(|| join_uri("http://localhost:9650/ext/", "bc/X/rpc").unwrap())
+    });
+    c.bench_function("join_uri_ipv6", |b| {
+        b.iter(|| join_uri("http://[::1]:9650", "/ext/health").unwrap())
+    });
+}
+
+/// Starts a server that answers every request with "ok".
+fn start_server(rt: &Runtime) -> SocketAddr {
+    rt.block_on(async {
+        let make_svc = make_service_fn(|_| async {
+            Ok::<_, Infallible>(service_fn(|_| async {
+                Ok::<_, Infallible>(Response::new(Body::from("ok")))
+            }))
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        addr
+    })
+}
+
+fn bench_small_get(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let addr = start_server(&rt);
+    let url = format!("http://{}", addr);
+
+    let manager = Manager::new().unwrap();
+    c.bench_function("manager_small_get", |b| {
+        b.to_async(&rt).iter(|| async {
+            let req = create_get(&url, "/").unwrap();
+            manager.read_bytes(req, true).await.unwrap()
+        })
+    });
+    c.bench_function("read_bytes_small_get", |b| {
+        b.to_async(&rt).iter(|| async {
+            let req = create_get(&url, "/").unwrap();
+            http_manager::read_bytes(req, Duration::from_secs(5), false, true)
+                .await
+                .unwrap()
+        })
+    });
+}
+
+criterion_group!(
+    benches,
+    bench_request_construction,
+    bench_join_uri,
+    bench_small_get
+);
+criterion_main!(benches);
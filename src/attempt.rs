@@ -0,0 +1,49 @@
+//! Metadata of requests that may take several attempts (e.g., failover
+//! across endpoints), returned alongside the result so that callers can
+//! log flaky upstreams without losing the value.
+
+use std::{io, time::Duration};
+
+/// A failed attempt.
+#[derive(Debug)]
+pub struct AttemptError {
+    /// Base URL (or URL) the attempt was sent to.
+    pub endpoint: String,
+    pub error: io::Error,
+    pub elapsed: Duration,
+}
+
+/// A successful result with the attempts that led to it.
+#[derive(Debug)]
+pub struct Attempted<T> {
+    pub value: T,
+    /// Number of attempts, including the successful one.
+    pub attempts: usize,
+    /// Errors of the failed attempts, in order.
+    pub errors: Vec<AttemptError>,
+    /// Endpoint that served the value.
+    pub endpoint: String,
+    /// Total time across all attempts.
+    pub elapsed: Duration,
+}
+
+impl<T> Attempted<T> {
+    /// Returns true if any attempt failed before the value was served.
+    pub fn is_retried(&self) -> bool {
+        !self.errors.is_empty()
+    }
+
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> Attempted<U> {
+        Attempted {
+            value: f(self.value),
+            attempts: self.attempts,
+            errors: self.errors,
+            endpoint: self.endpoint,
+            elapsed: self.elapsed,
+        }
+    }
+
+    pub fn into_value(self) -> T {
+        self.value
+    }
+}
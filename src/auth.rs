@@ -0,0 +1,117 @@
+//! "Authorization" header credentials.
+
+use std::{
+    fmt,
+    io::{self, Error, ErrorKind},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hyper::{
+    header::{HeaderValue, AUTHORIZATION},
+    HeaderMap, Request,
+};
+
+/// Credentials sent as the "Authorization" header, e.g., with
+/// "get_non_tls_with_auth" or "ManagerBuilder::auth". The header value is
+/// marked sensitive, and "Debug" does not print the secrets.
+#[derive(Clone, PartialEq, Eq)]
+pub enum Auth {
+    /// "Authorization: Bearer {token}" (e.g., OAuth access tokens).
+    Bearer(String),
+    /// "Authorization: Basic {base64(user:password)}".
+    /// ref. https://www.rfc-editor.org/rfc/rfc7617
+    Basic(String, String),
+}
+
+impl fmt::Debug for Auth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Auth::Bearer(_) => f.write_str("Bearer(***)"),
+            Auth::Basic(user, _) => write!(f, "Basic({:?}, ***)", user),
+        }
+    }
+}
+
+impl Auth {
+    pub fn bearer(token: &str) -> Self {
+        Auth::Bearer(token.to_string())
+    }
+
+    pub fn basic(user: &str, password: &str) -> Self {
+        Auth::Basic(user.to_string(), password.to_string())
+    }
+
+    /// Returns the "Authorization" header value, failing on credentials
+    /// that cannot be sent (e.g., a user ID with ":", or line breaks).
+    pub fn header_value(&self) -> io::Result<HeaderValue> {
+        let v = match self {
+            Auth::Bearer(token) => format!("Bearer {}", token),
+            Auth::Basic(user, password) => {
+                if user.contains(':') {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        "basic auth user ID must not contain ':'",
+                    ));
+                }
+                format!(
+                    "Basic {}",
+                    STANDARD.encode(format!("{}:{}", user, password))
+                )
+            }
+        };
+        let mut v = HeaderValue::from_str(&v).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid authorization credentials {}", e),
+            )
+        })?;
+        v.set_sensitive(true);
+        Ok(v)
+    }
+
+    /// Returns the header map with the "Authorization" header, for the
+    /// "*_with_headers" helpers.
+    pub fn headers(&self) -> io::Result<HeaderMap> {
+        let mut headers = HeaderMap::with_capacity(1);
+        headers.insert(AUTHORIZATION, self.header_value()?);
+        Ok(headers)
+    }
+
+    /// Sets the "Authorization" header of the request, replacing any.
+    pub fn apply<B>(&self, req: &mut Request<B>) -> io::Result<()> {
+        req.headers_mut()
+            .insert(AUTHORIZATION, self.header_value()?);
+        Ok(())
+    }
+}
+
+#[test]
+fn test_auth() {
+    let v = Auth::bearer("t0k3n").header_value().unwrap();
+    assert_eq!(v, "Bearer t0k3n");
+    assert!(v.is_sensitive());
+
+    // ref. https://www.rfc-editor.org/rfc/rfc7617#section-2
+    let v = Auth::basic("Aladdin", "open sesame")
+        .header_value()
+        .unwrap();
+    assert_eq!(v, "Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ==");
+
+    // the password may contain ':'
+    let v = Auth::basic("a", "b:c").header_value().unwrap();
+    assert_eq!(v, format!("Basic {}", STANDARD.encode("a:b:c")));
+    let e = Auth::basic("a:b", "c").header_value().unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidInput);
+    let e = Auth::bearer("a\r\nx-injected: 1")
+        .header_value()
+        .unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidInput);
+
+    let debug = format!("{:?}", Auth::basic("user", "secret"));
+    assert!(!debug.contains("secret"));
+    assert!(!format!("{:?}", Auth::bearer("t0k3n")).contains("t0k3n"));
+
+    let mut req = crate::create_get("http://localhost", "/").unwrap();
+    Auth::bearer("t").apply(&mut req).unwrap();
+    assert_eq!(req.headers()[AUTHORIZATION], "Bearer t");
+}
@@ -0,0 +1,339 @@
+//! Concurrent execution of many requests: fan-out of independent
+//! requests, and declarative batches of request specs with dependencies.
+
+use std::{
+    collections::HashMap,
+    io::{self, Error, ErrorKind},
+    time::{Duration, Instant},
+};
+
+use futures_util::stream::{self, FuturesUnordered, StreamExt};
+use hyper::{body::Bytes, Body, Request, Response};
+use serde::{Deserialize, Serialize};
+
+use crate::{spec::RequestSpec, Manager};
+
+/// Named request in a batch, e.g., in YAML:
+///
+/// ```yaml
+/// - name: create
+///   request:
+///     method: POST
+///     url: http://localhost:9650
+///     path: /items
+/// - name: verify
+///   depends_on: [create]
+///   request:
+///     url: http://localhost:9650
+///     path: /items/1
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BatchItem {
+    pub name: String,
+    /// Names of the items that must succeed (2xx) before this one runs.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub depends_on: Vec<String>,
+    pub request: RequestSpec,
+}
+
+/// Result of a batch item, in the same order as the input.
+#[derive(Debug, Clone)]
+pub struct BatchResult {
+    pub name: String,
+    pub outcome: BatchOutcome,
+}
+
+#[derive(Debug, Clone)]
+pub enum BatchOutcome {
+    /// Received a response (of any status code).
+    Completed {
+        status: u16,
+        latency: Duration,
+        body: Bytes,
+    },
+    /// Failed to send or read the response.
+    Failed { error: String, latency: Duration },
+    /// Not run because a dependency did not succeed.
+    Skipped { reason: String },
+}
+
+impl BatchResult {
+    /// Returns true if the request completed with a 2xx status code.
+    pub fn is_success(&self) -> bool {
+        is_success(Some(&self.outcome))
+    }
+}
+
+impl Manager {
+    /// Sends GET requests to the URLs with up to "max_concurrency" in
+    /// flight, and returns the responses (of any status code) in input
+    /// order. Each request fails on its own, without cancelling the others.
+    pub async fn get_many(
+        &self,
+        urls: &[&str],
+        max_concurrency: usize,
+    ) -> Vec<io::Result<Response<Bytes>>> {
+        let reqs = urls.iter().map(|u| crate::create_get(u, "")).collect();
+        self.fan_out(reqs, max_concurrency).await
+    }
+
+    /// Same as "get_many" but for arbitrary requests.
+    pub async fn execute_many(
+        &self,
+        reqs: Vec<Request<Body>>,
+        max_concurrency: usize,
+    ) -> Vec<io::Result<Response<Bytes>>> {
+        self.fan_out(reqs.into_iter().map(Ok).collect(), max_concurrency)
+            .await
+    }
+
+    async fn fan_out(
+        &self,
+        reqs: Vec<io::Result<Request<Body>>>,
+        max_concurrency: usize,
+    ) -> Vec<io::Result<Response<Bytes>>> {
+        stream::iter(reqs)
+            .map(|req| async move {
+                let req = req?;
+                let timeout_dur = self.timeout_for(req.uri());
+                self.fetch(req, timeout_dur).await
+            })
+            .buffered(max_concurrency.max(1))
+            .collect()
+            .await
+    }
+
+    /// Executes the items with up to "concurrency" requests in flight,
+    /// respecting "depends_on", and returns the results in input order.
+    /// Request failures are reported per item; only an invalid batch
+    /// (duplicate or unknown names, dependency cycles) is an error.
+    pub async fn execute_batch(
+        &self,
+        items: &[BatchItem],
+        concurrency: usize,
+    ) -> io::Result<Vec<BatchResult>> {
+        let deps = resolve_dependencies(items)?;
+        let concurrency = concurrency.max(1);
+
+        let mut outcomes: Vec<Option<BatchOutcome>> = vec![None; items.len()];
+        let mut started = vec![false; items.len()];
+        let mut running = FuturesUnordered::new();
+
+        loop {
+            // skipping an item may unblock (and skip) its dependents,
+            // so scan until nothing changes
+            let mut changed = true;
+            while changed {
+                changed = false;
+                for i in 0..items.len() {
+                    if started[i] || !deps[i].iter().all(|d| outcomes[*d].is_some()) {
+                        continue;
+                    }
+                    let failed: Vec<&str> = deps[i]
+                        .iter()
+                        .filter(|d| !is_success(outcomes[**d].as_ref()))
+                        .map(|d| items[*d].name.as_str())
+                        .collect();
+                    if !failed.is_empty() {
+                        started[i] = true;
+                        outcomes[i] = Some(BatchOutcome::Skipped {
+                            reason: format!("dependencies did not succeed: {:?}", failed),
+                        });
+                        changed = true;
+                        continue;
+                    }
+                    if running.len() < concurrency {
+                        started[i] = true;
+                        running.push(self.execute_item(i, &items[i].request));
+                    }
+                }
+            }
+
+            match running.next().await {
+                Some((i, outcome)) => outcomes[i] = Some(outcome),
+                None => break,
+            }
+        }
+
+        Ok(items
+            .iter()
+            .zip(outcomes)
+            .map(|(item, outcome)| BatchResult {
+                name: item.name.clone(),
+                outcome: outcome.expect("every batch item must finish"),
+            })
+            .collect())
+    }
+
+    async fn execute_item(&self, idx: usize, spec: &RequestSpec) -> (usize, BatchOutcome) {
+        let start = Instant::now();
+        let outcome = match self.execute(spec).await {
+            Ok(resp) => BatchOutcome::Completed {
+                status: resp.status().as_u16(),
+                latency: start.elapsed(),
+                body: resp.into_body(),
+            },
+            Err(e) => BatchOutcome::Failed {
+                error: e.to_string(),
+                latency: start.elapsed(),
+            },
+        };
+        (idx, outcome)
+    }
+}
+
+fn is_success(outcome: Option<&BatchOutcome>) -> bool {
+    matches!(outcome, Some(BatchOutcome::Completed { status, .. }) if (200..300).contains(status))
+}
+
+/// Returns the dependency indexes of each item, failing on duplicate or
+/// unknown names and on cycles.
+fn resolve_dependencies(items: &[BatchItem]) -> io::Result<Vec<Vec<usize>>> {
+    let mut index = HashMap::new();
+    for (i, item) in items.iter().enumerate() {
+        if index.insert(item.name.as_str(), i).is_some() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("duplicate batch item name '{}'", item.name),
+            ));
+        }
+    }
+
+    let mut deps = Vec::with_capacity(items.len());
+    for item in items.iter() {
+        let mut d = Vec::with_capacity(item.depends_on.len());
+        for name in item.depends_on.iter() {
+            match index.get(name.as_str()) {
+                Some(i) => d.push(*i),
+                None => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("batch item '{}' depends on unknown '{}'", item.name, name),
+                    ))
+                }
+            }
+        }
+        deps.push(d);
+    }
+
+    // Kahn's algorithm: every item must become ready eventually
+    let mut remaining: Vec<usize> = deps.iter().map(|d| d.len()).collect();
+    let mut ready: Vec<usize> = (0..items.len()).filter(|i| remaining[*i] == 0).collect();
+    let mut visited = 0;
+    while let Some(i) = ready.pop() {
+        visited += 1;
+        for (j, d) in deps.iter().enumerate() {
+            let n = d.iter().filter(|dep| **dep == i).count();
+            if n > 0 {
+                remaining[j] -= n;
+                if remaining[j] == 0 {
+                    ready.push(j);
+                }
+            }
+        }
+    }
+    if visited != items.len() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "batch items have a dependency cycle",
+        ));
+    }
+
+    Ok(deps)
+}
+
+/// RUST_LOG=debug cargo test --lib -- batch::test_get_many --exact --show-output
+#[tokio::test]
+async fn test_get_many() {
+    use hyper::Method;
+
+    let server = crate::testing::MockServer::start().await.unwrap();
+    for i in 0..10 {
+        server.stub(Method::GET, &format!("/{}", i), 200, i.to_string());
+    }
+
+    let mut urls: Vec<String> = (0..10).map(|i| format!("{}/{}", server.url(), i)).collect();
+    urls.insert(3, "ftp://localhost/x".to_string());
+    urls.push(format!("{}/missing", server.url()));
+    let urls: Vec<&str> = urls.iter().map(|s| s.as_str()).collect();
+
+    let manager = Manager::new().unwrap();
+    let results = manager.get_many(&urls, 4).await;
+    assert_eq!(results.len(), 12);
+    assert!(results[3].is_err());
+    assert_eq!(results[11].as_ref().unwrap().status(), 404);
+    let bodies: Vec<String> = results
+        .iter()
+        .filter_map(|r| r.as_ref().ok())
+        .filter(|r| r.status() == 200)
+        .map(|r| String::from_utf8(r.body().to_vec()).unwrap())
+        .collect();
+    let expected: Vec<String> = (0..10).map(|i| i.to_string()).collect();
+    assert_eq!(bodies, expected);
+
+    let reqs = vec![
+        crate::create_json_post(server.url(), "/0", "{}").unwrap(),
+        crate::create_get(server.url(), "/1").unwrap(),
+    ];
+    let results = manager.execute_many(reqs, 2).await;
+    assert_eq!(results[0].as_ref().unwrap().status(), 404);
+    assert_eq!(results[1].as_ref().unwrap().body(), "1");
+}
+
+/// RUST_LOG=debug cargo test --lib -- batch::test_execute_batch --exact --show-output
+#[tokio::test]
+async fn test_execute_batch() {
+    use hyper::Method;
+
+    let server = crate::testing::MockServer::start().await.unwrap();
+    server.stub(Method::GET, "/a", 200, "a");
+    server.stub(Method::GET, "/b", 200, "b");
+
+    let item = |name: &str, path: &str, depends_on: &[&str]| BatchItem {
+        name: name.to_string(),
+        depends_on: depends_on.iter().map(|s| s.to_string()).collect(),
+        request: RequestSpec::new(&server.url(), path),
+    };
+    let items = vec![
+        item("b", "/b", &["a"]),
+        item("a", "/a", &[]),
+        item("missing", "/missing", &[]),
+        item("after-missing", "/a", &["missing"]),
+        item("after-skipped", "/a", &["after-missing", "a"]),
+    ];
+
+    let manager = Manager::new().unwrap();
+    let results = manager.execute_batch(&items, 2).await.unwrap();
+    let names: Vec<&str> = results.iter().map(|r| r.name.as_str()).collect();
+    assert_eq!(
+        names,
+        vec!["b", "a", "missing", "after-missing", "after-skipped"]
+    );
+    assert!(results[0].is_success());
+    assert!(results[1].is_success());
+    match &results[0].outcome {
+        BatchOutcome::Completed { body, .. } => assert_eq!(body, "b"),
+        o => panic!("unexpected {:?}", o),
+    }
+    assert!(matches!(
+        results[2].outcome,
+        BatchOutcome::Completed { status: 404, .. }
+    ));
+    assert!(matches!(results[3].outcome, BatchOutcome::Skipped { .. }));
+    assert!(matches!(results[4].outcome, BatchOutcome::Skipped { .. }));
+    server.assert_received_in_order(&[(Method::GET, "/a"), (Method::GET, "/b")]);
+
+    let ret = manager
+        .execute_batch(&[item("x", "/a", &["nope"])], 1)
+        .await;
+    assert!(ret.is_err());
+    let ret = manager
+        .execute_batch(&[item("x", "/a", &["y"]), item("y", "/a", &["x"])], 1)
+        .await;
+    assert!(ret.unwrap_err().to_string().contains("cycle"));
+    let ret = manager
+        .execute_batch(&[item("x", "/a", &[]), item("x", "/b", &[])], 1)
+        .await;
+    assert!(ret.is_err());
+}
@@ -0,0 +1,272 @@
+//! "http-manager" CLI, so that ops scripts share the timeout, retry, and
+//! TLS behavior of the library (configured via "--config" or the
+//! "HTTP_MANAGER_*" environment variables): every request goes through
+//! the configured "Manager".
+
+use std::{
+    io::{self, Error, ErrorKind, Write},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use clap::{crate_version, value_parser, Arg, ArgAction, ArgMatches, Command};
+use http_manager::{
+    config::Config,
+    cookie::{CookieFileFormat, CookieJar},
+    loadtest::LoadTestConfig,
+    stall::LowSpeedLimit,
+    Checksum, Manager, ManagerBuilder,
+};
+
+const APP_NAME: &str = "http-manager";
+
+#[tokio::main]
+async fn main() -> io::Result<()> {
+    let matches = Command::new(APP_NAME)
+        .version(crate_version!())
+        .about("HTTP manager CLI")
+        .arg(
+            Arg::new("CONFIG")
+                .long("config")
+                .help("TOML or YAML config file (default: HTTP_MANAGER_* environment variables)")
+                .global(true),
+        )
+        .arg(
+            Arg::new("COOKIE_JAR")
+                .long("cookie-jar")
+                .help("cookie file (Netscape format) loaded before and saved after the request")
+                .global(true),
+        )
+        .arg(
+            Arg::new("LOG_LEVEL")
+                .long("log-level")
+                .default_value("info")
+                .global(true),
+        )
+        .subcommands(vec![
+            Command::new("get")
+                .about("Sends a GET request and writes the body to stdout")
+                .arg(Arg::new("URL").required(true))
+                .arg(Arg::new("PATH").default_value("")),
+            Command::new("post-json")
+                .about("Sends a JSON POST request and writes the body to stdout")
+                .arg(Arg::new("URL").required(true))
+                .arg(Arg::new("PATH").default_value(""))
+                .arg(Arg::new("DATA").long("data").required(true)),
+            Command::new("download")
+                .about("Downloads a file, optionally verifying its SHA-256 digest")
+                .arg(Arg::new("URL").required(true))
+                .arg(Arg::new("OUTPUT").long("output").short('o').required(true))
+                .arg(
+                    Arg::new("SHA256")
+                        .long("sha256")
+                        .help("expected hex-encoded SHA-256 digest"),
+                )
+                .arg(
+                    Arg::new("RESUME")
+                        .long("resume")
+                        .help("continues a partial download (OUTPUT.part) with a range request")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("SPEED_LIMIT")
+                        .long("speed-limit")
+                        .help("aborts if slower than this many bytes/sec for --speed-time")
+                        .value_parser(value_parser!(u64)),
+                )
+                .arg(
+                    Arg::new("SPEED_TIME")
+                        .long("speed-time")
+                        .help("seconds the download may stay below --speed-limit")
+                        .value_parser(value_parser!(u64))
+                        .default_value("30"),
+                ),
+            Command::new("wait-ready")
+                .about("Polls the URL until it responds with a 2xx status code")
+                .arg(Arg::new("URL").required(true))
+                .arg(Arg::new("PATH").default_value(""))
+                .arg(
+                    Arg::new("INTERVAL")
+                        .long("interval")
+                        .help("seconds between attempts")
+                        .value_parser(value_parser!(u64))
+                        .default_value("1"),
+                )
+                .arg(
+                    Arg::new("TIMEOUT")
+                        .long("timeout")
+                        .help("seconds to wait before giving up")
+                        .value_parser(value_parser!(u64))
+                        .default_value("60"),
+                ),
+            Command::new("loadtest")
+                .about("Sends GET requests at a fixed rate and reports latency percentiles")
+                .arg(Arg::new("URL").required(true))
+                .arg(
+                    Arg::new("RATE")
+                        .long("rate")
+                        .help("requests per second")
+                        .value_parser(value_parser!(u32))
+                        .default_value("50"),
+                )
+                .arg(
+                    Arg::new("DURATION")
+                        .long("duration")
+                        .help("seconds to send requests for")
+                        .value_parser(value_parser!(u64))
+                        .default_value("10"),
+                ),
+        ])
+        .subcommand_required(true)
+        .get_matches();
+
+    let log_level = matches.get_one::<String>("LOG_LEVEL").unwrap();
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(log_level)).init();
+
+    let mut builder = match matches.get_one::<String>("CONFIG") {
+        Some(p) => Config::load(p)?.apply(ManagerBuilder::default())?,
+        None => ManagerBuilder::from_env()?,
+    };
+    if let Some(("download", sub)) = matches.subcommand() {
+        if let Some(v) = sub.get_one::<u64>("SPEED_LIMIT") {
+            builder = builder.low_speed_limit(LowSpeedLimit::new(
+                *v,
+                Duration::from_secs(*sub.get_one::<u64>("SPEED_TIME").unwrap()),
+            ));
+        }
+    }
+    let cookie_jar = match matches.get_one::<String>("COOKIE_JAR") {
+        Some(p) => {
+            let jar = Arc::new(CookieJar::load_or_new(p)?);
+            builder = builder.cookie_jar(jar.clone());
+            Some((p, jar))
+        }
+        None => None,
+    };
+    let manager = builder.build()?;
+
+    let ret = run(&manager, &matches).await;
+    if let Some((p, jar)) = cookie_jar {
+        jar.save(p, CookieFileFormat::Netscape)?;
+    }
+    ret
+}
+
+async fn run(manager: &Manager, matches: &ArgMatches) -> io::Result<()> {
+    match matches.subcommand() {
+        Some(("get", sub)) => {
+            let out = manager
+                .read_bytes_with_retry(|| http_manager::create_get(url(sub), path(sub)), true)
+                .await?;
+            io::stdout().write_all(&out)
+        }
+        Some(("post-json", sub)) => {
+            let data = sub.get_one::<String>("DATA").unwrap();
+            // retried only if the retry policy sets "retry_non_idempotent"
+            let out = manager
+                .read_bytes_with_retry(
+                    || http_manager::create_json_post(url(sub), path(sub), data.clone()),
+                    true,
+                )
+                .await?;
+            io::stdout().write_all(&out)
+        }
+        Some(("download", sub)) => {
+            download(
+                manager,
+                url(sub),
+                sub.get_one::<String>("OUTPUT").unwrap(),
+                sub.get_one::<String>("SHA256").map(|s| s.as_str()),
+                sub.get_flag("RESUME"),
+            )
+            .await
+        }
+        Some(("wait-ready", sub)) => {
+            wait_ready(
+                manager,
+                url(sub),
+                path(sub),
+                Duration::from_secs(*sub.get_one::<u64>("INTERVAL").unwrap()),
+                Duration::from_secs(*sub.get_one::<u64>("TIMEOUT").unwrap()),
+            )
+            .await
+        }
+        Some(("loadtest", sub)) => {
+            let cfg = LoadTestConfig {
+                rate: *sub.get_one::<u32>("RATE").unwrap(),
+                duration: Duration::from_secs(*sub.get_one::<u64>("DURATION").unwrap()),
+                ..Default::default()
+            };
+            let report = manager.load_test(url(sub), &cfg).await?;
+            println!("{}", report);
+            Ok(())
+        }
+        _ => unreachable!("unknown subcommand"),
+    }
+}
+
+fn url(sub: &ArgMatches) -> &str {
+    sub.get_one::<String>("URL").unwrap()
+}
+
+fn path(sub: &ArgMatches) -> &str {
+    sub.get_one::<String>("PATH").unwrap()
+}
+
+/// Downloads via the manager, to "{output}.part" if resuming (see
+/// "Manager::download_file_resumable").
+async fn download(
+    manager: &Manager,
+    url: &str,
+    output: &str,
+    sha256: Option<&str>,
+    resume: bool,
+) -> io::Result<()> {
+    let checksum = sha256.map(|v| Checksum::Sha256(v.trim().to_string()));
+    if !resume {
+        if let Some(checksum) = checksum {
+            return manager.download_file_verified(url, output, checksum).await;
+        }
+        // a stale partial file is not resumed without "--resume"
+        match tokio::fs::remove_file(format!("{}.part", output)).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+
+    manager.download_file_resumable(url, output).await?;
+    log::info!("downloaded {}", output);
+    match checksum {
+        Some(checksum) => checksum.verify_file(output).await,
+        None => Ok(()),
+    }
+}
+
+async fn wait_ready(
+    manager: &Manager,
+    url: &str,
+    path: &str,
+    interval: Duration,
+    timeout: Duration,
+) -> io::Result<()> {
+    let start = Instant::now();
+    loop {
+        let req = http_manager::create_get(url, path)?;
+        match manager.send(req).await {
+            Ok(resp) if resp.status().is_success() => {
+                log::info!("ready after {:?}", start.elapsed());
+                return Ok(());
+            }
+            Ok(resp) => log::info!("not ready yet (status code {})", resp.status()),
+            Err(e) => log::info!("not ready yet ({})", e),
+        }
+
+        if start.elapsed() + interval > timeout {
+            return Err(Error::new(
+                ErrorKind::TimedOut,
+                format!("{} not ready after {:?}", url, timeout),
+            ));
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
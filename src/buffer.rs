@@ -0,0 +1,126 @@
+//! Pool of read buffers, so that services sending many requests per
+//! second do not allocate a new buffer for every multi-chunk body.
+
+use std::sync::Mutex;
+
+use bytes::{Bytes, BytesMut};
+use hyper::body::{Body, HttpBody};
+
+/// Buffers kept by the global pool.
+const MAX_POOLED: usize = 64;
+/// Larger buffers are dropped instead of pooled, so that a single large
+/// response does not pin its memory for the lifetime of the process.
+const MAX_RETAINED_CAPACITY: usize = 256 * 1024;
+const INITIAL_CAPACITY: usize = 8 * 1024;
+
+static GLOBAL: BufferPool = BufferPool::new(MAX_POOLED);
+
+pub(crate) struct BufferPool {
+    bufs: Mutex<Vec<BytesMut>>,
+    max_pooled: usize,
+}
+
+impl BufferPool {
+    pub(crate) const fn new(max_pooled: usize) -> Self {
+        Self {
+            bufs: Mutex::new(Vec::new()),
+            max_pooled,
+        }
+    }
+
+    pub(crate) fn take(&self) -> BytesMut {
+        match self.bufs.lock().unwrap().pop() {
+            Some(b) => b,
+            None => BytesMut::with_capacity(INITIAL_CAPACITY),
+        }
+    }
+
+    /// Returns the buffer to the pool. Its memory is reclaimed by the next
+    /// "reserve" once the "Bytes" split off from it are dropped.
+    pub(crate) fn give(&self, mut buf: BytesMut) {
+        if buf.capacity() > MAX_RETAINED_CAPACITY {
+            return;
+        }
+        buf.clear();
+        let mut bufs = self.bufs.lock().unwrap();
+        if bufs.len() < self.max_pooled {
+            bufs.push(buf);
+        }
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.bufs.lock().unwrap().len()
+    }
+
+    /// Reads the whole body. A single-chunk body is returned as is without
+    /// copying; otherwise the chunks are copied into a pooled buffer.
+    pub(crate) async fn collect(&self, mut body: Body) -> Result<Bytes, hyper::Error> {
+        let first = match body.data().await {
+            Some(c) => c?,
+            None => return Ok(Bytes::new()),
+        };
+        let second = match body.data().await {
+            Some(c) => c?,
+            None => return Ok(first),
+        };
+
+        let mut buf = self.take();
+        buf.reserve(first.len() + second.len() + body.size_hint().lower() as usize);
+        buf.extend_from_slice(&first);
+        buf.extend_from_slice(&second);
+        while let Some(c) = body.data().await {
+            match c {
+                Ok(c) => buf.extend_from_slice(&c),
+                Err(e) => {
+                    self.give(buf);
+                    return Err(e);
+                }
+            }
+        }
+        let out = buf.split().freeze();
+        self.give(buf);
+        Ok(out)
+    }
+}
+
+/// Reads the whole body with the global pool (see "BufferPool::collect").
+pub(crate) async fn collect(body: Body) -> Result<Bytes, hyper::Error> {
+    GLOBAL.collect(body).await
+}
+
+/// RUST_LOG=debug cargo test --lib -- buffer::test_buffer_pool --exact --show-output
+#[tokio::test]
+async fn test_buffer_pool() {
+    use futures_util::stream;
+
+    let chunked = |chunks: Vec<&'static str>| {
+        Body::wrap_stream(stream::iter(
+            chunks
+                .into_iter()
+                .map(|c| Ok::<_, std::io::Error>(Bytes::from_static(c.as_bytes()))),
+        ))
+    };
+
+    let pool = BufferPool::new(2);
+    assert_eq!(pool.collect(Body::empty()).await.unwrap(), "");
+    assert_eq!(pool.collect(Body::from("single")).await.unwrap(), "single");
+    // single chunks are not copied
+    assert_eq!(pool.len(), 0);
+
+    let out = pool.collect(chunked(vec!["a", "b", "c"])).await.unwrap();
+    assert_eq!(out, "abc");
+    assert_eq!(pool.len(), 1);
+    drop(out);
+
+    // the pooled buffer is reused instead of allocating a new one
+    let out = pool.collect(chunked(vec!["de", "f"])).await.unwrap();
+    assert_eq!(out, "def");
+    assert_eq!(pool.len(), 1);
+
+    pool.give(BytesMut::new());
+    pool.give(BytesMut::new());
+    assert_eq!(pool.len(), 2);
+    pool.give(BytesMut::with_capacity(MAX_RETAINED_CAPACITY + 1));
+    assert_eq!(pool.len(), 2);
+}
@@ -0,0 +1,138 @@
+//! Abstracts the request round trip of "Manager", so that the code sending
+//! requests can be tested without a server (see "mock::FakeClient").
+
+use std::{future::Future, io, pin::Pin};
+
+use hyper::{
+    body::Bytes,
+    client::{connect::Connect, Client},
+    Body, Method, Request, Response,
+};
+use url::Url;
+
+use crate::{error, Manager};
+
+/// Future of the whole response, returned by "HttpClient" methods.
+pub type ResponseFuture<'a> =
+    Pin<Box<dyn Future<Output = io::Result<Response<Bytes>>> + Send + 'a>>;
+
+/// Sends requests and reads the whole responses.
+/// Only "read_response" is required: "get", "post" and "request" build
+/// the request from "url_for" and send it through "read_response", so the
+/// code written against those works with any client.
+pub trait HttpClient: Send + Sync {
+    /// Sends the request and reads the whole body, keeping the status and
+    /// headers (of any status code).
+    fn read_response(&self, req: Request<Body>) -> ResponseFuture<'_>;
+
+    /// Returns the URL of the request path.
+    /// By default, the path must be an absolute URL.
+    fn url_for(&self, path: &str) -> io::Result<Url> {
+        crate::join_uri(path, "")
+    }
+
+    /// Sends a request to the path (see "url_for"), and reads the whole
+    /// response (see "read_response").
+    fn request(&self, method: Method, path: &str, body: Body) -> ResponseFuture<'_> {
+        let req = self.url_for(path).and_then(|url| {
+            Request::builder()
+                .method(method)
+                .uri(url.as_str())
+                .body(body)
+                .map_err(|e| {
+                    error::Error::UrlParse(format!("failed to create request {}", e)).into()
+                })
+        });
+        Box::pin(async move { self.read_response(req?).await })
+    }
+
+    /// Sends a GET request with no body to the path (see "request").
+    fn get(&self, path: &str) -> ResponseFuture<'_> {
+        self.request(Method::GET, path, Body::empty())
+    }
+
+    /// Sends a POST request with the body to the path (see "request").
+    fn post(&self, path: &str, body: Body) -> ResponseFuture<'_> {
+        self.request(Method::POST, path, body)
+    }
+}
+
+/// Sends the request as is, with no timeout.
+impl<C> HttpClient for Client<C, Body>
+where
+    C: Connect + Clone + Send + Sync + 'static,
+{
+    fn read_response(&self, req: Request<Body>) -> ResponseFuture<'_> {
+        Box::pin(async move {
+            let resp = self
+                .request(req)
+                .await
+                .map_err(|e| error::Error::from_hyper("failed to fetch response", &e))?;
+            let (parts, body) = resp.into_parts();
+            let body = crate::buffer::collect(body)
+                .await
+                .map_err(|e| error::Error::Body(format!("failed to read response body {}", e)))?;
+            Ok(Response::from_parts(parts, body))
+        })
+    }
+}
+
+/// Sends the requests over the pooled connections of the manager, with its
+/// policies and timeouts, and resolves relative paths against its base URL.
+impl HttpClient for Manager {
+    fn read_response(&self, req: Request<Body>) -> ResponseFuture<'_> {
+        Box::pin(Manager::read_response(self, req))
+    }
+
+    fn url_for(&self, path: &str) -> io::Result<Url> {
+        Manager::url_for(self, path)
+    }
+}
+
+/// RUST_LOG=debug cargo test --lib -- client::test_manager_client --exact --show-output
+#[tokio::test]
+async fn test_manager_client() {
+    let server = crate::testing::MockServer::start().await.unwrap();
+    server.stub(Method::GET, "/v1/status", 503, "busy");
+    server.stub(Method::POST, "/v1/echo", 200, "ok");
+
+    let client: Box<dyn HttpClient> = Box::new(
+        Manager::builder()
+            .base_url(&format!("{}/v1", server.url()))
+            .build()
+            .unwrap(),
+    );
+    let req = crate::create_get(server.url(), "/v1/status").unwrap();
+    let resp = client.read_response(req).await.unwrap();
+    assert_eq!(resp.status(), 503);
+    assert_eq!(resp.body(), "busy");
+
+    // relative to the base URL of the manager
+    let resp = client.get("status").await.unwrap();
+    assert_eq!(resp.status(), 503);
+    let resp = client.post("echo", Body::from("hello")).await.unwrap();
+    assert_eq!(resp.body(), "ok");
+
+    server.assert_received(Method::GET, "/v1/status").times(2);
+    server.assert_received(Method::POST, "/v1/echo").once();
+}
+
+/// RUST_LOG=debug cargo test --lib -- client::test_hyper_client --exact --show-output
+#[tokio::test]
+async fn test_hyper_client() {
+    let server = crate::testing::MockServer::start().await.unwrap();
+    server.stub(Method::GET, "/v1/status", 200, "ok");
+
+    let client = Client::new();
+    let resp = HttpClient::get(&client, &format!("{}/v1/status", server.url()))
+        .await
+        .unwrap();
+    assert_eq!(resp.body(), "ok");
+
+    // no base URL
+    assert!(matches!(
+        error::Error::from(HttpClient::get(&client, "/v1/status").await.unwrap_err()),
+        error::Error::UrlParse(_)
+    ));
+    server.assert_received(Method::GET, "/v1/status").once();
+}
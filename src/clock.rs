@@ -0,0 +1,128 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::time::Instant;
+
+/// Abstracts time for retry/backoff logic, so that the waits can be
+/// tested in milliseconds instead of real wall time.
+pub trait Clock: Send + Sync {
+    /// Returns the current instant.
+    fn now(&self) -> Instant;
+
+    /// Waits until "dur" has elapsed.
+    fn sleep(&self, dur: Duration) -> Pin<Box<dyn Future<Output = ()> + Send + '_>>;
+}
+
+/// Clock backed by "tokio::time".
+/// When the runtime clock is paused via "tokio::time::pause"
+/// (e.g., "#[tokio::test(start_paused = true)]"), sleeps auto-advance
+/// the paused clock and return immediately.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokioClock;
+
+impl Clock for TokioClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, dur: Duration) -> Pin<Box<dyn Future<Output = ()> + Send + '_>> {
+        Box::pin(tokio::time::sleep(dur))
+    }
+}
+
+/// Test clock that never blocks: each "sleep" advances the virtual time
+/// and records the requested duration, so tests can assert on the exact
+/// backoff sequence.
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    inner: Arc<Mutex<MockClockState>>,
+}
+
+#[derive(Debug)]
+struct MockClockState {
+    now: Instant,
+    sleeps: Vec<Duration>,
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockClock {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(MockClockState {
+                now: Instant::now(),
+                sleeps: Vec::new(),
+            })),
+        }
+    }
+
+    /// Moves the virtual time forward without recording a sleep.
+    pub fn advance(&self, dur: Duration) {
+        let mut state = self.inner.lock().unwrap();
+        state.now += dur;
+    }
+
+    /// Returns all durations passed to "sleep", in order.
+    pub fn sleeps(&self) -> Vec<Duration> {
+        self.inner.lock().unwrap().sleeps.clone()
+    }
+
+    /// Returns the sum of all recorded sleeps.
+    pub fn total_slept(&self) -> Duration {
+        self.inner.lock().unwrap().sleeps.iter().sum()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.inner.lock().unwrap().now
+    }
+
+    fn sleep(&self, dur: Duration) -> Pin<Box<dyn Future<Output = ()> + Send + '_>> {
+        let mut state = self.inner.lock().unwrap();
+        state.now += dur;
+        state.sleeps.push(dur);
+        Box::pin(async {})
+    }
+}
+
+/// RUST_LOG=debug cargo test --lib -- clock::test_mock_clock --exact --show-output
+#[test]
+fn test_mock_clock() {
+    let clock = MockClock::new();
+    let start = clock.now();
+
+    tokio_test::block_on(async {
+        clock.sleep(Duration::from_secs(1)).await;
+        clock.sleep(Duration::from_secs(2)).await;
+    });
+    clock.advance(Duration::from_millis(500));
+
+    assert_eq!(
+        clock.sleeps(),
+        vec![Duration::from_secs(1), Duration::from_secs(2)]
+    );
+    assert_eq!(clock.total_slept(), Duration::from_secs(3));
+    assert_eq!(clock.now() - start, Duration::from_millis(3500));
+}
+
+/// RUST_LOG=debug cargo test --lib -- clock::test_tokio_clock_paused --exact --show-output
+#[tokio::test(start_paused = true)]
+async fn test_tokio_clock_paused() {
+    let clock = TokioClock;
+    let started = std::time::Instant::now();
+    let start = clock.now();
+
+    clock.sleep(Duration::from_secs(60)).await;
+
+    assert!(clock.now() - start >= Duration::from_secs(60));
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
@@ -0,0 +1,221 @@
+//! Adaptive concurrency control, so that bulk workloads back off when an
+//! upstream saturates instead of tripping its rate limiter.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::sync::Notify;
+
+/// Additive-increase/multiplicative-decrease (AIMD) settings.
+/// The limit grows by "increase" per limit-many successful requests
+/// (i.e., about once per round of requests), and is multiplied by
+/// "decrease_factor" on an overload signal: an error, a 429 or 5xx
+/// response, or a latency above "latency_threshold".
+#[derive(Debug, Clone, PartialEq)]
+pub struct AimdConfig {
+    pub initial_limit: usize,
+    pub min_limit: usize,
+    pub max_limit: usize,
+    pub increase: f64,
+    pub decrease_factor: f64,
+    pub latency_threshold: Duration,
+}
+
+impl Default for AimdConfig {
+    fn default() -> Self {
+        Self {
+            initial_limit: 10,
+            min_limit: 1,
+            max_limit: 200,
+            increase: 1.0,
+            decrease_factor: 0.5,
+            latency_threshold: Duration::from_secs(2),
+        }
+    }
+}
+
+/// Observed result of a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Success,
+    /// Error, 429, 5xx, or too slow.
+    Overload,
+    /// Not counted towards the limit (e.g., a 4xx caused by the request).
+    Ignore,
+}
+
+#[derive(Debug)]
+struct State {
+    limit: f64,
+    in_flight: usize,
+}
+
+/// Limits in-flight requests to a limit adjusted by AIMD.
+#[derive(Debug, Clone)]
+pub struct AdaptiveLimiter {
+    config: AimdConfig,
+    state: Arc<Mutex<State>>,
+    notify: Arc<Notify>,
+}
+
+impl AdaptiveLimiter {
+    pub fn new(config: AimdConfig) -> Self {
+        let limit = config
+            .initial_limit
+            .clamp(config.min_limit.max(1), config.max_limit.max(1)) as f64;
+        Self {
+            config,
+            state: Arc::new(Mutex::new(State {
+                limit,
+                in_flight: 0,
+            })),
+            notify: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Returns the current limit.
+    pub fn limit(&self) -> usize {
+        self.state.lock().unwrap().limit as usize
+    }
+
+    /// Returns the number of requests holding a permit.
+    pub fn in_flight(&self) -> usize {
+        self.state.lock().unwrap().in_flight
+    }
+
+    /// Waits until the number of in-flight requests is below the limit.
+    pub async fn acquire(&self) -> Permit {
+        loop {
+            // registered before the check, so a release in between is not missed
+            let notified = self.notify.notified();
+            {
+                let mut state = self.state.lock().unwrap();
+                if state.in_flight < state.limit as usize {
+                    state.in_flight += 1;
+                    return Permit {
+                        limiter: self.clone(),
+                        start: Instant::now(),
+                        released: false,
+                    };
+                }
+            }
+            notified.await;
+        }
+    }
+
+    /// Classifies a response status code (see "AimdConfig").
+    pub fn classify(&self, status: Option<u16>, latency: Duration) -> Outcome {
+        match status {
+            None => Outcome::Overload,
+            Some(429) | Some(500..=599) => Outcome::Overload,
+            Some(_) if latency > self.config.latency_threshold => Outcome::Overload,
+            Some(400..=499) => Outcome::Ignore,
+            Some(_) => Outcome::Success,
+        }
+    }
+
+    fn release(&self, outcome: Outcome) {
+        let mut state = self.state.lock().unwrap();
+        state.in_flight -= 1;
+        let (min, max) = (
+            self.config.min_limit.max(1) as f64,
+            self.config.max_limit.max(1) as f64,
+        );
+        match outcome {
+            Outcome::Success => {
+                state.limit = (state.limit + self.config.increase / state.limit).min(max);
+            }
+            Outcome::Overload => {
+                state.limit = (state.limit * self.config.decrease_factor).max(min);
+            }
+            Outcome::Ignore => {}
+        }
+        drop(state);
+        self.notify.notify_waiters();
+    }
+}
+
+/// In-flight slot, released by "record" or, without adjusting the limit,
+/// when dropped.
+#[derive(Debug)]
+pub struct Permit {
+    limiter: AdaptiveLimiter,
+    start: Instant,
+    released: bool,
+}
+
+impl Permit {
+    /// Returns the time since the permit was acquired.
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// Releases the permit and adjusts the limit.
+    pub fn record(mut self, outcome: Outcome) {
+        self.released = true;
+        self.limiter.release(outcome);
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if !self.released {
+            self.limiter.release(Outcome::Ignore);
+        }
+    }
+}
+
+/// RUST_LOG=debug cargo test --lib -- concurrency::test_adaptive_limiter --exact --show-output
+#[tokio::test]
+async fn test_adaptive_limiter() {
+    let limiter = AdaptiveLimiter::new(AimdConfig {
+        initial_limit: 2,
+        min_limit: 1,
+        max_limit: 4,
+        ..Default::default()
+    });
+    assert_eq!(limiter.limit(), 2);
+
+    let p1 = limiter.acquire().await;
+    let _p2 = limiter.acquire().await;
+    assert_eq!(limiter.in_flight(), 2);
+    let blocked = tokio::time::timeout(Duration::from_millis(50), limiter.acquire()).await;
+    assert!(blocked.is_err());
+
+    // a release wakes up a waiter
+    let waiter = {
+        let limiter = limiter.clone();
+        tokio::spawn(async move { limiter.acquire().await })
+    };
+    p1.record(Outcome::Ignore);
+    let p3 = waiter.await.unwrap();
+    assert_eq!(limiter.in_flight(), 2);
+
+    p3.record(Outcome::Overload);
+    assert_eq!(limiter.limit(), 1);
+    drop(_p2);
+    assert_eq!(limiter.in_flight(), 0);
+
+    // additive increase of about one per round, up to the max
+    for _ in 0..50 {
+        limiter.acquire().await.record(Outcome::Success);
+    }
+    assert_eq!(limiter.limit(), 4);
+
+    assert_eq!(
+        limiter.classify(Some(200), Duration::from_millis(10)),
+        Outcome::Success
+    );
+    assert_eq!(
+        limiter.classify(Some(200), Duration::from_secs(3)),
+        Outcome::Overload
+    );
+    assert_eq!(
+        limiter.classify(Some(503), Duration::ZERO),
+        Outcome::Overload
+    );
+    assert_eq!(limiter.classify(Some(404), Duration::ZERO), Outcome::Ignore);
+    assert_eq!(limiter.classify(None, Duration::ZERO), Outcome::Overload);
+}
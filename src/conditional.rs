@@ -0,0 +1,359 @@
+//! Conditional requests: GETs so that polling loops skip unchanged
+//! documents, and writes that fail if the resource changed since it was
+//! read (optimistic concurrency).
+
+use std::{
+    error, fmt,
+    io::{self, Error, ErrorKind},
+};
+
+use hyper::{
+    body::Bytes,
+    header::{
+        HeaderName, HeaderValue, ETAG, IF_MATCH, IF_MODIFIED_SINCE, IF_NONE_MATCH,
+        IF_UNMODIFIED_SINCE, LAST_MODIFIED,
+    },
+    Body, HeaderMap, Method, Request, Response, StatusCode,
+};
+
+use crate::Manager;
+
+/// Cache validators of a response.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl Validators {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let get = |name| {
+            headers
+                .get(name)
+                .and_then(|v: &HeaderValue| v.to_str().ok())
+                .map(|v| v.to_string())
+        };
+        Self {
+            etag: get(ETAG),
+            last_modified: get(LAST_MODIFIED),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CachedFetch {
+    /// The document changed (or no validators were passed).
+    Fresh { body: Bytes, validators: Validators },
+    /// The document did not change since the validators were issued.
+    NotModified { validators: Validators },
+}
+
+impl CachedFetch {
+    /// Returns the validators to pass to the next fetch.
+    pub fn validators(&self) -> &Validators {
+        match self {
+            CachedFetch::Fresh { validators, .. } => validators,
+            CachedFetch::NotModified { validators } => validators,
+        }
+    }
+}
+
+/// Error of a conditional write whose precondition failed (412), since
+/// the resource changed after it was read. Returned as the inner error of
+/// an "io::Error" (see "is_conflict"), so that read-modify-write loops
+/// can read the resource again and retry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    /// Current validators, if the server sent them with the 412.
+    pub validators: Validators,
+    pub body: Bytes,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "precondition failed, the resource was modified")?;
+        if let Some(etag) = &self.validators.etag {
+            write!(f, " (current etag {})", etag)?;
+        }
+        Ok(())
+    }
+}
+
+impl error::Error for Conflict {}
+
+/// Returns true if the error is a "Conflict".
+pub fn is_conflict(e: &io::Error) -> bool {
+    matches!(
+        crate::error::Error::typed(e),
+        Some(crate::error::Error::Conflict(_))
+    )
+}
+
+impl Manager {
+    /// Sends a GET request with "If-None-Match" and "If-Modified-Since"
+    /// from the previous validators. Fails on a status code other than
+    /// 2xx and 304.
+    pub async fn fetch_if_modified(
+        &self,
+        url: &str,
+        path: &str,
+        previous: &Validators,
+    ) -> io::Result<CachedFetch> {
+        let mut req = crate::create_get(url, path)?;
+        let invalid = |e| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid cache validator {}", e),
+            )
+        };
+        if let Some(etag) = &previous.etag {
+            let v = HeaderValue::from_str(etag).map_err(invalid)?;
+            req.headers_mut().insert(IF_NONE_MATCH, v);
+        }
+        if let Some(lm) = &previous.last_modified {
+            let v = HeaderValue::from_str(lm).map_err(invalid)?;
+            req.headers_mut().insert(IF_MODIFIED_SINCE, v);
+        }
+
+        let resp = self.read_response(req).await?;
+        let mut validators = Validators::from_headers(resp.headers());
+        match resp.status() {
+            StatusCode::NOT_MODIFIED => {
+                // a 304 may omit the validators, which are then unchanged
+                if validators.etag.is_none() {
+                    validators.etag = previous.etag.clone();
+                }
+                if validators.last_modified.is_none() {
+                    validators.last_modified = previous.last_modified.clone();
+                }
+                Ok(CachedFetch::NotModified { validators })
+            }
+            s if s.is_success() => Ok(CachedFetch::Fresh {
+                body: resp.into_body(),
+                validators,
+            }),
+            s => Err(Error::new(
+                ErrorKind::Other,
+                format!(
+                    "{} returned unexpected status code {}",
+                    crate::redact::url(url),
+                    s
+                ),
+            )),
+        }
+    }
+
+    /// Sends a PUT request that only applies if the resource still has
+    /// the entity tag (e.g., "Validators::etag" of the last read). Fails
+    /// with a "Conflict" on 412, and on any other non-2xx status code.
+    pub async fn put_if_match(
+        &self,
+        url: &str,
+        path: &str,
+        etag: &str,
+        body: impl Into<Body>,
+    ) -> io::Result<Response<Bytes>> {
+        self.write_if(Method::PUT, url, path, IF_MATCH, etag, body.into())
+            .await
+    }
+
+    /// Sends a POST request that only applies if the resource was not
+    /// modified since the HTTP date (e.g., "Validators::last_modified" of
+    /// the last read). Fails with a "Conflict" on 412, and on any other
+    /// non-2xx status code.
+    pub async fn post_if_unmodified_since(
+        &self,
+        url: &str,
+        path: &str,
+        last_modified: &str,
+        body: impl Into<Body>,
+    ) -> io::Result<Response<Bytes>> {
+        self.write_if(
+            Method::POST,
+            url,
+            path,
+            IF_UNMODIFIED_SINCE,
+            last_modified,
+            body.into(),
+        )
+        .await
+    }
+
+    async fn write_if(
+        &self,
+        method: Method,
+        url: &str,
+        path: &str,
+        name: HeaderName,
+        validator: &str,
+        body: Body,
+    ) -> io::Result<Response<Bytes>> {
+        let uri = crate::join_uri(url, path)?;
+        let v = HeaderValue::from_str(validator).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid precondition {} {}", name, e),
+            )
+        })?;
+        let req = Request::builder()
+            .method(method)
+            .uri(uri.as_str())
+            .header(name, v)
+            .body(body)
+            .map_err(|e| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("failed to create request {}", e),
+                )
+            })?;
+
+        let resp = self.read_response(req).await?;
+        match resp.status() {
+            StatusCode::PRECONDITION_FAILED => Err(crate::error::Error::Conflict(Conflict {
+                validators: Validators::from_headers(resp.headers()),
+                body: resp.into_body(),
+            })
+            .into()),
+            s if s.is_success() => Ok(resp),
+            s => Err(Error::new(
+                ErrorKind::Other,
+                format!(
+                    "{} returned unexpected status code {}",
+                    crate::redact::url(uri.as_str()),
+                    s
+                ),
+            )),
+        }
+    }
+}
+
+/// RUST_LOG=debug cargo test --lib -- conditional::test_fetch_if_modified --exact --show-output
+#[tokio::test]
+async fn test_fetch_if_modified() {
+    use hyper::Method;
+
+    use crate::testing::Stub;
+
+    let server = crate::testing::MockServer::start().await.unwrap();
+    server.register(
+        Stub::new(Method::GET, "/config")
+            .respond(200, "v1")
+            .respond_header("etag", "\"v1\"")
+            .respond_header("last-modified", "Wed, 21 Oct 2015 07:28:00 GMT"),
+    );
+    server.register(
+        Stub::new(Method::GET, "/config")
+            .with_header("if-none-match", "\"v1\"")
+            .respond(304, ""),
+    );
+
+    let manager = Manager::new().unwrap();
+    let first = manager
+        .fetch_if_modified(&server.url(), "/config", &Validators::default())
+        .await
+        .unwrap();
+    let validators = match &first {
+        CachedFetch::Fresh { body, validators } => {
+            assert_eq!(body, "v1");
+            validators.clone()
+        }
+        _ => panic!("expected a fresh response"),
+    };
+    assert_eq!(validators.etag.as_deref(), Some("\"v1\""));
+
+    let second = manager
+        .fetch_if_modified(&server.url(), "/config", &validators)
+        .await
+        .unwrap();
+    assert_eq!(
+        second,
+        CachedFetch::NotModified {
+            validators: validators.clone()
+        }
+    );
+    server
+        .assert_received(Method::GET, "/config")
+        .with_header("if-modified-since", "Wed, 21 Oct 2015 07:28:00 GMT");
+
+    assert!(manager
+        .fetch_if_modified(&server.url(), "/missing", &validators)
+        .await
+        .is_err());
+}
+
+/// RUST_LOG=debug cargo test --lib -- conditional::test_conditional_writes --exact --show-output
+#[tokio::test]
+async fn test_conditional_writes() {
+    use crate::testing::Stub;
+
+    let server = crate::testing::MockServer::start().await.unwrap();
+    server.register(
+        Stub::new(Method::PUT, "/doc")
+            .respond(412, "")
+            .respond_header("etag", "\"v2\""),
+    );
+    server.register(
+        Stub::new(Method::PUT, "/doc")
+            .with_header("if-match", "\"v2\"")
+            .respond(200, "")
+            .respond_header("etag", "\"v3\""),
+    );
+    server.register(Stub::new(Method::POST, "/doc").respond(412, ""));
+    server.register(
+        Stub::new(Method::POST, "/doc")
+            .with_header("if-unmodified-since", "Wed, 21 Oct 2015 07:28:00 GMT")
+            .respond(201, "created"),
+    );
+
+    let manager = Manager::new().unwrap();
+
+    // a stale etag, then a retry with the current one
+    let e = manager
+        .put_if_match(&server.url(), "/doc", "\"v1\"", "a")
+        .await
+        .unwrap_err();
+    assert!(is_conflict(&e));
+    let etag = match crate::error::Error::from(e) {
+        crate::error::Error::Conflict(conflict) => conflict.validators.etag.unwrap(),
+        e => panic!("unexpected {:?}", e),
+    };
+    assert_eq!(etag, "\"v2\"");
+    let resp = manager
+        .put_if_match(&server.url(), "/doc", &etag, "a")
+        .await
+        .unwrap();
+    assert_eq!(
+        Validators::from_headers(resp.headers()).etag.as_deref(),
+        Some("\"v3\"")
+    );
+    server
+        .assert_received(Method::PUT, "/doc")
+        .times(2)
+        .with_header("if-match", "\"v2\"");
+
+    let resp = manager
+        .post_if_unmodified_since(&server.url(), "/doc", "Wed, 21 Oct 2015 07:28:00 GMT", "b")
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    let e = manager
+        .post_if_unmodified_since(&server.url(), "/doc", "Thu, 22 Oct 2015 07:28:00 GMT", "b")
+        .await
+        .unwrap_err();
+    assert!(is_conflict(&e));
+    assert!(e.to_string().starts_with("precondition failed"));
+
+    let e = manager
+        .put_if_match(&server.url(), "/missing", "\"v1\"", "a")
+        .await
+        .unwrap_err();
+    assert!(!is_conflict(&e));
+    assert!(manager
+        .put_if_match(&server.url(), "/doc", "bad\nvalue", "a")
+        .await
+        .is_err());
+}
@@ -0,0 +1,508 @@
+//! Cookie jar, shared by the requests of a manager (see
+//! "ManagerBuilder::cookie_jar"), that can be saved to and loaded from a
+//! file so that CLI tools keep a session across invocations.
+
+use std::{
+    fs,
+    io::{self, Error, ErrorKind, Write},
+    path::Path,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use hyper::{header::SET_COOKIE, HeaderMap};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::logging::wire_debug;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    /// Lowercase, without a leading dot.
+    pub domain: String,
+    /// True if the cookie is only sent to "domain" itself (no "Domain"
+    /// attribute), otherwise also to its subdomains.
+    pub host_only: bool,
+    pub path: String,
+    /// Unix time in seconds; None for a session cookie.
+    pub expires: Option<u64>,
+    pub secure: bool,
+    pub http_only: bool,
+}
+
+impl Cookie {
+    fn is_expired(&self, now: u64) -> bool {
+        self.expires.map_or(false, |e| e <= now)
+    }
+
+    fn matches(&self, url: &Url) -> bool {
+        let host = url.host_str().unwrap_or("").to_ascii_lowercase();
+        let domain_ok = if self.host_only {
+            host == self.domain
+        } else {
+            domain_matches(&host, &self.domain)
+        };
+        domain_ok
+            && path_matches(url.path(), &self.path)
+            && (!self.secure || url.scheme() == "https")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CookieFileFormat {
+    /// "cookies.txt" format of curl and browsers.
+    Netscape,
+    Json,
+}
+
+/// Thread-safe cookie store.
+#[derive(Debug, Default)]
+pub struct CookieJar {
+    cookies: Mutex<Vec<Cookie>>,
+}
+
+impl CookieJar {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the unexpired cookies.
+    pub fn cookies(&self) -> Vec<Cookie> {
+        let now = unix_now();
+        let cookies = self.cookies.lock().unwrap();
+        cookies
+            .iter()
+            .filter(|c| !c.is_expired(now))
+            .cloned()
+            .collect()
+    }
+
+    /// Adds the cookie, replacing the one with the same name, domain,
+    /// and path.
+    pub fn insert(&self, cookie: Cookie) {
+        let mut cookies = self.cookies.lock().unwrap();
+        cookies.retain(|c| {
+            !(c.name == cookie.name && c.domain == cookie.domain && c.path == cookie.path)
+        });
+        if !cookie.is_expired(unix_now()) {
+            cookies.push(cookie);
+        }
+    }
+
+    pub fn clear(&self) {
+        self.cookies.lock().unwrap().clear();
+    }
+
+    /// Stores the "Set-Cookie" headers of a response from the URL.
+    /// Cookies for other domains are ignored, and expired ones delete
+    /// the stored cookie.
+    pub fn store(&self, url: &Url, headers: &HeaderMap) {
+        for v in headers.get_all(SET_COOKIE).iter() {
+            let Ok(v) = v.to_str() else {
+                continue;
+            };
+            match parse_set_cookie(url, v, unix_now()) {
+                Some(c) => self.insert(c),
+                None => wire_debug!("ignoring cookie for another domain or malformed"),
+            }
+        }
+    }
+
+    /// Returns the "Cookie" header value for a request to the URL, if any
+    /// cookie matches (longer paths first).
+    pub fn cookie_header(&self, url: &Url) -> Option<String> {
+        let mut matched: Vec<Cookie> = self
+            .cookies()
+            .into_iter()
+            .filter(|c| c.matches(url))
+            .collect();
+        if matched.is_empty() {
+            return None;
+        }
+        matched.sort_by_key(|c| std::cmp::Reverse(c.path.len()));
+        Some(
+            matched
+                .iter()
+                .map(|c| format!("{}={}", c.name, c.value))
+                .collect::<Vec<_>>()
+                .join("; "),
+        )
+    }
+
+    /// Writes the unexpired cookies (including session cookies) to the
+    /// file, replacing it atomically. On unix, the file is only readable
+    /// and writable by the current user.
+    pub fn save(&self, file_path: impl AsRef<Path>, format: CookieFileFormat) -> io::Result<()> {
+        let file_path = file_path.as_ref();
+        let cookies = self.cookies();
+        let contents = match format {
+            CookieFileFormat::Netscape => to_netscape(&cookies),
+            CookieFileFormat::Json => serde_json::to_string_pretty(&cookies).map_err(|e| {
+                Error::new(
+                    ErrorKind::Other,
+                    format!("failed to serialize cookies {}", e),
+                )
+            })?,
+        };
+
+        let mut tmp = file_path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let mut opts = fs::OpenOptions::new();
+        opts.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            opts.mode(0o600);
+        }
+        let mut f = opts.open(&tmp)?;
+        #[cfg(unix)]
+        {
+            // "mode" does not apply to an existing file
+            use std::os::unix::fs::PermissionsExt;
+            f.set_permissions(fs::Permissions::from_mode(0o600))?;
+        }
+        f.write_all(contents.as_bytes())?;
+        f.sync_all()?;
+        fs::rename(&tmp, file_path)?;
+        wire_debug!("saved {} cookie(s)", cookies.len());
+        Ok(())
+    }
+
+    /// Adds the cookies of a file in either format, skipping the expired
+    /// ones.
+    pub fn load(&self, file_path: impl AsRef<Path>) -> io::Result<()> {
+        let contents = fs::read_to_string(file_path)?;
+        let cookies = if contents.trim_start().starts_with('[') {
+            serde_json::from_str(&contents).map_err(|e| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("failed to parse cookie file {}", e),
+                )
+            })?
+        } else {
+            from_netscape(&contents)?
+        };
+        for c in cookies.into_iter() {
+            self.insert(c);
+        }
+        Ok(())
+    }
+
+    /// Creates a jar with the cookies of the file, or an empty jar if
+    /// the file does not exist.
+    pub fn load_or_new(file_path: impl AsRef<Path>) -> io::Result<Self> {
+        let jar = Self::new();
+        match jar.load(file_path) {
+            Ok(()) => Ok(jar),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(jar),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn domain_matches(host: &str, domain: &str) -> bool {
+    host == domain
+        || (host.ends_with(domain)
+            && host.as_bytes()[host.len() - domain.len() - 1] == b'.'
+            && host.parse::<std::net::IpAddr>().is_err())
+}
+
+/// ref. https://www.rfc-editor.org/rfc/rfc6265#section-5.1.4
+fn path_matches(request_path: &str, cookie_path: &str) -> bool {
+    request_path == cookie_path
+        || (request_path.starts_with(cookie_path)
+            && (cookie_path.ends_with('/') || request_path.as_bytes()[cookie_path.len()] == b'/'))
+}
+
+/// Returns the directory of the request path (e.g., "/a" for "/a/b").
+fn default_path(url: &Url) -> String {
+    match url.path().rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(i) => url.path()[..i].to_string(),
+    }
+}
+
+/// Parses a "Set-Cookie" value received from the URL. Returns None if
+/// malformed or for a domain the URL cannot set.
+/// ref. https://www.rfc-editor.org/rfc/rfc6265#section-5.2
+fn parse_set_cookie(url: &Url, v: &str, now: u64) -> Option<Cookie> {
+    let host = url.host_str()?.to_ascii_lowercase();
+    let mut parts = v.split(';');
+    let (name, value) = parts.next()?.split_once('=')?;
+    let name = name.trim();
+    if name.is_empty() {
+        return None;
+    }
+    let mut c = Cookie {
+        name: name.to_string(),
+        value: value.trim().to_string(),
+        domain: host.clone(),
+        host_only: true,
+        path: default_path(url),
+        expires: None,
+        secure: false,
+        http_only: false,
+    };
+    let mut max_age = None;
+    for attr in parts {
+        let (k, v) = match attr.split_once('=') {
+            Some((k, v)) => (k.trim(), v.trim()),
+            None => (attr.trim(), ""),
+        };
+        match k.to_ascii_lowercase().as_str() {
+            "domain" if !v.is_empty() => {
+                let d = v.trim_start_matches('.').to_ascii_lowercase();
+                // no public suffix list, but at least reject top-level domains
+                if !domain_matches(&host, &d) || (!d.contains('.') && d != host) {
+                    return None;
+                }
+                c.domain = d;
+                c.host_only = false;
+            }
+            "path" if v.starts_with('/') => c.path = v.to_string(),
+            "expires" => {
+                if let Ok(t) = httpdate::parse_http_date(v) {
+                    c.expires = Some(
+                        t.duration_since(UNIX_EPOCH)
+                            .unwrap_or(Duration::ZERO)
+                            .as_secs(),
+                    );
+                }
+            }
+            "max-age" => max_age = v.parse::<i64>().ok(),
+            "secure" => c.secure = true,
+            "httponly" => c.http_only = true,
+            _ => {}
+        }
+    }
+    // "Max-Age" takes precedence over "Expires"
+    if let Some(age) = max_age {
+        c.expires = Some(if age <= 0 { 0 } else { now + age as u64 });
+    }
+    Some(c)
+}
+
+const HTTP_ONLY_PREFIX: &str = "#HttpOnly_";
+
+fn to_netscape(cookies: &[Cookie]) -> String {
+    let mut s = String::from("# Netscape HTTP Cookie File\n");
+    for c in cookies.iter() {
+        s.push_str(&format!(
+            "{}{}{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+            if c.http_only { HTTP_ONLY_PREFIX } else { "" },
+            if c.host_only { "" } else { "." },
+            c.domain,
+            if c.host_only { "FALSE" } else { "TRUE" },
+            c.path,
+            if c.secure { "TRUE" } else { "FALSE" },
+            c.expires.unwrap_or(0),
+            c.name,
+            c.value
+        ));
+    }
+    s
+}
+
+fn from_netscape(contents: &str) -> io::Result<Vec<Cookie>> {
+    let mut cookies = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let (http_only, line) = match line.strip_prefix(HTTP_ONLY_PREFIX) {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() != 7 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("invalid cookie file line {} (expected 7 fields)", i + 1),
+            ));
+        }
+        let expires: u64 = fields[4].trim().parse().map_err(|_| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("invalid cookie expiry at line {}", i + 1),
+            )
+        })?;
+        cookies.push(Cookie {
+            name: fields[5].to_string(),
+            value: fields[6].to_string(),
+            domain: fields[0].trim_start_matches('.').to_ascii_lowercase(),
+            host_only: !fields[1].eq_ignore_ascii_case("TRUE"),
+            path: fields[2].to_string(),
+            expires: if expires == 0 { None } else { Some(expires) },
+            secure: fields[3].eq_ignore_ascii_case("TRUE"),
+            http_only,
+        });
+    }
+    Ok(cookies)
+}
+
+#[test]
+fn test_parse_set_cookie() {
+    let url = Url::parse("https://api.example.com/v1/login").unwrap();
+    let c = parse_set_cookie(
+        &url,
+        "sid=abc; Domain=.Example.com; Path=/; Max-Age=60; Secure; HttpOnly",
+        1000,
+    )
+    .unwrap();
+    assert_eq!(
+        c,
+        Cookie {
+            name: "sid".to_string(),
+            value: "abc".to_string(),
+            domain: "example.com".to_string(),
+            host_only: false,
+            path: "/".to_string(),
+            expires: Some(1060),
+            secure: true,
+            http_only: true,
+        }
+    );
+    let c = parse_set_cookie(&url, "a=1; Expires=Wed, 21 Oct 2015 07:28:00 GMT", 0).unwrap();
+    assert_eq!(c.expires, Some(1445412480));
+    assert_eq!(c.path, "/v1");
+    assert!(c.host_only);
+
+    assert!(parse_set_cookie(&url, "a=1; Domain=other.com", 0).is_none());
+    assert!(parse_set_cookie(&url, "a=1; Domain=com", 0).is_none());
+    assert!(parse_set_cookie(&url, "=1", 0).is_none());
+    assert!(parse_set_cookie(&url, "novalue", 0).is_none());
+
+    assert!(path_matches("/v1/users", "/v1"));
+    assert!(path_matches("/v1/users", "/v1/"));
+    assert!(!path_matches("/v10", "/v1"));
+    assert!(domain_matches("a.example.com", "example.com"));
+    assert!(!domain_matches("badexample.com", "example.com"));
+}
+
+#[test]
+fn test_cookie_jar() {
+    let jar = CookieJar::new();
+    let login = Url::parse("https://example.com/login").unwrap();
+    let mut headers = HeaderMap::new();
+    headers.append(SET_COOKIE, "sid=abc; Path=/; Secure".parse().unwrap());
+    headers.append(
+        SET_COOKIE,
+        "theme=dark; Domain=example.com; Path=/".parse().unwrap(),
+    );
+    headers.append(SET_COOKIE, "cart=1; Path=/shop".parse().unwrap());
+    jar.store(&login, &headers);
+    assert_eq!(jar.cookies().len(), 3);
+
+    let u = |s| Url::parse(s).unwrap();
+    assert_eq!(
+        jar.cookie_header(&u("https://example.com/shop/item"))
+            .unwrap(),
+        "cart=1; sid=abc; theme=dark"
+    );
+    // secure and host-only cookies
+    assert_eq!(
+        jar.cookie_header(&u("http://www.example.com/")).unwrap(),
+        "theme=dark"
+    );
+    assert!(jar.cookie_header(&u("https://other.com/")).is_none());
+
+    // deleted by an expired cookie
+    let mut headers = HeaderMap::new();
+    headers.append(SET_COOKIE, "cart=; Path=/shop; Max-Age=0".parse().unwrap());
+    jar.store(&login, &headers);
+    assert_eq!(jar.cookies().len(), 2);
+}
+
+#[test]
+fn test_cookie_jar_save_load() {
+    let jar = CookieJar::new();
+    let url = Url::parse("https://example.com/").unwrap();
+    let mut headers = HeaderMap::new();
+    headers.append(SET_COOKIE, "sid=abc; HttpOnly; Secure".parse().unwrap());
+    headers.append(
+        SET_COOKIE,
+        "theme=dark; Domain=example.com; Max-Age=3600"
+            .parse()
+            .unwrap(),
+    );
+    jar.store(&url, &headers);
+
+    let dir = std::env::temp_dir();
+    for (format, name) in [
+        (CookieFileFormat::Netscape, "http-manager-test-cookies.txt"),
+        (CookieFileFormat::Json, "http-manager-test-cookies.json"),
+    ] {
+        let file_path = dir.join(name);
+        jar.save(&file_path, format).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&file_path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let loaded = CookieJar::load_or_new(&file_path).unwrap();
+        let mut got = loaded.cookies();
+        let mut expected = jar.cookies();
+        got.sort_by(|a, b| a.name.cmp(&b.name));
+        expected.sort_by(|a, b| a.name.cmp(&b.name));
+        assert_eq!(got, expected);
+        fs::remove_file(&file_path).unwrap();
+    }
+
+    let contents = "# Netscape HTTP Cookie File\n\
+                    #HttpOnly_.example.com\tTRUE\t/\tFALSE\t0\ta\t1\n\
+                    example.com\tFALSE\t/\tTRUE\t1\texpired\t1\n";
+    let cookies = from_netscape(contents).unwrap();
+    assert_eq!(cookies.len(), 2);
+    assert!(cookies[0].http_only && !cookies[0].host_only);
+    assert_eq!(cookies[0].expires, None);
+    assert!(from_netscape("example.com\tFALSE\t/\n").is_err());
+
+    assert!(
+        CookieJar::load_or_new(dir.join("http-manager-test-missing-cookies.txt"))
+            .unwrap()
+            .cookies()
+            .is_empty()
+    );
+}
+
+/// RUST_LOG=debug cargo test --lib -- cookie::test_manager_cookie_jar --exact --show-output
+#[tokio::test]
+async fn test_manager_cookie_jar() {
+    use std::sync::Arc;
+
+    use hyper::Method;
+
+    use crate::{testing::Stub, Manager};
+
+    let server = crate::testing::MockServer::start().await.unwrap();
+    server.register(
+        Stub::new(Method::POST, "/login")
+            .respond(200, "ok")
+            .respond_header("set-cookie", "sid=abc; Path=/; HttpOnly"),
+    );
+    server.stub(Method::GET, "/me", 200, "me");
+
+    let jar = Arc::new(CookieJar::new());
+    let manager = Manager::builder().cookie_jar(jar.clone()).build().unwrap();
+    let req = crate::create_json_post(server.url(), "/login", "{}").unwrap();
+    manager.read_response(req).await.unwrap();
+    assert_eq!(jar.cookies().len(), 1);
+
+    let req = crate::create_get(server.url(), "/me").unwrap();
+    manager.read_response(req).await.unwrap();
+    server
+        .assert_received(Method::GET, "/me")
+        .once()
+        .with_header("cookie", "sid=abc");
+}
@@ -0,0 +1,568 @@
+//! Bounded crawler and bulk link checks that report broken links, for
+//! validating docs sites, status dashboards, and artifact/mirror lists.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    io::{self, Error, ErrorKind},
+    time::{Duration, Instant},
+};
+
+use futures_util::stream::{self, FuturesUnordered, StreamExt};
+use hyper::{
+    header::{CONTENT_TYPE, LOCATION},
+    Body, Method, Request, StatusCode,
+};
+use url::Url;
+
+use crate::{logging::wire_debug, Manager};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CrawlConfig {
+    /// Link depth from the start page (0 fetches only the start page).
+    pub max_depth: usize,
+    /// Maximum number of same-origin pages fetched.
+    pub max_pages: usize,
+    pub concurrency: usize,
+    /// Also fetches links to other origins (without following their
+    /// links) to report them if broken.
+    pub check_external: bool,
+}
+
+impl Default for CrawlConfig {
+    fn default() -> Self {
+        Self {
+            max_depth: 3,
+            max_pages: 500,
+            concurrency: 8,
+            check_external: true,
+        }
+    }
+}
+
+/// Fetched URL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Page {
+    pub url: String,
+    /// Set if a response was received.
+    pub status: Option<u16>,
+    /// Set if no response was received.
+    pub error: Option<String>,
+    pub depth: usize,
+    /// Pages that link (or redirect) to this URL.
+    pub referrers: Vec<String>,
+}
+
+impl Page {
+    /// Returns true if the request failed or the status code is 400 or above.
+    pub fn is_broken(&self) -> bool {
+        self.error.is_some() || self.status.map_or(true, |s| s >= 400)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CrawlReport {
+    /// Every fetched URL, in fetch completion order.
+    pub pages: Vec<Page>,
+    /// True if "max_pages" stopped the crawl before all links were fetched.
+    pub truncated: bool,
+}
+
+impl CrawlReport {
+    pub fn broken(&self) -> Vec<&Page> {
+        self.pages.iter().filter(|p| p.is_broken()).collect()
+    }
+}
+
+/// Redirects followed by "check_links" before giving up.
+pub const MAX_REDIRECTS: usize = 10;
+
+/// Result of a link check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkCheck {
+    pub url: String,
+    /// Status code of the final response, if any.
+    pub status: Option<u16>,
+    /// URL after following the redirects.
+    pub final_url: String,
+    pub redirects: usize,
+    /// Set if no final response was received.
+    pub error: Option<String>,
+    /// Including the redirects.
+    pub latency: Duration,
+}
+
+impl LinkCheck {
+    /// Returns true if the final response is 2xx.
+    pub fn is_ok(&self) -> bool {
+        self.status.map_or(false, |s| (200..300).contains(&s))
+    }
+}
+
+struct Fetched {
+    url: Url,
+    depth: usize,
+    status: Option<u16>,
+    error: Option<String>,
+    /// Links and redirect targets, resolved against the page URL.
+    links: Vec<Url>,
+}
+
+impl Manager {
+    /// Crawls the same-origin pages from "start", following links in
+    /// HTML pages and redirects, and returns every fetched URL with its
+    /// status. Fails only on an invalid start URL.
+    pub async fn crawl(&self, start: &str, cfg: &CrawlConfig) -> io::Result<CrawlReport> {
+        let start = Url::parse(start).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("failed to parse start URL {}", e),
+            )
+        })?;
+        self.check_url(&start)?;
+        let origin = start.origin();
+
+        let mut seen = HashSet::new();
+        let mut referrers: HashMap<String, Vec<String>> = HashMap::new();
+        let mut queue = VecDeque::new();
+        seen.insert(start.to_string());
+        queue.push_back((start, 0));
+
+        let mut pages = Vec::new();
+        let mut fetched_pages = 0;
+        let mut truncated = false;
+        let mut running = FuturesUnordered::new();
+        loop {
+            while running.len() < cfg.concurrency.max(1) {
+                let (url, depth) = match queue.pop_front() {
+                    Some(next) => next,
+                    None => break,
+                };
+                let internal = url.origin() == origin;
+                if internal {
+                    if fetched_pages >= cfg.max_pages {
+                        truncated = true;
+                        continue;
+                    }
+                    fetched_pages += 1;
+                }
+                let follow = internal && depth < cfg.max_depth;
+                running.push(self.fetch_page(url, depth, follow));
+            }
+
+            let f = match running.next().await {
+                Some(f) => f,
+                None => break,
+            };
+            for link in f.links.iter() {
+                if link.origin() != origin && !cfg.check_external {
+                    continue;
+                }
+                if !matches!(link.scheme(), "http" | "https") {
+                    continue;
+                }
+                referrers
+                    .entry(link.to_string())
+                    .or_default()
+                    .push(f.url.to_string());
+                if seen.insert(link.to_string()) {
+                    // a redirect does not count as a level of depth
+                    let depth = if f.status.map_or(false, |s| (300..400).contains(&s)) {
+                        f.depth
+                    } else {
+                        f.depth + 1
+                    };
+                    queue.push_back((link.clone(), depth));
+                }
+            }
+            pages.push(Page {
+                url: f.url.to_string(),
+                status: f.status,
+                error: f.error,
+                depth: f.depth,
+                referrers: Vec::new(),
+            });
+        }
+
+        for p in pages.iter_mut() {
+            p.referrers = referrers.remove(&p.url).unwrap_or_default();
+        }
+        Ok(CrawlReport { pages, truncated })
+    }
+
+    /// Checks the URLs with HEAD requests (GET if the server responds
+    /// 405 or 501), following up to "MAX_REDIRECTS" redirects, with up to
+    /// "concurrency" in flight. Returns the results in input order.
+    pub async fn check_links(&self, urls: &[&str], concurrency: usize) -> Vec<LinkCheck> {
+        stream::iter(urls.iter())
+            .map(|u| self.check_link(u))
+            .buffered(concurrency.max(1))
+            .collect()
+            .await
+    }
+
+    async fn check_link(&self, url: &str) -> LinkCheck {
+        let start = Instant::now();
+        let mut check = LinkCheck {
+            url: url.to_string(),
+            status: None,
+            final_url: url.to_string(),
+            redirects: 0,
+            error: None,
+            latency: Duration::ZERO,
+        };
+        let mut current = match Url::parse(url) {
+            Ok(u) => u,
+            Err(e) => {
+                check.error = Some(format!("failed to parse URL {}", e));
+                return check;
+            }
+        };
+        loop {
+            check.final_url = current.to_string();
+            let (status, location) = match self.head_or_get(&current).await {
+                Ok(ret) => ret,
+                Err(e) => {
+                    check.status = None;
+                    check.error = Some(e.to_string());
+                    break;
+                }
+            };
+            check.status = Some(status.as_u16());
+            let next = match location {
+                Some(loc) if status.is_redirection() => current.join(&loc).ok(),
+                _ => None,
+            };
+            match next {
+                Some(next) if check.redirects < MAX_REDIRECTS => {
+                    check.redirects += 1;
+                    current = next;
+                }
+                Some(_) => {
+                    check.error = Some(format!("stopped after {} redirects", MAX_REDIRECTS));
+                    break;
+                }
+                None => break,
+            }
+        }
+        check.latency = start.elapsed();
+        check
+    }
+
+    /// Returns the status code and the "Location" header, if any.
+    async fn head_or_get(&self, url: &Url) -> io::Result<(StatusCode, Option<String>)> {
+        let mut status = StatusCode::METHOD_NOT_ALLOWED;
+        let mut location = None;
+        for method in [Method::HEAD, Method::GET] {
+            let req = Request::builder()
+                .method(method)
+                .uri(url.as_str())
+                .body(Body::empty())
+                .map_err(|e| {
+                    Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("failed to create request {}", e),
+                    )
+                })?;
+            // the body is not needed, and dropping it closes the connection
+            let resp = self.send(req).await?;
+            status = resp.status();
+            location = resp
+                .headers()
+                .get(LOCATION)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.to_string());
+            if !matches!(
+                status,
+                StatusCode::METHOD_NOT_ALLOWED | StatusCode::NOT_IMPLEMENTED
+            ) {
+                break;
+            }
+        }
+        Ok((status, location))
+    }
+
+    async fn fetch_page(&self, url: Url, depth: usize, follow: bool) -> Fetched {
+        wire_debug!(
+            "crawling {} (depth {})",
+            crate::redact::url(url.as_str()),
+            depth
+        );
+        let mut fetched = Fetched {
+            url,
+            depth,
+            status: None,
+            error: None,
+            links: Vec::new(),
+        };
+        let resp = match crate::create_get(fetched.url.as_str(), "") {
+            Ok(req) => self.read_response(req).await,
+            Err(e) => Err(e),
+        };
+        let resp = match resp {
+            Ok(resp) => resp,
+            Err(e) => {
+                fetched.error = Some(e.to_string());
+                return fetched;
+            }
+        };
+        fetched.status = Some(resp.status().as_u16());
+        if !follow {
+            return fetched;
+        }
+
+        if resp.status().is_redirection() {
+            if let Some(loc) = resp.headers().get(LOCATION).and_then(|v| v.to_str().ok()) {
+                fetched.links.extend(fetched.url.join(loc).ok());
+            }
+            return fetched;
+        }
+        let is_html = match resp.headers().get(CONTENT_TYPE) {
+            Some(v) => v.to_str().map_or(false, |v| v.contains("html")),
+            None => true,
+        };
+        if resp.status().is_success() && is_html {
+            let html = String::from_utf8_lossy(resp.body());
+            for link in extract_links(&html) {
+                if let Ok(mut u) = fetched.url.join(&link) {
+                    u.set_fragment(None);
+                    fetched.links.push(u);
+                }
+            }
+        }
+        fetched
+    }
+}
+
+/// Returns the "href" and "src" attribute values of the HTML, skipping
+/// fragment-only, "mailto:", "javascript:", and "data:" links.
+pub fn extract_links(html: &str) -> Vec<String> {
+    let mut links = Vec::new();
+    let lower = html.to_ascii_lowercase();
+    for attr in ["href", "src"] {
+        let mut pos = 0;
+        while let Some(i) = lower[pos..].find(attr) {
+            let start = pos + i;
+            pos = start + attr.len();
+            // must be a whole attribute name inside a tag
+            let prev = lower[..start].chars().last();
+            if !matches!(prev, Some(c) if c.is_whitespace()) {
+                continue;
+            }
+            let rest = lower[pos..].trim_start();
+            let rest = match rest.strip_prefix('=') {
+                Some(r) => r.trim_start(),
+                None => continue,
+            };
+            let value_start = lower.len() - rest.len();
+            let (value_start, value_end) = match rest.chars().next() {
+                Some(q @ ('"' | '\'')) => match rest[1..].find(q) {
+                    Some(end) => (value_start + 1, value_start + 1 + end),
+                    None => continue,
+                },
+                Some(_) => {
+                    let end = rest
+                        .find(|c: char| c.is_whitespace() || c == '>')
+                        .unwrap_or(rest.len());
+                    (value_start, value_start + end)
+                }
+                None => continue,
+            };
+            // the original case, since paths are case-sensitive
+            let value = html[value_start..value_end].trim().replace("&amp;", "&");
+            pos = value_end;
+
+            let l = value.to_ascii_lowercase();
+            if value.is_empty()
+                || value.starts_with('#')
+                || l.starts_with("mailto:")
+                || l.starts_with("javascript:")
+                || l.starts_with("data:")
+                || l.starts_with("tel:")
+            {
+                continue;
+            }
+            links.push(value);
+        }
+    }
+    links
+}
+
+#[test]
+fn test_extract_links() {
+    let html = r##"
+<html><head><link rel="stylesheet" href="/style.css"></head>
+<body>
+  <a href="/Docs/Intro">intro</a>
+  <A HREF='relative.html'>relative</A>
+  <a href=/unquoted?a=1&amp;b=2>unquoted</a>
+  <a href="#top">top</a>
+  <a href="mailto:ops@example.com">mail</a>
+  <img src="https://cdn.example.com/logo.png">
+  <a data-href="/not-a-link">x</a>
+  <p>href="/not-in-a-tag-but-close-enough"</p>
+</body></html>
+"##;
+    assert_eq!(
+        extract_links(html),
+        vec![
+            "/style.css",
+            "/Docs/Intro",
+            "relative.html",
+            "/unquoted?a=1&b=2",
+            "https://cdn.example.com/logo.png",
+        ]
+    );
+}
+
+/// RUST_LOG=debug cargo test --lib -- crawl::test_crawl --exact --show-output
+#[tokio::test]
+async fn test_crawl() {
+    let site = crate::testing::MockServer::start().await.unwrap();
+    let external = crate::testing::MockServer::start().await.unwrap();
+    external.stub(Method::GET, "/ok", 200, "ok");
+    site.stub(
+        Method::GET,
+        "/",
+        200,
+        format!(
+            r#"<a href="/a">a</a> <a href="/b#x">b</a> <a href="/missing">m</a>
+               <a href="{0}/ok">ext</a> <a href="{0}/gone">ext</a>"#,
+            external.url()
+        ),
+    );
+    site.stub(
+        Method::GET,
+        "/a",
+        200,
+        r#"<a href="/">home</a> <a href="c">c</a>"#,
+    );
+    site.stub(Method::GET, "/b", 200, r#"<a href="/deep/1">deep</a>"#);
+    site.stub(Method::GET, "/c", 200, "");
+    site.stub(
+        Method::GET,
+        "/deep/1",
+        200,
+        r#"<a href="/deep/2">deeper</a>"#,
+    );
+
+    let manager = Manager::new().unwrap();
+    let cfg = CrawlConfig {
+        max_depth: 2,
+        ..Default::default()
+    };
+    let report = manager.crawl(&site.url(), &cfg).await.unwrap();
+
+    let mut urls: Vec<String> = report
+        .pages
+        .iter()
+        .map(|p| {
+            p.url
+                .replace(&site.url(), "")
+                .replace(&external.url(), "ext:")
+        })
+        .collect();
+    urls.sort();
+    assert_eq!(
+        urls,
+        vec![
+            "/",
+            "/a",
+            "/b",
+            "/c",
+            "/deep/1",
+            "/missing",
+            "ext:/gone",
+            "ext:/ok"
+        ]
+    );
+    assert!(!report.truncated);
+
+    let mut broken: Vec<(String, Option<u16>)> = report
+        .broken()
+        .iter()
+        .map(|p| (p.url.replace(&external.url(), "ext:"), p.status))
+        .collect();
+    broken.sort();
+    assert_eq!(
+        broken,
+        vec![
+            ("ext:/gone".to_string(), Some(404)),
+            (format!("{}/missing", site.url()), Some(404)),
+        ]
+    );
+    let missing = report
+        .pages
+        .iter()
+        .find(|p| p.url.ends_with("/missing"))
+        .unwrap();
+    assert_eq!(missing.referrers, vec![format!("{}/", site.url())]);
+    // external pages are not crawled
+    assert_eq!(external.received_count(Method::GET, "/ok"), 1);
+
+    let report = manager
+        .crawl(
+            &site.url(),
+            &CrawlConfig {
+                max_pages: 2,
+                check_external: false,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(report.pages.len(), 2);
+    assert!(report.truncated);
+
+    assert!(manager.crawl("not a url", &cfg).await.is_err());
+}
+
+/// RUST_LOG=debug cargo test --lib -- crawl::test_check_links --exact --show-output
+#[tokio::test]
+async fn test_check_links() {
+    use crate::testing::Stub;
+
+    let server = crate::testing::MockServer::start().await.unwrap();
+    server.stub(Method::HEAD, "/ok", 200, "");
+    server.stub(Method::HEAD, "/get-only", 405, "");
+    server.stub(Method::GET, "/get-only", 200, "ok");
+    server.register(
+        Stub::new(Method::HEAD, "/old")
+            .respond(301, "")
+            .respond_header("location", "/ok"),
+    );
+    server.register(
+        Stub::new(Method::HEAD, "/loop")
+            .respond(302, "")
+            .respond_header("location", "/loop"),
+    );
+
+    let urls: Vec<String> = ["/ok", "/get-only", "/old", "/missing", "/loop"]
+        .iter()
+        .map(|p| format!("{}{}", server.url(), p))
+        .chain(["http://127.0.0.1:1/".to_string(), "not a url".to_string()])
+        .collect();
+    let urls: Vec<&str> = urls.iter().map(|s| s.as_str()).collect();
+    let checks = Manager::new().unwrap().check_links(&urls, 4).await;
+    assert_eq!(checks.len(), 7);
+
+    assert!(checks[0].is_ok());
+    assert_eq!(checks[0].redirects, 0);
+
+    assert!(checks[1].is_ok());
+    server.assert_received(Method::GET, "/get-only").once();
+
+    assert!(checks[2].is_ok());
+    assert_eq!(checks[2].redirects, 1);
+    assert_eq!(checks[2].final_url, format!("{}/ok", server.url()));
+
+    assert_eq!(checks[3].status, Some(404));
+    assert!(!checks[3].is_ok());
+
+    assert_eq!(checks[4].redirects, MAX_REDIRECTS);
+    assert!(checks[4].error.as_ref().unwrap().contains("redirects"));
+
+    assert_eq!(checks[5].status, None);
+    assert!(checks[5].error.is_some());
+    assert!(checks[6].error.is_some());
+    assert_eq!(checks[6].url, "not a url");
+}
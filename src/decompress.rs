@@ -0,0 +1,391 @@
+//! Limits on decompressed response bodies, so that a small compressed
+//! response cannot expand into gigabytes in memory ("decompression bomb").
+//!
+//! With the "compression" feature, the reads that return whole bodies
+//! ("read_bytes", "read_bytes_full", "Manager::read_bytes", and
+//! "Manager::read_response") send "Accept-Encoding: gzip, br, zstd" and
+//! decode the responses within these limits. "Manager::send" returns the
+//! body as is.
+
+use std::{
+    error, fmt,
+    io::{self, Error, ErrorKind},
+};
+
+use hyper::{
+    body::Bytes,
+    header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH, RANGE},
+    HeaderMap,
+};
+
+/// Decompressed bytes below which the ratio is not enforced, since small
+/// bodies (e.g., repetitive JSON) legitimately compress very well.
+const RATIO_GRACE: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecompressionLimits {
+    /// Maximum decompressed body size in bytes.
+    pub max_size: u64,
+    /// Maximum ratio of decompressed to compressed bytes, enforced once
+    /// the body exceeds 1 MiB.
+    pub max_ratio: u64,
+}
+
+impl Default for DecompressionLimits {
+    fn default() -> Self {
+        Self {
+            max_size: 256 * 1024 * 1024,
+            max_ratio: 100,
+        }
+    }
+}
+
+/// Error of a body that exceeded the decompression limits, returned as
+/// the inner error of an "InvalidData" "io::Error" (see
+/// "is_decompression_bomb").
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecompressionBomb {
+    pub compressed: u64,
+    pub decompressed: u64,
+    pub limits: DecompressionLimits,
+}
+
+impl fmt::Display for DecompressionBomb {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.decompressed > self.limits.max_size {
+            write!(
+                f,
+                "decompressed body exceeds {} bytes ({} compressed bytes)",
+                self.limits.max_size, self.compressed
+            )
+        } else {
+            write!(
+                f,
+                "decompressed body exceeds the compression ratio {} ({} bytes from {} compressed bytes)",
+                self.limits.max_ratio, self.decompressed, self.compressed
+            )
+        }
+    }
+}
+
+impl error::Error for DecompressionBomb {}
+
+/// Returns true if the error is a "DecompressionBomb".
+pub fn is_decompression_bomb(e: &io::Error) -> bool {
+    matches!(
+        crate::error::Error::typed(e),
+        Some(crate::error::Error::DecompressionBomb(_))
+    )
+}
+
+/// Tracks the compressed and decompressed bytes of a body while it is
+/// decoded, failing as soon as a limit is exceeded.
+#[derive(Debug, Clone)]
+pub struct ExpansionTracker {
+    limits: DecompressionLimits,
+    compressed: u64,
+    decompressed: u64,
+}
+
+impl ExpansionTracker {
+    pub fn new(limits: DecompressionLimits) -> Self {
+        Self {
+            limits,
+            compressed: 0,
+            decompressed: 0,
+        }
+    }
+
+    /// Counts compressed bytes fed to the decoder.
+    pub fn add_compressed(&mut self, n: usize) {
+        self.compressed += n as u64;
+    }
+
+    /// Counts bytes produced by the decoder.
+    pub fn add_decompressed(&mut self, n: usize) -> io::Result<()> {
+        self.decompressed += n as u64;
+        let over_size = self.decompressed > self.limits.max_size;
+        let over_ratio = self.decompressed > RATIO_GRACE
+            && self.decompressed > self.compressed.max(1).saturating_mul(self.limits.max_ratio);
+        if over_size || over_ratio {
+            return Err(crate::error::Error::DecompressionBomb(DecompressionBomb {
+                compressed: self.compressed,
+                decompressed: self.decompressed,
+                limits: self.limits,
+            })
+            .into());
+        }
+        Ok(())
+    }
+
+    pub fn compressed(&self) -> u64 {
+        self.compressed
+    }
+
+    pub fn decompressed(&self) -> u64 {
+        self.decompressed
+    }
+}
+
+/// "Accept-Encoding" of the requests whose responses are decoded.
+pub const ACCEPT_ENCODINGS: &str = "gzip, br, zstd";
+
+/// Asks for compressed responses, unless the request sets its own
+/// "Accept-Encoding" or reads a range (whose compressed bytes cannot be
+/// decoded on their own). A no-op without the "compression" feature.
+pub(crate) fn set_accept_encoding(headers: &mut HeaderMap) {
+    if cfg!(feature = "compression")
+        && !headers.contains_key(ACCEPT_ENCODING)
+        && !headers.contains_key(RANGE)
+    {
+        headers.insert(
+            ACCEPT_ENCODING,
+            hyper::header::HeaderValue::from_static(ACCEPT_ENCODINGS),
+        );
+    }
+}
+
+/// Decodes the response body of the "Content-Encoding" header (e.g.,
+/// "gzip", or "gzip, br" decoded in reverse order), and removes the
+/// headers that describe the encoded body. Bodies of unknown encodings are
+/// returned as is. A no-op without the "compression" feature.
+pub(crate) fn decode_response(
+    headers: &mut HeaderMap,
+    body: Bytes,
+    limits: &DecompressionLimits,
+) -> io::Result<Bytes> {
+    let encodings: Vec<String> = headers
+        .get_all(CONTENT_ENCODING)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|v| v.trim().to_ascii_lowercase())
+        .filter(|v| !v.is_empty() && v != "identity")
+        .collect();
+    if !cfg!(feature = "compression")
+        || encodings.is_empty()
+        || body.is_empty()
+        || !encodings.iter().all(|e| is_supported(e))
+    {
+        return Ok(body);
+    }
+
+    let mut body = body;
+    for encoding in encodings.iter().rev() {
+        body = decode(encoding, &body, limits)?;
+    }
+    headers.remove(CONTENT_ENCODING);
+    headers.remove(CONTENT_LENGTH);
+    Ok(body)
+}
+
+fn is_supported(encoding: &str) -> bool {
+    matches!(encoding, "gzip" | "x-gzip" | "deflate" | "br" | "zstd")
+}
+
+/// Decodes a body of the content coding ("gzip", "deflate", "br", or
+/// "zstd"), failing with "DecompressionBomb" once the output exceeds the
+/// limits.
+#[cfg(feature = "compression")]
+pub fn decode(encoding: &str, body: &[u8], limits: &DecompressionLimits) -> io::Result<Bytes> {
+    let mut tracker = ExpansionTracker::new(*limits);
+    tracker.add_compressed(body.len());
+    let decoded = match encoding {
+        "gzip" | "x-gzip" => read_all(flate2::read::MultiGzDecoder::new(body), &mut tracker),
+        // "deflate" is the zlib format
+        // ref. https://www.rfc-editor.org/rfc/rfc9110#name-deflate-coding
+        "deflate" => read_all(flate2::read::ZlibDecoder::new(body), &mut tracker),
+        "br" => read_all(
+            brotli_decompressor::Decompressor::new(body, 4096),
+            &mut tracker,
+        ),
+        "zstd" => read_all(zstd::stream::read::Decoder::new(body)?, &mut tracker),
+        _ => {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("unsupported content encoding '{}'", encoding),
+            ))
+        }
+    };
+    decoded.map_err(|e| {
+        if is_decompression_bomb(&e) {
+            return e;
+        }
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("failed to decode {} body {}", encoding, e),
+        )
+    })
+}
+
+#[cfg(not(feature = "compression"))]
+fn decode(encoding: &str, _body: &[u8], _limits: &DecompressionLimits) -> io::Result<Bytes> {
+    Err(Error::new(
+        ErrorKind::Unsupported,
+        format!(
+            "decoding '{}' requires the \"compression\" feature",
+            encoding
+        ),
+    ))
+}
+
+#[cfg(feature = "compression")]
+fn read_all(mut decoder: impl io::Read, tracker: &mut ExpansionTracker) -> io::Result<Bytes> {
+    let mut out = Vec::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = match decoder.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        tracker.add_decompressed(n)?;
+        out.extend_from_slice(&buf[..n]);
+    }
+    Ok(Bytes::from(out))
+}
+
+#[test]
+fn test_expansion_tracker() {
+    let limits = DecompressionLimits {
+        max_size: 10 * 1024 * 1024,
+        max_ratio: 100,
+    };
+
+    // small bodies may compress well
+    let mut t = ExpansionTracker::new(limits);
+    t.add_compressed(100);
+    t.add_decompressed(512 * 1024).unwrap();
+
+    // 10 KB expanding past the ratio
+    let mut t = ExpansionTracker::new(limits);
+    t.add_compressed(10 * 1024);
+    t.add_decompressed(1000 * 1024).unwrap();
+    let e = t.add_decompressed(100 * 1024).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidData);
+    assert!(is_decompression_bomb(&e));
+    assert!(e.to_string().contains("compression ratio 100"));
+
+    // within the ratio, but over the size
+    let mut t = ExpansionTracker::new(limits);
+    t.add_compressed(1024 * 1024);
+    t.add_decompressed(10 * 1024 * 1024).unwrap();
+    let e = t.add_decompressed(1).unwrap_err();
+    assert!(is_decompression_bomb(&e));
+    assert!(e
+        .to_string()
+        .starts_with("decompressed body exceeds 10485760 bytes"));
+    assert_eq!(t.decompressed(), 10 * 1024 * 1024 + 1);
+
+    assert!(!is_decompression_bomb(&Error::new(
+        ErrorKind::InvalidData,
+        "x"
+    )));
+}
+
+/// RUST_LOG=debug cargo test --all-features --lib -- decompress::test_decode_response --exact --show-output
+#[cfg(feature = "compression")]
+#[test]
+fn test_decode_response() {
+    use std::io::Write;
+
+    use hyper::header::HeaderValue;
+
+    let body = "hello ".repeat(1000);
+    let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    gz.write_all(body.as_bytes()).unwrap();
+    let gz = gz.finish().unwrap();
+    let zst = zstd::stream::encode_all(body.as_bytes(), 0).unwrap();
+
+    let limits = DecompressionLimits::default();
+    assert_eq!(decode("gzip", &gz, &limits).unwrap(), body);
+    assert_eq!(decode("zstd", &zst, &limits).unwrap(), body);
+    // "hello" in brotli
+    let br = [0x0b, 0x02, 0x80, 0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x03];
+    assert_eq!(decode("br", &br, &limits).unwrap(), "hello");
+    let e = decode("gzip", b"not gzip", &limits).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidData);
+
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+    headers.insert(CONTENT_LENGTH, HeaderValue::from(gz.len()));
+    let decoded = decode_response(&mut headers, Bytes::from(gz.clone()), &limits).unwrap();
+    assert_eq!(decoded, body);
+    assert!(headers.is_empty());
+
+    // unknown encodings are kept as is
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_ENCODING, HeaderValue::from_static("compress"));
+    let kept = decode_response(&mut headers, Bytes::from_static(b"abc"), &limits).unwrap();
+    assert_eq!(kept, "abc");
+    assert!(headers.contains_key(CONTENT_ENCODING));
+
+    // a small body expanding past the limits
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+    let limits = DecompressionLimits {
+        max_size: 1000,
+        max_ratio: 100,
+    };
+    let e = decode_response(&mut headers, Bytes::from(gz), &limits).unwrap_err();
+    assert!(is_decompression_bomb(&e));
+
+    let mut headers = HeaderMap::new();
+    set_accept_encoding(&mut headers);
+    assert_eq!(headers[ACCEPT_ENCODING], ACCEPT_ENCODINGS);
+    let mut headers = HeaderMap::new();
+    headers.insert(RANGE, HeaderValue::from_static("bytes=0-9"));
+    set_accept_encoding(&mut headers);
+    assert!(!headers.contains_key(ACCEPT_ENCODING));
+}
+
+/// RUST_LOG=debug cargo test --all-features --lib -- decompress::test_read_decoded --exact --show-output
+#[cfg(feature = "compression")]
+#[tokio::test]
+async fn test_read_decoded() {
+    use std::{io::Write, time::Duration};
+
+    use hyper::Method;
+
+    let body = "{\"result\":\"0x1\"}".repeat(100);
+    let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    gz.write_all(body.as_bytes()).unwrap();
+    let gz = gz.finish().unwrap();
+
+    let server = crate::testing::MockServer::start().await.unwrap();
+    server.register(
+        crate::testing::Stub::new(Method::GET, "/rpc")
+            .with_header("accept-encoding", ACCEPT_ENCODINGS)
+            .respond(200, gz.clone())
+            .respond_header("content-encoding", "gzip"),
+    );
+
+    let req = crate::create_get(server.url(), "/rpc").unwrap();
+    let out = crate::read_bytes(req, Duration::from_secs(5), false, true)
+        .await
+        .unwrap();
+    assert_eq!(out, body);
+
+    let manager = crate::Manager::new().unwrap();
+    let req = crate::create_get(server.url(), "/rpc").unwrap();
+    assert_eq!(manager.read_bytes(req, true).await.unwrap(), body);
+    let req = crate::create_get(server.url(), "/rpc").unwrap();
+    let resp = manager.read_response(req).await.unwrap();
+    assert_eq!(resp.body(), &body);
+    assert!(resp.headers().get(CONTENT_ENCODING).is_none());
+    // the traffic counters keep the compressed size
+    let size = crate::Manager::transfer_size(&resp).unwrap();
+    assert_eq!(size.response_body_bytes, gz.len() as u64);
+
+    let manager = crate::Manager::builder()
+        .decompression_limits(DecompressionLimits {
+            max_size: 100,
+            max_ratio: 100,
+        })
+        .build()
+        .unwrap();
+    let req = crate::create_get(server.url(), "/rpc").unwrap();
+    let e = manager.read_bytes(req, true).await.unwrap_err();
+    assert!(is_decompression_bomb(&e));
+}
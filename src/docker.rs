@@ -0,0 +1,317 @@
+//! Docker Engine API helper over the unix socket, for provisioning scripts
+//! that need the engine version, the containers, and image pulls.
+//! ref. https://docs.docker.com/engine/api/latest/
+
+use std::{
+    io::{self, Error, ErrorKind},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use hyper::{
+    body::Bytes,
+    client::conn,
+    header::{CONTENT_TYPE, HOST, USER_AGENT},
+    Body, Method, Request, Response,
+};
+use serde::{de::DeserializeOwned, Deserialize};
+use tokio::{net::UnixStream, time::timeout};
+
+use crate::logging::{wire_debug, wire_info};
+
+/// Default Docker Engine socket.
+pub const DEFAULT_SOCKET: &str = "/var/run/docker.sock";
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Version {
+    pub version: String,
+    pub api_version: String,
+    #[serde(default)]
+    pub os: String,
+    #[serde(default)]
+    pub arch: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Container {
+    pub id: String,
+    #[serde(default)]
+    pub names: Vec<String>,
+    pub image: String,
+    /// e.g., "running" or "exited".
+    pub state: String,
+    /// e.g., "Up 2 hours".
+    #[serde(default)]
+    pub status: String,
+}
+
+/// Docker Engine API client. Each call opens a new connection to the
+/// socket, since provisioning scripts make only a few calls.
+#[derive(Debug, Clone)]
+pub struct Docker {
+    socket: PathBuf,
+    timeout: Duration,
+}
+
+impl Default for Docker {
+    fn default() -> Self {
+        Self::with_socket(DEFAULT_SOCKET)
+    }
+}
+
+impl Docker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_socket(socket: impl AsRef<Path>) -> Self {
+        Self {
+            socket: socket.as_ref().to_path_buf(),
+            timeout: Duration::from_secs(30),
+        }
+    }
+
+    /// Replaces the timeout of the calls (default 30 seconds), which
+    /// includes downloading the image layers for "pull_image".
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub async fn version(&self) -> io::Result<Version> {
+        self.get_json("/version").await
+    }
+
+    /// Lists the running containers, or all containers if "all" is true.
+    pub async fn list_containers(&self, all: bool) -> io::Result<Vec<Container>> {
+        self.get_json(&format!("/containers/json?all={}", all))
+            .await
+    }
+
+    /// Pulls the image (e.g., "alpine:3.17"; "latest" if no tag), waiting
+    /// until the pull completes.
+    pub async fn pull_image(&self, image: &str) -> io::Result<()> {
+        let (name, tag) = split_image(image);
+        let mut path = format!(
+            "/images/create?fromImage={}",
+            crate::encode::encode_query_component(name)
+        );
+        if !tag.is_empty() {
+            path.push_str("&tag=");
+            path.push_str(&crate::encode::encode_query_component(tag));
+        }
+        wire_info!("pulling image {}", image);
+        let resp = self.request(Method::POST, &path).await?;
+        check_status(&resp)?;
+
+        // the progress is streamed as JSON lines, which carry the errors
+        // that occur after the 200 status code
+        for line in resp.body().split(|b| *b == b'\n') {
+            let msg: serde_json::Value = match serde_json::from_slice(line) {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            if let Some(e) = msg.get("error").and_then(|e| e.as_str()) {
+                return Err(Error::new(
+                    ErrorKind::Other,
+                    format!("failed to pull image {} ({})", image, e),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    async fn get_json<T: DeserializeOwned>(&self, path: &str) -> io::Result<T> {
+        let resp = self.request(Method::GET, path).await?;
+        check_status(&resp)?;
+        serde_json::from_slice(resp.body()).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("failed to parse docker response {}", e),
+            )
+        })
+    }
+
+    async fn request(&self, method: Method, path: &str) -> io::Result<Response<Bytes>> {
+        wire_debug!("docker {} {} via {}", method, path, self.socket.display());
+        let req = Request::builder()
+            .method(method)
+            .uri(path)
+            // the engine requires a host, which is ignored over the socket
+            .header(HOST, "docker")
+            .header(USER_AGENT, crate::DEFAULT_USER_AGENT)
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::empty())
+            .map_err(|e| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("failed to create docker request {}", e),
+                )
+            })?;
+
+        let task = async {
+            let stream = UnixStream::connect(&self.socket).await.map_err(|e| {
+                Error::new(
+                    e.kind(),
+                    format!("failed to connect {} {}", self.socket.display(), e),
+                )
+            })?;
+            let (mut sender, connection) = conn::handshake(stream).await.map_err(|e| {
+                Error::new(ErrorKind::Other, format!("failed HTTP handshake {}", e))
+            })?;
+            let connection = tokio::spawn(connection);
+
+            let ret = async {
+                let resp = sender.send_request(req).await.map_err(|e| {
+                    Error::new(
+                        ErrorKind::Other,
+                        format!("failed to send docker request {}", e),
+                    )
+                })?;
+                let (parts, body) = resp.into_parts();
+                let body = crate::buffer::collect(body).await.map_err(|e| {
+                    Error::new(
+                        ErrorKind::Other,
+                        format!("failed to read docker response {}", e),
+                    )
+                })?;
+                Ok(Response::from_parts(parts, body))
+            }
+            .await;
+            connection.abort();
+            ret
+        };
+        match timeout(self.timeout, task).await {
+            Ok(ret) => ret,
+            Err(e) => Err(Error::new(
+                ErrorKind::TimedOut,
+                format!("failed docker request within {:?} {}", self.timeout, e),
+            )),
+        }
+    }
+}
+
+fn check_status(resp: &Response<Bytes>) -> io::Result<()> {
+    if resp.status().is_success() {
+        return Ok(());
+    }
+    // errors are returned as '{"message": "..."}'
+    let msg = serde_json::from_slice::<serde_json::Value>(resp.body())
+        .ok()
+        .and_then(|v| v.get("message")?.as_str().map(|s| s.to_string()))
+        .unwrap_or_default();
+    Err(Error::new(
+        ErrorKind::Other,
+        format!(
+            "docker request failed (status code {}) {}",
+            resp.status(),
+            msg
+        ),
+    ))
+}
+
+/// Splits "registry:5000/app:1.0" into ("registry:5000/app", "1.0").
+/// Digest references are pulled as is, without a tag.
+fn split_image(image: &str) -> (&str, &str) {
+    if image.contains('@') {
+        return (image, "");
+    }
+    let name_start = image.rfind('/').map(|i| i + 1).unwrap_or(0);
+    match image[name_start..].rfind(':') {
+        Some(i) => (&image[..name_start + i], &image[name_start + i + 1..]),
+        None => (image, "latest"),
+    }
+}
+
+#[test]
+fn test_split_image() {
+    assert_eq!(split_image("alpine"), ("alpine", "latest"));
+    assert_eq!(split_image("alpine:3.17"), ("alpine", "3.17"));
+    assert_eq!(
+        split_image("registry:5000/team/app"),
+        ("registry:5000/team/app", "latest")
+    );
+    assert_eq!(
+        split_image("registry:5000/team/app:1.0"),
+        ("registry:5000/team/app", "1.0")
+    );
+    assert_eq!(
+        split_image("alpine@sha256:abcd"),
+        ("alpine@sha256:abcd", "")
+    );
+}
+
+/// RUST_LOG=debug cargo test --lib -- docker::test_docker --exact --show-output
+#[tokio::test]
+async fn test_docker() {
+    use std::convert::Infallible;
+
+    use hyper::{server::conn::Http, service::service_fn, StatusCode};
+    use tokio::net::UnixListener;
+
+    let socket =
+        std::env::temp_dir().join(format!("http-manager-docker-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&socket);
+    let listener = UnixListener::bind(&socket).unwrap();
+    let server = tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let svc = service_fn(|req: Request<Body>| async move {
+                let uri = req.uri().to_string();
+                let (status, body) = match (req.method().clone(), uri.as_str()) {
+                    (Method::GET, "/version") => (
+                        StatusCode::OK,
+                        r#"{"Version":"23.0.1","ApiVersion":"1.42","Os":"linux","Arch":"amd64"}"#,
+                    ),
+                    (Method::GET, "/containers/json?all=true") => (
+                        StatusCode::OK,
+                        r#"[{"Id":"abc","Names":["/web"],"Image":"nginx","State":"exited","Status":"Exited (0)"}]"#,
+                    ),
+                    (Method::POST, "/images/create?fromImage=alpine&tag=3.17") => (
+                        StatusCode::OK,
+                        "{\"status\":\"Pulling from library/alpine\"}\n{\"status\":\"Downloaded newer image\"}\n",
+                    ),
+                    (Method::POST, "/images/create?fromImage=private&tag=latest") => (
+                        StatusCode::OK,
+                        "{\"status\":\"Pulling\"}\n{\"error\":\"pull access denied\"}\n",
+                    ),
+                    _ => (StatusCode::NOT_FOUND, r#"{"message":"page not found"}"#),
+                };
+                Ok::<_, Infallible>(
+                    Response::builder()
+                        .status(status)
+                        .body(Body::from(body))
+                        .unwrap(),
+                )
+            });
+            tokio::spawn(Http::new().serve_connection(stream, svc));
+        }
+    });
+
+    let docker = Docker::with_socket(&socket);
+    let v = docker.version().await.unwrap();
+    assert_eq!(v.version, "23.0.1");
+    assert_eq!(v.api_version, "1.42");
+
+    let containers = docker.list_containers(true).await.unwrap();
+    assert_eq!(containers.len(), 1);
+    assert_eq!(containers[0].names, vec!["/web"]);
+    assert_eq!(containers[0].state, "exited");
+    let err = docker.list_containers(false).await.unwrap_err();
+    assert!(err.to_string().contains("page not found"));
+
+    docker.pull_image("alpine:3.17").await.unwrap();
+    let err = docker.pull_image("private").await.unwrap_err();
+    assert!(err.to_string().contains("pull access denied"));
+
+    server.abort();
+    std::fs::remove_file(&socket).unwrap();
+
+    let err = Docker::with_socket("/nonexistent/docker.sock")
+        .version()
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+}
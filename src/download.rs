@@ -0,0 +1,318 @@
+//! File downloads over the connections of the "Manager", with the same
+//! checks as the "download_file*" functions (see "download_file_verified"
+//! and "download_file_resumable").
+
+use std::io;
+
+use hyper::{body::Bytes, StatusCode, Uri};
+use url::Url;
+
+use crate::{
+    clock::TokioClock, error, redact, retry, Checksum, DownloadResponse, Manager,
+    DEFAULT_DOWNLOAD_BUFFER_SIZE, DEFAULT_DOWNLOAD_LOW_SPEED_LIMIT,
+};
+
+impl Manager {
+    /// Downloads the path (see "url_for") to "file_path", like
+    /// "download_file", but with the policies, TLS settings, proxies, and
+    /// default headers of the manager. The response headers must arrive
+    /// within the timeout of the host, and the body is only bounded by the
+    /// low-speed limit of the manager (or
+    /// "DEFAULT_DOWNLOAD_LOW_SPEED_LIMIT"). Connection errors, timeouts,
+    /// stalls, and the retryable status codes are retried with the retry
+    /// policy of the host (see "retry_policy_for").
+    pub async fn download_file(&self, path: &str, file_path: &str) -> io::Result<()> {
+        let url = self.url_for(path)?;
+        self.download_with_retry(&url, || async {
+            self.download_once(&url, file_path, |_| {}).await
+        })
+        .await
+    }
+
+    /// Same as "download_file", but checks the digest of the body (see
+    /// "download_file_verified"). The file is removed if the download
+    /// fails or the digest mismatches, and mismatches are not retried.
+    pub async fn download_file_verified(
+        &self,
+        path: &str,
+        file_path: &str,
+        checksum: Checksum,
+    ) -> io::Result<()> {
+        let url = self.url_for(path)?;
+        let checksum = &checksum;
+        self.download_with_retry(&url, || async {
+            let mut hasher = checksum.hasher();
+            let ret = self
+                .download_once(&url, file_path, |chunk| hasher.update(chunk))
+                .await;
+            checksum.verify_download(file_path, hasher, ret).await
+        })
+        .await
+    }
+
+    /// Same as "download_file", but downloads to "{file_path}.part" and
+    /// resumes from it (see "download_file_resumable"), including across
+    /// the retries of the same call.
+    pub async fn download_file_resumable(&self, path: &str, file_path: &str) -> io::Result<()> {
+        let url = self.url_for(path)?;
+        let uri = parse_uri(&url)?;
+        let host = crate::traffic::host_key(&uri);
+        let limit = self.download_speed_limit();
+        self.download_with_retry(&url, || async {
+            let received = crate::download_resumable(url.as_str(), file_path, limit, |from| {
+                let url = &url;
+                async move {
+                    let req = match from {
+                        Some(from) => crate::create_get_with_headers(
+                            url.as_str(),
+                            "",
+                            &crate::header_map(&[("range", &format!("bytes={}-", from))])?,
+                        )?,
+                        None => crate::create_get(url.as_str(), "")?,
+                    };
+                    Ok(DownloadResponse::from_hyper(self.send(req).await?))
+                }
+            })
+            .await?;
+            self.traffic().record_received(&host, received);
+            Ok(())
+        })
+        .await
+    }
+
+    async fn download_once<F>(&self, url: &Url, file_path: &str, mut on_chunk: F) -> io::Result<()>
+    where
+        F: FnMut(&[u8]),
+    {
+        let req = crate::create_get(url.as_str(), "")?;
+        let host = crate::traffic::host_key(req.uri());
+        let resp = self.send(req).await?;
+        let received = crate::write_download(
+            DownloadResponse::from_hyper(resp),
+            file_path,
+            DEFAULT_DOWNLOAD_BUFFER_SIZE,
+            self.download_speed_limit(),
+            |chunk, _, _| on_chunk(chunk),
+        )
+        .await?;
+        self.traffic().record_received(&host, received);
+        Ok(())
+    }
+
+    /// Runs the download attempts with the retry policy of the host, with
+    /// the retryable status codes of the failed attempts retried too.
+    async fn download_with_retry<F, Fut>(&self, url: &Url, mut attempt: F) -> io::Result<()>
+    where
+        F: FnMut() -> Fut,
+        Fut: std::future::Future<Output = io::Result<()>>,
+    {
+        let policy = self.retry_policy_for(&parse_uri(url)?);
+        let endpoint = redact::url(url.as_str());
+        let attempted = retry::run(policy, true, &TokioClock, &endpoint, || {
+            let ret = attempt();
+            async move {
+                match ret.await {
+                    Ok(()) => Ok((StatusCode::OK, Bytes::new())),
+                    Err(e) => match error::Error::typed(&e) {
+                        Some(error::Error::Status(status, body)) => Ok((*status, body.clone())),
+                        _ => Err(e),
+                    },
+                }
+            }
+        })
+        .await?;
+
+        let (status, body) = attempted.value;
+        if !status.is_success() {
+            return Err(error::Error::Status(status, body).into());
+        }
+        Ok(())
+    }
+
+    fn download_speed_limit(&self) -> crate::stall::LowSpeedLimit {
+        self.low_speed_limit()
+            .copied()
+            .unwrap_or(DEFAULT_DOWNLOAD_LOW_SPEED_LIMIT)
+    }
+}
+
+fn parse_uri(url: &Url) -> io::Result<Uri> {
+    url.as_str().parse().map_err(|e| {
+        error::Error::UrlParse(format!(
+            "failed to parse '{}' {}",
+            redact::url(url.as_str()),
+            e
+        ))
+        .into()
+    })
+}
+
+/// RUST_LOG=debug cargo test --lib -- download::test_manager_download_file --exact --show-output
+#[tokio::test]
+async fn test_manager_download_file() {
+    use hyper::Method;
+    use sha2::{Digest, Sha256};
+
+    use crate::testing;
+
+    let server = testing::MockServer::start().await.unwrap();
+    let body: Vec<u8> = (0..10_000).map(|i| (i % 251) as u8).collect();
+    server.register(
+        testing::Stub::new(Method::GET, "/v1/file.bin")
+            .with_header("x-tenant", "a")
+            .respond(200, body.clone()),
+    );
+    server.register(
+        testing::Stub::new(Method::GET, "/v1/file.bin")
+            .with_header("range", "bytes=4000-")
+            .respond(206, body[4000..].to_vec())
+            .respond_header("content-range", "bytes 4000-9999/10000"),
+    );
+    server.stub(Method::GET, "/v1/missing.bin", 404, "not found");
+
+    let manager = Manager::builder()
+        .base_url(&format!("{}/v1", server.url()))
+        .default_header("x-tenant", "a")
+        .build()
+        .unwrap();
+    let file_path = std::env::temp_dir().join("http-manager-test-manager-download.bin");
+    let file_path = file_path.to_str().unwrap();
+    let part_path = format!("{}.part", file_path);
+
+    manager.download_file("file.bin", file_path).await.unwrap();
+    assert_eq!(std::fs::read(file_path).unwrap(), body);
+    assert!(manager.traffic().total().bytes_received > body.len() as u64);
+
+    let digest = format!("{:x}", Sha256::digest(&body));
+    manager
+        .download_file_verified("file.bin", file_path, Checksum::Sha256(digest))
+        .await
+        .unwrap();
+    assert_eq!(std::fs::read(file_path).unwrap(), body);
+    let e = manager
+        .download_file_verified("file.bin", file_path, Checksum::Sha256("00".repeat(32)))
+        .await
+        .unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    assert!(!std::path::Path::new(file_path).exists());
+
+    std::fs::write(&part_path, &body[..4000]).unwrap();
+    manager
+        .download_file_resumable("file.bin", file_path)
+        .await
+        .unwrap();
+    assert_eq!(std::fs::read(file_path).unwrap(), body);
+    assert!(!std::path::Path::new(&part_path).exists());
+    server
+        .assert_received(Method::GET, "/v1/file.bin")
+        .with_header("range", "bytes=4000-")
+        .with_header("x-tenant", "a")
+        .once();
+    std::fs::remove_file(file_path).unwrap();
+
+    // not retried, and no file
+    let e = manager
+        .download_file("missing.bin", file_path)
+        .await
+        .unwrap_err();
+    match error::Error::from(e) {
+        error::Error::Status(status, body) => {
+            assert_eq!(status, StatusCode::NOT_FOUND);
+            assert_eq!(body, "not found");
+        }
+        e => panic!("unexpected {:?}", e),
+    }
+    assert!(!std::path::Path::new(file_path).exists());
+    server
+        .assert_received(Method::GET, "/v1/missing.bin")
+        .once();
+}
+
+/// RUST_LOG=debug cargo test --lib -- download::test_manager_download_retry --exact --show-output
+#[tokio::test]
+async fn test_manager_download_retry() {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        time::Duration,
+    };
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    // 503, then the first 4000 bytes before the connection drops, then the
+    // rest of the range
+    let body: Vec<u8> = (0..10_000).map(|i| (i % 251) as u8).collect();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/file.bin", listener.local_addr().unwrap());
+    let accepted = Arc::new(AtomicUsize::new(0));
+    let ranges = Arc::new(Mutex::new(Vec::new()));
+    let (counter, received, served) = (accepted.clone(), ranges.clone(), body.clone());
+    let head = |status: &str, headers: &str| {
+        format!(
+            "HTTP/1.1 {}\r\nconnection: close\r\n{}\r\n",
+            status, headers
+        )
+        .into_bytes()
+    };
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let mut buf = [0u8; 4096];
+            let n = stream.read(&mut buf).await.unwrap_or(0);
+            let req = String::from_utf8_lossy(&buf[..n]).to_lowercase();
+            received.lock().unwrap().push(
+                req.lines()
+                    .find_map(|l| l.strip_prefix("range: "))
+                    .map(|v| v.trim().to_string()),
+            );
+            let resp = match counter.fetch_add(1, Ordering::SeqCst) {
+                0 => [
+                    head("503 Service Unavailable", "content-length: 4\r\n"),
+                    b"busy".to_vec(),
+                ]
+                .concat(),
+                1 => [
+                    head("200 OK", "content-length: 10000\r\n"),
+                    served[..4000].to_vec(),
+                ]
+                .concat(),
+                _ => [
+                    head(
+                        "206 Partial Content",
+                        "content-length: 6000\r\ncontent-range: bytes 4000-9999/10000\r\n",
+                    ),
+                    served[4000..].to_vec(),
+                ]
+                .concat(),
+            };
+            let _ = stream.write_all(&resp).await;
+        }
+    });
+
+    let policy = retry::RetryPolicy {
+        max_attempts: 3,
+        base_delay: Duration::from_millis(10),
+        jitter: 0.0,
+        ..Default::default()
+    };
+    let manager = Manager::builder().retry_policy(policy).build().unwrap();
+    let file_path = std::env::temp_dir().join("http-manager-test-manager-download-retry.bin");
+    let file_path = file_path.to_str().unwrap();
+    let _ = std::fs::remove_file(format!("{}.part", file_path));
+
+    manager
+        .download_file_resumable(&url, file_path)
+        .await
+        .unwrap();
+    assert_eq!(std::fs::read(file_path).unwrap(), body);
+    assert_eq!(accepted.load(Ordering::SeqCst), 3);
+    assert_eq!(
+        *ranges.lock().unwrap(),
+        vec![None, None, Some("bytes=4000-".to_string())]
+    );
+    std::fs::remove_file(file_path).unwrap();
+}
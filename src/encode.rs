@@ -0,0 +1,95 @@
+//! Percent-encoding for URL components.
+//! Each component has its own reserved set, so encode values with the
+//! helper for where they end up, never by hand.
+
+use std::io::{self, Error, ErrorKind};
+
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+/// Everything but RFC 3986 unreserved characters (ALPHA / DIGIT / "-" / "." / "_" / "~").
+const UNRESERVED: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// RFC 3986 "pchar" minus unreserved: keeps sub-delims, ":", and "@",
+/// but encodes "/", "?", "#", "%", and everything else.
+const PATH_SEGMENT: &AsciiSet = &UNRESERVED
+    .remove(b'!')
+    .remove(b'$')
+    .remove(b'&')
+    .remove(b'\'')
+    .remove(b'(')
+    .remove(b')')
+    .remove(b'*')
+    .remove(b'+')
+    .remove(b',')
+    .remove(b';')
+    .remove(b'=')
+    .remove(b':')
+    .remove(b'@');
+
+/// Encodes a single path segment, so that "/" and "?" in the value do not
+/// split the path or start a query (e.g., "a/b c" to "a%2Fb%20c").
+pub fn encode_path_segment(s: &str) -> String {
+    utf8_percent_encode(s, PATH_SEGMENT).to_string()
+}
+
+/// Encodes a query key or value, keeping only unreserved characters, so
+/// the result is safe for both RFC 3986 and form parsers
+/// (e.g., "a b&c=d" to "a%20b%26c%3Dd").
+pub fn encode_query_component(s: &str) -> String {
+    utf8_percent_encode(s, UNRESERVED).to_string()
+}
+
+/// Encodes a value as "application/x-www-form-urlencoded", where spaces
+/// become "+" (e.g., "a b&c" to "a+b%26c").
+pub fn encode_form_component(s: &str) -> String {
+    url::form_urlencoded::byte_serialize(s.as_bytes()).collect()
+}
+
+/// Decodes a percent-encoded UTF-8 string ("+" is kept as is).
+pub fn decode(s: &str) -> io::Result<String> {
+    percent_decode_str(s)
+        .decode_utf8()
+        .map(|c| c.into_owned())
+        .map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("failed to percent-decode '{}' {}", s, e),
+            )
+        })
+}
+
+#[test]
+fn test_encode() {
+    assert_eq!(encode_path_segment("a/b c"), "a%2Fb%20c");
+    assert_eq!(encode_path_segment("v1:batch@x"), "v1:batch@x");
+    assert_eq!(encode_path_segment("50%?#"), "50%25%3F%23");
+    assert_eq!(encode_path_segment("한글"), "%ED%95%9C%EA%B8%80");
+
+    assert_eq!(encode_query_component("a b&c=d"), "a%20b%26c%3Dd");
+    assert_eq!(encode_query_component("x+y/z~"), "x%2By%2Fz~");
+
+    assert_eq!(encode_form_component("a b&c"), "a+b%26c");
+    assert_eq!(encode_form_component("x+y"), "x%2By");
+
+    for s in ["a/b c", "50%?#", "한글", "a b&c=d"] {
+        assert_eq!(decode(&encode_path_segment(s)).unwrap(), s);
+        assert_eq!(decode(&encode_query_component(s)).unwrap(), s);
+    }
+    assert!(decode("%FF").is_err());
+
+    let u = crate::join_uri(
+        "http://localhost:9650",
+        &format!(
+            "/ext/{}?q={}",
+            encode_path_segment("bc/X"),
+            encode_query_component("a&b")
+        ),
+    )
+    .unwrap();
+    assert_eq!(u.as_str(), "http://localhost:9650/ext/bc%2FX?q=a%26b");
+    assert_eq!(u.path_segments().unwrap().nth(1), Some("bc%2FX"));
+}
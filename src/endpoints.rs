@@ -0,0 +1,434 @@
+//! Pool of replica endpoints (e.g., RPC nodes) with health-based routing.
+
+use std::{
+    io::{self, Error, ErrorKind},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use futures_util::future;
+use hyper::{body::Bytes, Body, Request, Response};
+use tokio::{task::JoinHandle, time::interval};
+
+use crate::{
+    attempt::{AttemptError, Attempted},
+    logging::{wire_debug, wire_warn},
+    Manager,
+};
+
+/// How a request picks among the healthy endpoints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Selection {
+    RoundRobin,
+    /// Fewest in-flight requests, round-robin among ties.
+    LeastLoaded,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EndpointPoolConfig {
+    /// Path appended to each base URL for the health checks; a 2xx
+    /// response marks the endpoint healthy.
+    pub health_path: String,
+    pub health_interval: Duration,
+    /// Consecutive failed checks before an endpoint is taken out of
+    /// rotation (a single successful check puts it back).
+    pub unhealthy_threshold: usize,
+    pub selection: Selection,
+    /// Endpoints tried per request, failing over to the next healthy
+    /// endpoint on errors and 5xx responses (1 disables failover).
+    pub max_attempts: usize,
+}
+
+impl Default for EndpointPoolConfig {
+    fn default() -> Self {
+        Self {
+            health_path: "/health".to_string(),
+            health_interval: Duration::from_secs(10),
+            unhealthy_threshold: 2,
+            selection: Selection::RoundRobin,
+            max_attempts: 1,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Endpoint {
+    url: String,
+    healthy: AtomicBool,
+    failures: AtomicUsize,
+    in_flight: AtomicUsize,
+}
+
+/// Routes requests to the healthy endpoints. Endpoints start healthy;
+/// health checks run only after "start_health_checks" (or on
+/// "check_health").
+#[derive(Debug)]
+pub struct EndpointPool {
+    manager: Manager,
+    cfg: EndpointPoolConfig,
+    endpoints: Arc<Vec<Endpoint>>,
+    next: AtomicUsize,
+    health_task: Option<JoinHandle<()>>,
+}
+
+impl EndpointPool {
+    /// Fails on an empty list, or a base URL that is invalid or rejected
+    /// by the manager policies.
+    pub fn new(manager: Manager, urls: &[&str], cfg: EndpointPoolConfig) -> io::Result<Self> {
+        if urls.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "endpoint pool requires at least one URL",
+            ));
+        }
+        let mut endpoints = Vec::with_capacity(urls.len());
+        for u in urls.iter() {
+            manager.check_url(&crate::join_uri(u, &cfg.health_path)?)?;
+            endpoints.push(Endpoint {
+                url: u.to_string(),
+                healthy: AtomicBool::new(true),
+                failures: AtomicUsize::new(0),
+                in_flight: AtomicUsize::new(0),
+            });
+        }
+        Ok(Self {
+            manager,
+            cfg,
+            endpoints: Arc::new(endpoints),
+            next: AtomicUsize::new(0),
+            health_task: None,
+        })
+    }
+
+    /// Checks the endpoints every "health_interval" until the pool is
+    /// dropped.
+    pub fn start_health_checks(&mut self) {
+        if self.health_task.is_some() {
+            return;
+        }
+        let manager = self.manager.clone();
+        let cfg = self.cfg.clone();
+        let endpoints = self.endpoints.clone();
+        self.health_task = Some(tokio::spawn(async move {
+            let mut ticker = interval(cfg.health_interval);
+            loop {
+                ticker.tick().await;
+                check_all(&manager, &cfg, &endpoints).await;
+            }
+        }));
+    }
+
+    /// Checks every endpoint once.
+    pub async fn check_health(&self) {
+        check_all(&self.manager, &self.cfg, &self.endpoints).await;
+    }
+
+    /// Returns the base URLs of the healthy endpoints.
+    pub fn healthy_endpoints(&self) -> Vec<String> {
+        self.endpoints
+            .iter()
+            .filter(|e| e.healthy.load(Ordering::Relaxed))
+            .map(|e| e.url.clone())
+            .collect()
+    }
+
+    /// Sends a GET request for the path to a healthy endpoint.
+    pub async fn get(&self, path: &str) -> io::Result<Response<Bytes>> {
+        self.request(|base| crate::create_get(base, path)).await
+    }
+
+    /// Sends the request built by "new_req" from the base URL of a
+    /// healthy endpoint, and returns the response of any status code.
+    /// Fails if no endpoint is healthy.
+    pub async fn request<F>(&self, new_req: F) -> io::Result<Response<Bytes>>
+    where
+        F: Fn(&str) -> io::Result<Request<Body>>,
+    {
+        Ok(self.request_with_attempts(new_req).await?.value)
+    }
+
+    /// Same as "request", but also returns the attempts, when failover
+    /// is enabled ("max_attempts" > 1). The response of the last attempt
+    /// is returned even if it is a 5xx.
+    pub async fn request_with_attempts<F>(
+        &self,
+        new_req: F,
+    ) -> io::Result<Attempted<Response<Bytes>>>
+    where
+        F: Fn(&str) -> io::Result<Request<Body>>,
+    {
+        let started = Instant::now();
+        let max_attempts = self.cfg.max_attempts.max(1);
+        // failover continues in round-robin order from the first endpoint
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let mut tried = Vec::new();
+        let mut errors = Vec::new();
+        loop {
+            let (idx, ep) = match self.select(start, &tried) {
+                Ok(v) => v,
+                // every healthy endpoint was tried
+                Err(e) if !errors.is_empty() => return Err(exhausted(errors, e)),
+                Err(e) => return Err(e),
+            };
+            tried.push(idx);
+            wire_debug!("routing request to {}", crate::redact::url(&ep.url));
+
+            let attempt_started = Instant::now();
+            let ret = {
+                let _in_flight = InFlight::new(&ep.in_flight);
+                let req = new_req(&ep.url)?;
+                let timeout_dur = self.manager.timeout_for(req.uri());
+                self.manager.fetch(req, timeout_dur).await
+            };
+            let last = tried.len() >= max_attempts;
+            let error = match ret {
+                Ok(resp) if last || !resp.status().is_server_error() => {
+                    return Ok(Attempted {
+                        value: resp,
+                        attempts: tried.len(),
+                        errors,
+                        endpoint: ep.url.clone(),
+                        elapsed: started.elapsed(),
+                    });
+                }
+                Ok(resp) => Error::new(
+                    ErrorKind::Other,
+                    format!("unexpected HTTP response code {}", resp.status()),
+                ),
+                Err(e) if last => return Err(exhausted(errors, e)),
+                Err(e) => e,
+            };
+            wire_warn!(
+                "attempt {} to {} failed ({}), failing over",
+                tried.len(),
+                crate::redact::url(&ep.url),
+                error
+            );
+            errors.push(AttemptError {
+                endpoint: ep.url.clone(),
+                error,
+                elapsed: attempt_started.elapsed(),
+            });
+        }
+    }
+
+    /// Selects a healthy endpoint that is not in "tried", starting from
+    /// the "start"-th endpoint.
+    fn select(&self, start: usize, tried: &[usize]) -> io::Result<(usize, &Endpoint)> {
+        let n = self.endpoints.len();
+        let healthy = (0..n)
+            .map(|i| (start + i) % n)
+            .filter(|i| !tried.contains(i))
+            .map(|i| (i, &self.endpoints[i]))
+            .filter(|(_, e)| e.healthy.load(Ordering::Relaxed));
+        let selected = match self.cfg.selection {
+            Selection::RoundRobin => healthy.take(1).next(),
+            // "min_by_key" returns the first minimum, in round-robin order
+            Selection::LeastLoaded => {
+                healthy.min_by_key(|(_, e)| e.in_flight.load(Ordering::Relaxed))
+            }
+        };
+        selected.ok_or_else(|| Error::new(ErrorKind::Other, "no healthy endpoint in the pool"))
+    }
+}
+
+/// Returns the last error, noting the earlier failed attempts.
+fn exhausted(errors: Vec<AttemptError>, last: Error) -> Error {
+    if errors.is_empty() {
+        return last;
+    }
+    Error::new(
+        last.kind(),
+        format!("{} (after {} failed attempts)", last, errors.len()),
+    )
+}
+
+/// Counts a request as in flight until dropped, including when the
+/// request future is cancelled.
+struct InFlight<'a>(&'a AtomicUsize);
+
+impl<'a> InFlight<'a> {
+    fn new(n: &'a AtomicUsize) -> Self {
+        n.fetch_add(1, Ordering::Relaxed);
+        Self(n)
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Drop for EndpointPool {
+    fn drop(&mut self) {
+        if let Some(t) = self.health_task.take() {
+            t.abort();
+        }
+    }
+}
+
+async fn check_all(manager: &Manager, cfg: &EndpointPoolConfig, endpoints: &[Endpoint]) {
+    future::join_all(endpoints.iter().map(|ep| async move {
+        let ok = match crate::create_get(&ep.url, &cfg.health_path) {
+            Ok(req) => {
+                let timeout_dur = manager.timeout_for(req.uri());
+                matches!(manager.fetch(req, timeout_dur).await, Ok(resp) if resp.status().is_success())
+            }
+            Err(_) => false,
+        };
+        if ok {
+            ep.failures.store(0, Ordering::Relaxed);
+            if !ep.healthy.swap(true, Ordering::Relaxed) {
+                wire_warn!("endpoint {} is healthy again", crate::redact::url(&ep.url));
+            }
+            return;
+        }
+        let failures = ep.failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if failures >= cfg.unhealthy_threshold.max(1) && ep.healthy.swap(false, Ordering::Relaxed) {
+            wire_warn!(
+                "endpoint {} is unhealthy after {} failed checks",
+                crate::redact::url(&ep.url),
+                failures
+            );
+        }
+    }))
+    .await;
+}
+
+/// RUST_LOG=debug cargo test --lib -- endpoints::test_endpoint_pool --exact --show-output
+#[tokio::test]
+async fn test_endpoint_pool() {
+    use hyper::Method;
+
+    let s1 = crate::testing::MockServer::start().await.unwrap();
+    let s2 = crate::testing::MockServer::start().await.unwrap();
+    for (s, name) in [(&s1, "s1"), (&s2, "s2")] {
+        s.stub(Method::GET, "/health", 200, "ok");
+        s.stub(Method::GET, "/", 200, name);
+    }
+
+    let cfg = EndpointPoolConfig {
+        unhealthy_threshold: 1,
+        ..Default::default()
+    };
+    let pool = EndpointPool::new(
+        Manager::new().unwrap(),
+        &[&s1.url(), &s2.url()],
+        cfg.clone(),
+    )
+    .unwrap();
+
+    let mut bodies = Vec::new();
+    for _ in 0..4 {
+        bodies.push(pool.get("/").await.unwrap().into_body());
+    }
+    assert_eq!(bodies, vec!["s1", "s2", "s1", "s2"]);
+
+    s1.stub(Method::GET, "/health", 503, "down");
+    pool.check_health().await;
+    assert_eq!(pool.healthy_endpoints(), vec![s2.url()]);
+    for _ in 0..3 {
+        assert_eq!(pool.get("/").await.unwrap().into_body(), "s2");
+    }
+
+    s2.stub(Method::GET, "/health", 503, "down");
+    pool.check_health().await;
+    assert!(pool.get("/").await.is_err());
+
+    s1.stub(Method::GET, "/health", 200, "ok");
+    pool.check_health().await;
+    assert_eq!(pool.healthy_endpoints(), vec![s1.url()]);
+
+    assert!(EndpointPool::new(Manager::new().unwrap(), &[], cfg.clone()).is_err());
+    assert!(EndpointPool::new(Manager::new().unwrap(), &["ftp://a"], cfg).is_err());
+}
+
+/// RUST_LOG=debug cargo test --lib -- endpoints::test_endpoint_pool_least_loaded --exact --show-output
+#[tokio::test]
+async fn test_endpoint_pool_least_loaded() {
+    use hyper::Method;
+
+    let s1 = crate::testing::MockServer::start().await.unwrap();
+    let s2 = crate::testing::MockServer::start().await.unwrap();
+    s1.stub(Method::GET, "/", 200, "s1");
+    s2.stub(Method::GET, "/", 200, "s2");
+
+    let pool = EndpointPool::new(
+        Manager::new().unwrap(),
+        &[&s1.url(), &s2.url()],
+        EndpointPoolConfig {
+            selection: Selection::LeastLoaded,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    // the first endpoint is busy
+    pool.endpoints[0].in_flight.store(5, Ordering::Relaxed);
+    for _ in 0..3 {
+        assert_eq!(pool.get("/").await.unwrap().into_body(), "s2");
+    }
+    pool.endpoints[0].in_flight.store(0, Ordering::Relaxed);
+    let mut bodies = Vec::new();
+    for _ in 0..2 {
+        bodies.push(pool.get("/").await.unwrap().into_body());
+    }
+    bodies.sort();
+    assert_eq!(bodies, vec!["s1", "s2"]);
+}
+
+/// RUST_LOG=debug cargo test --lib -- endpoints::test_endpoint_pool_failover --exact --show-output
+#[tokio::test]
+async fn test_endpoint_pool_failover() {
+    use hyper::Method;
+
+    let s1 = crate::testing::MockServer::start().await.unwrap();
+    let s2 = crate::testing::MockServer::start().await.unwrap();
+    s1.stub(Method::GET, "/", 503, "s1");
+    s2.stub(Method::GET, "/", 200, "s2");
+
+    let pool = EndpointPool::new(
+        Manager::new().unwrap(),
+        &[&s1.url(), &s2.url()],
+        EndpointPoolConfig {
+            max_attempts: 2,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    let ret = pool
+        .request_with_attempts(|base| crate::create_get(base, "/"))
+        .await
+        .unwrap();
+    assert_eq!(ret.value.body(), "s2");
+    assert_eq!(ret.attempts, 2);
+    assert!(ret.is_retried());
+    assert_eq!(ret.endpoint, s2.url());
+    assert_eq!(ret.errors.len(), 1);
+    assert_eq!(ret.errors[0].endpoint, s1.url());
+    assert!(ret.errors[0].error.to_string().contains("503"));
+    assert!(ret.elapsed >= ret.errors[0].elapsed);
+
+    // starting from the second endpoint needs no failover
+    let ret = pool
+        .request_with_attempts(|base| crate::create_get(base, "/"))
+        .await
+        .unwrap();
+    assert_eq!(ret.attempts, 1);
+    assert!(!ret.is_retried());
+    assert_eq!(ret.endpoint, s2.url());
+
+    // the last 5xx response is returned as is
+    s2.stub(Method::GET, "/", 500, "s2");
+    let ret = pool
+        .request_with_attempts(|base| crate::create_get(base, "/"))
+        .await
+        .unwrap();
+    assert_eq!(ret.attempts, 2);
+    assert!(ret.value.status().is_server_error());
+}
@@ -0,0 +1,348 @@
+//! Typed errors, to tell timeouts, DNS and connection failures, and bad
+//! status codes apart programmatically.
+//!
+//! Functions return "io::Error" (so that "?" keeps working with other I/O
+//! code), with the typed error as the inner error of the failures below.
+//! "Error::from" recovers it, e.g.:
+//!
+//! ```ignore
+//! match http_manager::error::Error::from(e) {
+//!     Error::Timeout(_) | Error::Connect(_) => retry(),
+//!     Error::Status(code, body) => report(code, body),
+//!     e => return Err(e.into()),
+//! }
+//! ```
+
+use std::{
+    fmt,
+    io::{self, ErrorKind},
+};
+
+use hyper::{body::Bytes, StatusCode};
+
+use crate::{
+    conditional::Conflict, decompress::DecompressionBomb, stall::TransferStalled, tls::PinMismatch,
+};
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The request or the body read did not complete within the timeout.
+    Timeout(String),
+    /// The host name did not resolve.
+    Dns(String),
+    /// The TCP (or TLS) connection could not be established.
+    Connect(String),
+    /// The proxy could not be reached, or refused to connect to the host
+    /// (e.g., "407 Proxy Authentication Required").
+    Proxy(String),
+    /// The request failed on an established connection (e.g., the server
+    /// closed it before responding).
+    Request(String),
+    /// Non-2xx response, with its body.
+    Status(StatusCode, Bytes),
+    /// Invalid URL, or a path that does not join to it.
+    UrlParse(String),
+    /// The response body failed to read (or to parse, e.g., as JSON).
+    Body(String),
+    /// Invalid or contradictory settings (see "ManagerBuilder::validate").
+    Config(String),
+    Stalled(TransferStalled),
+    DecompressionBomb(DecompressionBomb),
+    Conflict(Conflict),
+    /// The server certificate matched none of the pins
+    /// (see "TlsConfig::pin_sha256").
+    PinMismatch(PinMismatch),
+    /// Any other failure.
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Timeout(msg)
+            | Error::Dns(msg)
+            | Error::Connect(msg)
+            | Error::Proxy(msg)
+            | Error::Request(msg)
+            | Error::UrlParse(msg)
+            | Error::Body(msg)
+            | Error::Config(msg) => f.write_str(msg),
+            Error::Status(status, _) => write!(
+                f,
+                "unexpected HTTP response code {} (server error {})",
+                status,
+                status.is_server_error()
+            ),
+            Error::Stalled(e) => e.fmt(f),
+            Error::DecompressionBomb(e) => e.fmt(f),
+            Error::Conflict(e) => e.fmt(f),
+            Error::PinMismatch(e) => e.fmt(f),
+            Error::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Stalled(e) => Some(e),
+            Error::DecompressionBomb(e) => Some(e),
+            Error::Conflict(e) => Some(e),
+            Error::PinMismatch(e) => Some(e),
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl Error {
+    /// Returns the kind of the "io::Error" that carries this error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Timeout(_) | Error::Stalled(_) => ErrorKind::TimedOut,
+            Error::UrlParse(_) | Error::Config(_) => ErrorKind::InvalidInput,
+            Error::DecompressionBomb(_) => ErrorKind::InvalidData,
+            Error::Io(e) => e.kind(),
+            _ => ErrorKind::Other,
+        }
+    }
+
+    /// Returns true for timeouts, including stalled transfers.
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, Error::Timeout(_) | Error::Stalled(_))
+    }
+
+    /// Returns the status code of a non-2xx response.
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            Error::Status(status, _) => Some(*status),
+            _ => None,
+        }
+    }
+
+    /// Returns the typed error carried by the "io::Error", if any.
+    pub(crate) fn typed(e: &io::Error) -> Option<&Self> {
+        e.get_ref().and_then(|inner| inner.downcast_ref())
+    }
+
+    /// Classifies a failed hyper request, with the message prefix
+    /// (e.g., "failed to fetch response from example.com").
+    pub(crate) fn from_hyper(prefix: &str, e: &hyper::Error) -> Self {
+        let msg = format!("{} {}", prefix, e);
+        if e.is_timeout() {
+            return Error::Timeout(msg);
+        }
+        if !e.is_connect() {
+            return Error::Request(msg);
+        }
+
+        // hyper wraps the resolver and socket errors
+        let mut source = std::error::Error::source(e);
+        while let Some(s) = source {
+            if let Some(io_err) = s.downcast_ref::<io::Error>() {
+                if io_err.kind() == ErrorKind::TimedOut {
+                    return Error::Timeout(msg);
+                }
+                match io_err.get_ref().and_then(|e| e.downcast_ref()) {
+                    Some(Error::Proxy(_)) => return Error::Proxy(msg),
+                    // failed lookups of "ssrf::GuardedResolver"
+                    Some(Error::Dns(_)) => return Error::Dns(msg),
+                    _ => {}
+                }
+            }
+            if let Some(e) = s.downcast_ref::<PinMismatch>() {
+                return Error::PinMismatch(e.clone());
+            }
+            source = s.source();
+        }
+        Error::Connect(msg)
+    }
+
+    /// Classifies a failed reqwest request (see "from_hyper").
+    pub(crate) fn from_reqwest(prefix: &str, e: reqwest::Error) -> io::Error {
+        let (timeout, connect, body) =
+            (e.is_timeout(), e.is_connect(), e.is_body() || e.is_decode());
+        let msg = format!("{} {}", prefix, e.without_url());
+        let e = if timeout {
+            Error::Timeout(msg)
+        } else if connect {
+            Error::Connect(msg)
+        } else if body {
+            Error::Body(msg)
+        } else {
+            Error::Request(msg)
+        };
+        e.into()
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(e: Error) -> Self {
+        let kind = e.kind();
+        match e {
+            Error::Io(e) => e,
+            e => io::Error::new(kind, e),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        let kind = e.kind();
+        if e.get_ref().is_none() {
+            // e.g., OS errors
+            return Error::Io(e);
+        }
+        let inner = e.into_inner().expect("checked inner error");
+        let inner = match inner.downcast::<Error>() {
+            Ok(e) => return *e,
+            Err(inner) => inner,
+        };
+        let inner = match inner.downcast::<TransferStalled>() {
+            Ok(e) => return Error::Stalled(*e),
+            Err(inner) => inner,
+        };
+        let inner = match inner.downcast::<DecompressionBomb>() {
+            Ok(e) => return Error::DecompressionBomb(*e),
+            Err(inner) => inner,
+        };
+        let inner = match inner.downcast::<Conflict>() {
+            Ok(e) => return Error::Conflict(*e),
+            Err(inner) => inner,
+        };
+        let inner = match inner.downcast::<PinMismatch>() {
+            Ok(e) => return Error::PinMismatch(*e),
+            Err(inner) => inner,
+        };
+        if kind == ErrorKind::TimedOut {
+            return Error::Timeout(inner.to_string());
+        }
+        Error::Io(io::Error::new(kind, inner))
+    }
+}
+
+#[test]
+fn test_error_conversions() {
+    let e = io::Error::from(Error::Status(StatusCode::BAD_GATEWAY, Bytes::from("down")));
+    assert_eq!(e.kind(), ErrorKind::Other);
+    assert!(e.to_string().contains("502"));
+    match Error::from(e) {
+        Error::Status(status, body) => {
+            assert_eq!(status, StatusCode::BAD_GATEWAY);
+            assert_eq!(body, "down");
+        }
+        e => panic!("unexpected {:?}", e),
+    }
+
+    let e = io::Error::from(Error::Timeout("slow".to_string()));
+    assert_eq!(e.kind(), ErrorKind::TimedOut);
+    assert!(Error::from(e).is_timeout());
+
+    // untyped errors keep their kind
+    let e = Error::from(io::Error::from(ErrorKind::NotFound));
+    assert_eq!(e.kind(), ErrorKind::NotFound);
+    assert!(matches!(e, Error::Io(_)));
+    let e = Error::from(io::Error::new(ErrorKind::TimedOut, "deadline has elapsed"));
+    assert!(matches!(e, Error::Timeout(_)));
+
+    // the errors of the "is_*" helpers are recovered, and kept as is
+    let e = io::Error::new(
+        ErrorKind::Other,
+        Conflict {
+            validators: Default::default(),
+            body: Bytes::new(),
+        },
+    );
+    let e = Error::from(e);
+    assert!(matches!(e, Error::Conflict(_)));
+    assert!(crate::conditional::is_conflict(&io::Error::from(e)));
+
+    let e = crate::parse_url("http://[::1").unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidInput);
+    assert!(matches!(Error::from(e), Error::UrlParse(_)));
+    let e = crate::join_uri_within("http://host/api/", "../admin").unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidInput);
+    assert!(matches!(Error::from(e), Error::UrlParse(_)));
+
+    let e = crate::Manager::builder()
+        .timeout(std::time::Duration::ZERO)
+        .build()
+        .err()
+        .unwrap();
+    assert_eq!(e.kind(), ErrorKind::InvalidInput);
+    assert!(matches!(Error::from(e), Error::Config(_)));
+}
+
+/// RUST_LOG=debug cargo test --lib -- error::test_request_errors --exact --show-output
+#[tokio::test]
+async fn test_request_errors() {
+    use std::time::Duration;
+
+    use hyper::Method;
+    use tokio::net::TcpListener;
+
+    let server = crate::testing::MockServer::start().await.unwrap();
+    server.stub(Method::GET, "/busy", 503, "retry later");
+
+    let manager = crate::Manager::builder()
+        .timeout(Duration::from_millis(500))
+        .connect_timeout(Duration::from_millis(500))
+        .build()
+        .unwrap();
+    let req = crate::create_get(server.url(), "/busy").unwrap();
+    let e = manager.read_bytes(req, true).await.unwrap_err();
+    match Error::from(e) {
+        Error::Status(status, body) => {
+            assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+            assert_eq!(body, "retry later");
+        }
+        e => panic!("unexpected {:?}", e),
+    }
+
+    // accepts connections, but never responds
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let hang_url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        let mut conns = Vec::new();
+        while let Ok((stream, _)) = listener.accept().await {
+            conns.push(stream);
+        }
+    });
+    let req = crate::create_get(&hang_url, "/").unwrap();
+    let e = manager.read_bytes(req, true).await.unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::TimedOut);
+    assert!(matches!(Error::from(e), Error::Timeout(_)));
+
+    // nothing listens on the port
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let closed_url = format!("http://{}", listener.local_addr().unwrap());
+    drop(listener);
+    let req = crate::create_get(&closed_url, "/").unwrap();
+    let e = manager.read_bytes(req, true).await.unwrap_err();
+    assert!(matches!(Error::from(e), Error::Connect(_)));
+
+    let req = crate::create_get("http://http-manager-test.invalid", "/").unwrap();
+    let e = manager.read_bytes(req, true).await.unwrap_err();
+    assert!(matches!(Error::from(e), Error::Dns(_) | Error::Timeout(_)));
+
+    // the free functions too
+    let req = crate::create_get(server.url(), "/busy").unwrap();
+    let e = crate::read_bytes(req, Duration::from_secs(5), false, true)
+        .await
+        .unwrap_err();
+    assert_eq!(
+        Error::from(e).status(),
+        Some(StatusCode::SERVICE_UNAVAILABLE)
+    );
+    let req = crate::create_get(&closed_url, "/").unwrap();
+    let e = crate::read_bytes(req, Duration::from_secs(5), false, true)
+        .await
+        .unwrap_err();
+    assert!(matches!(Error::from(e), Error::Connect(_)));
+    let req = crate::create_get("http://http-manager-test.invalid", "/").unwrap();
+    let e = crate::read_bytes(req, Duration::from_secs(5), false, true)
+        .await
+        .unwrap_err();
+    assert!(matches!(Error::from(e), Error::Dns(_) | Error::Timeout(_)));
+}
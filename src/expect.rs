@@ -0,0 +1,213 @@
+//! Response assertions for smoke and integration tests against real
+//! services, e.g.:
+//!
+//! ```no_run
+//! use http_manager::{expect::ResponseExpect, Manager};
+//!
+//! # async fn smoke() -> std::io::Result<()> {
+//! let manager = Manager::new()?;
+//! let req = http_manager::create_get("http://localhost:9650", "/health")?;
+//! manager
+//!     .read_response(req)
+//!     .await?
+//!     .expect_status(200)?
+//!     .expect_header("content-type", "application/json")?
+//!     .expect_json_eq(&serde_json::json!({"healthy": true}))?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::io::{self, Error, ErrorKind};
+
+use hyper::{body::Bytes, Response};
+use serde_json::Value;
+
+/// Limit of the body shown in failure messages.
+const BODY_PREVIEW: usize = 1024;
+
+/// Assertions that return the response on success, so that they chain
+/// with "?", and an "InvalidData" error describing the mismatch otherwise.
+pub trait ResponseExpect: Sized {
+    fn expect_status(self, status: u16) -> io::Result<Self>;
+
+    /// Expects the header to have the value (any of its values, if repeated).
+    fn expect_header(self, name: &str, value: &str) -> io::Result<Self>;
+
+    fn expect_body_contains(self, substring: &str) -> io::Result<Self>;
+
+    /// Expects the body to be JSON equal to "expected", listing every
+    /// differing path on failure.
+    fn expect_json_eq(self, expected: &Value) -> io::Result<Self>;
+}
+
+impl ResponseExpect for Response<Bytes> {
+    fn expect_status(self, status: u16) -> io::Result<Self> {
+        if self.status().as_u16() == status {
+            return Ok(self);
+        }
+        Err(mismatch(format!(
+            "expected status code {}, got {}\nbody: {}",
+            status,
+            self.status(),
+            crate::logging::preview(self.body(), BODY_PREVIEW)
+        )))
+    }
+
+    fn expect_header(self, name: &str, value: &str) -> io::Result<Self> {
+        let got: Vec<&str> = self
+            .headers()
+            .get_all(name)
+            .iter()
+            .map(|v| v.to_str().unwrap_or("<binary>"))
+            .collect();
+        if got.contains(&value) {
+            return Ok(self);
+        }
+        let redactor = crate::redact::global();
+        let got: Vec<&str> = got
+            .into_iter()
+            .map(|v| redactor.header_value(name, v))
+            .collect();
+        Err(mismatch(format!(
+            "expected header '{}: {}', got {:?}",
+            name,
+            redactor.header_value(name, value),
+            got
+        )))
+    }
+
+    fn expect_body_contains(self, substring: &str) -> io::Result<Self> {
+        let body = String::from_utf8_lossy(self.body());
+        if body.contains(substring) {
+            return Ok(self);
+        }
+        Err(mismatch(format!(
+            "expected body to contain {:?}\nbody: {}",
+            substring,
+            crate::logging::preview(self.body(), BODY_PREVIEW)
+        )))
+    }
+
+    fn expect_json_eq(self, expected: &Value) -> io::Result<Self> {
+        let actual: Value = serde_json::from_slice(self.body()).map_err(|e| {
+            mismatch(format!(
+                "expected JSON body, failed to parse {}\nbody: {}",
+                e,
+                crate::logging::preview(self.body(), BODY_PREVIEW)
+            ))
+        })?;
+        let mut diffs = Vec::new();
+        json_diff("$", expected, &actual, &mut diffs);
+        if diffs.is_empty() {
+            return Ok(self);
+        }
+        Err(mismatch(format!(
+            "JSON body differs at {} path(s):\n{}",
+            diffs.len(),
+            diffs.join("\n")
+        )))
+    }
+}
+
+fn mismatch(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+/// Appends a line per differing path (e.g., "$.items[1].id: expected 2, got 3").
+fn json_diff(path: &str, expected: &Value, actual: &Value, diffs: &mut Vec<String>) {
+    match (expected, actual) {
+        (Value::Object(e), Value::Object(a)) => {
+            for (k, ev) in e.iter() {
+                let p = format!("{}.{}", path, k);
+                match a.get(k) {
+                    Some(av) => json_diff(&p, ev, av, diffs),
+                    None => diffs.push(format!("{}: missing, expected {}", p, ev)),
+                }
+            }
+            for (k, av) in a.iter() {
+                if !e.contains_key(k) {
+                    diffs.push(format!("{}.{}: unexpected {}", path, k, av));
+                }
+            }
+        }
+        (Value::Array(e), Value::Array(a)) => {
+            for (i, (ev, av)) in e.iter().zip(a.iter()).enumerate() {
+                json_diff(&format!("{}[{}]", path, i), ev, av, diffs);
+            }
+            if e.len() != a.len() {
+                diffs.push(format!(
+                    "{}: expected {} element(s), got {}",
+                    path,
+                    e.len(),
+                    a.len()
+                ));
+            }
+        }
+        _ if expected != actual => {
+            diffs.push(format!("{}: expected {}, got {}", path, expected, actual));
+        }
+        _ => {}
+    }
+}
+
+#[test]
+fn test_response_expect() {
+    use serde_json::json;
+
+    let resp = || {
+        Response::builder()
+            .status(200)
+            .header("content-type", "application/json")
+            .header("authorization", "Bearer secret")
+            .body(Bytes::from(
+                r#"{"id": 1, "items": [{"name": "a"}, {"name": "b"}], "extra": true}"#,
+            ))
+            .unwrap()
+    };
+
+    resp()
+        .expect_status(200)
+        .unwrap()
+        .expect_header("content-type", "application/json")
+        .unwrap()
+        .expect_body_contains(r#""name": "b""#)
+        .unwrap()
+        .expect_json_eq(&json!({"id": 1, "items": [{"name": "a"}, {"name": "b"}], "extra": true}))
+        .unwrap();
+
+    let e = resp().expect_status(404).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidData);
+    assert!(e
+        .to_string()
+        .starts_with("expected status code 404, got 200 OK"));
+
+    let e = resp()
+        .expect_header("content-type", "text/plain")
+        .unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        r#"expected header 'content-type: text/plain', got ["application/json"]"#
+    );
+    // sensitive values are masked
+    let e = resp()
+        .expect_header("authorization", "Bearer other")
+        .unwrap_err();
+    assert!(!e.to_string().contains("secret"));
+
+    assert!(resp().expect_body_contains("missing").is_err());
+
+    let e = resp()
+        .expect_json_eq(
+            &json!({"id": 2, "items": [{"name": "a"}, {"name": "c"}, {"name": "d"}], "new": 1}),
+        )
+        .unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        "JSON body differs at 5 path(s):\n\
+         $.id: expected 2, got 1\n\
+         $.items[1].name: expected \"c\", got \"b\"\n\
+         $.items: expected 3 element(s), got 2\n\
+         $.new: missing, expected 1\n\
+         $.extra: unexpected true"
+    );
+}
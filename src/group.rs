@@ -0,0 +1,240 @@
+//! Structured request groups: requests spawned together are joined under
+//! a single deadline or aborted together, so that orchestration code does
+//! not leak half-finished transfers on early exit.
+
+use std::{
+    io::{self, Error, ErrorKind},
+    sync::Arc,
+    time::Duration,
+};
+
+use futures_util::stream::{FuturesUnordered, StreamExt};
+use hyper::{body::Bytes, Body, Request, Response};
+use tokio::{
+    task::{JoinError, JoinHandle},
+    time::{timeout_at, Instant},
+};
+
+use crate::{
+    logging::{wire_debug, wire_warn},
+    Manager,
+};
+
+struct Member {
+    critical: bool,
+    handle: JoinHandle<io::Result<Response<Bytes>>>,
+}
+
+/// Requests running as tasks in the background from the moment they are
+/// spawned. Aborting a request (or dropping the group) cancels its task,
+/// which closes the connection and drops any partially read body.
+pub struct RequestGroup {
+    manager: Arc<Manager>,
+    members: Vec<Member>,
+}
+
+impl std::fmt::Debug for RequestGroup {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RequestGroup")
+            .field("len", &self.members.len())
+            .finish()
+    }
+}
+
+impl RequestGroup {
+    pub fn new(manager: Manager) -> Self {
+        Self {
+            manager: Arc::new(manager),
+            members: Vec::new(),
+        }
+    }
+
+    /// Starts the request, whose response body is read in full, and
+    /// returns its index in the "join_all" results.
+    pub fn spawn(&mut self, req: Request<Body>) -> usize {
+        self.spawn_member(req, false)
+    }
+
+    /// Same as "spawn", but an error or a non-2xx response aborts all the
+    /// other requests of the group in "join_all".
+    pub fn spawn_critical(&mut self, req: Request<Body>) -> usize {
+        self.spawn_member(req, true)
+    }
+
+    fn spawn_member(&mut self, req: Request<Body>, critical: bool) -> usize {
+        let manager = self.manager.clone();
+        let handle = tokio::spawn(async move { manager.read_response(req).await });
+        self.members.push(Member { critical, handle });
+        self.members.len() - 1
+    }
+
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    /// Cancels every request that is still in flight. Their "join_all"
+    /// results are "Interrupted" errors.
+    pub fn abort_all(&self) {
+        for m in self.members.iter() {
+            m.handle.abort();
+        }
+    }
+
+    /// Waits for all the requests until the deadline, and returns their
+    /// results in spawn order. Requests still in flight at the deadline are
+    /// aborted with "TimedOut" errors, and a failed critical request aborts
+    /// the rest with "Interrupted" errors.
+    pub async fn join_all(mut self, deadline: Duration) -> Vec<io::Result<Response<Bytes>>> {
+        let deadline_dur = deadline;
+        let deadline = Instant::now() + deadline;
+        let mut members = std::mem::take(&mut self.members);
+        let mut results: Vec<Option<io::Result<Response<Bytes>>>> =
+            members.iter().map(|_| None).collect();
+
+        let mut stopped = None;
+        {
+            let mut pending: FuturesUnordered<_> = members
+                .iter_mut()
+                .enumerate()
+                .map(|(i, m)| async move { (i, m.critical, (&mut m.handle).await) })
+                .collect();
+            loop {
+                match timeout_at(deadline, pending.next()).await {
+                    Ok(Some((i, critical, joined))) => {
+                        let ret = joined.unwrap_or_else(|e| Err(join_error(e)));
+                        let failed = match &ret {
+                            Ok(resp) => !resp.status().is_success(),
+                            Err(_) => true,
+                        };
+                        results[i] = Some(ret);
+                        if critical && failed {
+                            wire_warn!("critical request {} failed, aborting the group", i);
+                            stopped = Some(Error::new(
+                                ErrorKind::Interrupted,
+                                format!("aborted, since critical request {} failed", i),
+                            ));
+                            break;
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(_) => {
+                        wire_debug!("request group deadline exceeded");
+                        stopped = Some(Error::new(
+                            ErrorKind::TimedOut,
+                            format!("aborted at the group deadline {:?}", deadline_dur),
+                        ));
+                        break;
+                    }
+                }
+            }
+        }
+
+        for (m, r) in members.iter().zip(results.iter_mut()) {
+            if r.is_none() {
+                m.handle.abort();
+                let e = stopped
+                    .as_ref()
+                    .expect("unfinished requests must be stopped");
+                *r = Some(Err(Error::new(e.kind(), e.to_string())));
+            }
+        }
+        results.into_iter().map(|r| r.unwrap()).collect()
+    }
+}
+
+impl Drop for RequestGroup {
+    fn drop(&mut self) {
+        self.abort_all();
+    }
+}
+
+fn join_error(e: JoinError) -> Error {
+    if e.is_cancelled() {
+        return Error::new(ErrorKind::Interrupted, "request aborted");
+    }
+    Error::new(ErrorKind::Other, format!("request task failed {}", e))
+}
+
+/// RUST_LOG=debug cargo test --lib -- group::test_request_group --exact --show-output
+#[tokio::test]
+async fn test_request_group() {
+    use hyper::Method;
+    use tokio::net::TcpListener;
+
+    let server = crate::testing::MockServer::start().await.unwrap();
+    server.stub(Method::GET, "/ok", 200, "ok");
+    server.stub(Method::GET, "/fail", 500, "");
+
+    // accepts connections, but never responds
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let hang_url = format!("http://{}", listener.local_addr().unwrap());
+    let (closed_tx, mut closed_rx) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let closed_tx = closed_tx.clone();
+            tokio::spawn(async move {
+                use tokio::io::AsyncReadExt;
+                let mut buf = [0u8; 1024];
+                while let Ok(n) = stream.read(&mut buf).await {
+                    if n == 0 {
+                        break;
+                    }
+                }
+                let _ = closed_tx.send(());
+            });
+        }
+    });
+
+    let manager = Manager::new().unwrap();
+    let started = Instant::now();
+    let mut group = RequestGroup::new(manager.clone());
+    group.spawn(crate::create_get(server.url(), "/ok").unwrap());
+    group.spawn(crate::create_get(&hang_url, "/").unwrap());
+    assert_eq!(group.len(), 2);
+    let results = group.join_all(Duration::from_millis(300)).await;
+    assert_eq!(results[0].as_ref().unwrap().body(), "ok");
+    assert_eq!(results[1].as_ref().unwrap_err().kind(), ErrorKind::TimedOut);
+    assert!(started.elapsed() < Duration::from_secs(5));
+    // the aborted request closed its connection
+    tokio::time::timeout(Duration::from_secs(5), closed_rx.recv())
+        .await
+        .unwrap();
+
+    // a failed critical request aborts the rest
+    let mut group = RequestGroup::new(manager.clone());
+    group.spawn(crate::create_get(&hang_url, "/").unwrap());
+    group.spawn_critical(crate::create_get(server.url(), "/fail").unwrap());
+    let results = group.join_all(Duration::from_secs(10)).await;
+    assert_eq!(
+        results[0].as_ref().unwrap_err().kind(),
+        ErrorKind::Interrupted
+    );
+    assert_eq!(results[1].as_ref().unwrap().status(), 500);
+    assert!(started.elapsed() < Duration::from_secs(5));
+
+    // explicit aborts, and dropping the group
+    let mut group = RequestGroup::new(manager.clone());
+    group.spawn(crate::create_get(&hang_url, "/").unwrap());
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    group.abort_all();
+    let results = group.join_all(Duration::from_secs(10)).await;
+    assert_eq!(
+        results[0].as_ref().unwrap_err().kind(),
+        ErrorKind::Interrupted
+    );
+
+    let mut group = RequestGroup::new(manager);
+    group.spawn(crate::create_get(&hang_url, "/").unwrap());
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    drop(group);
+    for _ in 0..2 {
+        tokio::time::timeout(Duration::from_secs(5), closed_rx.recv())
+            .await
+            .unwrap();
+    }
+}
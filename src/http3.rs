@@ -0,0 +1,415 @@
+//! Experimental HTTP/3 (QUIC) transport over quinn and h3 (see
+//! "ManagerBuilder::http3"). Requests to "https" URLs try QUIC first, and
+//! fall back to the TCP client (HTTP/1.1 or HTTP/2) if the QUIC handshake
+//! fails, e.g., servers without HTTP/3 or networks that block UDP. Hosts
+//! that fail are only retried over QUIC after "BROKEN_HOST_TTL".
+
+use std::{
+    collections::HashMap,
+    future,
+    io::{self, Error, ErrorKind},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use h3::client::SendRequest;
+use hyper::{
+    body::{Buf, Bytes, HttpBody},
+    header::UPGRADE,
+    http::uri::Scheme,
+    Body, Request, Response, Version,
+};
+use once_cell::sync::OnceCell;
+use rustls_quic::pki_types::CertificateDer;
+use tokio::time::timeout;
+
+use crate::{
+    logging::{wire_debug, wire_warn},
+    ssrf,
+    tls::{check_pins, TlsConfig},
+};
+
+/// How long a host that failed the QUIC handshake is sent over TCP.
+pub const BROKEN_HOST_TTL: Duration = Duration::from_secs(300);
+
+type Sender = SendRequest<h3_quinn::OpenStreams, Bytes>;
+
+/// Result of "Http3Client::send".
+pub(crate) enum Sent {
+    Response(Response<Body>),
+    /// The request to send over TCP instead.
+    Fallback(Request<Body>),
+}
+
+/// HTTP/3 connections of a manager, one per host and port.
+pub(crate) struct Http3Client {
+    config: quinn::ClientConfig,
+    /// Bound on the first request, since it requires the runtime.
+    endpoint: OnceCell<quinn::Endpoint>,
+    connections: Arc<Mutex<HashMap<String, (usize, Sender)>>>,
+    broken_hosts: Mutex<HashMap<String, Instant>>,
+    pins: Arc<Vec<[u8; 32]>>,
+    connect_timeout: Duration,
+    block_restricted_destinations: bool,
+}
+
+impl std::fmt::Debug for Http3Client {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Http3Client")
+            .field("connect_timeout", &self.connect_timeout)
+            .field(
+                "block_restricted_destinations",
+                &self.block_restricted_destinations,
+            )
+            .finish_non_exhaustive()
+    }
+}
+
+impl Http3Client {
+    pub(crate) fn new(
+        tls: &TlsConfig,
+        accept_invalid_certs: bool,
+        connect_timeout: Duration,
+        block_restricted_destinations: bool,
+    ) -> io::Result<Self> {
+        let crypto = quinn::crypto::rustls::QuicClientConfig::try_from(
+            tls.quic_client_config(accept_invalid_certs)?,
+        )
+        .map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("failed to build QUIC client config {}", e),
+            )
+        })?;
+        Ok(Self {
+            config: quinn::ClientConfig::new(Arc::new(crypto)),
+            endpoint: OnceCell::new(),
+            connections: Arc::new(Mutex::new(HashMap::new())),
+            broken_hosts: Mutex::new(HashMap::new()),
+            pins: Arc::new(tls.pins().to_vec()),
+            connect_timeout,
+            block_restricted_destinations,
+        })
+    }
+
+    /// Sends the request over HTTP/3, or returns it to be sent over TCP if
+    /// it is not an "https" request with a buffered body (streaming bodies
+    /// cannot be replayed), it is an upgrade (e.g., WebSocket), or the host
+    /// does not accept QUIC connections.
+    /// Fails if the request fails after the connection is established.
+    pub(crate) async fn send(&self, req: Request<Body>) -> io::Result<Sent> {
+        let (host, port) = match (req.uri().scheme(), req.uri().host()) {
+            (Some(scheme), Some(host)) if *scheme == Scheme::HTTPS => (
+                host.trim_start_matches('[')
+                    .trim_end_matches(']')
+                    .to_string(),
+                req.uri().port_u16().unwrap_or(443),
+            ),
+            _ => return Ok(Sent::Fallback(req)),
+        };
+        let key = format!("{}:{}", host, port);
+        if self.is_broken(&key)
+            || req.body().size_hint().exact().is_none()
+            || req.headers().contains_key(UPGRADE)
+        {
+            return Ok(Sent::Fallback(req));
+        }
+
+        let (parts, body) = req.into_parts();
+        let body = crate::buffer::collect(body).await.map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("failed to read request body {}", e),
+            )
+        })?;
+        let mut sender = match self.connection(&key, &host, port).await {
+            Ok(sender) => sender,
+            Err(e) => {
+                wire_warn!("falling back to TCP for {} ({})", key, e);
+                self.broken_hosts
+                    .lock()
+                    .unwrap()
+                    .insert(key, Instant::now());
+                return Ok(Sent::Fallback(Request::from_parts(parts, Body::from(body))));
+            }
+        };
+
+        let mut builder = http1::Request::builder()
+            .method(parts.method.as_str())
+            .uri(parts.uri.to_string());
+        for (name, v) in parts.headers.iter() {
+            // connection-specific headers are not allowed, and the host
+            // is sent as the ":authority"
+            // ref. https://www.rfc-editor.org/rfc/rfc9114#section-4.2
+            if !is_connection_specific(name.as_str()) {
+                builder = builder.header(name.as_str(), v.as_bytes());
+            }
+        }
+        let req = builder
+            .body(())
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, format!("invalid request {}", e)))?;
+
+        let mut stream = sender.send_request(req).await.map_err(h3_error)?;
+        if !body.is_empty() {
+            stream.send_data(body).await.map_err(h3_error)?;
+        }
+        stream.finish().await.map_err(h3_error)?;
+        let resp = stream.recv_response().await.map_err(h3_error)?;
+
+        let mut builder = Response::builder()
+            .status(resp.status().as_u16())
+            .version(Version::HTTP_3);
+        for (name, v) in resp.headers().iter() {
+            builder = builder.header(name.as_str(), v.as_bytes());
+        }
+        let (mut tx, body) = Body::channel();
+        tokio::spawn(async move {
+            loop {
+                match stream.recv_data().await {
+                    Ok(Some(mut chunk)) => {
+                        let b = chunk.copy_to_bytes(chunk.remaining());
+                        if tx.send_data(b).await.is_err() {
+                            break;
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        wire_debug!("failed to read HTTP/3 response body {}", e);
+                        tx.abort();
+                        break;
+                    }
+                }
+            }
+        });
+        let resp = builder
+            .body(body)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, format!("invalid response {}", e)))?;
+        Ok(Sent::Response(resp))
+    }
+
+    fn is_broken(&self, key: &str) -> bool {
+        let mut broken = self.broken_hosts.lock().unwrap();
+        match broken.get(key) {
+            Some(since) if since.elapsed() < BROKEN_HOST_TTL => true,
+            Some(_) => {
+                broken.remove(key);
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the open connection to the host, or connects.
+    async fn connection(&self, key: &str, host: &str, port: u16) -> io::Result<Sender> {
+        if let Some((_, sender)) = self.connections.lock().unwrap().get(key) {
+            return Ok(sender.clone());
+        }
+
+        let mut addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port)).await?.collect();
+        if self.block_restricted_destinations {
+            addrs.retain(|a| !ssrf::is_restricted_ip(a.ip()));
+        }
+        let addr = addrs
+            .first()
+            .copied()
+            .ok_or_else(|| Error::new(ErrorKind::PermissionDenied, "no allowed address"))?;
+
+        let connecting = self
+            .endpoint()?
+            .connect_with(self.config.clone(), addr, host)
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, e.to_string()))?;
+        let conn = match timeout(self.connect_timeout, connecting).await {
+            Ok(ret) => ret.map_err(|e| Error::new(ErrorKind::ConnectionRefused, e.to_string()))?,
+            Err(_) => {
+                return Err(Error::new(
+                    ErrorKind::TimedOut,
+                    format!("QUIC handshake timed out after {:?}", self.connect_timeout),
+                ))
+            }
+        };
+        if !self.pins.is_empty() {
+            let certs: Vec<Vec<u8>> = conn
+                .peer_identity()
+                .and_then(|id| id.downcast::<Vec<CertificateDer<'static>>>().ok())
+                .map(|certs| certs.iter().map(|c| c.to_vec()).collect())
+                .unwrap_or_default();
+            if let Err(e) = check_pins(host, &self.pins, &certs) {
+                conn.close(0u32.into(), b"");
+                return Err(crate::error::Error::PinMismatch(e).into());
+            }
+        }
+
+        let id = conn.stable_id();
+        let (mut driver, sender) = h3::client::new(h3_quinn::Connection::new(conn))
+            .await
+            .map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?;
+        let connections = self.connections.clone();
+        let driver_key = key.to_string();
+        tokio::spawn(async move {
+            let e = future::poll_fn(|cx| driver.poll_close(cx)).await;
+            wire_debug!("HTTP/3 connection to {} closed {}", driver_key, e);
+            let mut connections = connections.lock().unwrap();
+            if matches!(connections.get(&driver_key), Some((i, _)) if *i == id) {
+                connections.remove(&driver_key);
+            }
+        });
+        wire_debug!("connected to {} over HTTP/3", key);
+        self.connections
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), (id, sender.clone()));
+        Ok(sender)
+    }
+
+    /// Binds the UDP socket, dual-stack if IPv6 is available.
+    fn endpoint(&self) -> io::Result<&quinn::Endpoint> {
+        self.endpoint.get_or_try_init(|| {
+            quinn::Endpoint::client((Ipv6Addr::UNSPECIFIED, 0).into())
+                .or_else(|_| quinn::Endpoint::client((Ipv4Addr::UNSPECIFIED, 0).into()))
+        })
+    }
+}
+
+/// ref. https://www.rfc-editor.org/rfc/rfc9114#section-4.2
+fn is_connection_specific(name: &str) -> bool {
+    matches!(
+        name,
+        "host" | "connection" | "keep-alive" | "proxy-connection" | "transfer-encoding" | "upgrade"
+    )
+}
+
+fn h3_error(e: h3::error::StreamError) -> Error {
+    Error::new(ErrorKind::Other, format!("HTTP/3 request failed {}", e))
+}
+
+/// Serves "ok over h3" over HTTP/3 on the UDP port, returning the
+/// certificate and the received requests (method, path, and body).
+#[cfg(test)]
+async fn start_h3_server(port: u16) -> (Vec<u8>, Arc<Mutex<Vec<(String, String, Bytes)>>>) {
+    use rustls_quic::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
+
+    let cert =
+        rcgen::generate_simple_self_signed(vec!["localhost".to_string(), "127.0.0.1".to_string()])
+            .unwrap();
+    let cert_der = cert.serialize_der().unwrap();
+    let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(cert.serialize_private_key_der()));
+    let mut config = rustls_quic::ServerConfig::builder_with_provider(Arc::new(
+        rustls_quic::crypto::ring::default_provider(),
+    ))
+    .with_protocol_versions(&[&rustls_quic::version::TLS13])
+    .unwrap()
+    .with_no_client_auth()
+    .with_single_cert(vec![CertificateDer::from(cert_der.clone())], key)
+    .unwrap();
+    config.alpn_protocols = vec![b"h3".to_vec()];
+    let config = quinn::ServerConfig::with_crypto(Arc::new(
+        quinn::crypto::rustls::QuicServerConfig::try_from(config).unwrap(),
+    ));
+    let endpoint = quinn::Endpoint::server(config, (Ipv4Addr::LOCALHOST, port).into()).unwrap();
+
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let state = received.clone();
+    tokio::spawn(async move {
+        while let Some(incoming) = endpoint.accept().await {
+            let state = state.clone();
+            tokio::spawn(async move {
+                let conn = incoming.await.unwrap();
+                let mut conn: h3::server::Connection<_, Bytes> =
+                    h3::server::Connection::new(h3_quinn::Connection::new(conn))
+                        .await
+                        .unwrap();
+                while let Ok(Some(resolver)) = conn.accept().await {
+                    let (req, mut stream) = resolver.resolve_request().await.unwrap();
+                    let mut body = Vec::new();
+                    while let Some(mut chunk) = stream.recv_data().await.unwrap() {
+                        body.extend_from_slice(&chunk.copy_to_bytes(chunk.remaining()));
+                    }
+                    state.lock().unwrap().push((
+                        req.method().to_string(),
+                        req.uri().path().to_string(),
+                        Bytes::from(body),
+                    ));
+                    let resp = http1::Response::builder()
+                        .status(200)
+                        .header("x-transport", "h3")
+                        .body(())
+                        .unwrap();
+                    stream.send_response(resp).await.unwrap();
+                    stream
+                        .send_data(Bytes::from_static(b"ok over h3"))
+                        .await
+                        .unwrap();
+                    stream.finish().await.unwrap();
+                }
+            });
+        }
+    });
+    (cert_der, received)
+}
+
+/// RUST_LOG=debug cargo test --features http3 --lib -- http3::test_http3 --exact --show-output
+#[tokio::test]
+async fn test_http3() {
+    use hyper::Method;
+
+    // the same port over TCP and UDP, as CDNs serve both
+    let server = crate::testing::MockServer::start_https().await.unwrap();
+    server.stub(Method::GET, "/", 200, "ok over tcp");
+    let (h3_cert, received) = start_h3_server(server.addr().port()).await;
+    let url = format!("https://127.0.0.1:{}", server.addr().port());
+    let tls = TlsConfig::new()
+        .add_root_pem(server.cert_pem().unwrap())
+        .add_root_der(h3_cert);
+
+    let manager = crate::Manager::builder()
+        .tls_config(tls.clone())
+        .http3(true)
+        .build()
+        .unwrap();
+    let resp = manager
+        .send(crate::create_get(&url, "/").unwrap())
+        .await
+        .unwrap();
+    assert_eq!(resp.version(), Version::HTTP_3);
+    assert_eq!(resp.headers()["x-transport"], "h3");
+    let req = crate::create_json_post(&url, "/v1", "{\"a\":1}").unwrap();
+    assert_eq!(manager.read_bytes(req, true).await.unwrap(), "ok over h3");
+    {
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 2);
+        assert_eq!(
+            received[1],
+            (
+                "POST".to_string(),
+                "/v1".to_string(),
+                Bytes::from("{\"a\":1}")
+            )
+        );
+    }
+    assert!(server.received_requests().is_empty());
+
+    // HTTP/3 is opt-in
+    let manager = crate::Manager::builder()
+        .tls_config(tls.clone())
+        .build()
+        .unwrap();
+    let req = crate::create_get(&url, "/").unwrap();
+    assert_eq!(manager.read_bytes(req, true).await.unwrap(), "ok over tcp");
+
+    // falls back to TCP for servers without HTTP/3, and keeps using it
+    let tcp_only = crate::testing::MockServer::start_https().await.unwrap();
+    tcp_only.stub(Method::GET, "/", 200, "ok over tcp");
+    let manager = crate::Manager::builder()
+        .tls_config(TlsConfig::new().add_root_pem(tcp_only.cert_pem().unwrap()))
+        .connect_timeout(Duration::from_millis(300))
+        .http3(true)
+        .build()
+        .unwrap();
+    for _ in 0..2 {
+        let req = crate::create_get(tcp_only.url(), "/").unwrap();
+        let resp = manager.send(req).await.unwrap();
+        assert_eq!(resp.version(), Version::HTTP_11);
+    }
+    assert_eq!(tcp_only.received_requests().len(), 2);
+}
@@ -0,0 +1,746 @@
+//! HTTP Message Signatures (RFC 9421): signs outgoing requests (see
+//! "ManagerBuilder::message_signer") and verifies signed requests or
+//! responses. Only HMAC-SHA256 is built in; other algorithms (e.g.,
+//! "ed25519") plug in through "SigningKey".
+//! ref. https://www.rfc-editor.org/rfc/rfc9421
+
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{self, Error, ErrorKind},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hmac::{Hmac, Mac};
+use hyper::{
+    header::{HeaderValue, HOST},
+    HeaderMap, Method, Request, Response, StatusCode, Uri,
+};
+use sha2::{Digest, Sha256};
+
+pub const SIGNATURE_INPUT_HEADER: &str = "signature-input";
+pub const SIGNATURE_HEADER: &str = "signature";
+
+/// Raw parameters of a signature (e.g., ("keyid", "\"k1\"")).
+type Params = Vec<(String, String)>;
+
+/// Signature algorithm with its key.
+pub trait SigningKey: Send + Sync + fmt::Debug {
+    /// Algorithm name of the "alg" parameter (e.g., "hmac-sha256").
+    fn algorithm(&self) -> &str;
+    fn sign(&self, data: &[u8]) -> io::Result<Vec<u8>>;
+    fn verify(&self, data: &[u8], signature: &[u8]) -> bool;
+}
+
+/// "hmac-sha256" with a shared secret.
+pub struct HmacSha256Key(Vec<u8>);
+
+impl HmacSha256Key {
+    pub fn new(secret: impl Into<Vec<u8>>) -> Self {
+        Self(secret.into())
+    }
+
+    fn mac(&self, data: &[u8]) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.0).expect("HMAC accepts keys of any size");
+        mac.update(data);
+        mac
+    }
+}
+
+impl fmt::Debug for HmacSha256Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("HmacSha256Key(<redacted>)")
+    }
+}
+
+impl SigningKey for HmacSha256Key {
+    fn algorithm(&self) -> &str {
+        "hmac-sha256"
+    }
+
+    fn sign(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        Ok(self.mac(data).finalize().into_bytes().to_vec())
+    }
+
+    fn verify(&self, data: &[u8], signature: &[u8]) -> bool {
+        // constant-time comparison
+        self.mac(data).verify_slice(signature).is_ok()
+    }
+}
+
+/// Returns the "Content-Digest" header value (SHA-256) of the body, to
+/// cover the body with a signature.
+/// ref. https://www.rfc-editor.org/rfc/rfc9530
+pub fn content_digest(body: &[u8]) -> String {
+    format!("sha-256=:{}:", STANDARD.encode(Sha256::digest(body)))
+}
+
+/// Signs requests with the "Signature-Input" and "Signature" headers.
+#[derive(Debug, Clone)]
+pub struct MessageSigner {
+    label: String,
+    key_id: String,
+    key: Arc<dyn SigningKey>,
+    components: Vec<String>,
+    expires_in: Option<Duration>,
+    tag: Option<String>,
+    include_alg: bool,
+}
+
+impl MessageSigner {
+    /// Covers "@method", "@authority", and "@request-target" by default.
+    pub fn new(key_id: &str, key: Arc<dyn SigningKey>) -> Self {
+        Self {
+            label: "sig1".to_string(),
+            key_id: key_id.to_string(),
+            key,
+            components: vec![
+                "@method".to_string(),
+                "@authority".to_string(),
+                "@request-target".to_string(),
+            ],
+            expires_in: None,
+            tag: None,
+            include_alg: false,
+        }
+    }
+
+    pub fn label(mut self, label: &str) -> Self {
+        self.label = label.to_string();
+        self
+    }
+
+    /// Sets the covered components: derived ones (e.g., "@method",
+    /// "@target-uri", "@path", "@query", "@status") and header names
+    /// (e.g., "content-digest"). A missing header fails the signing.
+    pub fn components(mut self, components: &[&str]) -> Self {
+        self.components = components.iter().map(|c| c.to_ascii_lowercase()).collect();
+        self
+    }
+
+    /// Sets the "expires" parameter to "created" plus the duration.
+    pub fn expires_in(mut self, d: Duration) -> Self {
+        self.expires_in = Some(d);
+        self
+    }
+
+    /// Sets the application-specific "tag" parameter.
+    pub fn tag(mut self, tag: &str) -> Self {
+        self.tag = Some(tag.to_string());
+        self
+    }
+
+    /// Includes the "alg" parameter (left out by default, since the
+    /// verifier derives the algorithm from the key).
+    pub fn include_alg(mut self, include: bool) -> Self {
+        self.include_alg = include;
+        self
+    }
+
+    pub fn sign_request<B>(&self, req: &mut Request<B>) -> io::Result<()> {
+        self.sign_request_at(req, unix_now())
+    }
+
+    /// Signs the request as created at the Unix time (in seconds).
+    pub fn sign_request_at<B>(&self, req: &mut Request<B>, created: u64) -> io::Result<()> {
+        let mut params = vec![("created".to_string(), created.to_string())];
+        if let Some(d) = self.expires_in {
+            params.push(("expires".to_string(), (created + d.as_secs()).to_string()));
+        }
+        if self.include_alg {
+            params.push(("alg".to_string(), quote(self.key.algorithm())));
+        }
+        params.push(("keyid".to_string(), quote(&self.key_id)));
+        if let Some(t) = &self.tag {
+            params.push(("tag".to_string(), quote(t)));
+        }
+        let signature_params = serialize_params(&self.components, &params);
+
+        let msg = Message::Request {
+            method: req.method(),
+            uri: req.uri(),
+            headers: req.headers(),
+        };
+        let base = signature_base(&msg, &self.components, &signature_params)?;
+        let signature = self.key.sign(base.as_bytes())?;
+
+        let invalid = |e| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid signature header {}", e),
+            )
+        };
+        let input = HeaderValue::from_str(&format!("{}={}", self.label, signature_params))
+            .map_err(invalid)?;
+        let signature =
+            HeaderValue::from_str(&format!("{}=:{}:", self.label, STANDARD.encode(signature)))
+                .map_err(invalid)?;
+        req.headers_mut().insert(SIGNATURE_INPUT_HEADER, input);
+        req.headers_mut().insert(SIGNATURE_HEADER, signature);
+        Ok(())
+    }
+}
+
+/// Signature that passed verification.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifiedSignature {
+    pub label: String,
+    pub key_id: String,
+    pub components: Vec<String>,
+    pub created: Option<u64>,
+    pub expires: Option<u64>,
+    pub tag: Option<String>,
+}
+
+/// Verifies the signatures of requests (e.g., received webhooks) or
+/// responses against the known keys.
+#[derive(Debug, Clone, Default)]
+pub struct MessageVerifier {
+    keys: HashMap<String, Arc<dyn SigningKey>>,
+    required_components: Vec<String>,
+    max_age: Option<Duration>,
+}
+
+impl MessageVerifier {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn key(mut self, key_id: &str, key: Arc<dyn SigningKey>) -> Self {
+        self.keys.insert(key_id.to_string(), key);
+        self
+    }
+
+    /// Rejects signatures that do not cover all of the components.
+    pub fn required_components(mut self, components: &[&str]) -> Self {
+        self.required_components = components.iter().map(|c| c.to_ascii_lowercase()).collect();
+        self
+    }
+
+    /// Rejects signatures created longer ago than the duration (or
+    /// without "created").
+    pub fn max_age(mut self, d: Duration) -> Self {
+        self.max_age = Some(d);
+        self
+    }
+
+    pub fn verify_request<B>(&self, req: &Request<B>) -> io::Result<VerifiedSignature> {
+        self.verify_request_at(req, unix_now())
+    }
+
+    pub fn verify_request_at<B>(
+        &self,
+        req: &Request<B>,
+        now: u64,
+    ) -> io::Result<VerifiedSignature> {
+        let msg = Message::Request {
+            method: req.method(),
+            uri: req.uri(),
+            headers: req.headers(),
+        };
+        self.verify(&msg, now)
+    }
+
+    pub fn verify_response<B>(&self, resp: &Response<B>) -> io::Result<VerifiedSignature> {
+        self.verify_response_at(resp, unix_now())
+    }
+
+    pub fn verify_response_at<B>(
+        &self,
+        resp: &Response<B>,
+        now: u64,
+    ) -> io::Result<VerifiedSignature> {
+        let msg = Message::Response {
+            status: resp.status(),
+            headers: resp.headers(),
+        };
+        self.verify(&msg, now)
+    }
+
+    /// Returns the first signature that verifies, or the error of the
+    /// last one.
+    fn verify(&self, msg: &Message<'_>, now: u64) -> io::Result<VerifiedSignature> {
+        let headers = msg.headers();
+        let inputs = parse_dictionary(&combined_header(headers, SIGNATURE_INPUT_HEADER)?)?;
+        let signatures = parse_dictionary(&combined_header(headers, SIGNATURE_HEADER)?)?;
+        let mut last_err = invalid_signature("no signature".to_string());
+        for (label, input) in inputs.iter() {
+            match self.verify_one(msg, label, input, &signatures, now) {
+                Ok(v) => return Ok(v),
+                Err(e) => last_err = e,
+            }
+        }
+        Err(last_err)
+    }
+
+    fn verify_one(
+        &self,
+        msg: &Message<'_>,
+        label: &str,
+        input: &str,
+        signatures: &[(String, String)],
+        now: u64,
+    ) -> io::Result<VerifiedSignature> {
+        let (components, params) = parse_inner_list(input)?;
+        let param = |k: &str| {
+            params
+                .iter()
+                .find(|(pk, _)| pk == k)
+                .map(|(_, v)| v.as_str())
+        };
+        let int_param = |k: &str| -> io::Result<Option<u64>> {
+            param(k)
+                .map(|v| {
+                    v.parse::<u64>()
+                        .map_err(|_| invalid_signature(format!("invalid '{}' parameter", k)))
+                })
+                .transpose()
+        };
+        let key_id = param("keyid")
+            .map(unquote)
+            .ok_or_else(|| invalid_signature(format!("signature '{}' without keyid", label)))?;
+        let key = self
+            .keys
+            .get(&key_id)
+            .ok_or_else(|| invalid_signature(format!("unknown key '{}'", key_id)))?;
+        if let Some(alg) = param("alg").map(unquote) {
+            if alg != key.algorithm() {
+                return Err(invalid_signature(format!(
+                    "algorithm '{}' does not match the key",
+                    alg
+                )));
+            }
+        }
+        for c in self.required_components.iter() {
+            if !components.contains(c) {
+                return Err(invalid_signature(format!(
+                    "signature '{}' does not cover '{}'",
+                    label, c
+                )));
+            }
+        }
+        let created = int_param("created")?;
+        let expires = int_param("expires")?;
+        if let Some(e) = expires {
+            if now >= e {
+                return Err(invalid_signature(format!("signature '{}' expired", label)));
+            }
+        }
+        if let Some(max_age) = self.max_age {
+            match created {
+                Some(c) if c <= now + 60 && now.saturating_sub(c) <= max_age.as_secs() => {}
+                _ => {
+                    return Err(invalid_signature(format!(
+                        "signature '{}' is too old or not dated",
+                        label
+                    )))
+                }
+            }
+        }
+
+        let signature = signatures
+            .iter()
+            .find(|(l, _)| l == label)
+            .map(|(_, v)| v.as_str())
+            .and_then(|v| v.strip_prefix(':').and_then(|v| v.strip_suffix(':')))
+            .and_then(|v| STANDARD.decode(v).ok())
+            .ok_or_else(|| {
+                invalid_signature(format!("missing or invalid signature '{}'", label))
+            })?;
+        let base = signature_base(msg, &components, input)?;
+        if !key.verify(base.as_bytes(), &signature) {
+            return Err(invalid_signature(format!(
+                "signature '{}' does not match",
+                label
+            )));
+        }
+        Ok(VerifiedSignature {
+            label: label.to_string(),
+            key_id,
+            components,
+            created,
+            expires,
+            tag: param("tag").map(unquote),
+        })
+    }
+}
+
+enum Message<'a> {
+    Request {
+        method: &'a Method,
+        uri: &'a Uri,
+        headers: &'a HeaderMap,
+    },
+    Response {
+        status: StatusCode,
+        headers: &'a HeaderMap,
+    },
+}
+
+impl Message<'_> {
+    fn headers(&self) -> &HeaderMap {
+        match self {
+            Message::Request { headers, .. } => headers,
+            Message::Response { headers, .. } => headers,
+        }
+    }
+
+    /// Returns the value of the component.
+    /// ref. https://www.rfc-editor.org/rfc/rfc9421#section-2.2
+    fn component(&self, name: &str) -> io::Result<String> {
+        let unsupported = || {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("component '{}' is not available", name),
+            )
+        };
+        if !name.starts_with('@') {
+            return combined_header(self.headers(), name)?.ok_or_else(unsupported);
+        }
+        match (self, name) {
+            (Message::Response { status, .. }, "@status") => Ok(status.as_str().to_string()),
+            (Message::Request { method, .. }, "@method") => Ok(method.as_str().to_string()),
+            (Message::Request { uri, headers, .. }, _) => {
+                let authority = || -> io::Result<String> {
+                    let a = match uri.authority() {
+                        Some(a) => a.as_str().to_string(),
+                        None => headers
+                            .get(HOST)
+                            .and_then(|v| v.to_str().ok())
+                            .ok_or_else(unsupported)?
+                            .to_string(),
+                    };
+                    let a = a.to_ascii_lowercase();
+                    // the default port is left out
+                    let default_port = match uri.scheme_str() {
+                        Some("https") => ":443",
+                        _ => ":80",
+                    };
+                    Ok(a.strip_suffix(default_port)
+                        .map(|a| a.to_string())
+                        .unwrap_or(a))
+                };
+                let path = match uri.path() {
+                    "" => "/",
+                    p => p,
+                };
+                match name {
+                    "@target-uri" => Ok(uri.to_string()),
+                    "@authority" => authority(),
+                    "@scheme" => uri
+                        .scheme_str()
+                        .map(|s| s.to_ascii_lowercase())
+                        .ok_or_else(unsupported),
+                    "@request-target" => Ok(match uri.query() {
+                        Some(q) => format!("{}?{}", path, q),
+                        None => path.to_string(),
+                    }),
+                    "@path" => Ok(path.to_string()),
+                    "@query" => Ok(format!("?{}", uri.query().unwrap_or(""))),
+                    _ => Err(unsupported()),
+                }
+            }
+            _ => Err(unsupported()),
+        }
+    }
+}
+
+/// Returns the signature base to sign or verify.
+/// ref. https://www.rfc-editor.org/rfc/rfc9421#section-2.5
+fn signature_base(
+    msg: &Message<'_>,
+    components: &[String],
+    signature_params: &str,
+) -> io::Result<String> {
+    let mut base = String::new();
+    for (i, c) in components.iter().enumerate() {
+        if components[..i].contains(c) {
+            return Err(invalid_signature(format!("duplicate component '{}'", c)));
+        }
+        base.push_str(&format!("\"{}\": {}\n", c, msg.component(c)?));
+    }
+    base.push_str(&format!("\"@signature-params\": {}", signature_params));
+    Ok(base)
+}
+
+/// Serializes the inner list of components with its parameters
+/// (e.g., '("@method" "@path");created=1618884473;keyid="k"').
+fn serialize_params(components: &[String], params: &[(String, String)]) -> String {
+    let mut s = format!(
+        "({})",
+        components
+            .iter()
+            .map(|c| quote(c))
+            .collect::<Vec<_>>()
+            .join(" ")
+    );
+    for (k, v) in params.iter() {
+        s.push_str(&format!(";{}={}", k, v));
+    }
+    s
+}
+
+/// Returns the values of the header, combined with ", ".
+fn combined_header(headers: &HeaderMap, name: &str) -> io::Result<Option<String>> {
+    let mut values = Vec::new();
+    for v in headers.get_all(name).iter() {
+        let v = v.to_str().map_err(|_| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("header '{}' is not visible ASCII", name),
+            )
+        })?;
+        values.push(v.trim());
+    }
+    if values.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(values.join(", ")))
+}
+
+/// Splits a structured field dictionary into its members (keys and raw
+/// values), honoring quotes and parentheses.
+fn parse_dictionary(v: &Option<String>) -> io::Result<Vec<(String, String)>> {
+    let v = match v {
+        Some(v) => v,
+        None => return Err(invalid_signature("missing signature headers".to_string())),
+    };
+    let mut members = Vec::new();
+    let mut depth = 0;
+    let mut quoted = false;
+    let mut start = 0;
+    let bytes = v.as_bytes();
+    for i in 0..=bytes.len() {
+        let c = bytes.get(i).copied();
+        match c {
+            Some(b'"') if i == 0 || bytes[i - 1] != b'\\' => quoted = !quoted,
+            Some(b'(') if !quoted => depth += 1,
+            Some(b')') if !quoted => depth -= 1,
+            Some(b',') | None if !quoted && depth == 0 => {
+                let member = v[start..i].trim();
+                if !member.is_empty() {
+                    let (k, val) = member
+                        .split_once('=')
+                        .ok_or_else(|| invalid_signature(format!("invalid member '{}'", member)))?;
+                    members.push((k.trim().to_string(), val.trim().to_string()));
+                }
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    Ok(members)
+}
+
+/// Parses '("a" "b");k=v;k2="v2"' into the items and the (raw) parameters.
+fn parse_inner_list(v: &str) -> io::Result<(Vec<String>, Params)> {
+    let invalid = || invalid_signature(format!("invalid signature input '{}'", v));
+    let rest = v.trim().strip_prefix('(').ok_or_else(invalid)?;
+    let close = rest.find(')').ok_or_else(invalid)?;
+    let mut items = Vec::new();
+    for item in rest[..close].split_whitespace() {
+        // component parameters (e.g., ";sf", ";req") are not supported
+        let name = item
+            .strip_prefix('"')
+            .and_then(|i| i.strip_suffix('"'))
+            .ok_or_else(invalid)?;
+        items.push(name.to_string());
+    }
+    let mut params = Vec::new();
+    for p in rest[close + 1..].split(';').skip(1) {
+        let (k, v) = p.split_once('=').ok_or_else(invalid)?;
+        params.push((k.trim().to_string(), v.trim().to_string()));
+    }
+    Ok((items, params))
+}
+
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn unquote(s: &str) -> String {
+    s.strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .unwrap_or(s)
+        .replace("\\\"", "\"")
+        .replace("\\\\", "\\")
+}
+
+fn invalid_signature(msg: String) -> Error {
+    Error::new(ErrorKind::PermissionDenied, msg)
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// ref. https://www.rfc-editor.org/rfc/rfc9421#appendix-B.2.5
+#[test]
+fn test_rfc9421_hmac_sha256() {
+    let secret = STANDARD
+        .decode("uzvJfB4u3N0Jy4T7NZ75MDVcr8zSTInedJtkgcu46YW4XByzNJjxBdtjUkdJPBtbmHhIDi6pcl8jsasjlTMtDQ==")
+        .unwrap();
+    let key: Arc<dyn SigningKey> = Arc::new(HmacSha256Key::new(secret));
+    let new_req = || {
+        Request::builder()
+            .method(Method::POST)
+            .uri("/foo?param=Value&Pet=dog")
+            .header("host", "example.com")
+            .header("date", "Tue, 20 Apr 2021 02:07:55 GMT")
+            .header("content-type", "application/json")
+            .body(())
+            .unwrap()
+    };
+
+    let signer = MessageSigner::new("test-shared-secret", key.clone())
+        .label("sig-b25")
+        .components(&["date", "@authority", "content-type"]);
+    let mut req = new_req();
+    signer.sign_request_at(&mut req, 1618884473).unwrap();
+    assert_eq!(
+        req.headers()[SIGNATURE_INPUT_HEADER],
+        r#"sig-b25=("date" "@authority" "content-type");created=1618884473;keyid="test-shared-secret""#
+    );
+    assert_eq!(
+        req.headers()[SIGNATURE_HEADER],
+        "sig-b25=:pxcQw6G3AjtMBQjwo8XzkZf/bws5LelbaMk5rGIGtE8=:"
+    );
+
+    let verifier = MessageVerifier::new()
+        .key("test-shared-secret", key.clone())
+        .required_components(&["@authority"]);
+    let v = verifier.verify_request_at(&req, 1618884480).unwrap();
+    assert_eq!(v.key_id, "test-shared-secret");
+    assert_eq!(v.created, Some(1618884473));
+
+    // tampered
+    req.headers_mut()
+        .insert("content-type", HeaderValue::from_static("text/plain"));
+    let e = verifier.verify_request_at(&req, 1618884480).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::PermissionDenied);
+
+    // missing required component, unknown key, too old
+    let mut req = new_req();
+    signer.sign_request_at(&mut req, 1618884473).unwrap();
+    assert!(MessageVerifier::new()
+        .key("test-shared-secret", key.clone())
+        .required_components(&["@method"])
+        .verify_request_at(&req, 1618884480)
+        .is_err());
+    assert!(MessageVerifier::new()
+        .key("other", key.clone())
+        .verify_request_at(&req, 1618884480)
+        .is_err());
+    assert!(MessageVerifier::new()
+        .key("test-shared-secret", key.clone())
+        .max_age(Duration::from_secs(60))
+        .verify_request_at(&req, 1618884473 + 3600)
+        .is_err());
+    assert!(verifier.verify_request_at(&new_req(), 0).is_err());
+}
+
+#[test]
+fn test_message_signer_response() {
+    let key: Arc<dyn SigningKey> = Arc::new(HmacSha256Key::new("secret"));
+    let signer = MessageSigner::new("k1", key.clone())
+        .components(&[
+            "@method",
+            "@target-uri",
+            "@path",
+            "@query",
+            "content-digest",
+        ])
+        .expires_in(Duration::from_secs(300))
+        .tag("app")
+        .include_alg(true);
+    let mut req = Request::builder()
+        .method(Method::PUT)
+        .uri("https://Example.com:443/a/b?x=1")
+        .header("content-digest", content_digest(b"{}"))
+        .body(())
+        .unwrap();
+    signer.sign_request_at(&mut req, 1000).unwrap();
+    let input = req.headers()[SIGNATURE_INPUT_HEADER].to_str().unwrap();
+    assert!(input.ends_with(r#";created=1000;expires=1300;alg="hmac-sha256";keyid="k1";tag="app""#));
+    let verifier = MessageVerifier::new().key("k1", key.clone());
+    let v = verifier.verify_request_at(&req, 1200).unwrap();
+    assert_eq!(v.tag.as_deref(), Some("app"));
+    assert_eq!(v.expires, Some(1300));
+    assert!(verifier.verify_request_at(&req, 1300).is_err());
+
+    // the header to cover is missing
+    let mut req = Request::builder()
+        .uri("https://example.com/")
+        .body(())
+        .unwrap();
+    assert!(signer.sign_request_at(&mut req, 1000).is_err());
+
+    // "@status" of a response, with another signature label first
+    let msg = Message::Response {
+        status: StatusCode::OK,
+        headers: &HeaderMap::new(),
+    };
+    let components = vec!["@status".to_string()];
+    let params = serialize_params(&components, &[("keyid".to_string(), quote("k1"))]);
+    let base = signature_base(&msg, &components, &params).unwrap();
+    assert_eq!(
+        base,
+        "\"@status\": 200\n\"@signature-params\": (\"@status\");keyid=\"k1\""
+    );
+    let resp = Response::builder()
+        .status(200)
+        .header(
+            SIGNATURE_INPUT_HEADER,
+            format!("other=(\"@status\");keyid=\"x\", sig1={}", params),
+        )
+        .header(
+            SIGNATURE_HEADER,
+            format!(
+                "other=:AAAA:, sig1=:{}:",
+                STANDARD.encode(key.sign(base.as_bytes()).unwrap())
+            ),
+        )
+        .body(())
+        .unwrap();
+    assert_eq!(verifier.verify_response_at(&resp, 0).unwrap().label, "sig1");
+}
+
+/// RUST_LOG=debug cargo test --lib -- httpsig::test_manager_message_signer --exact --show-output
+#[tokio::test]
+async fn test_manager_message_signer() {
+    use hyper::Method;
+
+    use crate::Manager;
+
+    let server = crate::testing::MockServer::start().await.unwrap();
+    server.stub(Method::GET, "/resource", 200, "ok");
+
+    let key: Arc<dyn SigningKey> = Arc::new(HmacSha256Key::new("secret"));
+    let manager = Manager::builder()
+        .message_signer(MessageSigner::new("k1", key.clone()))
+        .build()
+        .unwrap();
+    let req = crate::create_get(server.url(), "/resource?a=1").unwrap();
+    manager.read_response(req).await.unwrap();
+
+    let received = server.received_requests().pop().unwrap();
+    let mut req = Request::builder()
+        .method(received.method)
+        .uri(received.uri)
+        .body(())
+        .unwrap();
+    *req.headers_mut() = received.headers;
+    let v = MessageVerifier::new()
+        .key("k1", key)
+        .required_components(&["@method", "@authority", "@request-target"])
+        .max_age(Duration::from_secs(60))
+        .verify_request(&req)
+        .unwrap();
+    assert_eq!(v.key_id, "k1");
+}
@@ -0,0 +1,105 @@
+//! Internationalized domain names (IDN).
+//! Hostnames are always connected to in their ASCII (punycode) form, and
+//! shown in both forms in logs and errors.
+
+use std::io::{self, Error, ErrorKind};
+
+use unicode_script::{Script, UnicodeScript};
+
+/// Converts the hostname to its ASCII (punycode) form
+/// (e.g., "münchen.de" to "xn--mnchen-3ya.de").
+pub fn to_ascii(host: &str) -> io::Result<String> {
+    idna::domain_to_ascii(host).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("failed to convert host '{}' to punycode {}", host, e),
+        )
+    })
+}
+
+/// Converts the hostname to its Unicode form
+/// (e.g., "xn--mnchen-3ya.de" to "münchen.de").
+/// Labels that fail to decode are kept as is.
+pub fn to_unicode(host: &str) -> String {
+    idna::domain_to_unicode(host).0
+}
+
+/// Returns the hostname for logs and errors: "ascii (unicode)" when the
+/// two forms differ, the ASCII form otherwise.
+pub fn display_host(host: &str) -> String {
+    let ascii = to_ascii(host).unwrap_or_else(|_| host.to_string());
+    let unicode = to_unicode(&ascii);
+    if unicode == ascii {
+        ascii
+    } else {
+        format!("{} ({})", ascii, unicode)
+    }
+}
+
+/// Returns true if any label of the hostname mixes Unicode scripts in a
+/// way that is typical of homograph attacks (e.g., Cyrillic "а" in an
+/// otherwise Latin "pаypal.com"). Latin combined with the CJK scripts
+/// used together in Japanese, Chinese, or Korean is allowed, per the
+/// "highly restrictive" profile of Unicode TS #39.
+pub fn is_mixed_script(host: &str) -> bool {
+    let unicode = to_unicode(host);
+    unicode.split('.').any(|label| {
+        let mut scripts: Vec<Script> = Vec::new();
+        for s in label.chars().map(|c| c.script()) {
+            if s != Script::Common && s != Script::Inherited && !scripts.contains(&s) {
+                scripts.push(s);
+            }
+        }
+        if scripts.len() <= 1 {
+            return false;
+        }
+
+        let allowed_sets: [&[Script]; 3] = [
+            &[
+                Script::Latin,
+                Script::Han,
+                Script::Hiragana,
+                Script::Katakana,
+            ],
+            &[Script::Latin, Script::Han, Script::Bopomofo],
+            &[Script::Latin, Script::Han, Script::Hangul],
+        ];
+        !allowed_sets
+            .iter()
+            .any(|set| scripts.iter().all(|s| set.contains(s)))
+    })
+}
+
+/// Fails if the hostname mixes scripts (see "is_mixed_script").
+pub fn check_mixed_script(host: &str) -> io::Result<()> {
+    if is_mixed_script(host) {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            format!("host {} mixes Unicode scripts", display_host(host)),
+        ));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_idn() {
+    assert_eq!(to_ascii("münchen.de").unwrap(), "xn--mnchen-3ya.de");
+    assert_eq!(to_ascii("Example.COM").unwrap(), "example.com");
+    assert_eq!(to_unicode("xn--mnchen-3ya.de"), "münchen.de");
+
+    assert_eq!(display_host("münchen.de"), "xn--mnchen-3ya.de (münchen.de)");
+    assert_eq!(
+        display_host("xn--mnchen-3ya.de"),
+        "xn--mnchen-3ya.de (münchen.de)"
+    );
+    assert_eq!(display_host("example.com"), "example.com");
+
+    // Cyrillic "а" (U+0430) in a Latin label
+    assert!(is_mixed_script("p\u{0430}ypal.com"));
+    assert!(is_mixed_script(&to_ascii("p\u{0430}ypal.com").unwrap()));
+    assert!(check_mixed_script("p\u{0430}ypal.com").is_err());
+    assert!(!is_mixed_script("münchen.de"));
+    assert!(!is_mixed_script("пример.рф"));
+    assert!(!is_mixed_script("ドメイン名例abc.jp"));
+    assert!(!is_mixed_script("example.com"));
+}
@@ -0,0 +1,453 @@
+//! Interim 1xx responses (e.g., "103 Early Hints"), which hyper reads and
+//! discards on the client side.
+
+use std::{
+    io::{self, Error, ErrorKind},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use hyper::{
+    body::Bytes,
+    client::conn,
+    header::{HeaderName, HeaderValue, HOST, LINK},
+    Body, HeaderMap, Request, Response, StatusCode,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    sync::mpsc,
+};
+use url::Url;
+
+use crate::{
+    decompress, error,
+    logging::wire_debug,
+    probe::Io,
+    traffic::{self, TransferSize},
+    Manager,
+};
+
+/// Response heads larger than this are not inspected.
+const MAX_HEAD_SIZE: usize = 64 * 1024;
+const MAX_HEADERS: usize = 100;
+
+/// Interim (1xx) response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Informational {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+}
+
+impl Informational {
+    /// Returns the targets of the "Link" headers with "rel=preload"
+    /// (e.g., "/style.css" from '</style.css>; rel=preload; as=style').
+    pub fn preload_links(&self) -> Vec<String> {
+        self.headers
+            .get_all(LINK)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(parse_links)
+            .filter(|(_, rels)| rels.iter().any(|r| r.eq_ignore_ascii_case("preload")))
+            .map(|(target, _)| target)
+            .collect()
+    }
+}
+
+/// Parses a "Link" header value into the targets and their "rel" values.
+/// ref. https://www.rfc-editor.org/rfc/rfc8288#section-3
+pub(crate) fn parse_links(v: &str) -> Vec<(String, Vec<String>)> {
+    let mut links = Vec::new();
+    let mut rest = v;
+    while let Some(open) = rest.find('<') {
+        let Some(close) = rest[open..].find('>') else {
+            break;
+        };
+        let target = rest[open + 1..open + close].trim().to_string();
+        rest = &rest[open + close + 1..];
+        // the parameters run up to the next link, outside of quotes
+        let mut end = rest.len();
+        let mut quoted = false;
+        for (i, c) in rest.char_indices() {
+            match c {
+                '"' => quoted = !quoted,
+                ',' if !quoted => {
+                    end = i;
+                    break;
+                }
+                _ => {}
+            }
+        }
+        let rels = rest[..end]
+            .split(';')
+            .filter_map(|p| p.split_once('='))
+            .filter(|(k, _)| k.trim().eq_ignore_ascii_case("rel"))
+            .flat_map(|(_, v)| {
+                v.trim()
+                    .trim_matches('"')
+                    .split_whitespace()
+                    .map(|r| r.to_string())
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        links.push((target, rels));
+        rest = &rest[end..];
+    }
+    links
+}
+
+impl Manager {
+    /// Sends the request over a new connection, calling "on_informational"
+    /// for every interim 1xx response (e.g., "103 Early Hints" to start
+    /// preloading), and returns the final response (decoded as "read_bytes"
+    /// does). HTTP/1.1 only. The request goes through the same policies,
+    /// default headers, cookies, signers, traffic counters, and concurrency
+    /// limiter as "send", but not through the connection pool: requests to
+    /// the URLs with a proxy are rejected, as the interim responses are not
+    /// relayed by every proxy, and so are scoped IPv6 hosts.
+    pub async fn send_with_informational<F>(
+        &self,
+        req: Request<Body>,
+        mut on_informational: F,
+    ) -> io::Result<Response<Bytes>>
+    where
+        F: FnMut(Informational),
+    {
+        if req.uri().host().map_or(false, |h| h.contains('%')) {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!(
+                    "informational responses are not supported for the scoped IPv6 host {}",
+                    req.uri().host().unwrap_or("")
+                ),
+            ));
+        }
+        if let Some(p) = self.proxy_config().proxy_for(req.uri()) {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!(
+                    "informational responses are not supported through the proxy {}",
+                    p.authority()
+                ),
+            ));
+        }
+        let timeout_dur = self.timeout_for(req.uri());
+        let (mut req, url) = self.prepare_request(req).await?;
+        self.set_accept_encoding(&mut req);
+        let host = traffic::host_key(req.uri());
+        let req = origin_form(req, &url)?;
+        let request_header_bytes = traffic::request_head_size(&req);
+        let request_body_bytes = traffic::request_body_size(&req);
+
+        let permit = match self.limiter() {
+            Some(l) => Some(l.acquire().await),
+            None => None,
+        };
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let ret = tokio::time::timeout(timeout_dur, async {
+            let c = self.connect(&url).await?;
+            let tap = Tap {
+                inner: c.stream,
+                head: Vec::new(),
+                done: false,
+                tx,
+            };
+            let (mut sender, connection) = conn::handshake(tap).await.map_err(|e| {
+                Error::new(ErrorKind::Other, format!("failed HTTP handshake {}", e))
+            })?;
+            let connection = tokio::spawn(connection);
+
+            let ret = async {
+                let send = sender.send_request(req);
+                tokio::pin!(send);
+                // the interim responses arrive before the final one
+                let resp = loop {
+                    tokio::select! {
+                        biased;
+                        Some(i) = rx.recv() => on_informational(i),
+                        resp = &mut send => break resp,
+                    }
+                };
+                while let Ok(i) = rx.try_recv() {
+                    on_informational(i);
+                }
+                let resp = resp.map_err(|e| {
+                    let prefix = format!(
+                        "failed to fetch response from {}",
+                        url.host_str().unwrap_or("")
+                    );
+                    Error::from(error::Error::from_hyper(&prefix, &e))
+                })?;
+                let (parts, body) = resp.into_parts();
+                let body = crate::buffer::collect(body).await.map_err(|e| {
+                    Error::from(error::Error::Body(format!("failed to read response {}", e)))
+                })?;
+                Ok(Response::from_parts(parts, body))
+            }
+            .await;
+            connection.abort();
+            ret
+        })
+        .await;
+        let ret: io::Result<Response<Bytes>> = match ret {
+            Ok(ret) => ret,
+            Err(e) => Err(error::Error::Timeout(format!(
+                "failed to fetch response from {} within {:?} {}",
+                url.host_str().unwrap_or(""),
+                timeout_dur,
+                e
+            ))
+            .into()),
+        };
+        if let (Some(p), Some(l)) = (permit, self.limiter()) {
+            let status = ret.as_ref().ok().map(|r| r.status().as_u16());
+            let outcome = l.classify(status, p.elapsed());
+            p.record(outcome);
+        }
+
+        let resp = ret?;
+        let response_header_bytes = traffic::response_head_size(&resp);
+        let (mut parts, body) = resp.into_parts();
+        if let Some(jar) = self.cookie_jar() {
+            jar.store(&url, &parts.headers);
+        }
+        let size = TransferSize {
+            request_header_bytes,
+            request_body_bytes,
+            response_header_bytes,
+            response_body_bytes: body.len() as u64,
+        };
+        self.traffic()
+            .record_request(&host, size.sent(), size.received());
+        parts.extensions.insert(size);
+        let body =
+            decompress::decode_response(&mut parts.headers, body, self.decompression_limits())?;
+        Ok(Response::from_parts(parts, body))
+    }
+}
+
+/// Rewrites the URI to the origin form ("/path?query") with the "Host"
+/// header, for a request over a connection of its own.
+fn origin_form(mut req: Request<Body>, url: &Url) -> io::Result<Request<Body>> {
+    let host = url.host_str().unwrap_or("");
+    let mut path = url.path().to_string();
+    if let Some(q) = url.query() {
+        path.push('?');
+        path.push_str(q);
+    }
+    *req.uri_mut() = path.parse().map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("failed to parse request path {}", e),
+        )
+    })?;
+    if !req.headers().contains_key(HOST) {
+        let authority = match url.port() {
+            Some(p) => format!("{}:{}", host, p),
+            None => host.to_string(),
+        };
+        let v = HeaderValue::from_str(&authority)
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, format!("invalid host {}", e)))?;
+        req.headers_mut().insert(HOST, v);
+    }
+    Ok(req)
+}
+
+/// Passes the connection through unchanged, parsing the response heads
+/// until the first final (non-1xx) one, and sending the interim ones.
+struct Tap {
+    inner: Box<dyn Io>,
+    head: Vec<u8>,
+    /// Whether the final response head has started.
+    done: bool,
+    tx: mpsc::UnboundedSender<Informational>,
+}
+
+impl Tap {
+    fn inspect(&mut self, data: &[u8]) {
+        self.head.extend_from_slice(data);
+        while !self.done {
+            let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+            let mut resp = httparse::Response::new(&mut headers);
+            let n = match resp.parse(&self.head) {
+                Ok(httparse::Status::Complete(n)) => n,
+                Ok(httparse::Status::Partial) if resp.code.is_none() => {
+                    // an interim response head starts with "HTTP/1.x 1"
+                    self.done = self.head.len() >= 10 && self.head[9] != b'1';
+                    break;
+                }
+                Ok(httparse::Status::Partial) => {
+                    self.done =
+                        !matches!(resp.code, Some(100..=199)) || self.head.len() > MAX_HEAD_SIZE;
+                    break;
+                }
+                Err(_) => {
+                    self.done = true;
+                    break;
+                }
+            };
+            let code = resp.code.unwrap_or(0);
+            // "101 Switching Protocols" is final
+            if !(100..=199).contains(&code) || code == 101 {
+                self.done = true;
+                break;
+            }
+            let mut info = Informational {
+                status: StatusCode::from_u16(code).unwrap_or(StatusCode::CONTINUE),
+                headers: HeaderMap::new(),
+            };
+            for h in resp.headers.iter() {
+                if let (Ok(k), Ok(v)) = (
+                    HeaderName::from_bytes(h.name.as_bytes()),
+                    HeaderValue::from_bytes(h.value),
+                ) {
+                    info.headers.append(k, v);
+                }
+            }
+            wire_debug!("received informational response {}", info.status);
+            let _ = self.tx.send(info);
+            self.head.drain(..n);
+        }
+        if self.done {
+            self.head = Vec::new();
+        }
+    }
+}
+
+impl AsyncRead for Tap {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let ret = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if !self.done {
+            if let Poll::Ready(Ok(())) = ret {
+                let data = buf.filled()[filled..].to_vec();
+                self.inspect(&data);
+            }
+        }
+        ret
+    }
+}
+
+impl AsyncWrite for Tap {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[test]
+fn test_preload_links() {
+    let mut headers = HeaderMap::new();
+    headers.append(
+        LINK,
+        HeaderValue::from_static(
+            r#"</style.css>; rel=preload; as=style, <https://cdn.example.com/a,b.js>; rel="preload modulepreload", </next>; rel=next"#,
+        ),
+    );
+    headers.append(
+        LINK,
+        HeaderValue::from_static("</font.woff2>; rel=PRELOAD; as=font"),
+    );
+    let info = Informational {
+        status: StatusCode::from_u16(103).unwrap(),
+        headers,
+    };
+    assert_eq!(
+        info.preload_links(),
+        vec![
+            "/style.css",
+            "https://cdn.example.com/a,b.js",
+            "/font.woff2"
+        ]
+    );
+}
+
+/// RUST_LOG=debug cargo test --lib -- informational::test_send_with_informational --exact --show-output
+#[tokio::test]
+async fn test_send_with_informational() {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let (mut s, _) = listener.accept().await.unwrap();
+        let mut buf = vec![0; 4096];
+        let mut req = Vec::new();
+        while !req.windows(4).any(|w| w == b"\r\n\r\n") {
+            let n = s.read(&mut buf).await.unwrap();
+            req.extend_from_slice(&buf[..n]);
+        }
+        // split across writes, to exercise partial heads
+        for part in [
+            &b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 103 Early"[..],
+            b" Hints\r\nLink: </style.css>; rel=preload; as=style\r\n\r\n",
+            b"HTTP/1.1 200 OK\r\ncontent-length: 5\r\ncontent-type: text/html\r\nset-cookie: b=2\r\n\r\nhello",
+        ] {
+            s.write_all(part).await.unwrap();
+            s.flush().await.unwrap();
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        String::from_utf8(req).unwrap()
+    });
+
+    let jar = std::sync::Arc::new(crate::cookie::CookieJar::new());
+    let u = Url::parse(&format!("http://{}/", addr)).unwrap();
+    let mut set_cookie = HeaderMap::new();
+    set_cookie.insert("set-cookie", HeaderValue::from_static("a=1"));
+    jar.store(&u, &set_cookie);
+    let manager = Manager::builder().cookie_jar(jar.clone()).build().unwrap();
+    let req = crate::create_get(format!("http://{}", addr), "/page?q=1").unwrap();
+    let mut interim = Vec::new();
+    let resp = manager
+        .send_with_informational(req, |i| interim.push(i))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()["content-type"], "text/html");
+    assert_eq!(resp.body(), "hello");
+    assert_eq!(interim.len(), 2);
+    assert_eq!(interim[0].status, StatusCode::CONTINUE);
+    assert_eq!(interim[1].status.as_u16(), 103);
+    assert_eq!(interim[1].preload_links(), vec!["/style.css"]);
+
+    let req = server.await.unwrap();
+    assert!(req.starts_with("GET /page?q=1 HTTP/1.1\r\n"));
+    assert!(req.contains(&format!("host: {}", addr)));
+    assert!(req.contains("cookie: a=1\r\n"));
+    assert_eq!(jar.cookie_header(&u).unwrap(), "a=1; b=2");
+    let traffic = manager.traffic().get(&addr.to_string()).unwrap();
+    assert_eq!(traffic.requests, 1);
+    assert!(traffic.bytes_received > 5);
+
+    // the interim responses are not relayed by every proxy
+    let manager = Manager::builder()
+        .proxy(
+            crate::proxy::ProxyConfig::new()
+                .all(crate::proxy::Proxy::new(&format!("http://{}", addr)).unwrap()),
+        )
+        .build()
+        .unwrap();
+    let req = crate::create_get(format!("http://{}", addr), "/").unwrap();
+    let e = manager
+        .send_with_informational(req, |_| {})
+        .await
+        .unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::Unsupported);
+}
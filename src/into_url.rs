@@ -0,0 +1,139 @@
+//! Conversion of URL arguments, so that callers composing URLs
+//! programmatically can pass an already-parsed "Url" (or "Uri") instead
+//! of round-tripping through strings.
+
+use std::io::{self, Error, ErrorKind};
+
+use hyper::Uri;
+use url::Url;
+
+/// A value that is (or parses into) an absolute URL: "&str", "String",
+/// "Url", or "Uri". A "Url" is used as is, without parsing again.
+pub trait IntoUrl {
+    fn into_url(self) -> io::Result<Url>;
+
+    /// Same as "into_url", but returns the zone identifier of a scoped
+    /// IPv6 literal host (e.g., "eth0" of "http://[fe80::1%25eth0]") apart
+    /// from the URL, which cannot carry it.
+    fn into_scoped_url(self) -> io::Result<(Url, Option<String>)>
+    where
+        Self: Sized,
+    {
+        Ok((self.into_url()?, None))
+    }
+}
+
+impl IntoUrl for Url {
+    fn into_url(self) -> io::Result<Url> {
+        Ok(self)
+    }
+}
+
+impl IntoUrl for &Url {
+    fn into_url(self) -> io::Result<Url> {
+        Ok(self.clone())
+    }
+}
+
+impl IntoUrl for &str {
+    fn into_url(self) -> io::Result<Url> {
+        crate::parse_url(self)
+    }
+
+    fn into_scoped_url(self) -> io::Result<(Url, Option<String>)> {
+        crate::parse_scoped_url(self)
+    }
+}
+
+/// Items of "&[&str]" (e.g., "urls.iter()").
+impl IntoUrl for &&str {
+    fn into_url(self) -> io::Result<Url> {
+        crate::parse_url(self)
+    }
+
+    fn into_scoped_url(self) -> io::Result<(Url, Option<String>)> {
+        crate::parse_scoped_url(self)
+    }
+}
+
+impl IntoUrl for String {
+    fn into_url(self) -> io::Result<Url> {
+        crate::parse_url(&self)
+    }
+
+    fn into_scoped_url(self) -> io::Result<(Url, Option<String>)> {
+        crate::parse_scoped_url(&self)
+    }
+}
+
+impl IntoUrl for &String {
+    fn into_url(self) -> io::Result<Url> {
+        crate::parse_url(self)
+    }
+
+    fn into_scoped_url(self) -> io::Result<(Url, Option<String>)> {
+        crate::parse_scoped_url(self)
+    }
+}
+
+impl IntoUrl for Uri {
+    fn into_url(self) -> io::Result<Url> {
+        (&self).into_url()
+    }
+
+    fn into_scoped_url(self) -> io::Result<(Url, Option<String>)> {
+        (&self).into_scoped_url()
+    }
+}
+
+impl IntoUrl for &Uri {
+    fn into_url(self) -> io::Result<Url> {
+        check_absolute(self)?;
+        crate::parse_url(&self.to_string())
+    }
+
+    fn into_scoped_url(self) -> io::Result<(Url, Option<String>)> {
+        check_absolute(self)?;
+        crate::parse_scoped_url(&self.to_string())
+    }
+}
+
+fn check_absolute(uri: &Uri) -> io::Result<()> {
+    if uri.scheme().is_none() || uri.authority().is_none() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "URI '{}' is not absolute",
+                crate::redact::url(&uri.to_string())
+            ),
+        ));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_into_url() {
+    let expected = "http://localhost:9650/ext/info?a=1";
+    let url = Url::parse(expected).unwrap();
+    let uri: Uri = expected.parse().unwrap();
+    assert_eq!(expected.into_url().unwrap(), url);
+    assert_eq!(expected.to_string().into_url().unwrap(), url);
+    assert_eq!((&url).into_url().unwrap(), url);
+    assert_eq!((&uri).into_url().unwrap(), url);
+    assert_eq!(uri.into_url().unwrap(), url);
+
+    let relative: Uri = "/ext/info".parse().unwrap();
+    assert_eq!(
+        relative.into_url().unwrap_err().kind(),
+        ErrorKind::InvalidInput
+    );
+    assert!("http://[fe80::1%eth0]:9650".into_url().is_err());
+
+    // joins and requests take any of them
+    let req = crate::create_get(&url, "status").unwrap();
+    assert_eq!(req.uri(), "http://localhost:9650/ext/status");
+    let req = crate::create_get(url.clone(), "").unwrap();
+    assert_eq!(req.uri(), expected);
+    let u = crate::join_uri_within(&url, "a").unwrap();
+    assert_eq!(u.as_str(), "http://localhost:9650/ext/a");
+}
@@ -0,0 +1,175 @@
+//! Latency injection for exercising timeout and hedging configuration
+//! under slow-network conditions.
+
+use std::{
+    collections::HashMap,
+    io::{self, Error, ErrorKind},
+    time::Duration,
+};
+
+use hyper::{body::Bytes, Body, Request, Uri};
+use rand::Rng;
+use tokio::time::{sleep, timeout};
+
+use crate::logging::wire_debug;
+
+/// Distribution of the injected delays.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Distribution {
+    /// Always the same delay.
+    Fixed(Duration),
+    /// Uniformly distributed in [min, max].
+    Uniform { min: Duration, max: Duration },
+    /// Heavy-tailed delay of at least "scale", where a lower "shape"
+    /// means a longer tail. Samples are capped at "max".
+    Pareto {
+        scale: Duration,
+        shape: f64,
+        max: Duration,
+    },
+}
+
+impl Distribution {
+    /// Draws a single delay.
+    pub fn sample(&self) -> Duration {
+        match self {
+            Distribution::Fixed(d) => *d,
+            Distribution::Uniform { min, max } => {
+                if max <= min {
+                    return *min;
+                }
+                let nanos = rand::thread_rng().gen_range(min.as_nanos()..=max.as_nanos());
+                Duration::from_nanos(nanos as u64)
+            }
+            Distribution::Pareto { scale, shape, max } => {
+                // inverse transform sampling, "u" in (0, 1]
+                let u = 1.0 - rand::thread_rng().gen::<f64>();
+                let secs = scale.as_secs_f64() / u.powf(1.0 / shape);
+                if !secs.is_finite() || secs >= max.as_secs_f64() {
+                    return *max;
+                }
+                Duration::from_secs_f64(secs)
+            }
+        }
+    }
+}
+
+/// Injects delays before requests, configured per host with an optional
+/// fallback for all other hosts.
+#[derive(Debug, Clone, Default)]
+pub struct LatencyInjector {
+    default: Option<Distribution>,
+    hosts: HashMap<String, Distribution>,
+}
+
+impl LatencyInjector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the distribution for hosts without a specific entry.
+    pub fn with_default(mut self, dist: Distribution) -> Self {
+        self.default = Some(dist);
+        self
+    }
+
+    /// Sets the distribution for requests to "host" (e.g., "localhost").
+    pub fn with_host(mut self, host: &str, dist: Distribution) -> Self {
+        self.hosts.insert(host.to_lowercase(), dist);
+        self
+    }
+
+    /// Returns the delay to inject for the request URI.
+    pub fn delay_for(&self, uri: &Uri) -> Duration {
+        let dist = uri
+            .host()
+            .and_then(|h| self.hosts.get(&h.to_lowercase()))
+            .or(self.default.as_ref());
+        match dist {
+            Some(d) => d.sample(),
+            None => Duration::ZERO,
+        }
+    }
+
+    /// Same as "crate::read_bytes" but delays the request first.
+    /// The injected delay counts against "timeout_dur".
+    pub async fn read_bytes(
+        &self,
+        req: Request<Body>,
+        timeout_dur: Duration,
+        is_https: bool,
+        check_status_code: bool,
+    ) -> io::Result<Bytes> {
+        let delay = self.delay_for(req.uri());
+        wire_debug!(
+            "injecting {:?} latency for {}",
+            delay,
+            crate::redact::url(&req.uri().to_string())
+        );
+
+        let task = async {
+            sleep(delay).await;
+            crate::read_bytes(req, timeout_dur, is_https, check_status_code).await
+        };
+        match timeout(timeout_dur, task).await {
+            Ok(ret) => ret,
+            Err(e) => Err(Error::new(
+                ErrorKind::TimedOut,
+                format!("failed to read response with injected latency {}", e),
+            )),
+        }
+    }
+}
+
+#[test]
+fn test_distribution_sample() {
+    let d = Distribution::Fixed(Duration::from_millis(10));
+    assert_eq!(d.sample(), Duration::from_millis(10));
+
+    let d = Distribution::Uniform {
+        min: Duration::from_millis(10),
+        max: Duration::from_millis(20),
+    };
+    for _ in 0..100 {
+        let s = d.sample();
+        assert!(s >= Duration::from_millis(10) && s <= Duration::from_millis(20));
+    }
+
+    let d = Distribution::Pareto {
+        scale: Duration::from_millis(10),
+        shape: 1.5,
+        max: Duration::from_secs(1),
+    };
+    for _ in 0..100 {
+        let s = d.sample();
+        assert!(s >= Duration::from_millis(10) && s <= Duration::from_secs(1));
+    }
+}
+
+/// RUST_LOG=debug cargo test --lib -- latency::test_latency_injector --exact --show-output
+#[tokio::test]
+async fn test_latency_injector() {
+    use hyper::Method;
+
+    let server = crate::testing::MockServer::start().await.unwrap();
+    server.stub(Method::GET, "/", 200, "ok");
+
+    let injector = LatencyInjector::new()
+        .with_host("127.0.0.1", Distribution::Fixed(Duration::from_millis(500)));
+
+    let req = crate::create_get(server.url(), "/").unwrap();
+    let ret = injector
+        .read_bytes(req, Duration::from_millis(100), false, true)
+        .await;
+    assert_eq!(ret.unwrap_err().kind(), ErrorKind::TimedOut);
+
+    // other hosts are not delayed
+    let injector = LatencyInjector::new()
+        .with_host("example.com", Distribution::Fixed(Duration::from_secs(10)));
+    let req = crate::create_get(server.url(), "/").unwrap();
+    let out = injector
+        .read_bytes(req, Duration::from_secs(5), false, true)
+        .await
+        .unwrap();
+    assert_eq!(out, "ok");
+}
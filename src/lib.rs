@@ -1,57 +1,338 @@
+pub mod attempt;
+pub mod auth;
+pub mod batch;
+mod buffer;
+pub mod client;
+pub mod clock;
+pub mod concurrency;
+pub mod conditional;
+pub mod config;
+pub mod cookie;
+pub mod crawl;
+pub mod decompress;
+#[cfg(unix)]
+pub mod docker;
+mod download;
+pub mod encode;
+pub mod endpoints;
+pub mod error;
+pub mod expect;
+pub mod group;
+#[cfg(feature = "http3")]
+pub mod http3;
+pub mod httpsig;
+pub mod idn;
+pub mod informational;
+pub mod into_url;
+pub mod latency;
+pub mod loadtest;
+pub mod logging;
+mod manager;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+pub mod monitor;
+pub mod multipart;
+pub mod negotiate;
+#[cfg(feature = "oci")]
+pub mod oci;
+pub mod paginate;
+pub mod policy;
+mod pool;
+pub mod probe;
+pub mod prometheus;
+pub mod proxy;
+pub mod range;
+pub mod redact;
+pub mod retry;
+pub mod signer;
+#[cfg(feature = "sigstore")]
+pub mod sigstore;
+#[cfg(feature = "sigv4")]
+pub mod sigv4;
+pub mod spec;
+pub mod sse;
+pub mod ssrf;
+pub mod stall;
+pub mod tail;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod tls;
+pub mod traffic;
+pub mod uri_template;
+pub mod webhook;
+#[cfg(feature = "ws")]
+pub mod ws;
+
+pub use into_url::IntoUrl;
+pub use manager::{check_scheme, HostOverride, Manager, ManagerBuilder, DEFAULT_ALLOWED_SCHEMES};
+
 use std::{
-    fs::File,
-    io::{self, copy, Cursor, Error, ErrorKind},
+    future::Future,
+    io::{self, Error, ErrorKind},
+    net::{IpAddr, SocketAddr},
+    sync::Mutex,
     time::Duration,
 };
 
-use hyper::{body::Bytes, client::HttpConnector, Body, Client, Method, Request, Response};
-use hyper_tls::HttpsConnector;
-use reqwest::{header::CONTENT_TYPE, ClientBuilder};
-use tokio::time::timeout;
+use futures_util::{stream::BoxStream, StreamExt, TryStreamExt};
+use hyper::{
+    body::Bytes, client::HttpConnector, Body, Client, Method, Request, Response, StatusCode, Uri,
+};
+use once_cell::sync::Lazy;
+use reqwest::{
+    header::{HeaderMap, HeaderValue, CONTENT_LENGTH, CONTENT_TYPE, USER_AGENT},
+    ClientBuilder,
+};
+use tokio::{
+    io::AsyncWriteExt,
+    time::{timeout, timeout_at, Instant},
+};
 use url::Url;
 
+use crate::logging::{wire_debug, wire_info, wire_warn};
+
 /// Creates a simple HTTP GET request with no header and no body.
-pub fn create_get(url: &str, path: &str) -> io::Result<Request<Body>> {
-    let uri = match join_uri(url, path) {
-        Ok(u) => u,
-        Err(e) => return Err(e),
-    };
+pub fn create_get(url: impl IntoUrl, path: &str) -> io::Result<Request<Body>> {
+    create_get_with_mode(url, path, JoinMode::Resolve)
+}
 
-    let req = match Request::builder()
-        .method(Method::GET)
-        .uri(uri.as_str())
-        .body(Body::empty())
-    {
-        Ok(r) => r,
-        Err(e) => {
-            return Err(Error::new(
-                ErrorKind::Other,
-                format!("failed to create request {}", e),
-            ));
-        }
-    };
+/// Same as "create_get" but joins the path with the given mode.
+pub fn create_get_with_mode(
+    url: impl IntoUrl,
+    path: &str,
+    mode: JoinMode,
+) -> io::Result<Request<Body>> {
+    create_request(Method::GET, url, path, mode, None)
+}
 
+/// Same as "create_get" but with the headers (e.g., "Authorization").
+pub fn create_get_with_headers(
+    url: impl IntoUrl,
+    path: &str,
+    headers: &HeaderMap,
+) -> io::Result<Request<Body>> {
+    let mut req = create_get(url, path)?;
+    set_headers(req.headers_mut(), headers);
     Ok(req)
 }
 
+/// Creates a simple HTTP HEAD request with no header and no body.
+pub fn create_head(url: impl IntoUrl, path: &str) -> io::Result<Request<Body>> {
+    create_request(Method::HEAD, url, path, JoinMode::Resolve, None)
+}
+
+/// Creates a simple HTTP OPTIONS request with no header and no body.
+pub fn create_options(url: impl IntoUrl, path: &str) -> io::Result<Request<Body>> {
+    create_request(Method::OPTIONS, url, path, JoinMode::Resolve, None)
+}
+
+/// Creates a simple HTTP DELETE request with no header and no body.
+pub fn create_delete(url: impl IntoUrl, path: &str) -> io::Result<Request<Body>> {
+    create_request(Method::DELETE, url, path, JoinMode::Resolve, None)
+}
+
 const JSON_CONTENT_TYPE: &str = "application/json";
+const OCTET_STREAM_CONTENT_TYPE: &str = "application/octet-stream";
+const FORM_CONTENT_TYPE: &str = "application/x-www-form-urlencoded";
 
 /// Creates a simple HTTP POST request with JSON header and body.
-pub fn create_json_post(url: &str, path: &str, d: &str) -> io::Result<Request<Body>> {
-    let uri = join_uri(url, path)?;
-
-    let req = match Request::builder()
-        .method(Method::POST)
-        .header("content-type", JSON_CONTENT_TYPE)
-        .uri(uri.as_str())
-        .body(Body::from(String::from(d)))
-    {
+/// The body is moved into the request without copying (e.g., "Bytes",
+/// "String", "Vec<u8>", or "Cow<'static, str>").
+pub fn create_json_post(
+    url: impl IntoUrl,
+    path: &str,
+    d: impl Into<Body>,
+) -> io::Result<Request<Body>> {
+    create_json_post_with_mode(url, path, d, JoinMode::Resolve)
+}
+
+/// Same as "create_json_post" but joins the path with the given mode.
+pub fn create_json_post_with_mode(
+    url: impl IntoUrl,
+    path: &str,
+    d: impl Into<Body>,
+    mode: JoinMode,
+) -> io::Result<Request<Body>> {
+    create_request(Method::POST, url, path, mode, Some(d.into()))
+}
+
+/// Same as "create_json_post" but with the headers, which replace the
+/// JSON content type if they set one.
+pub fn create_json_post_with_headers(
+    url: impl IntoUrl,
+    path: &str,
+    d: impl Into<Body>,
+    headers: &HeaderMap,
+) -> io::Result<Request<Body>> {
+    let mut req = create_json_post(url, path, d)?;
+    set_headers(req.headers_mut(), headers);
+    Ok(req)
+}
+
+/// Request bodies "create_json_post_gzip" and "post_non_tls_gzip" compress
+/// by default, since compressing smaller ones costs more than it saves.
+#[cfg(feature = "compression")]
+pub const DEFAULT_GZIP_MIN_SIZE: usize = 64 * 1024;
+
+/// Same as "create_json_post", but compresses bodies of at least
+/// "min_size" bytes with "Content-Encoding: gzip" (e.g., large JSON-RPC
+/// batches, for servers that accept compressed requests).
+#[cfg(feature = "compression")]
+pub fn create_json_post_gzip(
+    url: impl IntoUrl,
+    path: &str,
+    d: impl Into<Bytes>,
+    min_size: usize,
+) -> io::Result<Request<Body>> {
+    let (body, headers) = gzip_body(d.into(), min_size)?;
+    let mut req = create_json_post(url, path, body)?;
+    set_headers(req.headers_mut(), &headers);
+    Ok(req)
+}
+
+/// Compresses the body if it has at least "min_size" bytes, and returns
+/// the headers to send with it.
+#[cfg(feature = "compression")]
+fn gzip_body(data: Bytes, min_size: usize) -> io::Result<(Bytes, HeaderMap)> {
+    use std::io::Write;
+
+    let mut headers = HeaderMap::new();
+    if data.len() < min_size {
+        return Ok((data, headers));
+    }
+    let mut enc = flate2::write::GzEncoder::new(
+        Vec::with_capacity(data.len() / 4),
+        flate2::Compression::default(),
+    );
+    enc.write_all(&data)?;
+    let compressed = enc.finish()?;
+    wire_debug!(
+        "compressed the request body from {} to {} bytes",
+        data.len(),
+        compressed.len()
+    );
+    headers.insert(
+        reqwest::header::CONTENT_ENCODING,
+        HeaderValue::from_static("gzip"),
+    );
+    Ok((Bytes::from(compressed), headers))
+}
+
+/// Returns the header map of the name-value pairs
+/// (e.g., "[("authorization", "Bearer ..."), ("accept", "application/json")]").
+/// Repeated names keep all their values.
+pub fn header_map(pairs: &[(&str, &str)]) -> io::Result<HeaderMap> {
+    let mut headers = HeaderMap::with_capacity(pairs.len());
+    for (k, v) in pairs.iter() {
+        let (name, value) = manager::parse_header(k, v)?;
+        headers.append(name, value);
+    }
+    Ok(headers)
+}
+
+/// Replaces the request headers of the same names with "headers".
+fn set_headers(dst: &mut HeaderMap, headers: &HeaderMap) {
+    for name in headers.keys() {
+        dst.remove(name);
+        for v in headers.get_all(name) {
+            dst.append(name.clone(), v.clone());
+        }
+    }
+}
+
+/// Creates a simple HTTP PUT request with JSON header and body
+/// (see "create_json_post").
+pub fn create_put_json(
+    url: impl IntoUrl,
+    path: &str,
+    d: impl Into<Body>,
+) -> io::Result<Request<Body>> {
+    create_request(Method::PUT, url, path, JoinMode::Resolve, Some(d.into()))
+}
+
+/// Creates a simple HTTP PATCH request with JSON header and body
+/// (see "create_json_post").
+pub fn create_patch_json(
+    url: impl IntoUrl,
+    path: &str,
+    d: impl Into<Body>,
+) -> io::Result<Request<Body>> {
+    create_request(Method::PATCH, url, path, JoinMode::Resolve, Some(d.into()))
+}
+
+/// Creates a HTTP POST request with the "application/x-www-form-urlencoded"
+/// body of the pairs (e.g., OAuth token requests).
+pub fn create_form_post(
+    url: impl IntoUrl,
+    path: &str,
+    pairs: &[(&str, &str)],
+) -> io::Result<Request<Body>> {
+    let mut req = create_request(
+        Method::POST,
+        url,
+        path,
+        JoinMode::Resolve,
+        Some(Body::from(form_body(pairs))),
+    )?;
+    req.headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static(FORM_CONTENT_TYPE));
+    Ok(req)
+}
+
+/// Returns the form-encoded pairs (e.g., "a=1&b=x+y%26z").
+fn form_body(pairs: &[(&str, &str)]) -> String {
+    url::form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(pairs)
+        .finish()
+}
+
+/// Creates a HTTP POST request with the "multipart/form-data" body of the
+/// parts (e.g., file uploads). File parts are read into the body.
+pub async fn create_multipart_post(
+    url: impl IntoUrl,
+    path: &str,
+    parts: &[multipart::Part],
+) -> io::Result<Request<Body>> {
+    let (content_type, body) = multipart::encode(parts).await?;
+    let mut req = create_request(
+        Method::POST,
+        url,
+        path,
+        JoinMode::Resolve,
+        Some(body.into()),
+    )?;
+    req.headers_mut().insert(
+        CONTENT_TYPE,
+        HeaderValue::from_str(&content_type).expect("boundary is a valid header value"),
+    );
+    Ok(req)
+}
+
+/// Creates a request with the JSON body, if any.
+fn create_request(
+    method: Method,
+    url: impl IntoUrl,
+    path: &str,
+    mode: JoinMode,
+    json_body: Option<Body>,
+) -> io::Result<Request<Body>> {
+    let (url, zone) = url.into_scoped_url()?;
+    let uri = join_uri_with_mode(url, path, mode)?;
+    let uri = match zone {
+        Some(zone) => scoped_uri(&uri, &zone)?,
+        // moves the serialized URL into the URI without copying
+        None => Uri::try_from(String::from(uri))
+            .map_err(|e| error::Error::UrlParse(format!("failed to create request {}", e)))?,
+    };
+
+    let mut builder = Request::builder().method(method).uri(uri);
+    if json_body.is_some() {
+        builder = builder.header("content-type", JSON_CONTENT_TYPE);
+    }
+    let req = match builder.body(json_body.unwrap_or_else(Body::empty)) {
         Ok(r) => r,
         Err(e) => {
-            return Err(Error::new(
-                ErrorKind::Other,
-                format!("failed to create request {}", e),
-            ));
+            return Err(error::Error::UrlParse(format!("failed to create request {}", e)).into());
         }
     };
 
@@ -65,90 +346,427 @@ pub async fn read_bytes(
     is_https: bool,
     check_status_code: bool,
 ) -> io::Result<Bytes> {
-    let resp = send_req(req, timeout_dur, is_https).await?;
-    if !resp.status().is_success() {
-        log::warn!(
+    let resp = send_req(req, timeout_dur, is_https, None).await?;
+    read_body(
+        resp,
+        timeout_dur,
+        None,
+        &decompress::DecompressionLimits::default(),
+        check_status_code,
+    )
+    .await
+}
+
+/// Same as "read_bytes", but sends HTTPS requests with the TLS settings
+/// (e.g., a client certificate for mutual TLS, a custom CA bundle, or
+/// "danger_accept_invalid_certs" for "curl --insecure").
+pub async fn read_bytes_with_tls(
+    req: Request<Body>,
+    timeout_dur: Duration,
+    tls: &tls::TlsConfig,
+    check_status_code: bool,
+) -> io::Result<Bytes> {
+    let is_https = req.uri().scheme() == Some(&hyper::http::uri::Scheme::HTTPS);
+    let resp = send_req(req, timeout_dur, is_https, Some(tls)).await?;
+    read_body(
+        resp,
+        timeout_dur,
+        None,
+        &decompress::DecompressionLimits::default(),
+        check_status_code,
+    )
+    .await
+}
+
+/// Status, headers, and body of a response, for callers that need the
+/// metadata (e.g., pagination cursors, rate-limit headers, or ETags).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponseParts {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub bytes: Bytes,
+}
+
+impl ResponseParts {
+    pub fn is_success(&self) -> bool {
+        self.status.is_success()
+    }
+
+    /// Returns the first value of the header, if set and valid UTF-8.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|v| v.to_str().ok())
+    }
+}
+
+impl From<Response<Bytes>> for ResponseParts {
+    fn from(resp: Response<Bytes>) -> Self {
+        let (parts, bytes) = resp.into_parts();
+        Self {
+            status: parts.status,
+            headers: parts.headers,
+            bytes,
+        }
+    }
+}
+
+/// Same as "read_bytes", but returns the status and headers with the body
+/// of any status code.
+pub async fn read_bytes_full(
+    req: Request<Body>,
+    timeout_dur: Duration,
+    is_https: bool,
+) -> io::Result<ResponseParts> {
+    let resp = send_req(req, timeout_dur, is_https, None).await?;
+    let (mut parts, body) = resp.into_parts();
+    let bytes = read_body_bytes(body, timeout_dur, None).await?;
+    let bytes = decompress::decode_response(
+        &mut parts.headers,
+        bytes,
+        &decompress::DecompressionLimits::default(),
+    )?;
+    Ok(ResponseParts {
+        status: parts.status,
+        headers: parts.headers,
+        bytes,
+    })
+}
+
+/// Same as "read_bytes", but sends the request built by "new_req" again
+/// on connection errors, timeouts, and the retryable status codes of the
+/// policy. Non-idempotent requests (e.g., POST) are only retried if the
+/// policy sets "retry_non_idempotent".
+pub async fn read_bytes_with_retry<F>(
+    mut new_req: F,
+    timeout_dur: Duration,
+    is_https: bool,
+    check_status_code: bool,
+    policy: &retry::RetryPolicy,
+) -> io::Result<Bytes>
+where
+    F: FnMut() -> io::Result<Request<Body>>,
+{
+    let req = new_req()?;
+    let idempotent = req.method().is_idempotent();
+    let endpoint = redact::url(&req.uri().to_string());
+    let mut first = Some(req);
+    let attempted = retry::run(policy, idempotent, &clock::TokioClock, &endpoint, || {
+        let req = match first.take() {
+            Some(req) => Ok(req),
+            None => new_req(),
+        };
+        async move {
+            let resp = send_req(req?, timeout_dur, is_https, None).await?;
+            let status = resp.status();
+            let limits = decompress::DecompressionLimits::default();
+            let bytes = read_body(resp, timeout_dur, None, &limits, false).await?;
+            Ok((status, bytes))
+        }
+    })
+    .await?;
+
+    let (status, bytes) = attempted.value;
+    if check_status_code && !status.is_success() {
+        return Err(error::Error::Status(status, bytes).into());
+    }
+    Ok(bytes)
+}
+
+/// RUST_LOG=debug cargo test --lib -- test_read_bytes_with_retry --exact --show-output
+#[tokio::test]
+async fn test_read_bytes_with_retry() {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use tokio::{io::AsyncReadExt, net::TcpListener};
+
+    // responds 503 to the first two requests, then 200
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let received = Arc::new(AtomicUsize::new(0));
+    let counter = received.clone();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let mut buf = [0u8; 4096];
+            let _ = stream.read(&mut buf).await;
+            let resp = if counter.fetch_add(1, Ordering::SeqCst) < 2 {
+                "HTTP/1.1 503 Service Unavailable\r\ncontent-length: 4\r\nconnection: close\r\n\r\nbusy"
+            } else {
+                "HTTP/1.1 200 OK\r\ncontent-length: 2\r\nconnection: close\r\n\r\nok"
+            };
+            let _ = stream.write_all(resp.as_bytes()).await;
+        }
+    });
+
+    let policy = retry::RetryPolicy {
+        base_delay: Duration::from_millis(10),
+        ..Default::default()
+    };
+    let out = read_bytes_with_retry(
+        || create_get(&url, "/"),
+        Duration::from_secs(5),
+        false,
+        true,
+        &policy,
+    )
+    .await
+    .unwrap();
+    assert_eq!(out, "ok");
+    assert_eq!(received.load(Ordering::SeqCst), 3);
+
+    // POST is not retried by default
+    received.store(0, Ordering::SeqCst);
+    let out = post_non_tls_with_retry(&url, "/", "{}", false, &policy)
+        .await
+        .unwrap();
+    assert_eq!(out, "busy");
+    assert_eq!(received.load(Ordering::SeqCst), 1);
+    let policy = retry::RetryPolicy {
+        retry_non_idempotent: true,
+        ..policy
+    };
+    let out = post_non_tls_with_retry(&url, "/", "{}", false, &policy)
+        .await
+        .unwrap();
+    assert_eq!(out, "ok");
+    assert_eq!(received.load(Ordering::SeqCst), 3);
+
+    // the last response is returned once the attempts run out
+    received.store(0, Ordering::SeqCst);
+    let policy = retry::RetryPolicy {
+        max_attempts: 2,
+        ..policy
+    };
+    let out = get_non_tls_with_retry(&url, "/", false, &policy)
+        .await
+        .unwrap();
+    assert_eq!(out, "busy");
+    received.store(0, Ordering::SeqCst);
+    let e = read_bytes_with_retry(
+        || create_get(&url, "/"),
+        Duration::from_secs(5),
+        false,
+        true,
+        &policy,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(
+        error::Error::from(e).status(),
+        Some(StatusCode::SERVICE_UNAVAILABLE)
+    );
+}
+
+/// RUST_LOG=debug cargo test --lib -- test_read_bytes_with_tls --exact --show-output
+#[tokio::test]
+async fn test_read_bytes_with_tls() {
+    let server = testing::MockServer::start().await.unwrap();
+    server.stub(Method::GET, "/", 200, "ok");
+    let tls = tls::TlsConfig::new();
+    let req = create_get(server.url(), "/").unwrap();
+    let ret = read_bytes_with_tls(req, Duration::from_secs(5), &tls, true).await;
+    assert_eq!(ret.unwrap(), "ok");
+
+    // the self-signed certificate is not trusted
+    let tls_server = testing::MockServer::start_https().await.unwrap();
+    tls_server.stub(Method::GET, "/", 200, "ok");
+    let req = create_get(tls_server.url(), "/").unwrap();
+    assert!(read_bytes_with_tls(req, Duration::from_secs(5), &tls, true)
+        .await
+        .is_err());
+
+    let tls = tls::TlsConfig::new().client_identity(tls::ClientIdentity::from_pem("", ""));
+    let req = create_get(tls_server.url(), "/").unwrap();
+    let e = read_bytes_with_tls(req, Duration::from_secs(5), &tls, true)
+        .await
+        .unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidInput);
+}
+
+/// Reads the response body in "hyper::body::Bytes" with a timeout,
+/// decoded within the limits (see "decompress"), optionally failing on
+/// non-2xx status codes.
+pub(crate) async fn read_body(
+    resp: Response<Body>,
+    timeout_dur: Duration,
+    low_speed_limit: Option<stall::LowSpeedLimit>,
+    limits: &decompress::DecompressionLimits,
+    check_status_code: bool,
+) -> io::Result<Bytes> {
+    let status = resp.status();
+    if !status.is_success() {
+        wire_warn!(
             "unexpected HTTP response code {} (server error {})",
-            resp.status(),
-            resp.status().is_server_error()
+            status,
+            status.is_server_error()
         );
-        if check_status_code {
-            return Err(Error::new(
-                ErrorKind::Other,
-                format!(
-                    "unexpected HTTP response code {} (server error {})",
-                    resp.status(),
-                    resp.status().is_server_error()
-                ),
-            ));
-        }
     }
 
+    let (mut parts, body) = resp.into_parts();
+    let bytes = read_body_bytes(body, timeout_dur, low_speed_limit).await?;
+    let bytes = decompress::decode_response(&mut parts.headers, bytes, limits)?;
+    if check_status_code && !status.is_success() {
+        return Err(error::Error::Status(status, bytes).into());
+    }
+    Ok(bytes)
+}
+
+/// Reads the whole body with a timeout, or, if a low-speed limit is set,
+/// for as long as the transfer does not stall below it (so that a large
+/// body on a slow but steady link is not cut off).
+pub(crate) async fn read_body_bytes(
+    body: Body,
+    timeout_dur: Duration,
+    low_speed_limit: Option<stall::LowSpeedLimit>,
+) -> io::Result<Bytes> {
+    read_body_bytes_until(body, Instant::now() + timeout_dur, low_speed_limit).await
+}
+
+/// Same as "read_body_bytes", but fails at the deadline (e.g., the deadline
+/// of the whole request, shared with sending it).
+pub(crate) async fn read_body_bytes_until(
+    body: Body,
+    deadline: Instant,
+    low_speed_limit: Option<stall::LowSpeedLimit>,
+) -> io::Result<Bytes> {
     // set timeouts for reads
     // https://github.com/hyperium/hyper/issues/1097
-    let future_task = hyper::body::to_bytes(resp);
-    let ret = timeout(timeout_dur, future_task).await;
+    let ret = match low_speed_limit {
+        Some(limit) => Ok(stall::collect(body, limit).await),
+        None => {
+            timeout_at(deadline, async {
+                buffer::collect(body).await.map_err(|e| {
+                    error::Error::Body(format!("failed to read response {}", e)).into()
+                })
+            })
+            .await
+        }
+    };
 
-    let bytes;
-    match ret {
-        Ok(result) => match result {
-            Ok(b) => bytes = b,
-            Err(e) => {
-                return Err(Error::new(
-                    ErrorKind::Other,
-                    format!("failed to read response {}", e),
-                ));
-            }
-        },
+    let bytes = match ret {
+        Ok(result) => result?,
         Err(e) => {
-            return Err(Error::new(
-                ErrorKind::Other,
-                format!("failed to read response {}", e),
-            ));
+            return Err(error::Error::Timeout(format!(
+                "failed to read response before the deadline {}",
+                e
+            ))
+            .into());
         }
-    }
+    };
 
+    logging::log_response_preview(&bytes);
     Ok(bytes)
 }
 
+/// Default "User-Agent" header value (e.g., "http-manager/0.0.14").
+pub const DEFAULT_USER_AGENT: &str =
+    concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+/// Returns the "User-Agent" value for the product, optionally followed by
+/// the crate name and version (e.g., "my-service/1.2 http-manager/0.0.14").
+pub fn user_agent(product: &str, append_crate: bool) -> String {
+    if append_crate {
+        format!("{} {}", product, DEFAULT_USER_AGENT)
+    } else {
+        product.to_string()
+    }
+}
+
+// ref. https://github.com/tokio-rs/tokio-tls/blob/master/examples/hyper-client.rs
+// ref. https://docs.rs/hyper/latest/hyper/client/struct.HttpConnector.html
+// ref. https://github.com/hyperium/hyper-tls/blob/master/examples/client.rs
+fn new_connector() -> HttpConnector<ssrf::GuardedResolver> {
+    let mut connector = HttpConnector::new_with_resolver(ssrf::GuardedResolver::new(false));
+    // ref. https://github.com/hyperium/hyper/issues/1097
+    connector.set_connect_timeout(Some(Duration::from_secs(5)));
+    connector
+}
+
+/// Shared by all "send_req" calls, with no idle connections: a pooled
+/// connection is driven by a task on the runtime that opened it, so the
+/// process-wide clients would fail on any other runtime once that one
+/// shuts down ("dispatch task is gone"). Use "Manager" (owned by the
+/// caller, on its runtime) to keep connections alive and reuse them.
+static HTTP_CLIENT: Lazy<Client<HttpConnector<ssrf::GuardedResolver>>> =
+    Lazy::new(|| new_client_builder().build(new_connector()));
+type HttpsClient = Client<tls::HttpsConnector<HttpConnector<ssrf::GuardedResolver>>>;
+
+static HTTPS_CLIENT: Lazy<HttpsClient> = Lazy::new(|| {
+    let mut connector = new_connector();
+    connector.enforce_http(false);
+    let connector = tls::TlsConfig::default()
+        .https_connector(connector, false, false)
+        .expect("failed to build TLS connector");
+    new_client_builder().build(connector)
+});
+
+/// Returns the builder of the global clients (see "HTTP_CLIENT").
+fn new_client_builder() -> hyper::client::Builder {
+    let mut builder = Client::builder();
+    builder.pool_max_idle_per_host(0);
+    builder
+}
+
+/// Clients of "read_bytes_with_tls", one per TLS config (the oldest is
+/// dropped beyond "MAX_TLS_CLIENTS"), so that the TLS connectors are not
+/// rebuilt per request. Not pooled, as with "HTTPS_CLIENT".
+static TLS_CLIENTS: Lazy<Mutex<Vec<(tls::TlsConfig, HttpsClient)>>> =
+    Lazy::new(|| Mutex::new(Vec::new()));
+const MAX_TLS_CLIENTS: usize = 16;
+
+fn https_client_with_tls(tls: &tls::TlsConfig) -> io::Result<HttpsClient> {
+    let mut clients = TLS_CLIENTS.lock().unwrap();
+    if let Some((_, client)) = clients.iter().find(|(c, _)| c == tls) {
+        return Ok(client.clone());
+    }
+
+    let mut connector = new_connector();
+    connector.enforce_http(false);
+    let connector = tls.https_connector(connector, tls.accepts_invalid_certs(), false)?;
+    let client = new_client_builder().build(connector);
+    if clients.len() >= MAX_TLS_CLIENTS {
+        clients.remove(0);
+    }
+    clients.push((tls.clone(), client.clone()));
+    Ok(client)
+}
+
 /// Sends a HTTP(s) request and wait for its response.
+/// Sets "DEFAULT_USER_AGENT" unless the request has its own "User-Agent".
 async fn send_req(
-    req: Request<Body>,
+    mut req: Request<Body>,
     timeout_dur: Duration,
     is_https: bool,
+    tls: Option<&tls::TlsConfig>,
 ) -> io::Result<Response<Body>> {
-    // ref. https://github.com/tokio-rs/tokio-tls/blob/master/examples/hyper-client.rs
-    // ref. https://docs.rs/hyper/latest/hyper/client/struct.HttpConnector.html
-    // ref. https://github.com/hyperium/hyper-tls/blob/master/examples/client.rs
-    let mut connector = HttpConnector::new();
-    // ref. https://github.com/hyperium/hyper/issues/1097
-    connector.set_connect_timeout(Some(Duration::from_secs(5)));
+    if !req.headers().contains_key(USER_AGENT) {
+        req.headers_mut()
+            .insert(USER_AGENT, HeaderValue::from_static(DEFAULT_USER_AGENT));
+    }
+    decompress::set_accept_encoding(req.headers_mut());
+    let req = logging::log_request_preview(req).await;
 
-    let task = {
-        if !is_https {
-            let cli = Client::builder().build(connector);
-            cli.request(req)
-        } else {
-            // TODO: implement "curl --insecure"
-            let https_connector = HttpsConnector::new_with_connector(connector);
-            let cli = Client::builder().build(https_connector);
-            cli.request(req)
+    let task = if is_https {
+        match tls {
+            Some(tls) => https_client_with_tls(tls)?.request(req),
+            None => HTTPS_CLIENT.request(req),
         }
+    } else {
+        HTTP_CLIENT.request(req)
     };
 
-    let res = timeout(timeout_dur, task).await?;
-    match res {
-        Ok(resp) => Ok(resp),
-        Err(e) => {
-            return Err(Error::new(
-                ErrorKind::Other,
-                format!("failed to fetch response {}", e),
-            ))
-        }
-    }
+    let res = timeout(timeout_dur, task).await.map_err(|e| {
+        error::Error::Timeout(format!(
+            "failed to fetch response within {:?} {}",
+            timeout_dur, e
+        ))
+    })?;
+    res.map_err(|e| error::Error::from_hyper("failed to fetch response", &e).into())
 }
 
 #[test]
+#[allow(clippy::nonminimal_bool)]
 fn test_read_bytes_timeout() {
     let _ = env_logger::builder()
         .filter_level(log::LevelFilter::Info)
@@ -176,25 +794,43 @@ fn test_read_bytes_timeout() {
     assert!(!ret.is_ok());
 }
 
-pub fn join_uri(url: &str, path: &str) -> io::Result<Url> {
-    let mut uri = match Url::parse(url) {
-        Ok(u) => u,
-        Err(e) => {
-            return Err(Error::new(
-                ErrorKind::Other,
-                format!("failed to parse client URL {}", e),
-            ))
-        }
-    };
+/// RUST_LOG=debug cargo test --lib -- test_read_bytes_across_runtimes --exact --show-output
+#[test]
+fn test_read_bytes_across_runtimes() {
+    // the server outlives the runtimes of the requests
+    let server_rt = tokio::runtime::Runtime::new().unwrap();
+    let server = server_rt.block_on(testing::MockServer::start()).unwrap();
+    server.stub(Method::GET, "/", 200, "ok");
+
+    // the connection task of a request is spawned on its runtime, which
+    // no longer runs once "block_on" returns: a connection pooled by the
+    // global client would never respond in the next runtime
+    let mut runtimes = Vec::new();
+    for _ in 0..3 {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let req = create_get(server.url(), "/").unwrap();
+        let ret = rt.block_on(read_bytes(req, Duration::from_secs(2), false, true));
+        assert_eq!(ret.unwrap(), "ok");
+        runtimes.push(rt);
+    }
+    assert_eq!(server.received_requests().len(), 3);
+}
+
+/// Joins the path to the URL with "Url::join" semantics
+/// (see "JoinMode::Resolve", and "append_path" to keep the base path).
+pub fn join_uri(url: impl IntoUrl, path: &str) -> io::Result<Url> {
+    let mut uri = url.into_url()?;
 
     if !path.is_empty() {
         match uri.join(path) {
             Ok(u) => uri = u,
             Err(e) => {
-                return Err(Error::new(
-                    ErrorKind::Other,
-                    format!("failed to join parsed URL {}", e),
-                ));
+                return Err(
+                    error::Error::UrlParse(format!("failed to join parsed URL {}", e)).into(),
+                );
             }
         }
     }
@@ -202,6 +838,230 @@ pub fn join_uri(url: &str, path: &str) -> io::Result<Url> {
     Ok(uri)
 }
 
+/// Parses an absolute client URL, with hints for common mistakes with
+/// IPv6 literals. Scoped IPv6 literals fail, since "Url" cannot carry
+/// zone identifiers (see "parse_scoped_url").
+pub(crate) fn parse_url(url: &str) -> io::Result<Url> {
+    let (u, zone) = parse_scoped_url(url)?;
+    if let Some(zone) = zone {
+        return Err(error::Error::UrlParse(format!(
+            "failed to parse client URL '{}': IPv6 zone identifier '{}' cannot be carried by a parsed URL, pass the URL string or \"Uri\" to the request builders (e.g., \"create_get\")",
+            redact::url(url),
+            zone
+        ))
+        .into());
+    }
+    Ok(u)
+}
+
+/// Same as "parse_url", but returns the zone identifier (e.g., "eth0" of
+/// "http://[fe80::1%25eth0]" or "http://[fe80::1%eth0]") of a scoped IPv6
+/// literal host apart from the URL without it.
+pub(crate) fn parse_scoped_url(url: &str) -> io::Result<(Url, Option<String>)> {
+    if let Some(id) = ipv6_zone_id(url) {
+        let zone = id.strip_prefix("%25").unwrap_or(&id[1..]);
+        if zone.is_empty()
+            || !zone
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b"-._~".contains(&b))
+        {
+            return Err(error::Error::UrlParse(format!(
+                "failed to parse client URL '{}': invalid IPv6 zone identifier '{}'",
+                redact::url(url),
+                id
+            ))
+            .into());
+        }
+        let u = parse_unscoped_url(&url.replacen(id, "", 1))?;
+        return Ok((u, Some(zone.to_string())));
+    }
+    Ok((parse_unscoped_url(url)?, None))
+}
+
+fn parse_unscoped_url(url: &str) -> io::Result<Url> {
+    match Url::parse(url) {
+        Ok(u) => Ok(u),
+        Err(e) => {
+            let hint = if e == url::ParseError::InvalidIpv6Address || url.contains("::") {
+                " (IPv6 literals must be bracketed, e.g., \"http://[::1]:9650\")"
+            } else {
+                ""
+            };
+            Err(error::Error::UrlParse(format!("failed to parse client URL {}{}", e, hint)).into())
+        }
+    }
+}
+
+/// Returns the zone identifier (e.g., "%eth0" or "%25eth0") of a
+/// bracketed IPv6 literal host in the URL, if any.
+fn ipv6_zone_id(url: &str) -> Option<&str> {
+    let authority = url.split("://").nth(1)?.split(['/', '?', '#']).next()?;
+    let start = authority.find('[')?;
+    let end = authority[start..].find(']')? + start;
+    let host = &authority[start + 1..end];
+    host.find('%').map(|idx| &host[idx..])
+}
+
+/// Returns the URI of the URL with the zone identifier (percent-encoded as
+/// "%25") added to its IPv6 literal host, e.g., "http://[fe80::1%25eth0]/"
+/// for the zone "eth0". The manager connects to the address with the
+/// scope of the zone.
+pub fn scoped_uri(url: &Url, zone: &str) -> io::Result<Uri> {
+    let ip = match url.host() {
+        Some(url::Host::Ipv6(ip)) => ip,
+        _ => {
+            return Err(error::Error::UrlParse(format!(
+                "zone identifier '{}' requires an IPv6 literal host, got '{}'",
+                zone,
+                url.host_str().unwrap_or("")
+            ))
+            .into())
+        }
+    };
+    let host = format!("[{}]", ip);
+    url.as_str()
+        .replacen(&host, &format!("[{}%25{}]", ip, zone), 1)
+        .parse()
+        .map_err(|e| {
+            error::Error::UrlParse(format!(
+                "failed to parse scoped URI of '{}' {}",
+                redact::url(url.as_str()),
+                e
+            ))
+            .into()
+        })
+}
+
+/// Returns "host:port", bracketing IPv6 literals (e.g., "[::1]:9650").
+pub fn format_authority(host: &str, port: u16) -> String {
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V6(v6)) => format!("[{}]:{}", v6, port),
+        _ => format!("{}:{}", host, port),
+    }
+}
+
+/// Returns the base URI for the socket address (e.g., "http://[::1]:9650/").
+/// The scope id of a scoped IPv6 address is kept as the zone identifier
+/// (e.g., "http://[fe80::1%252]:9650/" for the scope id 2, see
+/// "scoped_uri"), so that requests built from the URI connect to the same
+/// scope.
+pub fn socket_addr_url(scheme: &str, addr: &SocketAddr) -> io::Result<Uri> {
+    let u = join_uri(
+        format!(
+            "{}://{}",
+            scheme,
+            format_authority(&addr.ip().to_string(), addr.port())
+        ),
+        "",
+    )?;
+    match addr {
+        SocketAddr::V6(v6) if v6.scope_id() != 0 => scoped_uri(&u, &v6.scope_id().to_string()),
+        _ => String::from(u).parse().map_err(|e| {
+            error::Error::UrlParse(format!("failed to parse URI of {} {}", addr, e)).into()
+        }),
+    }
+}
+
+#[test]
+fn test_ipv6_literals() {
+    let ret = join_uri("http://[::1]:9650", "/ext/info");
+    assert_eq!(ret.unwrap().as_str(), "http://[::1]:9650/ext/info");
+    let ret = join_uri("http://[2001:db8::1]/api/", "v1?x=1");
+    assert_eq!(ret.unwrap().as_str(), "http://[2001:db8::1]/api/v1?x=1");
+
+    // zone identifiers are carried by the request URIs, not by "Url"
+    for url in [
+        "http://[fe80::1%eth0]:9650",
+        "http://[fe80::1%25eth0]:9650/x",
+    ] {
+        let ret = join_uri(url, "/ext/info");
+        assert!(ret.unwrap_err().to_string().contains("zone identifier"));
+        let req = create_get(url, "/ext/info").unwrap();
+        assert_eq!(req.uri(), "http://[fe80::1%25eth0]:9650/ext/info");
+        let (u, zone) = parse_scoped_url(url).unwrap();
+        assert_eq!(u.host_str(), Some("[fe80::1]"));
+        assert_eq!(zone.as_deref(), Some("eth0"));
+    }
+    let uri: Uri = "http://[fe80::1%252]/v1/".parse().unwrap();
+    let req = create_get(uri, "status").unwrap();
+    assert_eq!(req.uri(), "http://[fe80::1%252]/v1/status");
+    for url in [
+        "http://[fe80::1%]",
+        "http://[fe80::1%25]",
+        "http://[fe80::1%e/0]",
+    ] {
+        assert!(parse_scoped_url(url).is_err());
+    }
+    let u = Url::parse("http://127.0.0.1").unwrap();
+    assert!(scoped_uri(&u, "eth0").is_err());
+    let ret = join_uri("http://[::1", "");
+    assert!(ret.unwrap_err().to_string().contains("must be bracketed"));
+
+    assert_eq!(format_authority("::1", 80), "[::1]:80");
+    assert_eq!(format_authority("127.0.0.1", 80), "127.0.0.1:80");
+    assert_eq!(format_authority("localhost", 80), "localhost:80");
+
+    let addr: SocketAddr = "[::1]:9650".parse().unwrap();
+    let u = socket_addr_url("http", &addr).unwrap();
+    assert_eq!(u, "http://[::1]:9650/");
+    let addr = SocketAddr::V6(std::net::SocketAddrV6::new(
+        "fe80::1".parse().unwrap(),
+        9650,
+        0,
+        2,
+    ));
+    let u = socket_addr_url("http", &addr).unwrap();
+    assert_eq!(u, "http://[fe80::1%252]:9650/");
+    let req = create_get(u, "/ext/info").unwrap();
+    assert_eq!(req.uri(), "http://[fe80::1%252]:9650/ext/info");
+}
+
+/// RUST_LOG=debug cargo test --lib -- test_ipv6_connect --exact --show-output
+#[tokio::test]
+async fn test_ipv6_connect() {
+    let server = match testing::MockServer::start_on("[::1]:0").await {
+        Ok(s) => s,
+        Err(e) => {
+            log::warn!("skipping, IPv6 loopback unavailable {}", e);
+            return;
+        }
+    };
+    server.stub(Method::GET, "/ext/info", 200, "ok");
+    assert!(server.url().starts_with("http://[::1]:"));
+
+    let req = create_get(server.url(), "/ext/info").unwrap();
+    let out = read_bytes(req, Duration::from_secs(5), false, true)
+        .await
+        .unwrap();
+    assert_eq!(out, "ok");
+
+    let manager = Manager::new().unwrap();
+    let req = create_get(server.url(), "/ext/info").unwrap();
+    assert_eq!(manager.read_bytes(req, true).await.unwrap(), "ok");
+
+    // the scope id survives the URI, and is set on the connected address
+    let addr = SocketAddr::V6(std::net::SocketAddrV6::new(
+        "::1".parse().unwrap(),
+        server.port(),
+        0,
+        1,
+    ));
+    let uri = socket_addr_url("http", &addr).unwrap();
+    let req = create_get(uri, "/ext/info").unwrap();
+    let host = req.uri().host().unwrap();
+    let name: hyper::client::connect::dns::Name = host[1..host.len() - 1].parse().unwrap();
+    let resolved: Vec<SocketAddr> =
+        hyper::service::Service::call(&mut ssrf::GuardedResolver::new(false), name)
+            .await
+            .unwrap()
+            .collect();
+    match resolved[..] {
+        [SocketAddr::V6(v6)] => assert_eq!(v6.scope_id(), 1),
+        _ => panic!("unexpected {:?}", resolved),
+    }
+    assert_eq!(manager.read_bytes(req, true).await.unwrap(), "ok");
+}
+
 #[test]
 fn test_join_uri() {
     let ret = Url::parse("http://localhost:9850/ext/X/sendMultiple");
@@ -223,69 +1083,1436 @@ fn test_join_uri() {
     assert_eq!(t, expected);
 }
 
-/// Downloads a file to the "file_path".
-pub async fn download_file(ep: &str, file_path: &str) -> io::Result<()> {
-    log::info!("downloading the file via {}", ep);
-    let resp = reqwest::get(ep)
-        .await
-        .map_err(|e| Error::new(ErrorKind::Other, format!("failed reqwest::get {}", e)))?;
+/// How a path is joined to a base URL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum JoinMode {
+    /// Resolves the path as a relative reference ("Url::join" semantics),
+    /// as browsers do: "http://host/v1" + "status" is "http://host/status",
+    /// and an absolute path replaces the base path entirely.
+    #[default]
+    Resolve,
+    /// Always appends the path segments to the base path:
+    /// "http://host/v1" + "status" (or "/status") is "http://host/v1/status".
+    Append,
+}
 
-    let mut content = Cursor::new(
-        resp.bytes()
-            .await
-            .map_err(|e| Error::new(ErrorKind::Other, format!("failed bytes {}", e)))?,
-    );
+/// Joins the path to the URL with the given mode.
+pub fn join_uri_with_mode(url: impl IntoUrl, path: &str, mode: JoinMode) -> io::Result<Url> {
+    match mode {
+        JoinMode::Resolve => join_uri(url, path),
+        JoinMode::Append => append_path(url, path),
+    }
+}
 
-    let mut f = File::create(file_path)?;
-    copy(&mut content, &mut f)?;
+/// Appends the path segments to the base URL path, keeping the base path
+/// (see "JoinMode::Append"). A query in "path" replaces the base query.
+/// Dot segments are resolved after appending.
+pub fn append_path(url: impl IntoUrl, path: &str) -> io::Result<Url> {
+    let mut uri = url.into_url()?;
+    if path.is_empty() {
+        return Ok(uri);
+    }
 
-    Ok(())
+    let (path, query) = match path.split_once('?') {
+        Some((p, q)) => (p, Some(q)),
+        None => (path, None),
+    };
+    let joined = format!(
+        "{}/{}",
+        uri.path().trim_end_matches('/'),
+        path.trim_start_matches('/')
+    );
+    uri.set_path(&joined);
+    if query.is_some() {
+        uri.set_query(query);
+    }
+    Ok(uri)
 }
 
-/// TODO: implement this with native Rust
-pub async fn get_non_tls(url: &str, url_path: &str) -> io::Result<Vec<u8>> {
-    let joined = join_uri(url, url_path)?;
-    log::debug!("non-TLS HTTP get for {:?}", joined);
-
-    let output = {
-        if url.starts_with("https") {
-            log::info!("sending via danger_accept_invalid_certs");
-            let cli = ClientBuilder::new()
-                .user_agent(env!("CARGO_PKG_NAME"))
-                .danger_accept_invalid_certs(true)
-                .timeout(Duration::from_secs(15))
-                .connection_verbose(true)
-                .build()
-                .map_err(|e| {
-                    Error::new(
-                        ErrorKind::Other,
-                        format!("failed ClientBuilder build {}", e),
-                    )
-                })?;
-            let resp = cli.get(joined.as_str()).send().await.map_err(|e| {
-                Error::new(ErrorKind::Other, format!("failed ClientBuilder send {}", e))
-            })?;
-            let out = resp.bytes().await.map_err(|e| {
-                Error::new(ErrorKind::Other, format!("failed ClientBuilder send {}", e))
-            })?;
-            out.into()
-        } else {
-            let req = create_get(url, url_path)?;
-            let buf = match read_bytes(
-                req,
-                Duration::from_secs(15),
-                url.starts_with("https"),
-                false,
-            )
+#[test]
+fn test_append_path() {
+    let cases = [
+        ("http://host/v1", "status", "http://host/v1/status"),
+        ("http://host/v1/", "status", "http://host/v1/status"),
+        ("http://host/v1", "/status", "http://host/v1/status"),
+        ("http://host/v1/", "/status/", "http://host/v1/status/"),
+        ("http://host", "status", "http://host/status"),
+        ("http://host/v1", "a/b?x=1", "http://host/v1/a/b?x=1"),
+        ("http://host/v1?k=v", "status", "http://host/v1/status?k=v"),
+        ("http://host/v1", "", "http://host/v1"),
+        ("http://host/v1/ext", "../status", "http://host/v1/status"),
+    ];
+    for (url, path, expected) in cases {
+        let ret = join_uri_with_mode(url, path, JoinMode::Append);
+        assert_eq!(ret.unwrap().as_str(), expected, "{} + {}", url, path);
+    }
+
+    let ret = join_uri_with_mode("http://host/v1", "status", JoinMode::Resolve);
+    assert_eq!(ret.unwrap().as_str(), "http://host/status");
+
+    let req = create_get_with_mode("http://host/v1", "status", JoinMode::Append).unwrap();
+    assert_eq!(req.uri(), "http://host/v1/status");
+    let req = create_json_post_with_mode("http://host/v1", "rpc", "{}", JoinMode::Append).unwrap();
+    assert_eq!(req.uri(), "http://host/v1/rpc");
+}
+
+/// Same as "join_uri" but rejects paths that escape the base URL path,
+/// for callers that pass user-influenced paths. The joined URL must keep
+/// the origin and stay under the base "directory" (the base path up to its
+/// last "/", so "http://host/api/" allows "/api/*" but "http://host/api"
+/// allows "/*"). Dot segments are resolved before the check, and
+/// percent-encoded path separators are rejected.
+pub fn join_uri_within(url: impl IntoUrl, path: &str) -> io::Result<Url> {
+    let base = url.into_url()?;
+
+    let lower = path.to_ascii_lowercase();
+    if lower.contains("%2f") || lower.contains("%5c") {
+        return Err(error::Error::UrlParse(format!(
+            "path '{}' contains an encoded path separator",
+            path
+        ))
+        .into());
+    }
+
+    let joined = join_uri(&base, path)?;
+    if joined.origin() != base.origin() {
+        return Err(error::Error::UrlParse(format!(
+            "path '{}' escapes the origin of '{}'",
+            path,
+            redact::url(base.as_str())
+        ))
+        .into());
+    }
+
+    let base_path = base.path();
+    let base_dir = match base_path.rfind('/') {
+        Some(idx) => &base_path[..=idx],
+        None => "/",
+    };
+    if !joined.path().starts_with(base_dir) {
+        return Err(error::Error::UrlParse(format!(
+            "path '{}' escapes the base path '{}' (joined '{}')",
+            path,
+            base_dir,
+            joined.path()
+        ))
+        .into());
+    }
+
+    Ok(joined)
+}
+
+#[test]
+fn test_join_uri_within() {
+    let ret = join_uri_within("http://host/api/", "v1/status");
+    assert_eq!(ret.unwrap().as_str(), "http://host/api/v1/status");
+
+    let ret = join_uri_within("http://host/api/", "v1/../status");
+    assert_eq!(ret.unwrap().as_str(), "http://host/api/status");
+
+    let ret = join_uri_within("http://host/api/", "/api/status");
+    assert_eq!(ret.unwrap().as_str(), "http://host/api/status");
+
+    for path in [
+        "../admin/secret",
+        "v1/../../admin",
+        "%2e%2e/admin",
+        "..%2fadmin",
+        "/admin",
+        "//evil.com/x",
+        "https://evil.com/",
+    ] {
+        let ret = join_uri_within("http://host/api/", path);
+        assert!(ret.is_err(), "path {} must be rejected", path);
+    }
+
+    let ret = join_uri_within("http://localhost:9850", "/ext/X/sendMultiple");
+    assert!(ret.is_ok());
+}
+
+/// Joins the path to the URL and appends the percent-encoded query pairs.
+/// Any query already present in the base URL or the path is preserved,
+/// followed by the new pairs in order.
+pub fn join_uri_with_query(
+    url: impl IntoUrl,
+    path: &str,
+    query: &[(&str, &str)],
+) -> io::Result<Url> {
+    let base = url.into_url()?;
+    let mut uri = join_uri(&base, path)?;
+
+    // "Url::join" drops the base query when joining a non-empty path
+    let mut pairs: Vec<(String, String)> = Vec::new();
+    if !path.is_empty() {
+        pairs.extend(base.query_pairs().into_owned());
+    }
+    pairs.extend(uri.query_pairs().into_owned());
+    pairs.extend(query.iter().map(|(k, v)| (k.to_string(), v.to_string())));
+
+    uri.set_query(None);
+    if !pairs.is_empty() {
+        uri.query_pairs_mut().extend_pairs(pairs);
+    }
+    Ok(uri)
+}
+
+/// Appends the query parameters serialized from "query" (e.g., a struct
+/// deriving "Serialize", or a map) to the request URI, after any query it
+/// already has. "None" fields are skipped.
+///
+/// ```ignore
+/// #[derive(Serialize)]
+/// struct ListParams { page: u32, per_page: u32, cursor: Option<String> }
+///
+/// let req = with_query(create_get(url, "/items")?, &ListParams { .. })?;
+/// ```
+pub fn with_query<B, T>(mut req: Request<B>, query: &T) -> io::Result<Request<B>>
+where
+    T: serde::Serialize + ?Sized,
+{
+    let q = serde_urlencoded::to_string(query)
+        .map_err(|e| error::Error::UrlParse(format!("failed to serialize query {}", e)))?;
+    if q.is_empty() {
+        return Ok(req);
+    }
+
+    let mut parts = req.uri().clone().into_parts();
+    let path_and_query = match parts.path_and_query.as_ref().map(|p| p.as_str()) {
+        Some(p) if p.ends_with('?') => format!("{}{}", p, q),
+        Some(p) if p.contains('?') => format!("{}&{}", p, q),
+        Some(p) => format!("{}?{}", p, q),
+        None => format!("/?{}", q),
+    };
+    let invalid = |e: String| {
+        io::Error::from(error::Error::UrlParse(format!(
+            "failed to append query {}",
+            e
+        )))
+    };
+    parts.path_and_query = Some(
+        path_and_query
+            .parse()
+            .map_err(|e: hyper::http::uri::InvalidUri| invalid(e.to_string()))?,
+    );
+    *req.uri_mut() = hyper::Uri::from_parts(parts).map_err(|e| invalid(e.to_string()))?;
+    Ok(req)
+}
+
+#[test]
+fn test_with_query() {
+    #[derive(serde::Serialize)]
+    struct ListParams {
+        q: &'static str,
+        per_page: u32,
+        cursor: Option<&'static str>,
+    }
+
+    let params = ListParams {
+        q: "a b&c",
+        per_page: 10,
+        cursor: None,
+    };
+    let req = with_query(
+        create_get("http://localhost:9850", "/ext/info").unwrap(),
+        &params,
+    )
+    .unwrap();
+    assert_eq!(
+        req.uri(),
+        "http://localhost:9850/ext/info?q=a+b%26c&per_page=10"
+    );
+
+    let req = with_query(
+        create_get("http://localhost:9850/?api_key=k", "").unwrap(),
+        &[("page", 2)],
+    )
+    .unwrap();
+    assert_eq!(req.uri(), "http://localhost:9850/?api_key=k&page=2");
+
+    let req = create_get("http://localhost:9850", "/a").unwrap();
+    let req = with_query(req, &std::collections::BTreeMap::<String, String>::new()).unwrap();
+    assert_eq!(req.uri(), "http://localhost:9850/a");
+
+    // only flat key-value pairs
+    let e = with_query(
+        create_get("http://localhost:9850", "/a").unwrap(),
+        &[("nested", vec![1, 2])],
+    )
+    .unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+fn test_join_uri_with_query() {
+    let ret = join_uri_with_query("http://localhost:9850", "/ext/info", &[]);
+    assert_eq!(ret.unwrap().as_str(), "http://localhost:9850/ext/info");
+
+    let ret = join_uri_with_query(
+        "http://localhost:9850",
+        "/ext/info",
+        &[("q", "a b&c"), ("per_page", "10")],
+    );
+    assert_eq!(
+        ret.unwrap().as_str(),
+        "http://localhost:9850/ext/info?q=a+b%26c&per_page=10"
+    );
+
+    let ret = join_uri_with_query(
+        "http://localhost:9850/?api_key=k",
+        "/ext/info?page=2",
+        &[("per_page", "10")],
+    );
+    assert_eq!(
+        ret.unwrap().as_str(),
+        "http://localhost:9850/ext/info?api_key=k&page=2&per_page=10"
+    );
+
+    let ret = join_uri_with_query("http://localhost:9850/?api_key=k", "", &[("a", "1")]);
+    assert_eq!(
+        ret.unwrap().as_str(),
+        "http://localhost:9850/?api_key=k&a=1"
+    );
+}
+
+/// Write buffer size of "download_file", which also bounds the memory
+/// used by a download regardless of the file size.
+pub const DEFAULT_DOWNLOAD_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Low-speed limit of the "download_file*" functions (see "stall"), which
+/// have no overall timeout: fails a download that receives (almost)
+/// nothing for a minute.
+pub const DEFAULT_DOWNLOAD_LOW_SPEED_LIMIT: stall::LowSpeedLimit = stall::LowSpeedLimit {
+    bytes_per_sec: 1,
+    time: Duration::from_secs(60),
+};
+
+/// Client of the download helpers, built once with "DEFAULT_USER_AGENT"
+/// and the connect timeout of "new_connector". The transfers have no
+/// overall timeout (see "DEFAULT_DOWNLOAD_LOW_SPEED_LIMIT"), and keep no
+/// idle connections (see "HTTP_CLIENT").
+static DOWNLOAD_CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    ClientBuilder::new()
+        .user_agent(DEFAULT_USER_AGENT)
+        .connect_timeout(Duration::from_secs(5))
+        .pool_max_idle_per_host(0)
+        .build()
+        .expect("failed to build download client")
+});
+
+pub(crate) fn download_client() -> &'static reqwest::Client {
+    &DOWNLOAD_CLIENT
+}
+
+/// Response of a download request, from the download client or a
+/// "Manager" (see "Manager::download_file").
+pub(crate) struct DownloadResponse {
+    pub(crate) status: StatusCode,
+    pub(crate) headers: HeaderMap,
+    pub(crate) chunks: BoxStream<'static, io::Result<Bytes>>,
+}
+
+impl DownloadResponse {
+    fn from_reqwest(resp: reqwest::Response) -> Self {
+        Self {
+            status: resp.status(),
+            headers: resp.headers().clone(),
+            chunks: resp
+                .bytes_stream()
+                .map_err(|e| error::Error::from_reqwest("failed chunk", e))
+                .boxed(),
+        }
+    }
+
+    pub(crate) fn from_hyper(resp: Response<Body>) -> Self {
+        let (parts, body) = resp.into_parts();
+        Self {
+            status: parts.status,
+            headers: parts.headers,
+            chunks: body
+                .map_err(|e| error::Error::from_hyper("failed chunk", &e).into())
+                .boxed(),
+        }
+    }
+
+    fn content_length(&self) -> Option<u64> {
+        self.headers
+            .get(CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok())
+    }
+
+    /// Reads the body (e.g., a 404 page) into "error::Error::Status".
+    async fn into_status_error(mut self) -> Error {
+        let mut body = Vec::new();
+        while let Some(Ok(chunk)) = self.chunks.next().await {
+            body.extend_from_slice(&chunk);
+        }
+        error::Error::Status(self.status, body.into()).into()
+    }
+}
+
+/// Downloads a file to the "file_path". Non-2xx responses fail with
+/// "error::Error::Status", without creating the file.
+pub async fn download_file(ep: &str, file_path: &str) -> io::Result<()> {
+    download_file_with_buffer(ep, file_path, DEFAULT_DOWNLOAD_BUFFER_SIZE).await
+}
+
+/// Same as "download_file", but buffers up to "buffer_size" bytes before
+/// each write to the file (e.g., larger for fast links to slow disks).
+pub async fn download_file_with_buffer(
+    ep: &str,
+    file_path: &str,
+    buffer_size: usize,
+) -> io::Result<()> {
+    download(
+        ep,
+        file_path,
+        buffer_size,
+        DEFAULT_DOWNLOAD_LOW_SPEED_LIMIT,
+        |_, _, _| {},
+    )
+    .await
+}
+
+/// Same as "download_file", but fails with "stall::TransferStalled" once
+/// the throughput stays below the limit (e.g., 100 KiB/s over 30 seconds
+/// for multi-GB files), instead of "DEFAULT_DOWNLOAD_LOW_SPEED_LIMIT".
+pub async fn download_file_with_low_speed_limit(
+    ep: &str,
+    file_path: &str,
+    limit: stall::LowSpeedLimit,
+) -> io::Result<()> {
+    download(
+        ep,
+        file_path,
+        DEFAULT_DOWNLOAD_BUFFER_SIZE,
+        limit,
+        |_, _, _| {},
+    )
+    .await
+}
+
+/// Same as "download_file", but calls "progress" with the bytes written
+/// so far and the total size ("Content-Length", if known) after every
+/// chunk, e.g., to render a progress bar.
+pub async fn download_file_with_progress<F>(
+    ep: &str,
+    file_path: &str,
+    mut progress: F,
+) -> io::Result<()>
+where
+    F: FnMut(u64, Option<u64>),
+{
+    download(
+        ep,
+        file_path,
+        DEFAULT_DOWNLOAD_BUFFER_SIZE,
+        DEFAULT_DOWNLOAD_LOW_SPEED_LIMIT,
+        |_, downloaded, total| progress(downloaded, total),
+    )
+    .await
+}
+
+/// Expected digest of a downloaded file, in lowercase or uppercase hex.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Checksum {
+    Sha256(String),
+    Sha512(String),
+}
+
+impl Checksum {
+    /// Hashes the file, and fails with "InvalidData" if the digest does
+    /// not match (e.g., after "download_file_resumable").
+    pub async fn verify_file(&self, file_path: &str) -> io::Result<()> {
+        use tokio::io::AsyncReadExt;
+
+        let mut f = tokio::fs::File::open(file_path).await?;
+        let mut buf = vec![0u8; DEFAULT_DOWNLOAD_BUFFER_SIZE];
+        let mut hasher = self.hasher();
+        loop {
+            let n = f.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+        }
+        self.check(file_path, &hasher.finalize())
+    }
+
+    pub(crate) fn hasher(&self) -> ChecksumHasher {
+        use sha2::Digest;
+
+        match self {
+            Checksum::Sha256(_) => ChecksumHasher::Sha256(sha2::Sha256::new()),
+            Checksum::Sha512(_) => ChecksumHasher::Sha512(sha2::Sha512::new()),
+        }
+    }
+
+    /// Checks the digest of a download that wrote "file_path" (see
+    /// "download_file_verified"), and removes the file if the download
+    /// failed or the digest mismatches.
+    pub(crate) async fn verify_download(
+        &self,
+        file_path: &str,
+        hasher: ChecksumHasher,
+        ret: io::Result<()>,
+    ) -> io::Result<()> {
+        if let Err(e) = ret {
+            let _ = tokio::fs::remove_file(file_path).await;
+            return Err(e);
+        }
+        if let Err(e) = self.check(file_path, &hasher.finalize()) {
+            tokio::fs::remove_file(file_path).await?;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Fails with "InvalidData" if the hex digest of the file does not
+    /// match.
+    fn check(&self, file_path: &str, actual: &str) -> io::Result<()> {
+        let expected = match self {
+            Checksum::Sha256(hex) | Checksum::Sha512(hex) => hex,
+        };
+        if !actual.eq_ignore_ascii_case(expected.trim()) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "checksum mismatch for {} (expected {}, got {})",
+                    file_path, expected, actual
+                ),
+            ));
+        }
+        wire_info!("verified the checksum {} of {}", actual, file_path);
+        Ok(())
+    }
+}
+
+/// Hashes the bytes of a "Checksum".
+pub(crate) enum ChecksumHasher {
+    Sha256(sha2::Sha256),
+    Sha512(sha2::Sha512),
+}
+
+impl ChecksumHasher {
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        use sha2::Digest;
+
+        match self {
+            ChecksumHasher::Sha256(h) => h.update(data),
+            ChecksumHasher::Sha512(h) => h.update(data),
+        }
+    }
+
+    /// Returns the lowercase hex digest.
+    fn finalize(self) -> String {
+        use sha2::Digest;
+
+        match self {
+            ChecksumHasher::Sha256(h) => format!("{:x}", h.finalize()),
+            ChecksumHasher::Sha512(h) => format!("{:x}", h.finalize()),
+        }
+    }
+}
+
+/// Same as "download_file", but hashes the body while writing it, and
+/// fails with "InvalidData" if the digest does not match. The file is
+/// removed if the download fails or the digest mismatches.
+pub async fn download_file_verified(
+    ep: &str,
+    file_path: &str,
+    checksum: Checksum,
+) -> io::Result<()> {
+    download_file_verified_with_low_speed_limit(
+        ep,
+        file_path,
+        checksum,
+        DEFAULT_DOWNLOAD_LOW_SPEED_LIMIT,
+    )
+    .await
+}
+
+/// Same as "download_file_verified", but with the low-speed limit (see
+/// "download_file_with_low_speed_limit").
+pub async fn download_file_verified_with_low_speed_limit(
+    ep: &str,
+    file_path: &str,
+    checksum: Checksum,
+    limit: stall::LowSpeedLimit,
+) -> io::Result<()> {
+    let mut hasher = checksum.hasher();
+    let ret = download(
+        ep,
+        file_path,
+        DEFAULT_DOWNLOAD_BUFFER_SIZE,
+        limit,
+        |chunk, _, _| hasher.update(chunk),
+    )
+    .await;
+    checksum.verify_download(file_path, hasher, ret).await
+}
+
+/// Downloads a file over "segments" concurrent connections, each reading
+/// a byte range into its place in the file, e.g., to speed up downloads
+/// from high-latency mirrors. Falls back to "download_file" if a HEAD
+/// request does not return the size, or the server does not advertise
+/// "Accept-Ranges: bytes".
+pub async fn download_file_segmented(ep: &str, file_path: &str, segments: usize) -> io::Result<()> {
+    let cli = download_client();
+    let resp = cli
+        .head(ep)
+        .send()
+        .await
+        .map_err(|e| error::Error::from_reqwest("failed head", e))?;
+    let accepts_ranges = resp
+        .headers()
+        .get(reqwest::header::ACCEPT_RANGES)
+        .map_or(false, |v| v.as_bytes().eq_ignore_ascii_case(b"bytes"));
+    // not "content_length", which is the size of the (empty) HEAD body
+    let total = resp
+        .headers()
+        .get(reqwest::header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    let total = match total {
+        Some(total) if resp.status().is_success() && accepts_ranges && total > 0 => total,
+        _ => {
+            wire_info!(
+                "{} does not support range requests, downloading in a single stream",
+                redact::url(ep)
+            );
+            return download_file(ep, file_path).await;
+        }
+    };
+
+    let segments = (segments.max(1) as u64).min(total);
+    let segment_size = (total + segments - 1) / segments;
+    // e.g., 6 segments of 10 bytes are 5 segments of 2 bytes
+    let segments = (total + segment_size - 1) / segment_size;
+    wire_info!(
+        "downloading {} bytes via {} in {} segments",
+        total,
+        redact::url(ep),
+        segments
+    );
+    tokio::fs::File::create(file_path)
+        .await?
+        .set_len(total)
+        .await?;
+
+    let downloads = (0..segments).map(|i| {
+        let start = i * segment_size;
+        let end = (start + segment_size).min(total) - 1;
+        download_segment(cli, ep, file_path, start, end)
+    });
+    if let Err(e) = futures_util::future::try_join_all(downloads).await {
+        let _ = tokio::fs::remove_file(file_path).await;
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Writes the inclusive byte range "start..=end" at its offset in the file.
+async fn download_segment(
+    cli: &reqwest::Client,
+    ep: &str,
+    file_path: &str,
+    start: u64,
+    end: u64,
+) -> io::Result<()> {
+    use tokio::io::AsyncSeekExt;
+
+    let resp = cli
+        .get(ep)
+        .header(reqwest::header::RANGE, format!("bytes={}-{}", start, end))
+        .send()
+        .await
+        .map_err(|e| error::Error::from_reqwest("failed send", e))?;
+    if !resp.status().is_success() {
+        let status = resp.status();
+        let body = resp.bytes().await.unwrap_or_default();
+        return Err(error::Error::Status(status, body).into());
+    }
+    if resp.status() != StatusCode::PARTIAL_CONTENT {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "unexpected status code {} for the range {}-{}",
+                resp.status(),
+                start,
+                end
+            ),
+        ));
+    }
+    let content_range = resp
+        .headers()
+        .get(reqwest::header::CONTENT_RANGE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("");
+    let range = range::parse_content_range(content_range)?;
+    if range.start != start || range.end != end {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "requested the range {}-{}, but the server sent {}-{}",
+                start, end, range.start, range.end
+            ),
+        ));
+    }
+
+    let mut f = tokio::fs::OpenOptions::new()
+        .write(true)
+        .open(file_path)
+        .await?;
+    f.seek(io::SeekFrom::Start(start)).await?;
+    let mut f = tokio::io::BufWriter::with_capacity(DEFAULT_DOWNLOAD_BUFFER_SIZE, f);
+    let mut chunks = resp
+        .bytes_stream()
+        .map_err(|e| error::Error::from_reqwest("failed chunk", e));
+    let mut monitor = stall::SpeedMonitor::new(DEFAULT_DOWNLOAD_LOW_SPEED_LIMIT);
+    let mut written = 0;
+    while let Some(chunk) = monitor.next_from(&mut chunks).await? {
+        written += chunk.len() as u64;
+        if written > range.len() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "range {}-{} returned more than {} bytes",
+                    start,
+                    end,
+                    range.len()
+                ),
+            ));
+        }
+        f.write_all(&chunk).await?;
+    }
+    f.flush().await?;
+    if written != range.len() {
+        return Err(Error::new(
+            ErrorKind::UnexpectedEof,
+            format!(
+                "range {}-{} ended after {} of {} bytes",
+                start,
+                end,
+                written,
+                range.len()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Streams the body to the file, calling "on_chunk" with every chunk, the
+/// bytes written so far, and "Content-Length" (if known).
+async fn download<F>(
+    ep: &str,
+    file_path: &str,
+    buffer_size: usize,
+    low_speed_limit: stall::LowSpeedLimit,
+    on_chunk: F,
+) -> io::Result<()>
+where
+    F: FnMut(&[u8], u64, Option<u64>),
+{
+    wire_info!(
+        "downloading the file via {} (buffer size {})",
+        redact::url(ep),
+        buffer_size
+    );
+    let resp = download_client()
+        .get(ep)
+        .send()
+        .await
+        .map_err(|e| error::Error::from_reqwest("failed get", e))?;
+    write_download(
+        DownloadResponse::from_reqwest(resp),
+        file_path,
+        buffer_size,
+        low_speed_limit,
+        on_chunk,
+    )
+    .await?;
+    Ok(())
+}
+
+/// Streams the body of a successful response to the file (see
+/// "download"), and returns the bytes written.
+pub(crate) async fn write_download<F>(
+    mut resp: DownloadResponse,
+    file_path: &str,
+    buffer_size: usize,
+    low_speed_limit: stall::LowSpeedLimit,
+    mut on_chunk: F,
+) -> io::Result<u64>
+where
+    F: FnMut(&[u8], u64, Option<u64>),
+{
+    if !resp.status.is_success() {
+        // e.g., a 404 page must not end up in the file
+        return Err(resp.into_status_error().await);
+    }
+    let total = resp.content_length();
+
+    // stream the chunks to the file, rather than buffering the whole body
+    let f = tokio::fs::File::create(file_path).await?;
+    let mut f = tokio::io::BufWriter::with_capacity(buffer_size.max(1), f);
+    let mut monitor = stall::SpeedMonitor::new(low_speed_limit);
+    let mut downloaded = 0;
+    while let Some(chunk) = monitor.next_from(&mut resp.chunks).await? {
+        f.write_all(&chunk).await?;
+        downloaded += chunk.len() as u64;
+        on_chunk(&chunk, downloaded, total);
+    }
+    f.flush().await?;
+
+    Ok(downloaded)
+}
+
+/// Downloads a file to the "file_path", resuming a previous attempt.
+/// The body is written to "{file_path}.part", which is renamed once
+/// complete. If the partial file exists (e.g., the link dropped), only the
+/// remaining bytes are requested with "Range: bytes=N-", and a server
+/// without range support has the whole file downloaded again.
+pub async fn download_file_resumable(ep: &str, file_path: &str) -> io::Result<()> {
+    download_file_resumable_with_low_speed_limit(ep, file_path, DEFAULT_DOWNLOAD_LOW_SPEED_LIMIT)
+        .await
+}
+
+/// Same as "download_file_resumable", but with the low-speed limit (see
+/// "download_file_with_low_speed_limit"). A stalled download keeps the
+/// partial file for the next attempt.
+pub async fn download_file_resumable_with_low_speed_limit(
+    ep: &str,
+    file_path: &str,
+    limit: stall::LowSpeedLimit,
+) -> io::Result<()> {
+    download_resumable(ep, file_path, limit, |from| async move {
+        let mut req = download_client().get(ep);
+        if let Some(from) = from {
+            req = req.header(reqwest::header::RANGE, format!("bytes={}-", from));
+        }
+        let resp = req
+            .send()
             .await
+            .map_err(|e| error::Error::from_reqwest("failed send", e))?;
+        Ok(DownloadResponse::from_reqwest(resp))
+    })
+    .await?;
+    Ok(())
+}
+
+/// Downloads to "{file_path}.part" and renames it once complete (see
+/// "download_file_resumable"), with the responses of "send", which
+/// requests the bytes from the offset if any ("Range: bytes=N-"). Returns
+/// the bytes received.
+pub(crate) async fn download_resumable<F, Fut>(
+    ep: &str,
+    file_path: &str,
+    limit: stall::LowSpeedLimit,
+    mut send: F,
+) -> io::Result<u64>
+where
+    F: FnMut(Option<u64>) -> Fut,
+    Fut: Future<Output = io::Result<DownloadResponse>>,
+{
+    let part_path = format!("{}.part", file_path);
+    let (append, mut resp) = loop {
+        let existing = match tokio::fs::metadata(&part_path).await {
+            Ok(m) => m.len(),
+            Err(e) if e.kind() == ErrorKind::NotFound => 0,
+            Err(e) => return Err(e),
+        };
+        let resp = if existing > 0 {
+            wire_info!(
+                "resuming the download via {} from byte {}",
+                redact::url(ep),
+                existing
+            );
+            send(Some(existing)).await?
+        } else {
+            wire_info!("downloading the file via {}", redact::url(ep));
+            send(None).await?
+        };
+
+        let content_range = resp
+            .headers
+            .get(reqwest::header::CONTENT_RANGE)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string());
+        match resp.status {
+            StatusCode::PARTIAL_CONTENT if existing > 0 => {
+                let range = range::parse_content_range(content_range.as_deref().unwrap_or(""))?;
+                if range.start != existing {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!(
+                            "requested bytes from {}, but the server sent {}-{}",
+                            existing, range.start, range.end
+                        ),
+                    ));
+                }
+                break (true, resp);
+            }
+            // "bytes */N": nothing left to read if the partial file is complete
+            StatusCode::RANGE_NOT_SATISFIABLE
+                if existing > 0
+                    && content_range.as_deref() == Some(&format!("bytes */{}", existing)) =>
             {
-                Ok(b) => b,
-                Err(e) => return Err(e),
-            };
-            buf.to_vec()
+                wire_info!("{} is already complete", part_path);
+                tokio::fs::rename(&part_path, file_path).await?;
+                return Ok(0);
+            }
+            StatusCode::RANGE_NOT_SATISFIABLE if existing > 0 => {
+                // e.g., the file changed on the server, so start over
+                wire_warn!("cannot resume {}, downloading the whole file", part_path);
+                tokio::fs::remove_file(&part_path).await?;
+            }
+            s if s.is_success() => {
+                if existing > 0 {
+                    wire_warn!(
+                        "{} ignored the range (status code {}), downloading the whole file",
+                        redact::url(ep),
+                        s
+                    );
+                }
+                break (false, resp);
+            }
+            _ => return Err(resp.into_status_error().await),
         }
     };
-    Ok(output)
+
+    let f = tokio::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(append)
+        .truncate(!append)
+        .open(&part_path)
+        .await?;
+    let mut f = tokio::io::BufWriter::with_capacity(DEFAULT_DOWNLOAD_BUFFER_SIZE, f);
+    let mut monitor = stall::SpeedMonitor::new(limit);
+    let mut downloaded = 0;
+    loop {
+        let chunk = match monitor.next_from(&mut resp.chunks).await {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => break,
+            Err(e) => {
+                // keeps the partial file for the next attempt
+                f.flush().await?;
+                return Err(e);
+            }
+        };
+        f.write_all(&chunk).await?;
+        downloaded += chunk.len() as u64;
+    }
+    f.flush().await?;
+    drop(f);
+
+    tokio::fs::rename(&part_path, file_path).await?;
+    Ok(downloaded)
+}
+
+/// RUST_LOG=debug cargo test --lib -- test_download_file --exact --show-output
+#[tokio::test]
+async fn test_download_file() {
+    let server = testing::MockServer::start().await.unwrap();
+    let body: Vec<u8> = (0..200_000).map(|i| (i % 251) as u8).collect();
+    server.stub(Method::GET, "/file.bin", 200, body.clone());
+
+    let file_path = std::env::temp_dir().join("http-manager-test-download.bin");
+    let file_path = file_path.to_str().unwrap();
+    download_file(&format!("{}/file.bin", server.url()), file_path)
+        .await
+        .unwrap();
+    assert_eq!(std::fs::read(file_path).unwrap(), body);
+    server
+        .assert_received(Method::GET, "/file.bin")
+        .with_header("user-agent", DEFAULT_USER_AGENT)
+        .once();
+
+    // buffers smaller and larger than the body
+    for buffer_size in [1000, 1024 * 1024] {
+        download_file_with_buffer(
+            &format!("{}/file.bin", server.url()),
+            file_path,
+            buffer_size,
+        )
+        .await
+        .unwrap();
+        assert_eq!(std::fs::read(file_path).unwrap(), body);
+    }
+
+    let mut reports = Vec::new();
+    download_file_with_progress(
+        &format!("{}/file.bin", server.url()),
+        file_path,
+        |n, total| reports.push((n, total)),
+    )
+    .await
+    .unwrap();
+    assert!(!reports.is_empty());
+    assert!(reports.windows(2).all(|w| w[0].0 < w[1].0));
+    assert_eq!(reports.last(), Some(&(200_000, Some(200_000))));
+    std::fs::remove_file(file_path).unwrap();
+
+    // error pages are not written to the file
+    server.stub(Method::GET, "/missing.bin", 404, "not found");
+    let e = download_file(&format!("{}/missing.bin", server.url()), file_path)
+        .await
+        .unwrap_err();
+    match error::Error::from(e) {
+        error::Error::Status(status, body) => {
+            assert_eq!(status, StatusCode::NOT_FOUND);
+            assert_eq!(body, "not found");
+        }
+        e => panic!("unexpected error {:?}", e),
+    }
+    assert!(!std::path::Path::new(file_path).exists());
+}
+
+/// RUST_LOG=debug cargo test --lib -- test_download_file_verified --exact --show-output
+#[tokio::test]
+async fn test_download_file_verified() {
+    let server = testing::MockServer::start().await.unwrap();
+    server.stub(Method::GET, "/hello.txt", 200, "hello");
+    let ep = format!("{}/hello.txt", server.url());
+
+    let file_path = std::env::temp_dir().join("http-manager-test-download-verified.txt");
+    let file_path = file_path.to_str().unwrap();
+    let sha256 = "2CF24DBA5FB0A30E26E83B2AC5B9E29E1B161E5C1FA7425E73043362938B9824";
+    download_file_verified(&ep, file_path, Checksum::Sha256(sha256.to_string()))
+        .await
+        .unwrap();
+    assert_eq!(std::fs::read(file_path).unwrap(), b"hello");
+
+    let sha512 = "9b71d224bd62f3785d96d46ad3ea3d73319bfbc2890caadae2dff72519673ca72323c3d99ba5c11d7c7acc6e14b8c5da0c4663475c2e5c3adef46f73bcdec043";
+    download_file_verified(&ep, file_path, Checksum::Sha512(sha512.to_string()))
+        .await
+        .unwrap();
+
+    Checksum::Sha256(sha256.to_string())
+        .verify_file(file_path)
+        .await
+        .unwrap();
+    let e = Checksum::Sha512("00".repeat(64))
+        .verify_file(file_path)
+        .await
+        .unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidData);
+
+    let e = download_file_verified(&ep, file_path, Checksum::Sha256("00".repeat(32)))
+        .await
+        .unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidData);
+    assert!(!std::path::Path::new(file_path).exists());
+}
+
+/// RUST_LOG=debug cargo test --lib -- test_download_file_segmented --exact --show-output
+#[tokio::test]
+async fn test_download_file_segmented() {
+    let server = testing::MockServer::start().await.unwrap();
+    let body: Vec<u8> = (0..10_000).map(|i| (i % 251) as u8).collect();
+    server.register(
+        testing::Stub::new(Method::HEAD, "/file.bin")
+            .respond_header("content-length", "10000")
+            .respond_header("accept-ranges", "bytes"),
+    );
+    for (start, end) in [(0, 3333), (3334, 6667), (6668, 9999)] {
+        server.register(
+            testing::Stub::new(Method::GET, "/file.bin")
+                .with_header("range", &format!("bytes={}-{}", start, end))
+                .respond(206, body[start..=end].to_vec())
+                .respond_header("content-range", &format!("bytes {}-{}/10000", start, end)),
+        );
+    }
+    // without "accept-ranges"
+    server.register(
+        testing::Stub::new(Method::HEAD, "/plain.bin").respond_header("content-length", "10000"),
+    );
+    server.stub(Method::GET, "/plain.bin", 200, body.clone());
+
+    let file_path = std::env::temp_dir().join("http-manager-test-download-segmented.bin");
+    let file_path = file_path.to_str().unwrap();
+    download_file_segmented(&format!("{}/file.bin", server.url()), file_path, 3)
+        .await
+        .unwrap();
+    assert_eq!(std::fs::read(file_path).unwrap(), body);
+    server
+        .assert_received(Method::GET, "/file.bin")
+        .with_header("user-agent", DEFAULT_USER_AGENT)
+        .times(3);
+    server
+        .assert_received(Method::HEAD, "/file.bin")
+        .with_header("user-agent", DEFAULT_USER_AGENT)
+        .once();
+
+    download_file_segmented(&format!("{}/plain.bin", server.url()), file_path, 3)
+        .await
+        .unwrap();
+    assert_eq!(std::fs::read(file_path).unwrap(), body);
+    server.assert_received(Method::GET, "/plain.bin").once();
+
+    // a segment the server does not serve
+    let e = download_file_segmented(&format!("{}/file.bin", server.url()), file_path, 4)
+        .await
+        .unwrap_err();
+    assert!(e.to_string().contains("404"));
+    assert!(!std::path::Path::new(file_path).exists());
+}
+
+/// RUST_LOG=debug cargo test --lib -- test_download_file_resumable --exact --show-output
+#[tokio::test]
+async fn test_download_file_resumable() {
+    let server = testing::MockServer::start().await.unwrap();
+    let body: Vec<u8> = (0..10_000).map(|i| (i % 251) as u8).collect();
+    server.stub(Method::GET, "/file.bin", 200, body.clone());
+    server.register(
+        testing::Stub::new(Method::GET, "/file.bin")
+            .with_header("range", "bytes=4000-")
+            .respond(206, body[4000..].to_vec())
+            .respond_header("content-range", "bytes 4000-9999/10000"),
+    );
+    server.register(
+        testing::Stub::new(Method::GET, "/file.bin")
+            .with_header("range", "bytes=10000-")
+            .respond(416, "")
+            .respond_header("content-range", "bytes */10000"),
+    );
+    let ep = format!("{}/file.bin", server.url());
+
+    let file_path = std::env::temp_dir().join("http-manager-test-download-resumable.bin");
+    let file_path = file_path.to_str().unwrap();
+    let part_path = format!("{}.part", file_path);
+    let _ = std::fs::remove_file(file_path);
+
+    // from scratch
+    download_file_resumable(&ep, file_path).await.unwrap();
+    assert_eq!(std::fs::read(file_path).unwrap(), body);
+    assert!(!std::path::Path::new(&part_path).exists());
+
+    // resumed, and already complete
+    std::fs::write(&part_path, &body[..4000]).unwrap();
+    download_file_resumable(&ep, file_path).await.unwrap();
+    assert_eq!(std::fs::read(file_path).unwrap(), body);
+    server
+        .assert_received(Method::GET, "/file.bin")
+        .with_header("range", "bytes=4000-")
+        .once();
+
+    std::fs::write(&part_path, &body).unwrap();
+    download_file_resumable(&ep, file_path).await.unwrap();
+    assert_eq!(std::fs::read(file_path).unwrap(), body);
+
+    // the server ignores the range
+    std::fs::write(&part_path, &body[..1234]).unwrap();
+    download_file_resumable(&ep, file_path).await.unwrap();
+    assert_eq!(std::fs::read(file_path).unwrap(), body);
+    std::fs::remove_file(file_path).unwrap();
+
+    // typed errors
+    server.stub(Method::GET, "/busy.bin", 503, "retry later");
+    let e = download_file_resumable(&format!("{}/busy.bin", server.url()), file_path)
+        .await
+        .unwrap_err();
+    assert_eq!(
+        error::Error::from(e).status(),
+        Some(StatusCode::SERVICE_UNAVAILABLE)
+    );
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let closed = format!("http://{}/file.bin", listener.local_addr().unwrap());
+    drop(listener);
+    let e = download_file_resumable(&closed, file_path)
+        .await
+        .unwrap_err();
+    assert!(matches!(error::Error::from(e), error::Error::Connect(_)));
+    assert!(!std::path::Path::new(file_path).exists());
+}
+
+/// RUST_LOG=debug cargo test --lib -- test_download_file_stalled --exact --show-output
+#[tokio::test]
+async fn test_download_file_stalled() {
+    use tokio::net::TcpListener;
+
+    // sends the headers and a few bytes, then hangs
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let ep = format!("http://{}/file.bin", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                stream
+                    .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 1000000\r\n\r\nhello")
+                    .await
+                    .unwrap();
+                tokio::time::sleep(Duration::from_secs(30)).await;
+            });
+        }
+    });
+
+    let file_path = std::env::temp_dir().join("http-manager-test-download-stalled.bin");
+    let file_path = file_path.to_str().unwrap();
+    let part_path = format!("{}.part", file_path);
+    let limit = stall::LowSpeedLimit::new(1024, Duration::from_millis(300));
+
+    let started = std::time::Instant::now();
+    let e = download_file_with_low_speed_limit(&ep, file_path, limit)
+        .await
+        .unwrap_err();
+    assert!(stall::is_stalled(&e), "{}", e);
+
+    // keeps the partial file for the next attempt
+    let e = download_file_resumable_with_low_speed_limit(&ep, file_path, limit)
+        .await
+        .unwrap_err();
+    assert!(stall::is_stalled(&e), "{}", e);
+    assert_eq!(std::fs::read(&part_path).unwrap(), b"hello");
+    assert!(started.elapsed() < Duration::from_secs(5));
+
+    let _ = std::fs::remove_file(file_path);
+    std::fs::remove_file(&part_path).unwrap();
+}
+
+/// Sends a GET request and returns the body regardless of the status code.
+/// HTTPS certificates are verified unless "insecure" is true
+/// ("curl --insecure"), which is only meant for self-signed test endpoints.
+/// TODO: implement this with native Rust
+pub async fn get_non_tls(url: &str, url_path: &str, insecure: bool) -> io::Result<Bytes> {
+    Ok(send_non_tls(
+        Method::GET,
+        url,
+        url_path,
+        None,
+        &HeaderMap::new(),
+        insecure,
+    )
+    .await?
+    .bytes)
+}
+
+/// Same as "get_non_tls", but returns the status and headers with the
+/// body.
+pub async fn get_non_tls_full(
+    url: &str,
+    url_path: &str,
+    insecure: bool,
+) -> io::Result<ResponseParts> {
+    send_non_tls(
+        Method::GET,
+        url,
+        url_path,
+        None,
+        &HeaderMap::new(),
+        insecure,
+    )
+    .await
+}
+
+/// Same as "get_non_tls" but with the headers (see "header_map").
+pub async fn get_non_tls_with_headers(
+    url: &str,
+    url_path: &str,
+    headers: &HeaderMap,
+    insecure: bool,
+) -> io::Result<Bytes> {
+    Ok(
+        send_non_tls(Method::GET, url, url_path, None, headers, insecure)
+            .await?
+            .bytes,
+    )
+}
+
+/// Same as "get_non_tls" but with the "Authorization" header of the
+/// credentials.
+pub async fn get_non_tls_with_auth(
+    url: &str,
+    url_path: &str,
+    auth: &auth::Auth,
+    insecure: bool,
+) -> io::Result<Bytes> {
+    get_non_tls_with_headers(url, url_path, &auth.headers()?, insecure).await
+}
+
+/// Same as "get_non_tls", but retries connection errors, timeouts, and
+/// the retryable status codes of the policy.
+pub async fn get_non_tls_with_retry(
+    url: &str,
+    url_path: &str,
+    insecure: bool,
+    policy: &retry::RetryPolicy,
+) -> io::Result<Bytes> {
+    let endpoint = redact::url(join_uri(url, url_path)?.as_str());
+    let headers = HeaderMap::new();
+    let attempted = retry::run(policy, true, &clock::TokioClock, &endpoint, || {
+        let sent = send_non_tls(Method::GET, url, url_path, None, &headers, insecure);
+        async move { sent.await.map(|p| (p.status, p.bytes)) }
+    })
+    .await?;
+    Ok(attempted.value.1)
+}
+
+/// Sends the request with the JSON body, if any, and returns the
+/// response of any status code. HTTPS requests are sent via "reqwest".
+async fn send_non_tls(
+    method: Method,
+    url: &str,
+    url_path: &str,
+    json_body: Option<Bytes>,
+    headers: &HeaderMap,
+    insecure: bool,
+) -> io::Result<ResponseParts> {
+    let joined = join_uri(url, url_path)?;
+    wire_debug!(
+        "non-TLS HTTP {} {}-byte data to {}",
+        method,
+        json_body.as_ref().map_or(0, |b| b.len()),
+        redact::url(joined.as_str())
+    );
+
+    if url.starts_with("https") {
+        let cli = non_tls_client(Duration::from_secs(15), insecure)?;
+        let mut req = cli.request(method, joined.as_str());
+        if let Some(data) = json_body {
+            req = req.header(CONTENT_TYPE, JSON_CONTENT_TYPE).body(data);
+        }
+        send_reqwest(req.headers(headers.clone())).await
+    } else {
+        let mut req = create_request(
+            method,
+            url,
+            url_path,
+            JoinMode::Resolve,
+            json_body.map(Body::from),
+        )?;
+        set_headers(req.headers_mut(), headers);
+        read_bytes_full(req, Duration::from_secs(15), false).await
+    }
+}
+
+/// Sends the body stream with the content length if known (otherwise
+/// with chunked transfer encoding), as "application/octet-stream". The
+/// timeout covers the whole upload.
+async fn send_stream_non_tls<S>(
+    method: Method,
+    url: &str,
+    url_path: &str,
+    body: S,
+    content_length: Option<u64>,
+    timeout_dur: Duration,
+    insecure: bool,
+) -> io::Result<ResponseParts>
+where
+    S: futures_util::Stream<Item = io::Result<Bytes>> + Send + Sync + 'static,
+{
+    let joined = join_uri(url, url_path)?;
+    wire_debug!(
+        "non-TLS HTTP {} {:?}-byte stream to {}",
+        method,
+        content_length,
+        redact::url(joined.as_str())
+    );
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static(OCTET_STREAM_CONTENT_TYPE),
+    );
+    if let Some(n) = content_length {
+        headers.insert(CONTENT_LENGTH, HeaderValue::from(n));
+    }
+
+    if url.starts_with("https") {
+        let cli = non_tls_client(timeout_dur, insecure)?;
+        let req = cli
+            .request(method, joined.as_str())
+            .headers(headers)
+            .body(reqwest::Body::wrap_stream(body));
+        send_reqwest(req).await
+    } else {
+        let mut req = create_request(
+            method,
+            url,
+            url_path,
+            JoinMode::Resolve,
+            Some(Body::wrap_stream(body)),
+        )?;
+        set_headers(req.headers_mut(), &headers);
+        read_bytes_full(req, timeout_dur, false).await
+    }
+}
+
+/// Returns the client of the non-TLS helpers for HTTPS URLs.
+fn non_tls_client(timeout_dur: Duration, insecure: bool) -> io::Result<reqwest::Client> {
+    if insecure {
+        wire_warn!("sending via danger_accept_invalid_certs");
+    }
+    ClientBuilder::new()
+        .user_agent(DEFAULT_USER_AGENT)
+        .danger_accept_invalid_certs(insecure)
+        .timeout(timeout_dur)
+        .connection_verbose(true)
+        .build()
+        .map_err(|e| error::Error::from_reqwest("failed ClientBuilder build", e))
+}
+
+async fn send_reqwest(req: reqwest::RequestBuilder) -> io::Result<ResponseParts> {
+    let resp = req
+        .send()
+        .await
+        .map_err(|e| error::Error::from_reqwest("failed ClientBuilder send", e))?;
+    let status = resp.status();
+    let headers = resp.headers().clone();
+    let bytes = resp
+        .bytes()
+        .await
+        .map_err(|e| error::Error::from_reqwest("failed ClientBuilder send", e))?;
+    Ok(ResponseParts {
+        status,
+        headers,
+        bytes,
+    })
+}
+
+/// RUST_LOG=debug cargo test --lib -- test_read_bytes_keep_alive --exact --show-output
+#[tokio::test]
+async fn test_read_bytes_keep_alive() {
+    let server = testing::MockServer::start().await.unwrap();
+    server.stub(Method::GET, "/", 200, "ok");
+
+    // the global clients keep no idle connections (see "HTTP_CLIENT")
+    for _ in 0..3 {
+        let req = create_get(server.url(), "/").unwrap();
+        let out = read_bytes(req, Duration::from_secs(5), false, true).await;
+        assert_eq!(out.unwrap(), "ok");
+    }
+    assert_eq!(server.accepted_connections(), 3);
+
+    // the manager does
+    let manager = Manager::new().unwrap();
+    for _ in 0..3 {
+        let req = create_get(server.url(), "/").unwrap();
+        assert_eq!(manager.read_bytes(req, true).await.unwrap(), "ok");
+    }
+    assert_eq!(server.accepted_connections(), 4);
+}
+
+/// RUST_LOG=debug cargo test --lib -- test_user_agent --exact --show-output
+#[tokio::test]
+async fn test_user_agent() {
+    let server = testing::MockServer::start().await.unwrap();
+    server.register(
+        testing::Stub::new(Method::GET, "/")
+            .with_header("user-agent", DEFAULT_USER_AGENT)
+            .respond(200, "ok"),
+    );
+
+    let req = create_get(server.url(), "/").unwrap();
+    let out = read_bytes(req, Duration::from_secs(5), false, true).await;
+    assert_eq!(out.unwrap(), "ok");
+
+    assert_eq!(
+        user_agent("my-service/1.2", true),
+        format!("my-service/1.2 http-manager/{}", env!("CARGO_PKG_VERSION"))
+    );
+    assert_eq!(user_agent("my-service/1.2", false), "my-service/1.2");
 }
 
 /// RUST_LOG=debug cargo test --lib -- test_get_non_tls --exact --show-output
@@ -300,53 +2527,923 @@ fn test_get_non_tls() {
         .block_on(get_non_tls(
             "https://api.github.com",
             "repos/ava-labs/avalanchego/releases/latest",
+            false,
         ))
         .unwrap();
-    println!("out: {}", String::from_utf8(out).unwrap());
+    println!("out: {}", String::from_utf8(out.to_vec()).unwrap());
 }
 
-/// Posts JSON body.
-pub async fn post_non_tls(url: &str, url_path: &str, data: &str) -> io::Result<Vec<u8>> {
-    let joined = join_uri(url, url_path)?;
-    log::debug!("non-TLS HTTP post {}-byte data to {:?}", data.len(), joined);
-
-    let output = {
-        if url.starts_with("https") {
-            log::info!("sending via danger_accept_invalid_certs");
-
-            let cli = ClientBuilder::new()
-                .user_agent(env!("CARGO_PKG_NAME"))
-                .danger_accept_invalid_certs(true)
-                .timeout(Duration::from_secs(15))
-                .connection_verbose(true)
-                .build()
-                .map_err(|e| {
-                    Error::new(
-                        ErrorKind::Other,
-                        format!("failed ClientBuilder build {}", e),
-                    )
-                })?;
-            let resp = cli
-                .post(joined.as_str())
-                .header(CONTENT_TYPE, "application/json")
-                .body(data.to_string())
-                .send()
-                .await
-                .map_err(|e| {
-                    Error::new(ErrorKind::Other, format!("failed ClientBuilder send {}", e))
-                })?;
-            let out = resp.bytes().await.map_err(|e| {
-                Error::new(ErrorKind::Other, format!("failed ClientBuilder send {}", e))
-            })?;
-            out.into()
-        } else {
-            let req = create_json_post(url, url_path, data)?;
-            let buf = match read_bytes(req, Duration::from_secs(15), false, false).await {
-                Ok(b) => b,
-                Err(e) => return Err(e),
-            };
-            buf.to_vec()
+/// Posts JSON body (see "get_non_tls" for "insecure").
+pub async fn post_non_tls(
+    url: &str,
+    url_path: &str,
+    data: impl Into<Bytes>,
+    insecure: bool,
+) -> io::Result<Bytes> {
+    Ok(send_non_tls(
+        Method::POST,
+        url,
+        url_path,
+        Some(data.into()),
+        &HeaderMap::new(),
+        insecure,
+    )
+    .await?
+    .bytes)
+}
+
+/// Same as "post_non_tls", but returns the status and headers with the
+/// body.
+pub async fn post_non_tls_full(
+    url: &str,
+    url_path: &str,
+    data: impl Into<Bytes>,
+    insecure: bool,
+) -> io::Result<ResponseParts> {
+    let data = Some(data.into());
+    send_non_tls(
+        Method::POST,
+        url,
+        url_path,
+        data,
+        &HeaderMap::new(),
+        insecure,
+    )
+    .await
+}
+
+/// Same as "post_non_tls" but with the headers (see "header_map").
+pub async fn post_non_tls_with_headers(
+    url: &str,
+    url_path: &str,
+    data: impl Into<Bytes>,
+    headers: &HeaderMap,
+    insecure: bool,
+) -> io::Result<Bytes> {
+    let ret = send_non_tls(
+        Method::POST,
+        url,
+        url_path,
+        Some(data.into()),
+        headers,
+        insecure,
+    )
+    .await?;
+    Ok(ret.bytes)
+}
+
+/// Same as "post_non_tls" but with the "Authorization" header of the
+/// credentials.
+pub async fn post_non_tls_with_auth(
+    url: &str,
+    url_path: &str,
+    data: impl Into<Bytes>,
+    auth: &auth::Auth,
+    insecure: bool,
+) -> io::Result<Bytes> {
+    post_non_tls_with_headers(url, url_path, data, &auth.headers()?, insecure).await
+}
+
+/// Same as "post_non_tls", but compresses bodies of at least "min_size"
+/// bytes (see "create_json_post_gzip").
+#[cfg(feature = "compression")]
+pub async fn post_non_tls_gzip(
+    url: &str,
+    url_path: &str,
+    data: impl Into<Bytes>,
+    min_size: usize,
+    insecure: bool,
+) -> io::Result<Bytes> {
+    let (body, headers) = gzip_body(data.into(), min_size)?;
+    let ret = send_non_tls(Method::POST, url, url_path, Some(body), &headers, insecure).await?;
+    Ok(ret.bytes)
+}
+
+/// Same as "post_non_tls", but retries connection errors, timeouts, and
+/// the retryable status codes of the policy. Since POST is not
+/// idempotent, only a single attempt is made unless the policy sets
+/// "retry_non_idempotent" (e.g., for read-only JSON-RPC calls).
+pub async fn post_non_tls_with_retry(
+    url: &str,
+    url_path: &str,
+    data: impl Into<Bytes>,
+    insecure: bool,
+    policy: &retry::RetryPolicy,
+) -> io::Result<Bytes> {
+    let data: Bytes = data.into();
+    let endpoint = redact::url(join_uri(url, url_path)?.as_str());
+    let headers = HeaderMap::new();
+    let attempted = retry::run(policy, false, &clock::TokioClock, &endpoint, || {
+        let sent = send_non_tls(
+            Method::POST,
+            url,
+            url_path,
+            Some(data.clone()),
+            &headers,
+            insecure,
+        );
+        async move { sent.await.map(|p| (p.status, p.bytes)) }
+    })
+    .await?;
+    Ok(attempted.value.1)
+}
+
+/// Posts the "application/x-www-form-urlencoded" body of the pairs (see
+/// "create_form_post"), and returns the body regardless of the status
+/// code (see "get_non_tls" for "insecure").
+pub async fn post_form_non_tls(
+    url: &str,
+    url_path: &str,
+    pairs: &[(&str, &str)],
+    insecure: bool,
+) -> io::Result<Bytes> {
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static(FORM_CONTENT_TYPE));
+    let ret = send_non_tls(
+        Method::POST,
+        url,
+        url_path,
+        Some(Bytes::from(form_body(pairs))),
+        &headers,
+        insecure,
+    )
+    .await?;
+    Ok(ret.bytes)
+}
+
+/// Posts the "multipart/form-data" body of the parts (see
+/// "create_multipart_post"), and returns the body regardless of the
+/// status code (see "get_non_tls" for "insecure").
+pub async fn post_multipart(
+    url: &str,
+    url_path: &str,
+    parts: &[multipart::Part],
+    insecure: bool,
+) -> io::Result<Bytes> {
+    let (content_type, body) = multipart::encode(parts).await?;
+    let headers = header_map(&[("content-type", &content_type)])?;
+    let ret = send_non_tls(Method::POST, url, url_path, Some(body), &headers, insecure).await?;
+    Ok(ret.bytes)
+}
+
+/// Size of the chunks read from the upload sources of "post_stream" and
+/// "put_stream".
+pub const DEFAULT_UPLOAD_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Posts the bytes read from "reader" as the request body, without
+/// buffering them in memory (e.g., multi-gigabyte uploads), with chunked
+/// transfer encoding. Returns the body regardless of the status code (see
+/// "get_non_tls" for "insecure"). The timeout covers the whole upload.
+pub async fn post_stream<R>(
+    url: &str,
+    url_path: &str,
+    reader: R,
+    timeout_dur: Duration,
+    insecure: bool,
+) -> io::Result<Bytes>
+where
+    R: tokio::io::AsyncRead + Send + Sync + 'static,
+{
+    upload_stream_with_progress(
+        Method::POST,
+        url,
+        url_path,
+        reader,
+        None,
+        timeout_dur,
+        insecure,
+        |_, _| {},
+    )
+    .await
+}
+
+/// Same as "post_stream", but with the PUT method.
+pub async fn put_stream<R>(
+    url: &str,
+    url_path: &str,
+    reader: R,
+    timeout_dur: Duration,
+    insecure: bool,
+) -> io::Result<Bytes>
+where
+    R: tokio::io::AsyncRead + Send + Sync + 'static,
+{
+    upload_stream_with_progress(
+        Method::PUT,
+        url,
+        url_path,
+        reader,
+        None,
+        timeout_dur,
+        insecure,
+        |_, _| {},
+    )
+    .await
+}
+
+/// Same as "post_stream", but streams the file with its size as
+/// "Content-Length", since some servers (e.g., object stores) reject
+/// chunked uploads.
+pub async fn post_file(
+    url: &str,
+    url_path: &str,
+    file_path: &str,
+    timeout_dur: Duration,
+    insecure: bool,
+) -> io::Result<Bytes> {
+    upload_file_with_progress(
+        Method::POST,
+        url,
+        url_path,
+        file_path,
+        timeout_dur,
+        insecure,
+        |_, _| {},
+    )
+    .await
+}
+
+/// Same as "post_file", but with the PUT method.
+pub async fn put_file(
+    url: &str,
+    url_path: &str,
+    file_path: &str,
+    timeout_dur: Duration,
+    insecure: bool,
+) -> io::Result<Bytes> {
+    upload_file_with_progress(
+        Method::PUT,
+        url,
+        url_path,
+        file_path,
+        timeout_dur,
+        insecure,
+        |_, _| {},
+    )
+    .await
+}
+
+/// Same as "post_stream" and "put_stream", but with the method, and calls
+/// "progress" with the bytes sent so far and the total size (if known,
+/// sent as "Content-Length") after every chunk, e.g., to render a
+/// progress bar. A chunk counts as sent once handed to the connection.
+#[allow(clippy::too_many_arguments)]
+pub async fn upload_stream_with_progress<R, F>(
+    method: Method,
+    url: &str,
+    url_path: &str,
+    reader: R,
+    total: Option<u64>,
+    timeout_dur: Duration,
+    insecure: bool,
+    mut progress: F,
+) -> io::Result<Bytes>
+where
+    R: tokio::io::AsyncRead + Send + Sync + 'static,
+    F: FnMut(u64, Option<u64>) + Send + Sync + 'static,
+{
+    use futures_util::StreamExt;
+
+    let mut sent = 0;
+    let body = tokio_util::io::ReaderStream::with_capacity(reader, DEFAULT_UPLOAD_CHUNK_SIZE).map(
+        move |chunk| {
+            if let Ok(chunk) = &chunk {
+                sent += chunk.len() as u64;
+                progress(sent, total);
+            }
+            chunk
+        },
+    );
+    let ret =
+        send_stream_non_tls(method, url, url_path, body, total, timeout_dur, insecure).await?;
+    Ok(ret.bytes)
+}
+
+/// Same as "post_file" and "put_file", but with the method and the
+/// progress callback of "upload_stream_with_progress".
+pub async fn upload_file_with_progress<F>(
+    method: Method,
+    url: &str,
+    url_path: &str,
+    file_path: &str,
+    timeout_dur: Duration,
+    insecure: bool,
+    progress: F,
+) -> io::Result<Bytes>
+where
+    F: FnMut(u64, Option<u64>) + Send + Sync + 'static,
+{
+    let f = tokio::fs::File::open(file_path).await?;
+    let size = f.metadata().await?.len();
+    upload_stream_with_progress(
+        method,
+        url,
+        url_path,
+        f,
+        Some(size),
+        timeout_dur,
+        insecure,
+        progress,
+    )
+    .await
+}
+
+/// Sends a GET request and parses the JSON response body, failing on
+/// non-2xx status codes ("error::Error::Status" with the body).
+pub async fn get_json<T: serde::de::DeserializeOwned>(url: &str, url_path: &str) -> io::Result<T> {
+    let ret = send_non_tls(Method::GET, url, url_path, None, &HeaderMap::new(), false).await?;
+    parse_json_response(ret)
+}
+
+/// Posts the JSON-serialized body and parses the JSON response body (see
+/// "get_json").
+pub async fn post_json<B, T>(url: &str, url_path: &str, body: &B) -> io::Result<T>
+where
+    B: serde::Serialize + ?Sized,
+    T: serde::de::DeserializeOwned,
+{
+    let data = serde_json::to_vec(body).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("failed to serialize JSON request {}", e),
+        )
+    })?;
+    let ret = send_non_tls(
+        Method::POST,
+        url,
+        url_path,
+        Some(Bytes::from(data)),
+        &HeaderMap::new(),
+        false,
+    )
+    .await?;
+    parse_json_response(ret)
+}
+
+fn parse_json_response<T: serde::de::DeserializeOwned>(ret: ResponseParts) -> io::Result<T> {
+    if !ret.is_success() {
+        return Err(error::Error::Status(ret.status, ret.bytes).into());
+    }
+    serde_json::from_slice(&ret.bytes)
+        .map_err(|e| error::Error::Body(format!("failed to parse JSON response {}", e)).into())
+}
+
+/// Puts JSON body (see "get_non_tls" for "insecure").
+pub async fn put_non_tls(
+    url: &str,
+    url_path: &str,
+    data: impl Into<Bytes>,
+    insecure: bool,
+) -> io::Result<Bytes> {
+    let ret = send_non_tls(
+        Method::PUT,
+        url,
+        url_path,
+        Some(data.into()),
+        &HeaderMap::new(),
+        insecure,
+    )
+    .await?;
+    Ok(ret.bytes)
+}
+
+/// Patches with JSON body (see "get_non_tls" for "insecure").
+pub async fn patch_non_tls(
+    url: &str,
+    url_path: &str,
+    data: impl Into<Bytes>,
+    insecure: bool,
+) -> io::Result<Bytes> {
+    let ret = send_non_tls(
+        Method::PATCH,
+        url,
+        url_path,
+        Some(data.into()),
+        &HeaderMap::new(),
+        insecure,
+    )
+    .await?;
+    Ok(ret.bytes)
+}
+
+/// Sends a DELETE request and returns the body regardless of the status
+/// code (see "get_non_tls" for "insecure").
+pub async fn delete_non_tls(url: &str, url_path: &str, insecure: bool) -> io::Result<Bytes> {
+    Ok(send_non_tls(
+        Method::DELETE,
+        url,
+        url_path,
+        None,
+        &HeaderMap::new(),
+        insecure,
+    )
+    .await?
+    .bytes)
+}
+
+/// Sends a HEAD request and returns the status code
+/// (see "get_non_tls" for "insecure").
+pub async fn head_non_tls(url: &str, url_path: &str, insecure: bool) -> io::Result<StatusCode> {
+    Ok(send_non_tls(
+        Method::HEAD,
+        url,
+        url_path,
+        None,
+        &HeaderMap::new(),
+        insecure,
+    )
+    .await?
+    .status)
+}
+
+/// Sends an OPTIONS request and returns the body regardless of the
+/// status code (see "get_non_tls" for "insecure").
+pub async fn options_non_tls(url: &str, url_path: &str, insecure: bool) -> io::Result<Bytes> {
+    Ok(send_non_tls(
+        Method::OPTIONS,
+        url,
+        url_path,
+        None,
+        &HeaderMap::new(),
+        insecure,
+    )
+    .await?
+    .bytes)
+}
+
+/// RUST_LOG=debug cargo test --lib -- test_method_helpers --exact --show-output
+#[tokio::test]
+async fn test_method_helpers() {
+    let server = testing::MockServer::start().await.unwrap();
+    server.stub(Method::PUT, "/kv/a", 200, "put");
+    server.stub(Method::PATCH, "/kv/a", 200, "patched");
+    server.stub(Method::DELETE, "/kv/a", 204, "");
+    server.stub(Method::HEAD, "/kv/a", 200, "");
+    server.stub(Method::OPTIONS, "/kv/a", 200, "GET, PUT");
+
+    let req = create_put_json(server.url(), "/kv/a", "{\"v\":1}").unwrap();
+    assert_eq!(req.method(), Method::PUT);
+    assert_eq!(req.headers()["content-type"], JSON_CONTENT_TYPE);
+    let req = create_patch_json(server.url(), "/kv/a", "{}").unwrap();
+    assert_eq!(req.method(), Method::PATCH);
+    for (req, method) in [
+        (
+            create_delete(server.url(), "/kv/a").unwrap(),
+            Method::DELETE,
+        ),
+        (create_head(server.url(), "/kv/a").unwrap(), Method::HEAD),
+        (
+            create_options(server.url(), "/kv/a").unwrap(),
+            Method::OPTIONS,
+        ),
+    ] {
+        assert_eq!(req.method(), method);
+        assert!(!req.headers().contains_key("content-type"));
+        assert_eq!(req.uri().path(), "/kv/a");
+    }
+
+    let url = server.url();
+    assert_eq!(
+        put_non_tls(&url, "/kv/a", "{\"v\":1}", false)
+            .await
+            .unwrap(),
+        "put"
+    );
+    assert_eq!(
+        patch_non_tls(&url, "/kv/a", "{}", false).await.unwrap(),
+        "patched"
+    );
+    assert!(delete_non_tls(&url, "/kv/a", false)
+        .await
+        .unwrap()
+        .is_empty());
+    assert_eq!(
+        head_non_tls(&url, "/kv/a", false).await.unwrap(),
+        StatusCode::OK
+    );
+    assert_eq!(
+        head_non_tls(&url, "/missing", false).await.unwrap(),
+        StatusCode::NOT_FOUND
+    );
+    assert_eq!(
+        options_non_tls(&url, "/kv/a", false).await.unwrap(),
+        "GET, PUT"
+    );
+    server
+        .assert_received(Method::PUT, "/kv/a")
+        .once()
+        .with_header("content-type", JSON_CONTENT_TYPE)
+        .with_json_body(&serde_json::json!({ "v": 1 }));
+}
+
+/// RUST_LOG=debug cargo test --lib -- test_post_form --exact --show-output
+#[tokio::test]
+async fn test_post_form() {
+    let server = testing::MockServer::start().await.unwrap();
+    server.register(
+        testing::Stub::new(Method::POST, "/token")
+            .with_header("content-type", "application/x-www-form-urlencoded")
+            .respond(200, r#"{"access_token":"t"}"#),
+    );
+    let pairs = [
+        ("grant_type", "client_credentials"),
+        ("scope", "read write"),
+        ("secret", "a&b=c"),
+    ];
+    let expected = "grant_type=client_credentials&scope=read+write&secret=a%26b%3Dc";
+
+    let req = create_form_post(server.url(), "/token", &pairs).unwrap();
+    assert_eq!(req.headers().get_all("content-type").iter().count(), 1);
+    let out = read_bytes(req, Duration::from_secs(5), false, true)
+        .await
+        .unwrap();
+    assert_eq!(out, r#"{"access_token":"t"}"#);
+
+    let out = post_form_non_tls(&server.url(), "/token", &pairs, false)
+        .await
+        .unwrap();
+    assert_eq!(out, r#"{"access_token":"t"}"#);
+
+    let received = server.assert_received(Method::POST, "/token").times(2);
+    for r in received.requests() {
+        assert_eq!(r.body, expected);
+    }
+}
+
+/// RUST_LOG=debug cargo test --lib -- test_post_multipart --exact --show-output
+#[tokio::test]
+async fn test_post_multipart() {
+    let server = testing::MockServer::start().await.unwrap();
+    server.stub(Method::POST, "/upload", 201, "uploaded");
+
+    let parts = vec![
+        multipart::Part::text("name", "release"),
+        multipart::Part::bytes("artifact", "binary").filename("app.bin"),
+    ];
+    let req = create_multipart_post(server.url(), "/upload", &parts)
+        .await
+        .unwrap();
+    let content_type = req.headers()["content-type"].to_str().unwrap().to_string();
+    assert!(content_type.starts_with("multipart/form-data; boundary="));
+    assert_eq!(req.headers().get_all("content-type").iter().count(), 1);
+    let out = read_bytes(req, Duration::from_secs(5), false, true)
+        .await
+        .unwrap();
+    assert_eq!(out, "uploaded");
+
+    let out = post_multipart(&server.url(), "/upload", &parts, false)
+        .await
+        .unwrap();
+    assert_eq!(out, "uploaded");
+
+    let received = server.assert_received(Method::POST, "/upload").times(2);
+    for r in received.requests() {
+        let content_type = r.headers["content-type"].to_str().unwrap();
+        let boundary = content_type.split_once("boundary=").unwrap().1;
+        let body = String::from_utf8(r.body.to_vec()).unwrap();
+        assert!(body.starts_with(&format!("--{}\r\n", boundary)));
+        assert!(body.ends_with(&format!("--{}--\r\n", boundary)));
+        assert!(body.contains("name=\"artifact\"; filename=\"app.bin\""));
+        assert!(body.contains("\r\n\r\nbinary\r\n"));
+    }
+}
+
+/// RUST_LOG=debug cargo test --lib -- test_upload_stream --exact --show-output
+#[tokio::test]
+async fn test_upload_stream() {
+    let server = testing::MockServer::start().await.unwrap();
+    server.stub(Method::POST, "/upload", 201, "posted");
+    server.stub(Method::PUT, "/upload", 200, "put");
+
+    let data: Vec<u8> = (0..300_000).map(|i| (i % 251) as u8).collect();
+    let url = server.url();
+    let out = post_stream(
+        &url,
+        "/upload",
+        std::io::Cursor::new(data.clone()),
+        Duration::from_secs(5),
+        false,
+    )
+    .await
+    .unwrap();
+    assert_eq!(out, "posted");
+    let received = server.assert_received(Method::POST, "/upload").once();
+    let r = &received.requests()[0];
+    assert_eq!(r.body, data);
+    assert_eq!(r.headers["transfer-encoding"], "chunked");
+    assert_eq!(r.headers["content-type"], "application/octet-stream");
+
+    let file_path = std::env::temp_dir().join("http-manager-test-upload.bin");
+    std::fs::write(&file_path, &data).unwrap();
+    let out = put_file(
+        &url,
+        "/upload",
+        file_path.to_str().unwrap(),
+        Duration::from_secs(5),
+        false,
+    )
+    .await
+    .unwrap();
+    assert_eq!(out, "put");
+    let received = server.assert_received(Method::PUT, "/upload").once();
+    let r = &received.requests()[0];
+    assert_eq!(r.body, data);
+    assert_eq!(r.headers["content-length"], "300000");
+    assert!(r.headers.get("transfer-encoding").is_none());
+
+    let reports = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let recorded = reports.clone();
+    upload_file_with_progress(
+        Method::PUT,
+        &url,
+        "/upload",
+        file_path.to_str().unwrap(),
+        Duration::from_secs(5),
+        false,
+        move |n, total| recorded.lock().unwrap().push((n, total)),
+    )
+    .await
+    .unwrap();
+    let reports = reports.lock().unwrap().clone();
+    assert!(reports.len() > 1);
+    assert!(reports.windows(2).all(|w| w[0].0 < w[1].0));
+    assert_eq!(reports.last(), Some(&(300_000, Some(300_000))));
+    std::fs::remove_file(&file_path).unwrap();
+
+    let e = put_file(
+        &url,
+        "/upload",
+        "/nonexistent",
+        Duration::from_secs(5),
+        false,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::NotFound);
+
+    // HTTPS via reqwest
+    let server = testing::MockServer::start_https().await.unwrap();
+    server.stub(Method::POST, "/upload", 201, "posted");
+    let out = post_stream(
+        &server.url(),
+        "/upload",
+        std::io::Cursor::new(data.clone()),
+        Duration::from_secs(5),
+        true,
+    )
+    .await
+    .unwrap();
+    assert_eq!(out, "posted");
+    let received = server.assert_received(Method::POST, "/upload").once();
+    assert_eq!(received.requests()[0].body, data);
+}
+
+/// RUST_LOG=debug cargo test --all-features --lib -- test_post_gzip --exact --show-output
+#[cfg(feature = "compression")]
+#[tokio::test]
+async fn test_post_gzip() {
+    use std::io::Read;
+
+    let server = testing::MockServer::start().await.unwrap();
+    server.stub(Method::POST, "/rpc", 200, "ok");
+    let url = server.url();
+
+    let batch = format!(
+        "[{}]",
+        vec!["{\"method\":\"eth_blockNumber\"}"; 1000].join(",")
+    );
+    let req = create_json_post_gzip(&url, "/rpc", batch.clone(), 1024).unwrap();
+    assert_eq!(req.headers()["content-encoding"], "gzip");
+    assert_eq!(req.headers()["content-type"], "application/json");
+    read_bytes(req, Duration::from_secs(5), false, true)
+        .await
+        .unwrap();
+    assert_eq!(
+        post_non_tls_gzip(&url, "/rpc", batch.clone(), 1024, false)
+            .await
+            .unwrap(),
+        "ok"
+    );
+    let received = server.assert_received(Method::POST, "/rpc").times(2);
+    for r in received.requests() {
+        assert_eq!(r.headers["content-encoding"], "gzip");
+        assert!(r.body.len() < batch.len() / 10);
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(&r.body[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, batch);
+    }
+
+    // below the threshold
+    let req = create_json_post_gzip(&url, "/rpc", "{}", DEFAULT_GZIP_MIN_SIZE).unwrap();
+    assert!(req.headers().get("content-encoding").is_none());
+    assert_eq!(hyper::body::to_bytes(req.into_body()).await.unwrap(), "{}");
+}
+
+/// RUST_LOG=debug cargo test --lib -- test_typed_json --exact --show-output
+#[tokio::test]
+async fn test_typed_json() {
+    #[derive(Debug, serde::Serialize, serde::Deserialize, PartialEq)]
+    struct Info {
+        name: String,
+        height: u64,
+    }
+
+    let server = testing::MockServer::start().await.unwrap();
+    server.stub(
+        Method::GET,
+        "/info",
+        200,
+        r#"{"name":"mainnet","height":7,"extra":true}"#,
+    );
+    server.stub(Method::GET, "/missing", 404, "not found");
+    server.stub(Method::GET, "/text", 200, "ok");
+    server.register(
+        testing::Stub::new(Method::POST, "/echo")
+            .with_json_body(|v| v["name"] == "testnet")
+            .respond(200, r#"{"name":"testnet","height":1}"#),
+    );
+    let url = server.url();
+
+    let info: Info = get_json(&url, "/info").await.unwrap();
+    assert_eq!(
+        info,
+        Info {
+            name: "mainnet".to_string(),
+            height: 7
         }
+    );
+
+    let req = Info {
+        name: "testnet".to_string(),
+        height: 0,
     };
-    Ok(output)
+    let resp: Info = post_json(&url, "/echo", &req).await.unwrap();
+    assert_eq!(resp.height, 1);
+    server
+        .assert_received(Method::POST, "/echo")
+        .with_header("content-type", "application/json")
+        .once();
+
+    let e = get_json::<Info>(&url, "/missing").await.unwrap_err();
+    assert_eq!(error::Error::from(e).status(), Some(StatusCode::NOT_FOUND));
+    let e = get_json::<Info>(&url, "/text").await.unwrap_err();
+    assert!(matches!(error::Error::from(e), error::Error::Body(_)));
+}
+
+/// RUST_LOG=debug cargo test --lib -- test_request_auth --exact --show-output
+#[tokio::test]
+async fn test_request_auth() {
+    let server = testing::MockServer::start().await.unwrap();
+    server.register(
+        testing::Stub::new(Method::GET, "/info")
+            .with_header("authorization", "Basic dXNlcjpwYXNz")
+            .respond(200, "info"),
+    );
+    server.register(
+        testing::Stub::new(Method::POST, "/rpc")
+            .with_header("authorization", "Bearer t")
+            .respond(200, "posted"),
+    );
+    let url = server.url();
+
+    let out = get_non_tls_with_auth(&url, "/info", &auth::Auth::basic("user", "pass"), false)
+        .await
+        .unwrap();
+    assert_eq!(out, "info");
+    let out = post_non_tls_with_auth(&url, "/rpc", "{}", &auth::Auth::bearer("t"), false)
+        .await
+        .unwrap();
+    assert_eq!(out, "posted");
+
+    let manager = Manager::builder()
+        .auth(auth::Auth::bearer("t"))
+        .build()
+        .unwrap();
+    let req = create_json_post(&url, "/rpc", "{}").unwrap();
+    assert_eq!(manager.read_bytes(req, true).await.unwrap(), "posted");
+    assert!(Manager::builder()
+        .auth(auth::Auth::basic("a:b", "c"))
+        .build()
+        .is_err());
+}
+
+/// RUST_LOG=debug cargo test --lib -- test_request_headers --exact --show-output
+#[tokio::test]
+async fn test_request_headers() {
+    let server = testing::MockServer::start().await.unwrap();
+    server.register(
+        testing::Stub::new(Method::GET, "/info")
+            .with_header("authorization", "Bearer t")
+            .respond(200, "info"),
+    );
+    server.register(
+        testing::Stub::new(Method::POST, "/rpc")
+            .with_header("x-api-key", "k")
+            .with_header("content-type", "application/json-rpc")
+            .respond(200, "posted"),
+    );
+
+    let headers = header_map(&[("authorization", "Bearer t"), ("accept", "text/plain")]).unwrap();
+    let req = create_get_with_headers(server.url(), "/info", &headers).unwrap();
+    assert_eq!(req.headers()["accept"], "text/plain");
+    let out = read_bytes(req, Duration::from_secs(5), false, true)
+        .await
+        .unwrap();
+    assert_eq!(out, "info");
+
+    let url = server.url();
+    assert_eq!(
+        get_non_tls_with_headers(&url, "/info", &headers, false)
+            .await
+            .unwrap(),
+        "info"
+    );
+    // not matched without the header
+    assert!(read_bytes(
+        create_get(&url, "/info").unwrap(),
+        Duration::from_secs(5),
+        false,
+        true
+    )
+    .await
+    .is_err());
+
+    // replaces the JSON content type
+    let headers =
+        header_map(&[("x-api-key", "k"), ("content-type", "application/json-rpc")]).unwrap();
+    let req = create_json_post_with_headers(&url, "/rpc", "{}", &headers).unwrap();
+    assert_eq!(req.headers().get_all("content-type").iter().count(), 1);
+    assert_eq!(
+        post_non_tls_with_headers(&url, "/rpc", "{}", &headers, false)
+            .await
+            .unwrap(),
+        "posted"
+    );
+
+    let e = header_map(&[("bad header", "v")]).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidInput);
+    assert!(header_map(&[("x-a", "line\nbreak")]).is_err());
+    let repeated = header_map(&[("x-a", "1"), ("x-a", "2")]).unwrap();
+    assert_eq!(repeated.get_all("x-a").iter().count(), 2);
+}
+
+/// RUST_LOG=debug cargo test --lib -- test_response_parts --exact --show-output
+#[tokio::test]
+async fn test_response_parts() {
+    let server = testing::MockServer::start().await.unwrap();
+    server.register(
+        testing::Stub::new(Method::GET, "/items")
+            .respond(200, "[1,2]")
+            .respond_header("link", "</items?page=2>; rel=\"next\"")
+            .respond_header("etag", "\"v1\""),
+    );
+    server.register(
+        testing::Stub::new(Method::POST, "/rpc")
+            .respond(429, "slow down")
+            .respond_header("retry-after", "3"),
+    );
+
+    let url = server.url();
+    let req = create_get(&url, "/items").unwrap();
+    let parts = read_bytes_full(req, Duration::from_secs(5), false)
+        .await
+        .unwrap();
+    assert!(parts.is_success());
+    assert_eq!(parts.header("etag"), Some("\"v1\""));
+    assert_eq!(parts.bytes, "[1,2]");
+
+    let parts = get_non_tls_full(&url, "/items", false).await.unwrap();
+    assert_eq!(parts.status, StatusCode::OK);
+    assert_eq!(parts.header("link"), Some("</items?page=2>; rel=\"next\""));
+
+    let parts = post_non_tls_full(&url, "/rpc", "{}", false).await.unwrap();
+    assert_eq!(parts.status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(parts.header("retry-after"), Some("3"));
+    assert_eq!(parts.bytes, "slow down");
+    assert_eq!(parts.header("missing"), None);
+
+    let resp = Manager::new()
+        .unwrap()
+        .read_response(create_get(&url, "/items").unwrap())
+        .await
+        .unwrap();
+    assert_eq!(ResponseParts::from(resp).header("etag"), Some("\"v1\""));
+}
+
+/// RUST_LOG=debug cargo test --lib -- test_non_tls_insecure --exact --show-output
+#[tokio::test]
+async fn test_non_tls_insecure() {
+    let server = testing::MockServer::start_https().await.unwrap();
+    server.stub(Method::GET, "/", 200, "ok");
+    server.stub(Method::POST, "/", 200, "posted");
+
+    assert!(get_non_tls(&server.url(), "/", false).await.is_err());
+    assert!(post_non_tls(&server.url(), "/", "{}", false).await.is_err());
+
+    let out = get_non_tls(&server.url(), "/", true).await.unwrap();
+    assert_eq!(out, "ok");
+    let out = post_non_tls(&server.url(), "/", "{}", true).await.unwrap();
+    assert_eq!(out, "posted");
 }
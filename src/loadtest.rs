@@ -0,0 +1,287 @@
+//! Built-in load testing, for capacity tests of the endpoints with the
+//! same client (and pool, TLS, and timeout settings) as production traffic.
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    io::{self, Error, ErrorKind},
+    time::{Duration, Instant},
+};
+
+use futures_util::stream::{FuturesUnordered, StreamExt};
+use hyper::{Body, Request};
+use tokio::time::{interval, MissedTickBehavior};
+
+use crate::{logging::wire_info, IntoUrl, Manager};
+
+/// Open-loop load: requests are started at a fixed rate regardless of how
+/// fast the previous ones complete, so a saturated endpoint shows up as
+/// rising latency instead of a silently lower request rate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoadTestConfig {
+    /// Requests started per second.
+    pub rate: u32,
+    pub duration: Duration,
+    /// Caps the in-flight requests; new requests are delayed while at the
+    /// cap, which lowers the achieved rate (see "LoadTestReport::throughput").
+    pub max_in_flight: usize,
+}
+
+impl Default for LoadTestConfig {
+    fn default() -> Self {
+        Self {
+            rate: 50,
+            duration: Duration::from_secs(10),
+            max_in_flight: 1000,
+        }
+    }
+}
+
+/// Latency distribution of a set of samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Percentiles {
+    pub min: Duration,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+    pub mean: Duration,
+}
+
+impl Percentiles {
+    /// Computes the nearest-rank percentiles (all zero for no samples).
+    pub fn from_samples(samples: &[Duration]) -> Self {
+        if samples.is_empty() {
+            return Self::default();
+        }
+        let mut sorted = samples.to_vec();
+        sorted.sort_unstable();
+        let rank = |p: f64| {
+            let i = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+            sorted[i.clamp(1, sorted.len()) - 1]
+        };
+        Self {
+            min: sorted[0],
+            p50: rank(50.0),
+            p90: rank(90.0),
+            p95: rank(95.0),
+            p99: rank(99.0),
+            max: sorted[sorted.len() - 1],
+            mean: sorted.iter().sum::<Duration>() / sorted.len() as u32,
+        }
+    }
+}
+
+/// Result of a load test.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadTestReport {
+    /// Number of requests sent.
+    pub requests: usize,
+    /// Number of requests with a 2xx response.
+    pub succeeded: usize,
+    /// Number of failed requests by reason, e.g., "status 503" or
+    /// "TimedOut" (the I/O error kind).
+    pub errors: BTreeMap<String, usize>,
+    /// Latency of all requests, including the failed ones.
+    pub latency: Percentiles,
+    /// Time from the first request to the last response.
+    pub elapsed: Duration,
+}
+
+impl LoadTestReport {
+    /// Returns the completed requests per second.
+    pub fn throughput(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs == 0.0 {
+            return 0.0;
+        }
+        self.requests as f64 / secs
+    }
+
+    /// Returns the number of failed requests.
+    pub fn failed(&self) -> usize {
+        self.errors.values().sum()
+    }
+}
+
+impl fmt::Display for LoadTestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "requests {} (succeeded {}, failed {}) in {:?}, {:.1} req/s",
+            self.requests,
+            self.succeeded,
+            self.failed(),
+            self.elapsed,
+            self.throughput()
+        )?;
+        let l = &self.latency;
+        write!(
+            f,
+            "latency min {:?}, p50 {:?}, p90 {:?}, p95 {:?}, p99 {:?}, max {:?}, mean {:?}",
+            l.min, l.p50, l.p90, l.p95, l.p99, l.max, l.mean
+        )?;
+        for (reason, n) in self.errors.iter() {
+            write!(f, "\nerror {}: {}", reason, n)?;
+        }
+        Ok(())
+    }
+}
+
+impl Manager {
+    /// Sends GET requests to the URL at the configured rate.
+    pub async fn load_test(
+        &self,
+        url: impl IntoUrl,
+        cfg: &LoadTestConfig,
+    ) -> io::Result<LoadTestReport> {
+        let url = url.into_url()?;
+        self.load_test_with(cfg, || crate::create_get(&url, ""))
+            .await
+    }
+
+    /// Sends the requests created by "new_req" at the configured rate,
+    /// reading each response body in full. Request failures are counted
+    /// in the report; only an invalid config or request is an error.
+    pub async fn load_test_with<F>(
+        &self,
+        cfg: &LoadTestConfig,
+        new_req: F,
+    ) -> io::Result<LoadTestReport>
+    where
+        F: Fn() -> io::Result<Request<Body>>,
+    {
+        if cfg.rate == 0 || cfg.duration.is_zero() || cfg.max_in_flight == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "load test requires a non-zero rate ({}), duration ({:?}), and max in-flight ({})",
+                    cfg.rate, cfg.duration, cfg.max_in_flight
+                ),
+            ));
+        }
+        let total = (cfg.duration.as_secs_f64() * cfg.rate as f64).ceil() as usize;
+        wire_info!("starting load test of {} requests at {}/s", total, cfg.rate);
+
+        let mut ticker = interval(Duration::from_secs_f64(1.0 / cfg.rate as f64));
+        // catches up after delays at the in-flight cap
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Burst);
+
+        let mut in_flight = FuturesUnordered::new();
+        let mut sent = 0;
+        let mut latencies = Vec::with_capacity(total);
+        let mut succeeded = 0;
+        let mut errors = BTreeMap::new();
+        let start = Instant::now();
+        loop {
+            tokio::select! {
+                _ = ticker.tick(), if sent < total && in_flight.len() < cfg.max_in_flight => {
+                    let req = new_req()?;
+                    sent += 1;
+                    in_flight.push(async move {
+                        let started = Instant::now();
+                        let timeout_dur = self.timeout_for(req.uri());
+                        let ret = self.fetch(req, timeout_dur).await;
+                        (ret.map(|resp| resp.status()), started.elapsed())
+                    });
+                }
+                Some((ret, latency)) = in_flight.next(), if !in_flight.is_empty() => {
+                    latencies.push(latency);
+                    let reason = match ret {
+                        Ok(status) if status.is_success() => {
+                            succeeded += 1;
+                            continue;
+                        }
+                        Ok(status) => format!("status {}", status.as_u16()),
+                        Err(e) => format!("{:?}", e.kind()),
+                    };
+                    *errors.entry(reason).or_insert(0) += 1;
+                }
+                else => break,
+            }
+        }
+
+        let report = LoadTestReport {
+            requests: sent,
+            succeeded,
+            errors,
+            latency: Percentiles::from_samples(&latencies),
+            elapsed: start.elapsed(),
+        };
+        wire_info!("finished load test\n{}", report);
+        Ok(report)
+    }
+}
+
+#[test]
+fn test_percentiles() {
+    let samples: Vec<Duration> = (1..=100).rev().map(Duration::from_millis).collect();
+    let p = Percentiles::from_samples(&samples);
+    assert_eq!(p.min, Duration::from_millis(1));
+    assert_eq!(p.p50, Duration::from_millis(50));
+    assert_eq!(p.p90, Duration::from_millis(90));
+    assert_eq!(p.p99, Duration::from_millis(99));
+    assert_eq!(p.max, Duration::from_millis(100));
+    assert_eq!(p.mean, Duration::from_micros(50_500));
+
+    let p = Percentiles::from_samples(&[Duration::from_millis(7)]);
+    assert_eq!(p.p50, Duration::from_millis(7));
+    assert_eq!(p.p99, Duration::from_millis(7));
+    assert_eq!(Percentiles::from_samples(&[]), Percentiles::default());
+}
+
+/// RUST_LOG=debug cargo test --lib -- loadtest::test_load_test --exact --show-output
+#[tokio::test]
+async fn test_load_test() {
+    use hyper::Method;
+
+    let server = crate::testing::MockServer::start().await.unwrap();
+    server.stub(Method::GET, "/ok", 200, "ok");
+    server.stub(Method::GET, "/busy", 503, "busy");
+
+    let manager = Manager::builder().build().unwrap();
+    let cfg = LoadTestConfig {
+        rate: 100,
+        duration: Duration::from_millis(200),
+        ..Default::default()
+    };
+    let report = manager
+        .load_test(&format!("{}/ok", server.url()), &cfg)
+        .await
+        .unwrap();
+    assert_eq!(report.requests, 20);
+    assert_eq!(report.succeeded, 20);
+    assert_eq!(report.failed(), 0);
+    assert!(report.latency.max >= report.latency.p50);
+    assert!(report.elapsed >= Duration::from_millis(190));
+    assert!(report.throughput() > 0.0);
+
+    // alternates between a 2xx and a 503
+    let n = std::sync::atomic::AtomicUsize::new(0);
+    let report = manager
+        .load_test_with(&cfg, || {
+            let path = if n.fetch_add(1, std::sync::atomic::Ordering::Relaxed) % 2 == 0 {
+                "/ok"
+            } else {
+                "/busy"
+            };
+            crate::create_get(server.url(), path)
+        })
+        .await
+        .unwrap();
+    assert_eq!(report.succeeded, 10);
+    assert_eq!(report.errors.get("status 503"), Some(&10));
+    assert!(report.to_string().contains("error status 503: 10"));
+
+    assert!(manager
+        .load_test(
+            &server.url(),
+            &LoadTestConfig {
+                rate: 0,
+                ..Default::default()
+            }
+        )
+        .await
+        .is_err());
+}
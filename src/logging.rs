@@ -0,0 +1,159 @@
+//! Crate-internal logging.
+//! Every log line of the crate is emitted under "TARGET", so applications
+//! can filter it on its own (e.g., "RUST_LOG=info,http_manager::wire=warn")
+//! or lower its verbosity at runtime with "set_max_level".
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use hyper::{
+    body::{Bytes, HttpBody},
+    Body, Request,
+};
+use log::{Level, LevelFilter};
+
+/// Log target of the crate-internal logs.
+pub const TARGET: &str = "http_manager::wire";
+
+static MAX_LEVEL: AtomicUsize = AtomicUsize::new(LevelFilter::Trace as usize);
+static BODY_PREVIEW: AtomicUsize = AtomicUsize::new(0);
+
+macro_rules! wire_log {
+    ($lvl:expr, $($arg:tt)+) => {
+        if $lvl <= $crate::logging::max_level() {
+            log::log!(target: $crate::logging::TARGET, $lvl, $($arg)+);
+        }
+    };
+}
+
+macro_rules! wire_debug {
+    ($($arg:tt)+) => { $crate::logging::wire_log!(log::Level::Debug, $($arg)+) };
+}
+
+macro_rules! wire_info {
+    ($($arg:tt)+) => { $crate::logging::wire_log!(log::Level::Info, $($arg)+) };
+}
+
+macro_rules! wire_warn {
+    ($($arg:tt)+) => { $crate::logging::wire_log!(log::Level::Warn, $($arg)+) };
+}
+
+pub(crate) use {wire_debug, wire_info, wire_log, wire_warn};
+
+/// Sets the most verbose level logged by the crate (default "Trace", which
+/// defers to the logger filters). "LevelFilter::Off" silences the crate.
+pub fn set_max_level(level: LevelFilter) {
+    MAX_LEVEL.store(level as usize, Ordering::Relaxed);
+}
+
+/// Returns the level set by "set_max_level".
+pub fn max_level() -> LevelFilter {
+    match MAX_LEVEL.load(Ordering::Relaxed) {
+        0 => LevelFilter::Off,
+        1 => LevelFilter::Error,
+        2 => LevelFilter::Warn,
+        3 => LevelFilter::Info,
+        4 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+/// Logs up to the first "limit" bytes of request and response bodies at
+/// debug level (default 0, disabled). Streaming request bodies of unknown
+/// length are never buffered for the preview.
+pub fn set_body_preview(limit: usize) {
+    BODY_PREVIEW.store(limit, Ordering::Relaxed);
+}
+
+/// Returns the limit set by "set_body_preview".
+pub fn body_preview() -> usize {
+    BODY_PREVIEW.load(Ordering::Relaxed)
+}
+
+/// Returns a printable preview of the first "limit" bytes, or a
+/// placeholder for binary data (NUL bytes or invalid UTF-8).
+pub fn preview(b: &[u8], limit: usize) -> String {
+    let head = &b[..b.len().min(limit)];
+    let text = match std::str::from_utf8(head) {
+        Ok(s) => Some(s),
+        // cut in the middle of a multi-byte character
+        Err(e) if e.error_len().is_none() => {
+            Some(std::str::from_utf8(&head[..e.valid_up_to()]).unwrap())
+        }
+        Err(_) => None,
+    };
+    match text {
+        Some(s) if !s.contains('\0') => {
+            let rest = b.len() - s.len();
+            if rest > 0 {
+                format!("{:?} ({} more bytes)", s, rest)
+            } else {
+                format!("{:?}", s)
+            }
+        }
+        _ => format!("<binary, {} bytes>", b.len()),
+    }
+}
+
+fn preview_enabled() -> Option<usize> {
+    let limit = body_preview();
+    if limit == 0 || Level::Debug > max_level() || !log::log_enabled!(target: TARGET, Level::Debug)
+    {
+        return None;
+    }
+    Some(limit)
+}
+
+/// Logs the request body preview if enabled, buffering only bodies of
+/// known length.
+pub(crate) async fn log_request_preview(req: Request<Body>) -> Request<Body> {
+    let limit = match preview_enabled() {
+        Some(l) => l,
+        None => return req,
+    };
+    if req.body().size_hint().exact().is_none() {
+        return req;
+    }
+
+    let (parts, body) = req.into_parts();
+    let b = match crate::buffer::collect(body).await {
+        Ok(b) => b,
+        Err(_) => Bytes::new(),
+    };
+    if !b.is_empty() {
+        wire_debug!("request body {}", preview(&b, limit));
+    }
+    Request::from_parts(parts, Body::from(b))
+}
+
+/// Logs the response body preview if enabled.
+pub(crate) fn log_response_preview(b: &[u8]) {
+    if let Some(limit) = preview_enabled() {
+        wire_debug!("response body {}", preview(b, limit));
+    }
+}
+
+#[test]
+fn test_preview() {
+    assert_eq!(preview(b"{\"id\":1}", 100), "\"{\\\"id\\\":1}\"");
+    assert_eq!(preview(b"abcdef", 3), "\"abc\" (3 more bytes)");
+    assert_eq!(preview("한글".as_bytes(), 4), "\"한\" (3 more bytes)");
+    assert_eq!(preview(&[0x00, 0x01, 0x02], 10), "<binary, 3 bytes>");
+    assert_eq!(preview(&[0xff, 0xfe], 10), "<binary, 2 bytes>");
+    assert_eq!(preview(b"", 10), "\"\"");
+}
+
+#[test]
+fn test_max_level() {
+    assert_eq!(max_level(), LevelFilter::Trace);
+    for level in [
+        LevelFilter::Off,
+        LevelFilter::Error,
+        LevelFilter::Warn,
+        LevelFilter::Info,
+        LevelFilter::Debug,
+        LevelFilter::Trace,
+    ] {
+        set_max_level(level);
+        assert_eq!(max_level(), level);
+    }
+}
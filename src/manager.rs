@@ -0,0 +1,2098 @@
+use std::{
+    env,
+    io::{self, Error, ErrorKind},
+    sync::Arc,
+    time::Duration,
+};
+
+use futures_util::future;
+use hyper::{
+    body::Bytes,
+    client::HttpConnector,
+    header::{
+        HeaderMap, HeaderName, HeaderValue, ACCEPT_ENCODING, AUTHORIZATION, COOKIE,
+        PROXY_AUTHORIZATION, USER_AGENT,
+    },
+    Body, Client, Method, Request, Response, Uri,
+};
+use tokio::time::{timeout_at, Instant};
+use url::Url;
+
+use crate::{
+    auth::Auth,
+    clock::TokioClock,
+    concurrency::{AdaptiveLimiter, AimdConfig, Outcome},
+    cookie::CookieJar,
+    decompress::{self, DecompressionLimits},
+    error,
+    httpsig::MessageSigner,
+    idn,
+    logging::{self, wire_debug, wire_warn},
+    policy::{self, HostPolicy},
+    pool::LimitedConnector,
+    proxy::{ProxyConfig, ProxyConnector},
+    retry::{self, RetryPolicy},
+    signer::{self, RequestSigner},
+    ssrf::{self, GuardedResolver},
+    stall::LowSpeedLimit,
+    tls::{HttpsConnector, TlsConfig},
+    traffic::{self, TrafficCounters, TransferSize},
+};
+
+#[cfg(feature = "sigv4")]
+use crate::sigv4::SigV4Signer;
+
+/// Schemes allowed by default for outgoing requests.
+pub const DEFAULT_ALLOWED_SCHEMES: [&str; 2] = ["http", "https"];
+
+/// Reusable HTTP(s) client with its request policies.
+/// The underlying connection pool is shared by all requests sent through
+/// the same manager (and its clones).
+#[derive(Debug, Clone)]
+pub struct Manager {
+    client: HttpsClient,
+    base_url: Option<Url>,
+    timeout: Duration,
+    allowed_schemes: Vec<String>,
+    host_policy: HostPolicy,
+    block_restricted_destinations: bool,
+    reject_mixed_script_hosts: bool,
+    danger_accept_invalid_certs: bool,
+    /// For the connections made outside of the client (e.g., probes).
+    tls: TlsConfig,
+    proxy: Arc<ProxyConfig>,
+    #[cfg(feature = "http3")]
+    http3: Option<Arc<crate::http3::Http3Client>>,
+    /// Includes the "User-Agent", if any.
+    default_headers: HeaderMap,
+    host_overrides: Vec<ResolvedHostOverride>,
+    limiter: Option<AdaptiveLimiter>,
+    cookie_jar: Option<Arc<CookieJar>>,
+    #[cfg(feature = "sigv4")]
+    sigv4_signer: Option<Arc<SigV4Signer>>,
+    message_signer: Option<Arc<MessageSigner>>,
+    request_signer: Option<Arc<dyn RequestSigner>>,
+    decompression_limits: DecompressionLimits,
+    low_speed_limit: Option<LowSpeedLimit>,
+    retry_policy: RetryPolicy,
+    traffic: Arc<TrafficCounters>,
+}
+
+type HttpsClient =
+    Client<HttpsConnector<LimitedConnector<ProxyConnector<HttpConnector<GuardedResolver>>>>>;
+
+/// Settings that replace the manager defaults for requests to matching
+/// hosts (see "ManagerBuilder::host_override").
+#[derive(Debug, Clone, Default)]
+pub struct HostOverride {
+    timeout: Option<Duration>,
+    headers: HeaderMap,
+    header_strs: Vec<(String, String)>,
+    danger_accept_invalid_certs: Option<bool>,
+    tls: Option<TlsConfig>,
+    retry_policy: Option<RetryPolicy>,
+}
+
+impl HostOverride {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces the manager timeout.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Adds headers on top of the manager default headers.
+    pub fn headers(mut self, headers: HeaderMap) -> Self {
+        self.headers.extend(headers);
+        self
+    }
+
+    /// Adds a header (see "headers"). Invalid names or values fail at
+    /// "ManagerBuilder::build".
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.header_strs.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Replaces the manager TLS certificate verification setting
+    /// (see "ManagerBuilder::danger_accept_invalid_certs").
+    pub fn danger_accept_invalid_certs(mut self, accept: bool) -> Self {
+        self.danger_accept_invalid_certs = Some(accept);
+        self
+    }
+
+    /// Replaces the manager TLS settings (e.g., the roots and client
+    /// certificate of an internal service, or its pins), with connections
+    /// of their own.
+    pub fn tls_config(mut self, tls: TlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Replaces the manager retry policy
+    /// (see "ManagerBuilder::retry_policy").
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(policy);
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ResolvedHostOverride {
+    pattern: String,
+    timeout: Option<Duration>,
+    /// Default headers merged with the override headers.
+    headers: HeaderMap,
+    danger_accept_invalid_certs: bool,
+    tls: Option<TlsConfig>,
+    retry_policy: Option<RetryPolicy>,
+    /// Set only if the TLS settings differ from the manager's.
+    client: Option<HttpsClient>,
+}
+
+/// Builds a "Manager".
+#[derive(Debug, Clone)]
+pub struct ManagerBuilder {
+    base_url: Option<String>,
+    timeout: Duration,
+    connect_timeout: Duration,
+    pool_idle_timeout: Duration,
+    pool_max_idle_per_host: usize,
+    max_connections_per_host: Option<usize>,
+    http2: bool,
+    http2_prior_knowledge: bool,
+    #[cfg(feature = "http3")]
+    http3: bool,
+    allowed_schemes: Vec<String>,
+    host_policy: HostPolicy,
+    block_restricted_destinations: bool,
+    reject_mixed_script_hosts: bool,
+    danger_accept_invalid_certs: bool,
+    tls: TlsConfig,
+    /// None to read the proxies from the environment at "build".
+    proxy: Option<ProxyConfig>,
+    user_agent: Option<String>,
+    default_headers: HeaderMap,
+    default_header_strs: Vec<(String, String)>,
+    host_overrides: Vec<(String, HostOverride)>,
+    adaptive_concurrency: Option<AimdConfig>,
+    cookie_jar: Option<Arc<CookieJar>>,
+    #[cfg(feature = "sigv4")]
+    sigv4_signer: Option<Arc<SigV4Signer>>,
+    message_signer: Option<Arc<MessageSigner>>,
+    request_signer: Option<Arc<dyn RequestSigner>>,
+    decompression_limits: DecompressionLimits,
+    low_speed_limit: Option<LowSpeedLimit>,
+    retry_policy: RetryPolicy,
+    auth: Option<Auth>,
+}
+
+impl Default for ManagerBuilder {
+    fn default() -> Self {
+        Self {
+            base_url: None,
+            timeout: Duration::from_secs(15),
+            connect_timeout: Duration::from_secs(5),
+            pool_idle_timeout: Duration::from_secs(90),
+            pool_max_idle_per_host: usize::MAX,
+            max_connections_per_host: None,
+            http2: false,
+            http2_prior_knowledge: false,
+            #[cfg(feature = "http3")]
+            http3: false,
+            allowed_schemes: DEFAULT_ALLOWED_SCHEMES
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+            host_policy: HostPolicy::default(),
+            block_restricted_destinations: false,
+            reject_mixed_script_hosts: false,
+            danger_accept_invalid_certs: false,
+            tls: TlsConfig::default(),
+            proxy: None,
+            user_agent: Some(crate::DEFAULT_USER_AGENT.to_string()),
+            default_headers: HeaderMap::new(),
+            default_header_strs: Vec::new(),
+            host_overrides: Vec::new(),
+            adaptive_concurrency: None,
+            cookie_jar: None,
+            #[cfg(feature = "sigv4")]
+            sigv4_signer: None,
+            message_signer: None,
+            request_signer: None,
+            decompression_limits: DecompressionLimits::default(),
+            low_speed_limit: None,
+            retry_policy: RetryPolicy::default(),
+            auth: None,
+        }
+    }
+}
+
+impl ManagerBuilder {
+    /// Creates a builder from the environment variables:
+    ///   - "HTTP_MANAGER_BASE_URL": base URL of relative request paths
+    ///   - "HTTP_MANAGER_TIMEOUT": request timeout (e.g., "30", "30s", "500ms", "2m")
+    ///   - "HTTP_MANAGER_CONNECT_TIMEOUT": TCP connect timeout
+    ///   - "HTTP_MANAGER_POOL_IDLE_TIMEOUT": idle pooled connection timeout
+    ///   - "HTTP_MANAGER_POOL_MAX_IDLE_PER_HOST": max idle pooled connections per host
+    ///   - "HTTP_MANAGER_MAX_CONNECTIONS_PER_HOST": max open connections per host
+    ///   - "HTTP_MANAGER_INSECURE": "true" to skip TLS certificate verification
+    ///   - "HTTP_MANAGER_ALLOWED_SCHEMES": comma-separated schemes
+    ///   - "HTTP_MANAGER_ALLOWED_HOSTS": comma-separated host patterns
+    ///   - "HTTP_MANAGER_DENIED_HOSTS": comma-separated host patterns
+    ///   - "HTTP_MANAGER_BLOCK_RESTRICTED_DESTINATIONS": "true" to enable the SSRF guard
+    ///   - "HTTP_MANAGER_USER_AGENT": "User-Agent" header value
+    ///   - "HTTP_MANAGER_DEFAULT_HEADERS": default headers as a JSON object
+    ///     of strings (e.g., '{"x-api-key": "..."}')
+    ///
+    /// Unset variables keep the defaults, and invalid values are errors.
+    pub fn from_env() -> io::Result<Self> {
+        Self::from_env_with(|k| env::var(k).ok())
+    }
+
+    fn from_env_with<F>(lookup: F) -> io::Result<Self>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let mut builder = Self::default();
+        let get = |k: &str| lookup(k).filter(|v| !v.trim().is_empty());
+
+        if let Some(v) = get("HTTP_MANAGER_BASE_URL") {
+            builder = builder.base_url(v.trim());
+        }
+        if let Some(v) = get("HTTP_MANAGER_TIMEOUT") {
+            builder = builder.timeout(parse_env_duration("HTTP_MANAGER_TIMEOUT", &v)?);
+        }
+        if let Some(v) = get("HTTP_MANAGER_CONNECT_TIMEOUT") {
+            builder =
+                builder.connect_timeout(parse_env_duration("HTTP_MANAGER_CONNECT_TIMEOUT", &v)?);
+        }
+        if let Some(v) = get("HTTP_MANAGER_POOL_IDLE_TIMEOUT") {
+            builder = builder
+                .pool_idle_timeout(parse_env_duration("HTTP_MANAGER_POOL_IDLE_TIMEOUT", &v)?);
+        }
+        if let Some(v) = get("HTTP_MANAGER_POOL_MAX_IDLE_PER_HOST") {
+            builder = builder.pool_max_idle_per_host(parse_env_usize(
+                "HTTP_MANAGER_POOL_MAX_IDLE_PER_HOST",
+                &v,
+            )?);
+        }
+        if let Some(v) = get("HTTP_MANAGER_MAX_CONNECTIONS_PER_HOST") {
+            builder = builder.max_connections_per_host(parse_env_usize(
+                "HTTP_MANAGER_MAX_CONNECTIONS_PER_HOST",
+                &v,
+            )?);
+        }
+        if let Some(v) = get("HTTP_MANAGER_INSECURE") {
+            builder =
+                builder.danger_accept_invalid_certs(parse_env_bool("HTTP_MANAGER_INSECURE", &v)?);
+        }
+        if let Some(v) = get("HTTP_MANAGER_ALLOWED_SCHEMES") {
+            builder = builder.allowed_schemes(&split_list(&v));
+        }
+        if let Some(v) = get("HTTP_MANAGER_ALLOWED_HOSTS") {
+            builder = builder.allowed_hosts(&split_list(&v));
+        }
+        if let Some(v) = get("HTTP_MANAGER_DENIED_HOSTS") {
+            builder = builder.denied_hosts(&split_list(&v));
+        }
+        if let Some(v) = get("HTTP_MANAGER_BLOCK_RESTRICTED_DESTINATIONS") {
+            builder = builder.block_restricted_destinations(parse_env_bool(
+                "HTTP_MANAGER_BLOCK_RESTRICTED_DESTINATIONS",
+                &v,
+            )?);
+        }
+        if let Some(v) = get("HTTP_MANAGER_USER_AGENT") {
+            builder = builder.user_agent(v.trim());
+        }
+        if let Some(v) = get("HTTP_MANAGER_DEFAULT_HEADERS") {
+            for (name, value) in parse_env_headers("HTTP_MANAGER_DEFAULT_HEADERS", &v)? {
+                builder = builder.default_header(&name, &value);
+            }
+        }
+
+        Ok(builder)
+    }
+
+    /// Sets the base URL that the paths of "Manager::get", "post", and
+    /// "request" are joined to (e.g., "http://localhost:9650/ext").
+    pub fn base_url(mut self, url: &str) -> Self {
+        self.base_url = Some(url.to_string());
+        self
+    }
+
+    /// Sets the timeout of a request: sending it and reading its response
+    /// body share one deadline (see "low_speed_limit" for large bodies).
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets the TCP connect timeout.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Sets how long an idle pooled connection is kept open (default 90s).
+    pub fn pool_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.pool_idle_timeout = timeout;
+        self
+    }
+
+    /// Sets the maximum number of idle pooled connections per host
+    /// (default unlimited). 0 disables connection reuse.
+    pub fn pool_max_idle_per_host(mut self, max: usize) -> Self {
+        self.pool_max_idle_per_host = max;
+        self
+    }
+
+    /// Caps the number of open connections per host (default unlimited),
+    /// to bound file descriptor usage. Each HTTP/1 connection carries one
+    /// request at a time, so requests beyond the cap wait for a free
+    /// connection.
+    pub fn max_connections_per_host(mut self, max: usize) -> Self {
+        self.max_connections_per_host = Some(max);
+        self
+    }
+
+    /// Offers HTTP/2 in the TLS handshake (ALPN), so that servers that
+    /// support it multiplex concurrent requests over one connection per
+    /// host instead of opening one per request. Servers without HTTP/2
+    /// keep using HTTP/1.1. Disabled by default.
+    pub fn http2(mut self, enable: bool) -> Self {
+        self.http2 = enable;
+        self
+    }
+
+    /// Speaks HTTP/2 on every connection without negotiating it, including
+    /// cleartext "h2c" for "http" URLs ("curl --http2-prior-knowledge").
+    /// Only for servers known to support HTTP/2 (e.g., gRPC gateways),
+    /// since requests to others fail.
+    pub fn http2_prior_knowledge(mut self, enable: bool) -> Self {
+        self.http2_prior_knowledge = enable;
+        self
+    }
+
+    /// Sends "https" requests over HTTP/3 (QUIC), falling back to TCP for
+    /// servers that do not complete the QUIC handshake within the connect
+    /// timeout (see "http3" for details). Experimental. Requests with
+    /// streaming bodies, and to hosts whose override changes the TLS
+    /// settings, always use TCP.
+    #[cfg(feature = "http3")]
+    pub fn http3(mut self, enable: bool) -> Self {
+        self.http3 = enable;
+        self
+    }
+
+    /// Restricts outgoing requests to the schemes (default "http" and
+    /// "https"). Requests with any other scheme (e.g., "file", "ftp",
+    /// "gopher") fail before any connection attempt.
+    pub fn allowed_schemes(mut self, schemes: &[&str]) -> Self {
+        self.allowed_schemes = schemes.iter().map(|s| s.to_ascii_lowercase()).collect();
+        self
+    }
+
+    /// Only permits requests to hosts matching the patterns
+    /// (see "policy::HostPolicy" for the pattern syntax).
+    pub fn allowed_hosts(mut self, patterns: &[&str]) -> Self {
+        self.host_policy = self.host_policy.allow(patterns);
+        self
+    }
+
+    /// Rejects requests to hosts matching the patterns, even if allowed
+    /// by "allowed_hosts".
+    pub fn denied_hosts(mut self, patterns: &[&str]) -> Self {
+        self.host_policy = self.host_policy.deny(patterns);
+        self
+    }
+
+    /// Refuses to connect to loopback, private, link-local, and
+    /// metadata-service addresses (see "ssrf::is_restricted_ip").
+    /// Hostnames are checked after DNS resolution, and only the vetted
+    /// addresses are dialed, so DNS rebinding cannot bypass the guard.
+    /// Through proxies, the hosts are resolved locally as well, and the
+    /// proxy is asked to connect to the vetted address (so "http" URLs are
+    /// tunneled instead of forwarded). The proxies themselves may be on
+    /// restricted addresses.
+    pub fn block_restricted_destinations(mut self, block: bool) -> Self {
+        self.block_restricted_destinations = block;
+        self
+    }
+
+    /// Rejects internationalized hostnames that mix Unicode scripts, to
+    /// mitigate homograph tricks in user-supplied URLs
+    /// (see "idn::is_mixed_script").
+    pub fn reject_mixed_script_hosts(mut self, reject: bool) -> Self {
+        self.reject_mixed_script_hosts = reject;
+        self
+    }
+
+    /// Skips TLS certificate and hostname verification ("curl --insecure").
+    /// Only for testing against self-signed endpoints.
+    pub fn danger_accept_invalid_certs(mut self, accept: bool) -> Self {
+        self.danger_accept_invalid_certs = accept;
+        self
+    }
+
+    /// Sets the TLS settings of the HTTPS connector (e.g., a client
+    /// certificate for mutual TLS, or the roots of internal services, see
+    /// "tls").
+    pub fn tls_config(mut self, tls: TlsConfig) -> Self {
+        self.tls = tls;
+        self
+    }
+
+    /// Sends requests through the proxies (see "proxy"), instead of those
+    /// of the environment variables.
+    pub fn proxy(mut self, proxy: ProxyConfig) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Connects directly, ignoring the proxy environment variables.
+    pub fn no_proxy(mut self) -> Self {
+        self.proxy = Some(ProxyConfig::new());
+        self
+    }
+
+    /// Sets the "User-Agent" header sent with every request that does not
+    /// set its own (default "DEFAULT_USER_AGENT"). Use "crate::user_agent"
+    /// to append the crate name and version to the product.
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// Sends no "User-Agent" header unless the request sets one.
+    pub fn no_user_agent(mut self) -> Self {
+        self.user_agent = None;
+        self
+    }
+
+    /// Sets headers (e.g., "Accept", "Authorization", tenant headers) sent
+    /// with every request. A header set on the request itself replaces
+    /// all default values of that header.
+    pub fn default_headers(mut self, headers: HeaderMap) -> Self {
+        self.default_headers.extend(headers);
+        self
+    }
+
+    /// Adds a default header (see "default_headers"). Invalid names or
+    /// values fail at "build".
+    pub fn default_header(mut self, name: &str, value: &str) -> Self {
+        self.default_header_strs
+            .push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Overrides the defaults for requests to hosts matching the pattern
+    /// (see "policy::HostPolicy" for the pattern syntax), e.g., a longer
+    /// timeout for a slow internal API. When several patterns match, the
+    /// most specific one applies: exact hosts, then the longest
+    /// "*.suffix", then "*".
+    pub fn host_override(mut self, pattern: &str, o: HostOverride) -> Self {
+        self.host_overrides.push((pattern.to_string(), o));
+        self
+    }
+
+    /// Limits the in-flight requests of the manager (and its clones) with
+    /// a limit that adapts to the observed latency and errors
+    /// (see "concurrency::AimdConfig"). Requests wait for a free slot
+    /// before their timeout starts.
+    pub fn adaptive_concurrency(mut self, config: AimdConfig) -> Self {
+        self.adaptive_concurrency = Some(config);
+        self
+    }
+
+    /// Sends the matching cookies of the jar with every request (unless
+    /// the request sets "Cookie"), and stores the "Set-Cookie" headers of
+    /// every response in it. The jar may be shared with other managers.
+    pub fn cookie_jar(mut self, jar: Arc<CookieJar>) -> Self {
+        self.cookie_jar = Some(jar);
+        self
+    }
+
+    /// Sends the credentials as the default "Authorization" header,
+    /// replacing any set by "default_headers" (see "auth").
+    pub fn auth(mut self, auth: Auth) -> Self {
+        self.auth = Some(auth);
+        self
+    }
+
+    /// Signs every request (after the default headers and cookies are
+    /// added) with AWS Signature Version 4 (see "sigv4"). In-memory
+    /// bodies are hashed, and streaming ones are sent as
+    /// "UNSIGNED-PAYLOAD".
+    #[cfg(feature = "sigv4")]
+    pub fn sigv4_signer(mut self, signer: SigV4Signer) -> Self {
+        self.sigv4_signer = Some(Arc::new(signer));
+        self
+    }
+
+    /// Signs every request (after the default headers and cookies are
+    /// added) with HTTP Message Signatures (see "httpsig").
+    pub fn message_signer(mut self, signer: MessageSigner) -> Self {
+        self.message_signer = Some(Arc::new(signer));
+        self
+    }
+
+    /// Signs every request just before it is sent, after any other
+    /// signature is added (see "signer"). In-memory bodies are buffered to
+    /// be signed.
+    pub fn request_signer(mut self, signer: Arc<dyn RequestSigner>) -> Self {
+        self.request_signer = Some(signer);
+        self
+    }
+
+    /// Sets the limits on decompressed response bodies (see "decompress").
+    pub fn decompression_limits(mut self, limits: DecompressionLimits) -> Self {
+        self.decompression_limits = limits;
+        self
+    }
+
+    /// Fails body reads whose throughput stays below the limit (see
+    /// "stall"), instead of waiting for the request timeout. The body reads
+    /// are then bounded by the limit only, so that large bodies on slow but
+    /// steady links are not cut off (the timeout still applies until the
+    /// response headers arrive).
+    pub fn low_speed_limit(mut self, limit: LowSpeedLimit) -> Self {
+        self.low_speed_limit = Some(limit);
+        self
+    }
+
+    /// Sets the retry policy of the requests that are retried (e.g.,
+    /// "Manager::read_bytes_with_retry" and the event stream reconnects).
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Fails on invalid or contradictory settings, so that "build" reports
+    /// them instead of the first request.
+    pub fn validate(&self) -> io::Result<()> {
+        let invalid = |msg: String| Err(error::Error::Config(msg).into());
+
+        if let Some(u) = &self.base_url {
+            let url = crate::parse_url(u)?;
+            let allowed: Vec<&str> = self.allowed_schemes.iter().map(|s| s.as_str()).collect();
+            check_scheme(&url, &allowed)?;
+        }
+        if self.timeout.is_zero() {
+            return invalid("timeout must be greater than zero".to_string());
+        }
+        if self.connect_timeout.is_zero() {
+            return invalid("connect timeout must be greater than zero".to_string());
+        }
+        if self.connect_timeout > self.timeout {
+            return invalid(format!(
+                "connect timeout {:?} exceeds the request timeout {:?}",
+                self.connect_timeout, self.timeout
+            ));
+        }
+        if self.max_connections_per_host == Some(0) {
+            return invalid("max connections per host must be greater than zero".to_string());
+        }
+
+        if self.allowed_schemes.is_empty() {
+            return Err(
+                error::Error::UrlParse("allowed schemes must not be empty".to_string()).into(),
+            );
+        }
+        for scheme in self.allowed_schemes.iter() {
+            if !DEFAULT_ALLOWED_SCHEMES.contains(&scheme.as_str()) {
+                return Err(error::Error::UrlParse(format!(
+                    "allowed scheme '{}' is not supported (expected {:?})",
+                    scheme, DEFAULT_ALLOWED_SCHEMES
+                ))
+                .into());
+            }
+        }
+        self.host_policy.validate()?;
+
+        if let Some(c) = &self.adaptive_concurrency {
+            if c.min_limit == 0 || c.min_limit > c.max_limit {
+                return invalid(format!(
+                    "adaptive concurrency limits must satisfy 0 < min ({}) <= max ({})",
+                    c.min_limit, c.max_limit
+                ));
+            }
+            if !(c.decrease_factor > 0.0 && c.decrease_factor < 1.0) || c.increase <= 0.0 {
+                return invalid(format!(
+                    "adaptive concurrency requires 0 < decrease factor ({}) < 1 and a positive increase ({})",
+                    c.decrease_factor, c.increase
+                ));
+            }
+        }
+
+        let l = &self.decompression_limits;
+        if l.max_size == 0 || l.max_ratio == 0 {
+            return invalid(format!(
+                "decompression limits must be greater than zero (size {}, ratio {})",
+                l.max_size, l.max_ratio
+            ));
+        }
+
+        if let Some(l) = &self.low_speed_limit {
+            if l.bytes_per_sec == 0 || l.time.is_zero() {
+                return invalid(format!(
+                    "low-speed limit must be greater than zero ({} bytes/sec over {:?})",
+                    l.bytes_per_sec, l.time
+                ));
+            }
+        }
+
+        for (pattern, o) in self.host_overrides.iter() {
+            policy::check_pattern(pattern)?;
+            if let Some(t) = o.timeout {
+                if t.is_zero() || self.connect_timeout > t {
+                    return invalid(format!(
+                        "host override '{}' timeout {:?} must be greater than zero and at least the connect timeout {:?}",
+                        pattern, t, self.connect_timeout
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn build(self) -> io::Result<Manager> {
+        self.validate()?;
+
+        let mut default_headers = self.default_headers.clone();
+        for (k, v) in self.default_header_strs.iter() {
+            let (name, value) = parse_header(k, v)?;
+            default_headers.append(name, value);
+        }
+        if let Some(auth) = &self.auth {
+            default_headers.insert(AUTHORIZATION, auth.header_value()?);
+        }
+        if let Some(v) = &self.user_agent {
+            if !default_headers.contains_key(USER_AGENT) {
+                let ua = HeaderValue::from_str(v).map_err(|e| {
+                    Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("invalid user agent '{}' {}", v, e),
+                    )
+                })?;
+                default_headers.insert(USER_AGENT, ua);
+            }
+        }
+
+        // either setting skips verification, unless a host override
+        // enables it again
+        let insecure = self.danger_accept_invalid_certs || self.tls.accepts_invalid_certs();
+        let proxy = Arc::new(self.proxy.clone().unwrap_or_else(ProxyConfig::from_env));
+        let mut host_overrides = Vec::with_capacity(self.host_overrides.len());
+        for (pattern, o) in self.host_overrides.iter() {
+            let mut headers = default_headers.clone();
+            let mut override_headers = o.headers.clone();
+            for (k, v) in o.header_strs.iter() {
+                let (name, value) = parse_header(k, v)?;
+                override_headers.append(name, value);
+            }
+            for name in override_headers.keys() {
+                headers.remove(name);
+            }
+            headers.extend(override_headers);
+
+            let accept = match &o.tls {
+                Some(tls) => o
+                    .danger_accept_invalid_certs
+                    .unwrap_or(self.danger_accept_invalid_certs || tls.accepts_invalid_certs()),
+                None => o.danger_accept_invalid_certs.unwrap_or(insecure),
+            };
+            let client = match &o.tls {
+                Some(tls) => Some(self.build_client(tls, accept, &proxy)?),
+                None if accept != insecure => Some(self.build_client(&self.tls, accept, &proxy)?),
+                None => None,
+            };
+            host_overrides.push(ResolvedHostOverride {
+                pattern: pattern.clone(),
+                timeout: o.timeout,
+                headers,
+                danger_accept_invalid_certs: accept,
+                tls: o.tls.clone(),
+                retry_policy: o.retry_policy.clone(),
+                client,
+            });
+        }
+
+        let base_url = self.base_url.as_deref().map(crate::parse_url).transpose()?;
+        let client = self.build_client(&self.tls, insecure, &proxy)?;
+        Ok(Manager {
+            client,
+            base_url,
+            timeout: self.timeout,
+            allowed_schemes: self.allowed_schemes,
+            host_policy: self.host_policy,
+            block_restricted_destinations: self.block_restricted_destinations,
+            reject_mixed_script_hosts: self.reject_mixed_script_hosts,
+            danger_accept_invalid_certs: insecure,
+            tls: self.tls.clone(),
+            proxy,
+            #[cfg(feature = "http3")]
+            http3: if self.http3 {
+                Some(Arc::new(crate::http3::Http3Client::new(
+                    &self.tls,
+                    insecure,
+                    self.connect_timeout,
+                    self.block_restricted_destinations,
+                )?))
+            } else {
+                None
+            },
+            default_headers,
+            host_overrides,
+            limiter: self.adaptive_concurrency.clone().map(AdaptiveLimiter::new),
+            cookie_jar: self.cookie_jar.clone(),
+            #[cfg(feature = "sigv4")]
+            sigv4_signer: self.sigv4_signer.clone(),
+            message_signer: self.message_signer.clone(),
+            request_signer: self.request_signer.clone(),
+            decompression_limits: self.decompression_limits,
+            low_speed_limit: self.low_speed_limit,
+            retry_policy: self.retry_policy,
+            traffic: Arc::new(TrafficCounters::new()),
+        })
+    }
+
+    fn build_client(
+        &self,
+        tls: &TlsConfig,
+        danger_accept_invalid_certs: bool,
+        proxy: &Arc<ProxyConfig>,
+    ) -> io::Result<HttpsClient> {
+        let resolver = GuardedResolver::new(self.block_restricted_destinations);
+        let mut connector = HttpConnector::new_with_resolver(resolver);
+        connector.set_connect_timeout(Some(self.connect_timeout));
+        connector.enforce_http(false);
+        let connector = ProxyConnector::new(
+            connector,
+            proxy.clone(),
+            self.connect_timeout,
+            self.block_restricted_destinations,
+        );
+        let connector = LimitedConnector::new(connector, self.max_connections_per_host);
+
+        let https_connector =
+            tls.https_connector(connector, danger_accept_invalid_certs, self.http2)?;
+        Ok(Client::builder()
+            .pool_idle_timeout(self.pool_idle_timeout)
+            .pool_max_idle_per_host(self.pool_max_idle_per_host)
+            .http2_only(self.http2_prior_knowledge)
+            .build(https_connector))
+    }
+}
+
+impl Manager {
+    /// Creates a manager with the default settings.
+    pub fn new() -> io::Result<Self> {
+        ManagerBuilder::default().build()
+    }
+
+    pub fn builder() -> ManagerBuilder {
+        ManagerBuilder::default()
+    }
+
+    /// Creates a manager from the environment variables
+    /// (see "ManagerBuilder::from_env").
+    pub fn from_env() -> io::Result<Self> {
+        ManagerBuilder::from_env()?.build()
+    }
+
+    /// Creates a manager from a TOML or YAML config file
+    /// (see "config::Config").
+    pub fn from_config_file(file_path: impl AsRef<std::path::Path>) -> io::Result<Self> {
+        let cfg = crate::config::Config::load(file_path)?;
+        cfg.apply(ManagerBuilder::default())?.build()
+    }
+
+    /// Checks the URL against the request policies without sending anything.
+    pub fn check_url(&self, url: &Url) -> io::Result<()> {
+        let allowed: Vec<&str> = self.allowed_schemes.iter().map(|s| s.as_str()).collect();
+        check_scheme(url, &allowed)?;
+        self.host_policy.check(url)?;
+        if self.block_restricted_destinations {
+            ssrf::check_ip_literal(url)?;
+        }
+        if self.reject_mixed_script_hosts {
+            if let Some(host) = url.host_str() {
+                idn::check_mixed_script(host)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends the request and waits for the response headers.
+    pub async fn send(&self, req: Request<Body>) -> io::Result<Response<Body>> {
+        let timeout_dur = self.timeout_for(req.uri());
+        self.send_with_timeout(req, timeout_dur).await
+    }
+
+    /// Checks the request URL against the policies, and applies the
+    /// default headers (after host overrides), the cookies, and the signers.
+    pub(crate) async fn prepare_request(
+        &self,
+        mut req: Request<Body>,
+    ) -> io::Result<(Request<Body>, Url)> {
+        // the policies check the address of scoped IPv6 hosts, without the
+        // zone (which the resolver applies when connecting)
+        let (url, _) = crate::parse_scoped_url(&req.uri().to_string())?;
+        self.check_url(&url)?;
+        let default_headers = self.default_headers_for(url.host_str().unwrap_or(""));
+        for name in default_headers.keys() {
+            if !req.headers().contains_key(name) {
+                for v in default_headers.get_all(name) {
+                    req.headers_mut().append(name.clone(), v.clone());
+                }
+            }
+        }
+
+        if let Some(jar) = &self.cookie_jar {
+            if !req.headers().contains_key(COOKIE) {
+                if let Some(v) = jar
+                    .cookie_header(&url)
+                    .and_then(|v| HeaderValue::from_str(&v).ok())
+                {
+                    req.headers_mut().insert(COOKIE, v);
+                }
+            }
+        }
+
+        #[cfg(feature = "sigv4")]
+        if let Some(signer) = &self.sigv4_signer {
+            req = signer.sign(req).await?;
+        }
+        if let Some(signer) = &self.message_signer {
+            signer.sign_request(&mut req)?;
+        }
+        if let Some(s) = &self.request_signer {
+            req = signer::sign_request(s.as_ref(), req).await?;
+        }
+        Ok((req, url))
+    }
+
+    pub(crate) async fn send_with_timeout(
+        &self,
+        req: Request<Body>,
+        timeout_dur: Duration,
+    ) -> io::Result<Response<Body>> {
+        self.send_until(req, Instant::now() + timeout_dur).await
+    }
+
+    /// Same as "send_with_timeout", but fails at the deadline, so that the
+    /// body read can share it (see "ManagerBuilder::timeout").
+    pub(crate) async fn send_until(
+        &self,
+        req: Request<Body>,
+        deadline: Instant,
+    ) -> io::Result<Response<Body>> {
+        let (mut req, url) = self.prepare_request(req).await?;
+        let host_override = url.host_str().and_then(|h| self.host_override(h));
+        // tunneled requests (e.g., "https") authenticate on "CONNECT"
+        let proxy = self.proxy.proxy_for(req.uri());
+        if let Some(p) = proxy.filter(|p| p.forwards(req.uri(), self.block_restricted_destinations))
+        {
+            if let Some(v) = p.authorization() {
+                if !req.headers().contains_key(PROXY_AUTHORIZATION) {
+                    req.headers_mut().insert(PROXY_AUTHORIZATION, v.clone());
+                }
+            }
+        }
+
+        // the host display (IDN conversions) is only formatted if logged
+        wire_debug!(
+            "sending {} {} to {}",
+            req.method(),
+            url.path(),
+            idn::display_host(url.host_str().unwrap_or(""))
+        );
+
+        let req = logging::log_request_preview(req).await;
+        let host = traffic::host_key(req.uri());
+        let request_header_bytes = traffic::request_head_size(&req);
+        let request_body_bytes = traffic::request_body_size(&req);
+
+        let client = host_override
+            .and_then(|o| o.client.as_ref())
+            .unwrap_or(&self.client);
+        let permit = match &self.limiter {
+            Some(l) => Some(l.acquire().await),
+            None => None,
+        };
+        let fetch_error = |e: hyper::Error| {
+            let prefix = format!(
+                "failed to fetch response from {}",
+                idn::display_host(url.host_str().unwrap_or(""))
+            );
+            io::Error::from(error::Error::from_hyper(&prefix, &e))
+        };
+        let sending = async {
+            #[cfg(feature = "http3")]
+            let req = match (&self.http3, host_override.and_then(|o| o.client.as_ref())) {
+                // QUIC does not go through the proxy
+                (Some(h3), None) if proxy.is_none() => match h3.send(req).await? {
+                    crate::http3::Sent::Response(resp) => return Ok(resp),
+                    crate::http3::Sent::Fallback(req) => req,
+                },
+                _ => req,
+            };
+            client.request(req).await.map_err(fetch_error)
+        };
+        let ret = match timeout_at(deadline, sending).await {
+            Ok(ret) => ret,
+            Err(e) => {
+                if let Some(p) = permit {
+                    p.record(Outcome::Overload);
+                }
+                return Err(error::Error::Timeout(format!(
+                    "failed to fetch response from {} before the deadline {}",
+                    idn::display_host(url.host_str().unwrap_or("")),
+                    e
+                ))
+                .into());
+            }
+        };
+        if let (Some(p), Some(l)) = (permit, &self.limiter) {
+            let status = ret.as_ref().ok().map(|r| r.status().as_u16());
+            let outcome = l.classify(status, p.elapsed());
+            p.record(outcome);
+        }
+        if let (Ok(resp), Some(jar)) = (&ret, &self.cookie_jar) {
+            jar.store(&url, resp.headers());
+        }
+        ret.map(|mut resp| {
+            let size = TransferSize {
+                request_header_bytes,
+                request_body_bytes,
+                response_header_bytes: traffic::response_head_size(&resp),
+                response_body_bytes: 0,
+            };
+            self.traffic
+                .record_request(&host, size.sent(), size.received());
+            resp.extensions_mut().insert(size);
+            resp
+        })
+    }
+
+    /// Sends the request and reads the response in "hyper::body::Bytes".
+    pub async fn read_bytes(
+        &self,
+        mut req: Request<Body>,
+        check_status_code: bool,
+    ) -> io::Result<Bytes> {
+        // one deadline for sending the request and reading the body
+        let deadline = Instant::now() + self.timeout_for(req.uri());
+        let host = traffic::host_key(req.uri());
+        self.set_accept_encoding(&mut req);
+        let resp = self.send_until(req, deadline).await?;
+        let (mut parts, body) = resp.into_parts();
+        if !parts.status.is_success() {
+            wire_warn!(
+                "unexpected HTTP response code {} (server error {})",
+                parts.status,
+                parts.status.is_server_error()
+            );
+        }
+        let bytes = crate::read_body_bytes_until(body, deadline, self.low_speed_limit).await?;
+        // counts the bytes on the wire, before decoding
+        self.traffic.record_received(&host, bytes.len() as u64);
+        let bytes =
+            decompress::decode_response(&mut parts.headers, bytes, &self.decompression_limits)?;
+        if check_status_code && !parts.status.is_success() {
+            return Err(error::Error::Status(parts.status, bytes).into());
+        }
+        Ok(bytes)
+    }
+
+    /// Same as "read_bytes", but sends the request built by "new_req" again
+    /// on connection errors, timeouts, and the retryable status codes of
+    /// the retry policy of the host (see "retry_policy_for").
+    /// Non-idempotent requests (e.g., POST) are only retried if the policy
+    /// sets "retry_non_idempotent".
+    pub async fn read_bytes_with_retry<F>(
+        &self,
+        mut new_req: F,
+        check_status_code: bool,
+    ) -> io::Result<Bytes>
+    where
+        F: FnMut() -> io::Result<Request<Body>>,
+    {
+        let req = new_req()?;
+        let policy = self.retry_policy_for(req.uri());
+        let idempotent = req.method().is_idempotent();
+        let endpoint = crate::redact::url(&req.uri().to_string());
+        let mut first = Some(req);
+        let attempted = retry::run(policy, idempotent, &TokioClock, &endpoint, || {
+            let req = match first.take() {
+                Some(req) => Ok(req),
+                None => new_req(),
+            };
+            async move {
+                let req = req?;
+                let timeout_dur = self.timeout_for(req.uri());
+                let resp = self.fetch(req, timeout_dur).await?;
+                Ok((resp.status(), resp.into_body()))
+            }
+        })
+        .await?;
+
+        let (status, bytes) = attempted.value;
+        if check_status_code && !status.is_success() {
+            return Err(error::Error::Status(status, bytes).into());
+        }
+        Ok(bytes)
+    }
+
+    /// Sends the request and reads the whole body, keeping the status and
+    /// headers (of any status code).
+    pub async fn read_response(&self, req: Request<Body>) -> io::Result<Response<Bytes>> {
+        let timeout_dur = self.timeout_for(req.uri());
+        self.fetch(req, timeout_dur).await
+    }
+
+    /// Sends the request and reads the whole body within "timeout_dur" in
+    /// total.
+    pub(crate) async fn fetch(
+        &self,
+        mut req: Request<Body>,
+        timeout_dur: Duration,
+    ) -> io::Result<Response<Bytes>> {
+        let deadline = Instant::now() + timeout_dur;
+        let host = traffic::host_key(req.uri());
+        self.set_accept_encoding(&mut req);
+        let resp = self.send_until(req, deadline).await?;
+        let (mut parts, body) = resp.into_parts();
+        let bytes = crate::read_body_bytes_until(body, deadline, self.low_speed_limit).await?;
+        self.traffic.record_received(&host, bytes.len() as u64);
+        if let Some(size) = parts.extensions.get_mut::<TransferSize>() {
+            size.response_body_bytes = bytes.len() as u64;
+        }
+        let bytes =
+            decompress::decode_response(&mut parts.headers, bytes, &self.decompression_limits)?;
+        Ok(Response::from_parts(parts, bytes))
+    }
+
+    /// Asks for compressed responses in the reads that decode them, unless
+    /// the default headers set "Accept-Encoding" (see "decompress").
+    pub(crate) fn set_accept_encoding(&self, req: &mut Request<Body>) {
+        if !self.default_headers.contains_key(ACCEPT_ENCODING) {
+            decompress::set_accept_encoding(req.headers_mut());
+        }
+    }
+
+    /// Returns the base URL of relative request paths, if any.
+    pub fn base_url(&self) -> Option<&Url> {
+        self.base_url.as_ref()
+    }
+
+    /// Returns the URL of the request path: appended to the base URL
+    /// path (see "JoinMode::Append"), or the path as is if it is an
+    /// absolute URL.
+    pub fn url_for(&self, path: &str) -> io::Result<Url> {
+        if path.contains("://") {
+            return crate::parse_url(path);
+        }
+        match &self.base_url {
+            Some(base) => crate::append_path(base, path),
+            None => Err(error::Error::UrlParse(format!(
+                "relative path '{}' requires a base URL",
+                path
+            ))
+            .into()),
+        }
+    }
+
+    /// Sends a GET request for the path (see "request").
+    pub async fn get(&self, path: &str) -> io::Result<Response<Bytes>> {
+        self.request(Method::GET, path, Body::empty()).await
+    }
+
+    /// Sends a POST request with the body to the path (see "request").
+    pub async fn post(&self, path: &str, body: impl Into<Body>) -> io::Result<Response<Bytes>> {
+        self.request(Method::POST, path, body).await
+    }
+
+    /// Sends a request to the path, relative to the base URL, over the
+    /// pooled connections of the manager, and reads the whole body
+    /// (see "read_response").
+    pub async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        body: impl Into<Body>,
+    ) -> io::Result<Response<Bytes>> {
+        let url = self.url_for(path)?;
+        let req = Request::builder()
+            .method(method)
+            .uri(url.as_str())
+            .body(body.into())
+            .map_err(|e| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("failed to create request {}", e),
+                )
+            })?;
+        self.read_response(req).await
+    }
+
+    /// Opens (and TLS-handshakes) a pooled connection to each URL's host
+    /// ahead of time, by sending "HEAD /" and discarding the response, so
+    /// that the first real request skips the handshake. Any response
+    /// status counts as warmed up. Returns the results in input order.
+    pub async fn warm_up(&self, urls: &[&str]) -> Vec<io::Result<()>> {
+        let tasks = urls.iter().map(|url| async move {
+            let uri = crate::join_uri(url, "/")?;
+            let req = Request::builder()
+                .method(Method::HEAD)
+                .uri(uri.as_str())
+                .body(Body::empty())
+                .map_err(|e| {
+                    Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("failed to create request {}", e),
+                    )
+                })?;
+            let deadline = Instant::now() + self.timeout_for(req.uri());
+            let resp = self.send_until(req, deadline).await?;
+            // drain so that the connection goes back to the pool
+            crate::read_body_bytes_until(resp.into_body(), deadline, None).await?;
+            Ok(())
+        });
+        future::join_all(tasks).await
+    }
+
+    /// Returns the adaptive concurrency limiter, if enabled.
+    pub fn limiter(&self) -> Option<&AdaptiveLimiter> {
+        self.limiter.as_ref()
+    }
+
+    pub fn decompression_limits(&self) -> &DecompressionLimits {
+        &self.decompression_limits
+    }
+
+    pub fn low_speed_limit(&self) -> Option<&LowSpeedLimit> {
+        self.low_speed_limit.as_ref()
+    }
+
+    /// Returns the bytes sent and received per upstream host, shared by
+    /// the clones of this manager.
+    pub fn traffic(&self) -> &TrafficCounters {
+        &self.traffic
+    }
+
+    /// Returns the bytes of the request and response (see "traffic").
+    pub fn transfer_size<B>(resp: &Response<B>) -> Option<&TransferSize> {
+        resp.extensions().get::<TransferSize>()
+    }
+
+    /// Returns the proxies of the manager (empty if it connects directly).
+    pub fn proxy_config(&self) -> &ProxyConfig {
+        &self.proxy
+    }
+
+    /// Returns the cookie jar, if any (e.g., to save it before exiting).
+    pub fn cookie_jar(&self) -> Option<&Arc<CookieJar>> {
+        self.cookie_jar.as_ref()
+    }
+
+    /// Returns the timeout for requests to the URI, after host overrides.
+    pub(crate) fn timeout_for(&self, uri: &Uri) -> Duration {
+        uri.host()
+            .and_then(|h| self.host_override(h))
+            .and_then(|o| o.timeout)
+            .unwrap_or(self.timeout)
+    }
+
+    /// Returns the default headers for requests to the host, after host
+    /// overrides.
+    pub(crate) fn default_headers_for(&self, host: &str) -> &HeaderMap {
+        match self.host_override(host) {
+            Some(o) => &o.headers,
+            None => &self.default_headers,
+        }
+    }
+
+    /// Returns true if TLS certificates of the host are not verified.
+    pub(crate) fn accepts_invalid_certs(&self, host: &str) -> bool {
+        match self.host_override(host) {
+            Some(o) => o.danger_accept_invalid_certs,
+            None => self.danger_accept_invalid_certs,
+        }
+    }
+
+    /// Returns the TLS settings for connections to the host, after host
+    /// overrides.
+    pub(crate) fn tls_config_for(&self, host: &str) -> &TlsConfig {
+        self.host_override(host)
+            .and_then(|o| o.tls.as_ref())
+            .unwrap_or(&self.tls)
+    }
+
+    /// Returns the retry policy for requests to the URI, after host
+    /// overrides.
+    pub fn retry_policy_for(&self, uri: &Uri) -> &RetryPolicy {
+        uri.host()
+            .and_then(|h| self.host_override(h))
+            .and_then(|o| o.retry_policy.as_ref())
+            .unwrap_or(&self.retry_policy)
+    }
+
+    pub(crate) fn blocks_restricted_destinations(&self) -> bool {
+        self.block_restricted_destinations
+    }
+
+    /// Returns the most specific override matching the host.
+    fn host_override(&self, host: &str) -> Option<&ResolvedHostOverride> {
+        self.host_overrides
+            .iter()
+            .filter(|o| policy::host_matches(&o.pattern, host))
+            .max_by_key(|o| specificity(&o.pattern))
+    }
+}
+
+/// Ranks host patterns: exact hosts, then longer "*.suffix", then "*".
+fn specificity(pattern: &str) -> usize {
+    match pattern.strip_prefix("*.") {
+        Some(suffix) => suffix.len() + 1,
+        None if pattern == "*" => 0,
+        None => usize::MAX,
+    }
+}
+
+pub(crate) fn parse_header(name: &str, value: &str) -> io::Result<(HeaderName, HeaderValue)> {
+    let n = HeaderName::from_bytes(name.as_bytes()).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid header name '{}' {}", name, e),
+        )
+    })?;
+    let v = HeaderValue::from_str(value).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid header value for '{}' {}", name, e),
+        )
+    })?;
+    Ok((n, v))
+}
+
+/// Parses a duration in seconds, with an optional "ms", "s", "m", or "h" unit.
+pub(crate) fn parse_duration(s: &str) -> Option<Duration> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (num, unit) = s.split_at(split);
+    let n: u64 = num.parse().ok()?;
+    match unit.trim() {
+        "ms" => Some(Duration::from_millis(n)),
+        "" | "s" => Some(Duration::from_secs(n)),
+        "m" => Some(Duration::from_secs(n * 60)),
+        "h" => Some(Duration::from_secs(n * 3600)),
+        _ => None,
+    }
+}
+
+fn parse_env_duration(key: &str, v: &str) -> io::Result<Duration> {
+    parse_duration(v).ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid duration '{}' for {}", v, key),
+        )
+    })
+}
+
+fn parse_env_bool(key: &str, v: &str) -> io::Result<bool> {
+    match v.trim().to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Ok(true),
+        "0" | "false" | "no" | "off" => Ok(false),
+        _ => Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid boolean '{}' for {}", v, key),
+        )),
+    }
+}
+
+fn parse_env_usize(key: &str, v: &str) -> io::Result<usize> {
+    v.trim().parse().map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid number '{}' for {} {}", v, key, e),
+        )
+    })
+}
+
+/// Parses a JSON object of header names to string values. The values are
+/// not echoed in the errors, as they may hold credentials.
+fn parse_env_headers(key: &str, v: &str) -> io::Result<Vec<(String, String)>> {
+    let obj: serde_json::Map<String, serde_json::Value> =
+        serde_json::from_str(v.trim()).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid JSON object for {} {}", key, e),
+            )
+        })?;
+    obj.into_iter()
+        .map(|(name, value)| match value {
+            serde_json::Value::String(s) => Ok((name, s)),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("header '{}' in {} must be a string", name, key),
+            )),
+        })
+        .collect()
+}
+
+fn split_list(v: &str) -> Vec<&str> {
+    v.split(',')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+/// Fails if the URL scheme is not in the allowlist (case-insensitive).
+pub fn check_scheme(url: &Url, allowed: &[&str]) -> io::Result<()> {
+    if allowed.iter().any(|s| s.eq_ignore_ascii_case(url.scheme())) {
+        return Ok(());
+    }
+    Err(error::Error::UrlParse(format!(
+        "scheme '{}' is not allowed (allowed {:?})",
+        url.scheme(),
+        allowed
+    ))
+    .into())
+}
+
+#[test]
+fn test_check_scheme() {
+    let allowed = DEFAULT_ALLOWED_SCHEMES;
+    for u in ["http://a/b", "HTTPS://a/b"] {
+        assert!(check_scheme(&Url::parse(u).unwrap(), &allowed).is_ok());
+    }
+    for u in [
+        "file:///etc/passwd",
+        "ftp://a/b",
+        "gopher://a:70/x",
+        "data:text/plain,hi",
+    ] {
+        let ret = check_scheme(&Url::parse(u).unwrap(), &allowed);
+        assert!(matches!(
+            error::Error::from(ret.unwrap_err()),
+            error::Error::UrlParse(_)
+        ));
+    }
+}
+
+/// RUST_LOG=debug cargo test --lib -- manager::test_manager_allowed_schemes --exact --show-output
+#[tokio::test]
+async fn test_manager_allowed_schemes() {
+    use hyper::Method;
+
+    let server = crate::testing::MockServer::start().await.unwrap();
+    server.stub(Method::GET, "/", 200, "ok");
+
+    let manager = Manager::new().unwrap();
+    let req = crate::create_get(server.url(), "/").unwrap();
+    assert_eq!(manager.read_bytes(req, true).await.unwrap(), "ok");
+
+    let manager = Manager::builder()
+        .allowed_schemes(&["https"])
+        .build()
+        .unwrap();
+    let req = crate::create_get(server.url(), "/").unwrap();
+    let ret = manager.read_bytes(req, true).await;
+    assert!(matches!(
+        error::Error::from(ret.unwrap_err()),
+        error::Error::UrlParse(_)
+    ));
+    assert_eq!(server.received_requests().len(), 1);
+}
+
+/// RUST_LOG=debug cargo test --lib -- manager::test_manager_block_restricted_destinations --exact --show-output
+#[tokio::test]
+async fn test_manager_block_restricted_destinations() {
+    use hyper::Method;
+
+    let server = crate::testing::MockServer::start().await.unwrap();
+    server.stub(Method::GET, "/", 200, "ok");
+
+    let manager = Manager::builder()
+        .block_restricted_destinations(true)
+        .build()
+        .unwrap();
+
+    // IP literal
+    let req = crate::create_get(server.url(), "/").unwrap();
+    let ret = manager.read_bytes(req, true).await;
+    assert_eq!(ret.unwrap_err().kind(), ErrorKind::PermissionDenied);
+
+    // hostname resolved to loopback
+    let url = format!("http://localhost:{}", server.port());
+    let req = crate::create_get(&url, "/").unwrap();
+    let ret = manager.read_bytes(req, true).await;
+    assert!(ret.unwrap_err().to_string().contains("restricted"));
+    assert!(server.received_requests().is_empty());
+
+    let manager = Manager::new().unwrap();
+    let req = crate::create_get(&url, "/").unwrap();
+    assert_eq!(manager.read_bytes(req, true).await.unwrap(), "ok");
+}
+
+/// RUST_LOG=debug cargo test --lib -- manager::test_manager_host_policy --exact --show-output
+#[tokio::test]
+async fn test_manager_host_policy() {
+    use hyper::Method;
+
+    let server = crate::testing::MockServer::start().await.unwrap();
+    server.stub(Method::GET, "/", 200, "ok");
+    let url = format!("http://localhost:{}", server.port());
+
+    let manager = Manager::builder()
+        .allowed_hosts(&["*.example.com"])
+        .build()
+        .unwrap();
+    let req = crate::create_get(&url, "/").unwrap();
+    let ret = manager.read_bytes(req, true).await;
+    assert_eq!(ret.unwrap_err().kind(), ErrorKind::PermissionDenied);
+
+    let manager = Manager::builder()
+        .allowed_hosts(&["localhost"])
+        .denied_hosts(&["127.0.0.1"])
+        .build()
+        .unwrap();
+    let req = crate::create_get(&url, "/").unwrap();
+    assert_eq!(manager.read_bytes(req, true).await.unwrap(), "ok");
+    let req = crate::create_get(server.url(), "/").unwrap();
+    let ret = manager.read_bytes(req, true).await;
+    assert_eq!(ret.unwrap_err().kind(), ErrorKind::PermissionDenied);
+    assert_eq!(server.received_requests().len(), 1);
+}
+
+#[test]
+fn test_manager_reject_mixed_script_hosts() {
+    let manager = Manager::builder()
+        .reject_mixed_script_hosts(true)
+        .build()
+        .unwrap();
+    let u = crate::join_uri("https://p\u{0430}ypal.com", "/login").unwrap();
+    assert_eq!(u.host_str(), Some("xn--pypal-4ve.com"));
+    let ret = manager.check_url(&u);
+    assert_eq!(ret.unwrap_err().kind(), ErrorKind::PermissionDenied);
+
+    let u = crate::join_uri("https://münchen.de", "/").unwrap();
+    assert!(manager.check_url(&u).is_ok());
+}
+
+#[test]
+fn test_builder_from_env() {
+    use std::collections::HashMap;
+
+    let vars: HashMap<&str, &str> = [
+        ("HTTP_MANAGER_TIMEOUT", "500ms"),
+        ("HTTP_MANAGER_CONNECT_TIMEOUT", "2"),
+        ("HTTP_MANAGER_INSECURE", "true"),
+        ("HTTP_MANAGER_ALLOWED_HOSTS", "a.com, *.b.com"),
+        ("HTTP_MANAGER_DENIED_HOSTS", ""),
+        ("HTTP_MANAGER_USER_AGENT", "my-service/1.0"),
+        ("HTTP_MANAGER_MAX_CONNECTIONS_PER_HOST", "8"),
+    ]
+    .into_iter()
+    .collect();
+    let builder = ManagerBuilder::from_env_with(|k| vars.get(k).map(|v| v.to_string())).unwrap();
+    assert_eq!(builder.timeout, Duration::from_millis(500));
+    assert_eq!(builder.connect_timeout, Duration::from_secs(2));
+    assert!(builder.danger_accept_invalid_certs);
+    assert!(builder.host_policy.is_allowed("x.b.com"));
+    assert!(!builder.host_policy.is_allowed("c.com"));
+    assert!(!builder.block_restricted_destinations);
+    assert_eq!(builder.user_agent.as_deref(), Some("my-service/1.0"));
+    assert_eq!(builder.max_connections_per_host, Some(8));
+
+    let ret = ManagerBuilder::from_env_with(|k| {
+        (k == "HTTP_MANAGER_TIMEOUT").then(|| "soon".to_string())
+    });
+    assert!(ret
+        .unwrap_err()
+        .to_string()
+        .contains("HTTP_MANAGER_TIMEOUT"));
+    let ret = ManagerBuilder::from_env_with(|k| {
+        (k == "HTTP_MANAGER_INSECURE").then(|| "maybe".to_string())
+    });
+    assert!(ret.is_err());
+
+    let builder = ManagerBuilder::from_env_with(|k| {
+        (k == "HTTP_MANAGER_DEFAULT_HEADERS")
+            .then(|| r#"{"x-api-key": "secret", "x-env": "dev"}"#.to_string())
+    })
+    .unwrap();
+    let mut headers = builder.default_header_strs.clone();
+    headers.sort();
+    assert_eq!(
+        headers,
+        vec![
+            ("x-api-key".to_string(), "secret".to_string()),
+            ("x-env".to_string(), "dev".to_string()),
+        ]
+    );
+    assert!(builder.build().is_ok());
+    for v in [
+        r#"{"x-api-key": "#,
+        r#"["x-api-key"]"#,
+        r#"{"x-retries": 3}"#,
+    ] {
+        let ret = ManagerBuilder::from_env_with(|k| {
+            (k == "HTTP_MANAGER_DEFAULT_HEADERS").then(|| v.to_string())
+        });
+        let e = ret.err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::InvalidInput);
+        assert!(e.to_string().contains("HTTP_MANAGER_DEFAULT_HEADERS"));
+    }
+    // invalid header names fail at "build"
+    let ret = ManagerBuilder::from_env_with(|k| {
+        (k == "HTTP_MANAGER_DEFAULT_HEADERS").then(|| r#"{"bad name": "v"}"#.to_string())
+    })
+    .unwrap()
+    .build();
+    assert!(ret.is_err());
+
+    assert_eq!(parse_duration("2m"), Some(Duration::from_secs(120)));
+    assert_eq!(parse_duration(" 1h "), Some(Duration::from_secs(3600)));
+    assert_eq!(parse_duration("1.5s"), None);
+}
+
+/// RUST_LOG=debug cargo test --lib -- manager::test_manager_insecure --exact --show-output
+#[tokio::test]
+async fn test_manager_insecure() {
+    use hyper::Method;
+
+    let server = crate::testing::MockServer::start_https().await.unwrap();
+    server.stub(Method::GET, "/", 200, "ok");
+
+    let manager = Manager::new().unwrap();
+    let req = crate::create_get(server.url(), "/").unwrap();
+    assert!(manager.read_bytes(req, true).await.is_err());
+
+    let manager = Manager::builder()
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap();
+    let req = crate::create_get(server.url(), "/").unwrap();
+    assert_eq!(manager.read_bytes(req, true).await.unwrap(), "ok");
+}
+
+/// RUST_LOG=debug cargo test --lib -- manager::test_manager_user_agent --exact --show-output
+#[tokio::test]
+async fn test_manager_user_agent() {
+    use hyper::Method;
+
+    use crate::testing::Stub;
+
+    let server = crate::testing::MockServer::start().await.unwrap();
+    server.register(
+        Stub::new(Method::GET, "/default")
+            .with_header("user-agent", crate::DEFAULT_USER_AGENT)
+            .respond(200, "ok"),
+    );
+    let ua = crate::user_agent("my-service/1.0", true);
+    server.register(
+        Stub::new(Method::GET, "/custom")
+            .with_header("user-agent", &ua)
+            .respond(200, "ok"),
+    );
+    server.register(
+        Stub::new(Method::GET, "/override")
+            .with_header("user-agent", "curl/8.0")
+            .respond(200, "ok"),
+    );
+
+    let manager = Manager::new().unwrap();
+    let req = crate::create_get(server.url(), "/default").unwrap();
+    assert_eq!(manager.read_bytes(req, true).await.unwrap(), "ok");
+
+    let manager = Manager::builder().user_agent(ua).build().unwrap();
+    let req = crate::create_get(server.url(), "/custom").unwrap();
+    assert_eq!(manager.read_bytes(req, true).await.unwrap(), "ok");
+
+    let mut req = crate::create_get(server.url(), "/override").unwrap();
+    req.headers_mut()
+        .insert(USER_AGENT, HeaderValue::from_static("curl/8.0"));
+    assert_eq!(manager.read_bytes(req, true).await.unwrap(), "ok");
+
+    let manager = Manager::builder().no_user_agent().build().unwrap();
+    let req = crate::create_get(server.url(), "/").unwrap();
+    manager.read_bytes(req, false).await.unwrap();
+    assert!(server.received_requests()[3]
+        .headers
+        .get(USER_AGENT)
+        .is_none());
+
+    assert!(Manager::builder().user_agent("a\nb").build().is_err());
+}
+
+/// RUST_LOG=debug cargo test --lib -- manager::test_manager_default_headers --exact --show-output
+#[tokio::test]
+async fn test_manager_default_headers() {
+    use hyper::{header::ACCEPT, Method};
+
+    let server = crate::testing::MockServer::start().await.unwrap();
+    server.stub(Method::GET, "/", 200, "ok");
+
+    let mut headers = HeaderMap::new();
+    headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
+    headers.append("x-tenant", HeaderValue::from_static("a"));
+    headers.append("x-tenant", HeaderValue::from_static("b"));
+    let manager = Manager::builder()
+        .default_headers(headers)
+        .default_header("authorization", "Bearer t")
+        .build()
+        .unwrap();
+
+    let req = crate::create_get(server.url(), "/").unwrap();
+    manager.read_bytes(req, true).await.unwrap();
+
+    let mut req = crate::create_get(server.url(), "/").unwrap();
+    req.headers_mut()
+        .insert("x-tenant", HeaderValue::from_static("c"));
+    manager.read_bytes(req, true).await.unwrap();
+
+    let received = server.received_requests();
+    let h = &received[0].headers;
+    assert_eq!(h.get(ACCEPT).unwrap(), "application/json");
+    assert_eq!(h.get("authorization").unwrap(), "Bearer t");
+    assert_eq!(h.get_all("x-tenant").iter().count(), 2);
+    assert_eq!(h.get(USER_AGENT).unwrap(), crate::DEFAULT_USER_AGENT);
+    let tenants: Vec<_> = received[1].headers.get_all("x-tenant").iter().collect();
+    assert_eq!(tenants, vec!["c"]);
+
+    assert!(Manager::builder()
+        .default_header("bad name", "v")
+        .build()
+        .is_err());
+}
+
+/// RUST_LOG=debug cargo test --lib -- manager::test_manager_host_override --exact --show-output
+#[tokio::test]
+async fn test_manager_host_override() {
+    use hyper::Method;
+
+    let server = crate::testing::MockServer::start_https().await.unwrap();
+    server.stub(Method::GET, "/", 200, "ok");
+
+    let manager = Manager::builder()
+        .timeout(Duration::from_secs(5))
+        .connect_timeout(Duration::from_secs(1))
+        .default_header("x-tenant", "a")
+        .default_header("accept", "text/plain")
+        .host_override("*", HostOverride::new().timeout(Duration::from_secs(1)))
+        .host_override(
+            "localhost",
+            HostOverride::new()
+                .timeout(Duration::from_secs(120))
+                .header("x-tenant", "b")
+                .danger_accept_invalid_certs(true),
+        )
+        .build()
+        .unwrap();
+    assert_eq!(
+        manager.timeout_for(&"https://localhost:1".parse().unwrap()),
+        Duration::from_secs(120)
+    );
+    assert_eq!(
+        manager.timeout_for(&"https://example.com".parse().unwrap()),
+        Duration::from_secs(1)
+    );
+
+    // only "localhost" skips certificate verification
+    let req = crate::create_get(server.url(), "/").unwrap();
+    assert_eq!(manager.read_bytes(req, true).await.unwrap(), "ok");
+    let url = server.url().replace("localhost", "127.0.0.1");
+    let req = crate::create_get(&url, "/").unwrap();
+    assert!(manager.read_bytes(req, true).await.is_err());
+
+    let received = server.received_requests();
+    assert_eq!(received[0].headers.get("x-tenant").unwrap(), "b");
+    assert_eq!(received[0].headers.get("accept").unwrap(), "text/plain");
+
+    // TLS settings of their own: roots, and pins
+    let trusted = TlsConfig::new().add_root_pem(server.cert_pem().unwrap());
+    let other_pin = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+    let manager = Manager::builder()
+        .host_override("localhost", HostOverride::new().tls_config(trusted.clone()))
+        .host_override(
+            "127.0.0.1",
+            HostOverride::new().tls_config(trusted.pin_sha256(&[other_pin]).unwrap()),
+        )
+        .build()
+        .unwrap();
+    let req = crate::create_get(server.url(), "/").unwrap();
+    assert_eq!(manager.read_bytes(req, true).await.unwrap(), "ok");
+    let req = crate::create_get(&url, "/").unwrap();
+    let e = manager.read_bytes(req, true).await.unwrap_err();
+    assert!(matches!(
+        error::Error::from(e),
+        error::Error::PinMismatch(_)
+    ));
+
+    // retry policies
+    let server = crate::testing::MockServer::start().await.unwrap();
+    server.stub(Method::GET, "/busy", 503, "busy");
+    let policy = RetryPolicy {
+        base_delay: Duration::from_millis(1),
+        ..Default::default()
+    };
+    let manager = Manager::builder()
+        .retry_policy(policy.clone())
+        .host_override(
+            "127.0.0.1",
+            HostOverride::new().retry_policy(RetryPolicy {
+                max_attempts: 1,
+                ..policy.clone()
+            }),
+        )
+        .build()
+        .unwrap();
+    assert_eq!(
+        manager.retry_policy_for(&"http://example.com".parse().unwrap()),
+        &policy
+    );
+    let e = manager
+        .read_bytes_with_retry(|| crate::create_get(server.url(), "/busy"), true)
+        .await
+        .unwrap_err();
+    assert!(matches!(error::Error::from(e), error::Error::Status(s, _) if s == 503));
+    server.assert_received(Method::GET, "/busy").once();
+    let url = server.url().replace("127.0.0.1", "localhost");
+    let out = manager
+        .read_bytes_with_retry(|| crate::create_get(&url, "/busy"), false)
+        .await
+        .unwrap();
+    assert_eq!(out, "busy");
+    server.assert_received(Method::GET, "/busy").times(4);
+
+    assert!(Manager::builder()
+        .host_override("a.com", HostOverride::new().header("x", "\n"))
+        .build()
+        .is_err());
+}
+
+/// RUST_LOG=debug cargo test --lib -- manager::test_manager_body_preview --exact --show-output
+#[tokio::test]
+async fn test_manager_body_preview() {
+    use hyper::Method;
+
+    let _ = env_logger::builder()
+        .filter_level(log::LevelFilter::Debug)
+        .is_test(true)
+        .try_init();
+    logging::set_body_preview(8);
+
+    let server = crate::testing::MockServer::start().await.unwrap();
+    server.stub(Method::POST, "/", 200, "response body");
+
+    // the preview must not consume the request body
+    let manager = Manager::new().unwrap();
+    let req = crate::create_json_post(server.url(), "/", r#"{"id":1,"method":"info"}"#).unwrap();
+    assert_eq!(
+        manager.read_bytes(req, true).await.unwrap(),
+        "response body"
+    );
+    server
+        .assert_received(Method::POST, "/")
+        .with_json_body(&serde_json::json!({"id": 1, "method": "info"}))
+        .once();
+}
+
+/// RUST_LOG=debug cargo test --lib -- manager::test_manager_max_connections_per_host --exact --show-output
+#[tokio::test]
+async fn test_manager_max_connections_per_host() {
+    use hyper::Method;
+
+    let server = crate::testing::MockServer::start().await.unwrap();
+    server.stub(Method::GET, "/", 200, "ok");
+
+    let manager = Manager::builder()
+        .max_connections_per_host(1)
+        .build()
+        .unwrap();
+    let mut handles = Vec::new();
+    for _ in 0..5 {
+        let manager = manager.clone();
+        let url = server.url();
+        handles.push(tokio::spawn(async move {
+            let req = crate::create_get(&url, "/").unwrap();
+            manager.read_bytes(req, true).await
+        }));
+    }
+    for h in handles {
+        assert_eq!(h.await.unwrap().unwrap(), "ok");
+    }
+    assert_eq!(server.received_count(Method::GET, "/"), 5);
+    assert_eq!(server.accepted_connections(), 1);
+
+    let manager = Manager::builder()
+        .pool_max_idle_per_host(0)
+        .build()
+        .unwrap();
+    for _ in 0..2 {
+        let req = crate::create_get(server.url(), "/").unwrap();
+        manager.read_bytes(req, true).await.unwrap();
+    }
+    assert_eq!(server.accepted_connections(), 3);
+}
+
+/// RUST_LOG=debug cargo test --lib -- manager::test_manager_http2 --exact --show-output
+#[tokio::test]
+async fn test_manager_http2() {
+    use hyper::{Method, Version};
+
+    let server = crate::testing::MockServer::start_https().await.unwrap();
+    server.stub(Method::GET, "/", 200, "ok");
+    let tls = TlsConfig::new().add_root_pem(server.cert_pem().unwrap());
+
+    // HTTP/1.1 unless enabled
+    let manager = Manager::builder().tls_config(tls.clone()).build().unwrap();
+    let req = crate::create_get(server.url(), "/").unwrap();
+    assert_eq!(manager.read_bytes(req, true).await.unwrap(), "ok");
+    assert_eq!(server.received_requests()[0].version, Version::HTTP_11);
+
+    // negotiated via ALPN, then concurrent requests share the connection
+    let manager = Manager::builder()
+        .tls_config(tls)
+        .http2(true)
+        .build()
+        .unwrap();
+    let req = crate::create_get(server.url(), "/").unwrap();
+    assert_eq!(manager.read_bytes(req, true).await.unwrap(), "ok");
+    let mut handles = Vec::new();
+    for _ in 0..5 {
+        let manager = manager.clone();
+        let url = server.url();
+        handles.push(tokio::spawn(async move {
+            let req = crate::create_get(&url, "/").unwrap();
+            manager.read_bytes(req, true).await
+        }));
+    }
+    for h in handles {
+        assert_eq!(h.await.unwrap().unwrap(), "ok");
+    }
+    let received = server.received_requests();
+    assert_eq!(received.len(), 7);
+    assert!(received[1..].iter().all(|r| r.version == Version::HTTP_2));
+    assert_eq!(server.accepted_connections(), 2);
+
+    // cleartext h2c with prior knowledge
+    let server = crate::testing::MockServer::start().await.unwrap();
+    server.stub(Method::GET, "/", 200, "ok");
+    let manager = Manager::builder()
+        .http2_prior_knowledge(true)
+        .build()
+        .unwrap();
+    let req = crate::create_get(server.url(), "/").unwrap();
+    assert_eq!(manager.read_bytes(req, true).await.unwrap(), "ok");
+    assert_eq!(server.received_requests()[0].version, Version::HTTP_2);
+
+    // "http2" alone keeps HTTP/1.1 for cleartext
+    let manager = Manager::builder().http2(true).build().unwrap();
+    let req = crate::create_get(server.url(), "/").unwrap();
+    assert_eq!(manager.read_bytes(req, true).await.unwrap(), "ok");
+    assert_eq!(server.received_requests()[1].version, Version::HTTP_11);
+}
+
+#[test]
+fn test_builder_validate() {
+    assert!(ManagerBuilder::default().validate().is_ok());
+
+    let err = Manager::builder()
+        .timeout(Duration::from_secs(1))
+        .connect_timeout(Duration::from_secs(5))
+        .build()
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    assert!(err.to_string().contains("connect timeout"));
+
+    assert!(Manager::builder().timeout(Duration::ZERO).build().is_err());
+    assert!(Manager::builder()
+        .max_connections_per_host(0)
+        .build()
+        .is_err());
+    assert!(Manager::builder().allowed_schemes(&[]).build().is_err());
+    assert!(Manager::builder()
+        .allowed_schemes(&["ftp"])
+        .build()
+        .is_err());
+    assert!(Manager::builder()
+        .allowed_hosts(&["api.*"])
+        .build()
+        .is_err());
+    assert!(Manager::builder()
+        .host_override("a.com", HostOverride::new().timeout(Duration::from_secs(1)))
+        .build()
+        .is_err());
+    assert!(Manager::builder()
+        .decompression_limits(DecompressionLimits {
+            max_ratio: 0,
+            ..Default::default()
+        })
+        .build()
+        .is_err());
+    assert!(Manager::builder()
+        .low_speed_limit(LowSpeedLimit::new(0, Duration::from_secs(30)))
+        .build()
+        .is_err());
+}
+
+/// RUST_LOG=debug cargo test --lib -- manager::test_manager_warm_up --exact --show-output
+#[tokio::test]
+async fn test_manager_warm_up() {
+    let server = crate::testing::MockServer::start().await.unwrap();
+    server.stub(Method::GET, "/", 200, "ok");
+
+    let manager = Manager::new().unwrap();
+    let ret = manager.warm_up(&[&server.url(), "ftp://localhost"]).await;
+    assert!(ret[0].is_ok());
+    assert!(ret[1].is_err());
+    assert_eq!(server.received_count(Method::HEAD, "/"), 1);
+    assert_eq!(server.accepted_connections(), 1);
+
+    let req = crate::create_get(server.url(), "/").unwrap();
+    assert_eq!(manager.read_bytes(req, true).await.unwrap(), "ok");
+    assert_eq!(server.accepted_connections(), 1);
+}
+
+/// RUST_LOG=debug cargo test --lib -- manager::test_manager_adaptive_concurrency --exact --show-output
+#[tokio::test]
+async fn test_manager_adaptive_concurrency() {
+    let server = crate::testing::MockServer::start().await.unwrap();
+    server.stub(Method::GET, "/ok", 200, "ok");
+    server.stub(Method::GET, "/busy", 503, "busy");
+
+    let manager = Manager::builder()
+        .adaptive_concurrency(AimdConfig {
+            initial_limit: 8,
+            ..Default::default()
+        })
+        .build()
+        .unwrap();
+    let limiter = manager.limiter().unwrap();
+
+    let req = crate::create_get(server.url(), "/busy").unwrap();
+    manager.read_bytes(req, false).await.unwrap();
+    assert_eq!(limiter.limit(), 4);
+
+    for _ in 0..10 {
+        let req = crate::create_get(server.url(), "/ok").unwrap();
+        manager.read_bytes(req, true).await.unwrap();
+    }
+    assert_eq!(limiter.limit(), 6);
+    assert_eq!(limiter.in_flight(), 0);
+
+    assert!(Manager::builder()
+        .adaptive_concurrency(AimdConfig {
+            decrease_factor: 1.5,
+            ..Default::default()
+        })
+        .build()
+        .is_err());
+}
+
+/// RUST_LOG=debug cargo test --lib -- manager::test_manager_base_url --exact --show-output
+#[tokio::test]
+async fn test_manager_base_url() {
+    let server = crate::testing::MockServer::start().await.unwrap();
+    server.stub(Method::GET, "/ext/info", 200, "info");
+    server.stub(Method::POST, "/ext/bc/X", 200, "posted");
+    server.stub(Method::PUT, "/ext/kv", 204, "");
+
+    let manager = Manager::builder()
+        .base_url(&format!("{}/ext", server.url()))
+        .build()
+        .unwrap();
+    assert_eq!(
+        manager.base_url().unwrap().as_str(),
+        format!("{}/ext", server.url())
+    );
+    assert_eq!(manager.get("info").await.unwrap().body(), "info");
+    assert_eq!(manager.get("/info").await.unwrap().body(), "info");
+    let resp = manager.post("bc/X", "{}").await.unwrap();
+    assert_eq!(resp.body(), "posted");
+    server.assert_received(Method::POST, "/ext/bc/X").once();
+    let resp = manager.request(Method::PUT, "kv", "v").await.unwrap();
+    assert_eq!(resp.status(), 204);
+    // absolute URLs are sent as is
+    let resp = manager
+        .get(&format!("{}/ext/info", server.url()))
+        .await
+        .unwrap();
+    assert_eq!(resp.body(), "info");
+    // all over the same pooled connection
+    assert_eq!(server.accepted_connections(), 1);
+
+    let e = Manager::new().unwrap().get("info").await.unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidInput);
+    assert!(Manager::builder()
+        .base_url("localhost:9650")
+        .build()
+        .is_err());
+    assert!(Manager::builder()
+        .base_url("ftp://localhost")
+        .build()
+        .is_err());
+
+    let builder = ManagerBuilder::from_env_with(|k| {
+        (k == "HTTP_MANAGER_BASE_URL").then(|| "http://localhost:9650/ext".to_string())
+    })
+    .unwrap();
+    assert_eq!(
+        builder.base_url.as_deref(),
+        Some("http://localhost:9650/ext")
+    );
+}
+
+/// RUST_LOG=debug cargo test --lib -- manager::test_manager_timeout_deadline --exact --show-output
+#[tokio::test]
+async fn test_manager_timeout_deadline() {
+    use tokio::{io::AsyncWriteExt, net::TcpListener};
+
+    // sends the headers after 400ms, then trickles the body over 600ms,
+    // each within the 700ms timeout, but not in total
+    async fn trickle() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            tokio::time::sleep(Duration::from_millis(400)).await;
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 6\r\n\r\n")
+                .await
+                .unwrap();
+            for _ in 0..6 {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                stream.write_all(b"a").await.unwrap();
+            }
+        });
+        format!("http://{}", addr)
+    }
+
+    let manager = Manager::builder()
+        .timeout(Duration::from_millis(700))
+        .connect_timeout(Duration::from_millis(500))
+        .build()
+        .unwrap();
+    let started = Instant::now();
+    let req = crate::create_get(trickle().await, "/").unwrap();
+    let e = manager.read_bytes(req, true).await.unwrap_err();
+    assert!(error::Error::from(e).is_timeout());
+    assert!(started.elapsed() < Duration::from_millis(900));
+
+    let req = crate::create_get(trickle().await, "/").unwrap();
+    assert!(manager.read_response(req).await.is_err());
+
+    let manager = Manager::builder()
+        .timeout(Duration::from_secs(5))
+        .build()
+        .unwrap();
+    let req = crate::create_get(trickle().await, "/").unwrap();
+    assert_eq!(manager.read_bytes(req, true).await.unwrap(), "aaaaaa");
+}
@@ -0,0 +1,169 @@
+//! In-memory "HttpClient" with queued responses, for tests of the code
+//! sending requests (no server, no sockets).
+//! Enabled for this crate's own tests, and for downstream crates via the
+//! "mock" feature.
+
+use std::{
+    collections::VecDeque,
+    io::{self, Error, ErrorKind},
+    sync::{Arc, Mutex},
+};
+
+use hyper::{body::Bytes, Body, HeaderMap, Method, Request, Response, StatusCode, Uri};
+
+use crate::client::{HttpClient, ResponseFuture};
+
+/// Request as sent to the fake client.
+#[derive(Debug, Clone)]
+pub struct SentRequest {
+    pub method: Method,
+    pub uri: Uri,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+/// Returns the queued responses in order, one per request, and records
+/// the requests. Requests after the queue runs out fail. Clones share the
+/// queue and the recorded requests.
+///
+/// ```ignore
+/// let client = FakeClient::new();
+/// client.push_response(503, "busy");
+/// client.push_response(200, r#"{"result": "0x1"}"#);
+/// let resp = client.read_response(req).await?;
+/// assert_eq!(client.requests().len(), 1);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct FakeClient {
+    inner: Arc<Mutex<FakeClientState>>,
+}
+
+#[derive(Debug, Default)]
+struct FakeClientState {
+    responses: VecDeque<io::Result<Response<Bytes>>>,
+    requests: Vec<SentRequest>,
+}
+
+impl FakeClient {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues the response.
+    pub fn push(&self, resp: Response<Bytes>) {
+        self.inner.lock().unwrap().responses.push_back(Ok(resp));
+    }
+
+    /// Queues a response with the status code and body, and no header.
+    pub fn push_response(&self, status: u16, body: impl Into<Bytes>) {
+        let mut resp = Response::new(body.into());
+        *resp.status_mut() = StatusCode::from_u16(status).expect("invalid status code");
+        self.push(resp);
+    }
+
+    /// Queues the error (e.g., "error::Error::Timeout" to test retries).
+    pub fn push_error(&self, e: io::Error) {
+        self.inner.lock().unwrap().responses.push_back(Err(e));
+    }
+
+    /// Returns the number of queued responses not returned yet.
+    pub fn remaining(&self) -> usize {
+        self.inner.lock().unwrap().responses.len()
+    }
+
+    /// Returns the requests sent so far, in order.
+    pub fn requests(&self) -> Vec<SentRequest> {
+        self.inner.lock().unwrap().requests.clone()
+    }
+}
+
+impl HttpClient for FakeClient {
+    fn read_response(&self, req: Request<Body>) -> ResponseFuture<'_> {
+        Box::pin(async move {
+            let (parts, body) = req.into_parts();
+            let body = crate::buffer::collect(body).await.map_err(|e| {
+                Error::new(
+                    ErrorKind::Other,
+                    format!("failed to read request body {}", e),
+                )
+            })?;
+            let mut state = self.inner.lock().unwrap();
+            state.requests.push(SentRequest {
+                method: parts.method.clone(),
+                uri: parts.uri.clone(),
+                headers: parts.headers,
+                body,
+            });
+            state.responses.pop_front().unwrap_or_else(|| {
+                Err(Error::new(
+                    ErrorKind::Other,
+                    format!("no queued response for {} {}", parts.method, parts.uri),
+                ))
+            })
+        })
+    }
+}
+
+/// RUST_LOG=debug cargo test --lib -- mock::test_fake_client --exact --show-output
+#[tokio::test]
+async fn test_fake_client() {
+    use crate::error;
+
+    // code under test, generic over the client
+    async fn block_number(client: &impl HttpClient) -> io::Result<String> {
+        let resp = client
+            .post(
+                "http://localhost:9650/ext/bc/C/rpc",
+                Body::from(r#"{"method":"eth_blockNumber"}"#),
+            )
+            .await?;
+        if !resp.status().is_success() {
+            let (parts, body) = resp.into_parts();
+            return Err(error::Error::Status(parts.status, body).into());
+        }
+        Ok(String::from_utf8_lossy(resp.body()).to_string())
+    }
+
+    let client = FakeClient::new();
+    client.push_response(503, "busy");
+    client.push_error(error::Error::Timeout("timed out".to_string()).into());
+    client.push(
+        Response::builder()
+            .header("content-type", "application/json")
+            .body(Bytes::from_static(b"0x1"))
+            .unwrap(),
+    );
+    assert_eq!(client.remaining(), 3);
+
+    match error::Error::from(block_number(&client).await.unwrap_err()) {
+        error::Error::Status(status, body) => {
+            assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+            assert_eq!(body, "busy");
+        }
+        e => panic!("unexpected {:?}", e),
+    }
+    assert!(matches!(
+        error::Error::from(block_number(&client).await.unwrap_err()),
+        error::Error::Timeout(_)
+    ));
+    assert_eq!(block_number(&client.clone()).await.unwrap(), "0x1");
+
+    // the queue ran out
+    assert_eq!(
+        block_number(&client).await.unwrap_err().kind(),
+        ErrorKind::Other
+    );
+
+    let requests = client.requests();
+    assert_eq!(requests.len(), 4);
+    assert_eq!(requests[0].method, Method::POST);
+    assert_eq!(requests[0].uri.path(), "/ext/bc/C/rpc");
+    assert_eq!(requests[0].body, r#"{"method":"eth_blockNumber"}"#);
+
+    // relative paths need a base URL
+    assert!(matches!(
+        error::Error::from(client.get("/ext/health").await.unwrap_err()),
+        error::Error::UrlParse(_)
+    ));
+    assert_eq!(client.requests().len(), 4);
+}
@@ -0,0 +1,331 @@
+//! Uptime monitoring: periodic checks of registered endpoints with the
+//! shared client, reporting every result and up/down transitions.
+
+use std::{
+    collections::HashMap,
+    io::{self, Error, ErrorKind},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::{
+    sync::mpsc,
+    task::JoinHandle,
+    time::{interval, MissedTickBehavior},
+};
+
+use crate::{logging::wire_info, Manager};
+
+/// Endpoint to check, e.g.:
+///
+/// ```
+/// use std::time::Duration;
+/// use http_manager::monitor::Check;
+///
+/// let check = Check::new("api", "https://api.example.com/health")
+///     .interval(Duration::from_secs(30))
+///     .expect_status(200)
+///     .expect_body("ok")
+///     .failure_threshold(3);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    name: String,
+    url: String,
+    interval: Duration,
+    timeout: Option<Duration>,
+    expected_status: Option<u16>,
+    expected_body: Option<String>,
+    failure_threshold: usize,
+}
+
+impl Check {
+    /// Checks the URL every minute, expecting any 2xx response.
+    pub fn new(name: &str, url: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            url: url.to_string(),
+            interval: Duration::from_secs(60),
+            timeout: None,
+            expected_status: None,
+            expected_body: None,
+            failure_threshold: 1,
+        }
+    }
+
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Replaces the manager timeout.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Expects exactly this status code instead of any 2xx.
+    pub fn expect_status(mut self, status: u16) -> Self {
+        self.expected_status = Some(status);
+        self
+    }
+
+    /// Expects the response body to contain the substring.
+    pub fn expect_body(mut self, substring: &str) -> Self {
+        self.expected_body = Some(substring.to_string());
+        self
+    }
+
+    /// Number of consecutive failures before the endpoint is marked down
+    /// (default 1), to ride out a single dropped request.
+    pub fn failure_threshold(mut self, n: usize) -> Self {
+        self.failure_threshold = n.max(1);
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Up,
+    Down,
+}
+
+/// Result of a single check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckResult {
+    pub name: String,
+    /// Status after this check.
+    pub status: Status,
+    /// Status before this check ("None" for the first check).
+    pub previous: Option<Status>,
+    /// Set if this check failed.
+    pub error: Option<String>,
+    pub consecutive_failures: usize,
+    pub latency: Duration,
+}
+
+impl CheckResult {
+    /// Returns true if the status changed with this check (including the
+    /// first check).
+    pub fn is_transition(&self) -> bool {
+        self.previous != Some(self.status)
+    }
+}
+
+/// Checks registered endpoints on their intervals.
+#[derive(Debug, Clone)]
+pub struct Monitor {
+    manager: Manager,
+    checks: Vec<Check>,
+}
+
+impl Monitor {
+    pub fn new(manager: Manager) -> Self {
+        Self {
+            manager,
+            checks: Vec::new(),
+        }
+    }
+
+    /// Fails on a duplicate name, a zero interval, or an invalid URL.
+    pub fn register(&mut self, check: Check) -> io::Result<()> {
+        if self.checks.iter().any(|c| c.name == check.name) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("duplicate check name '{}'", check.name),
+            ));
+        }
+        if check.interval.is_zero() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("check '{}' has a zero interval", check.name),
+            ));
+        }
+        crate::create_get(&check.url, "")?;
+        self.checks.push(check);
+        Ok(())
+    }
+
+    /// Starts checking every endpoint (the first check runs immediately)
+    /// and returns the handle and the results. Checks stop when the handle
+    /// is dropped; a full channel delays the checks until results are read.
+    pub fn start(self) -> (MonitorHandle, mpsc::Receiver<CheckResult>) {
+        let (tx, rx) = mpsc::channel(1024);
+        let states = Arc::new(Mutex::new(HashMap::new()));
+        let tasks = self
+            .checks
+            .into_iter()
+            .map(|check| {
+                let manager = self.manager.clone();
+                let tx = tx.clone();
+                let states = states.clone();
+                tokio::spawn(async move {
+                    let mut ticker = interval(check.interval);
+                    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                    let mut previous = None;
+                    let mut consecutive_failures = 0;
+                    loop {
+                        ticker.tick().await;
+                        let start = Instant::now();
+                        let error = run_check(&manager, &check).await.err();
+                        let latency = start.elapsed();
+
+                        let status = match error {
+                            Some(_) => {
+                                consecutive_failures += 1;
+                                if consecutive_failures >= check.failure_threshold {
+                                    Status::Down
+                                } else {
+                                    // keeps the previous status until the threshold
+                                    previous.unwrap_or(Status::Up)
+                                }
+                            }
+                            None => {
+                                consecutive_failures = 0;
+                                Status::Up
+                            }
+                        };
+                        let result = CheckResult {
+                            name: check.name.clone(),
+                            status,
+                            previous,
+                            error,
+                            consecutive_failures,
+                            latency,
+                        };
+                        if result.is_transition() {
+                            wire_info!(
+                                "check '{}' is {:?} (was {:?})",
+                                check.name,
+                                status,
+                                previous
+                            );
+                        }
+                        previous = Some(status);
+                        states
+                            .lock()
+                            .unwrap()
+                            .insert(check.name.clone(), result.clone());
+                        // keeps checking for "MonitorHandle::latest" even
+                        // if the receiver is dropped
+                        let _ = tx.send(result).await;
+                    }
+                })
+            })
+            .collect();
+        (MonitorHandle { tasks, states }, rx)
+    }
+}
+
+async fn run_check(manager: &Manager, check: &Check) -> Result<(), String> {
+    let req = crate::create_get(&check.url, "").map_err(|e| e.to_string())?;
+    let timeout_dur = check
+        .timeout
+        .unwrap_or_else(|| manager.timeout_for(req.uri()));
+    let resp = manager
+        .fetch(req, timeout_dur)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let status = resp.status();
+    let ok = match check.expected_status {
+        Some(s) => status.as_u16() == s,
+        None => status.is_success(),
+    };
+    if !ok {
+        return Err(format!("unexpected status code {}", status));
+    }
+    if let Some(expected) = &check.expected_body {
+        let body = String::from_utf8_lossy(resp.body());
+        if !body.contains(expected.as_str()) {
+            return Err(format!("response body does not contain '{}'", expected));
+        }
+    }
+    Ok(())
+}
+
+/// Stops the checks when dropped.
+#[derive(Debug)]
+pub struct MonitorHandle {
+    tasks: Vec<JoinHandle<()>>,
+    states: Arc<Mutex<HashMap<String, CheckResult>>>,
+}
+
+impl MonitorHandle {
+    /// Returns the latest result of the check, if it ran at least once.
+    pub fn latest(&self, name: &str) -> Option<CheckResult> {
+        self.states.lock().unwrap().get(name).cloned()
+    }
+
+    /// Stops the checks.
+    pub fn stop(self) {}
+}
+
+impl Drop for MonitorHandle {
+    fn drop(&mut self) {
+        for t in self.tasks.iter() {
+            t.abort();
+        }
+    }
+}
+
+/// RUST_LOG=debug cargo test --lib -- monitor::test_monitor --exact --show-output
+#[tokio::test]
+async fn test_monitor() {
+    use hyper::Method;
+
+    let server = crate::testing::MockServer::start().await.unwrap();
+    server.stub(Method::GET, "/health", 200, "status: ok");
+
+    let mut monitor = Monitor::new(Manager::new().unwrap());
+    let check = Check::new("api", &format!("{}/health", server.url()))
+        .interval(Duration::from_millis(20))
+        .expect_body("ok")
+        .failure_threshold(2);
+    monitor.register(check.clone()).unwrap();
+    assert!(monitor.register(check).is_err());
+    assert!(monitor
+        .register(Check::new("zero", &server.url()).interval(Duration::ZERO))
+        .is_err());
+
+    let (handle, mut rx) = monitor.start();
+    async fn recv(rx: &mut mpsc::Receiver<CheckResult>) -> CheckResult {
+        tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    let first = recv(&mut rx).await;
+    assert_eq!(first.status, Status::Up);
+    assert_eq!(first.previous, None);
+    assert!(first.is_transition());
+
+    server.stub(Method::GET, "/health", 200, "status: degraded");
+    let mut result = recv(&mut rx).await;
+    while result.error.is_none() {
+        result = recv(&mut rx).await;
+    }
+    // a single failure is below the threshold
+    assert_eq!(result.status, Status::Up);
+    assert_eq!(result.consecutive_failures, 1);
+    assert!(!result.is_transition());
+
+    let result = recv(&mut rx).await;
+    assert_eq!(result.status, Status::Down);
+    assert_eq!(result.previous, Some(Status::Up));
+    assert_eq!(result.consecutive_failures, 2);
+    assert!(result.is_transition());
+
+    server.stub(Method::GET, "/health", 200, "status: ok");
+    let mut result = recv(&mut rx).await;
+    while result.status == Status::Down {
+        result = recv(&mut rx).await;
+    }
+    assert_eq!(result.previous, Some(Status::Down));
+    assert_eq!(result.consecutive_failures, 0);
+    assert_eq!(handle.latest("api").unwrap().status, Status::Up);
+
+    handle.stop();
+    while rx.recv().await.is_some() {}
+}
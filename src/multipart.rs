@@ -0,0 +1,163 @@
+//! "multipart/form-data" request bodies, for file uploads.
+//! ref. https://www.rfc-editor.org/rfc/rfc7578
+
+use std::{io, path::PathBuf};
+
+use hyper::body::Bytes;
+use rand::Rng;
+
+#[derive(Debug, Clone)]
+enum Source {
+    Bytes(Bytes),
+    /// Read when the body is encoded.
+    File(PathBuf),
+}
+
+/// A single form field, either a text value or a file.
+///
+/// ```ignore
+/// let parts = vec![
+///     Part::text("name", "release"),
+///     Part::file("artifact", "/tmp/app.tar.gz").content_type("application/gzip"),
+///     Part::bytes("checksum", sha256).filename("app.tar.gz.sha256"),
+/// ];
+/// ```
+#[derive(Debug, Clone)]
+pub struct Part {
+    name: String,
+    filename: Option<String>,
+    content_type: Option<String>,
+    source: Source,
+}
+
+impl Part {
+    /// Text field, sent without a filename or content type.
+    pub fn text(name: &str, value: impl Into<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            filename: None,
+            content_type: None,
+            source: Source::Bytes(Bytes::from(value.into())),
+        }
+    }
+
+    /// In-memory file content, sent as "application/octet-stream" unless
+    /// "content_type" is set.
+    pub fn bytes(name: &str, data: impl Into<Bytes>) -> Self {
+        Self {
+            name: name.to_string(),
+            filename: None,
+            content_type: Some(OCTET_STREAM.to_string()),
+            source: Source::Bytes(data.into()),
+        }
+    }
+
+    /// File on disk, sent with its file name as "application/octet-stream"
+    /// unless "filename" or "content_type" is set.
+    pub fn file(name: &str, path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        Self {
+            name: name.to_string(),
+            filename: path.file_name().map(|f| f.to_string_lossy().into_owned()),
+            content_type: Some(OCTET_STREAM.to_string()),
+            source: Source::File(path),
+        }
+    }
+
+    pub fn filename(mut self, filename: &str) -> Self {
+        self.filename = Some(filename.to_string());
+        self
+    }
+
+    pub fn content_type(mut self, content_type: &str) -> Self {
+        self.content_type = Some(content_type.to_string());
+        self
+    }
+}
+
+const OCTET_STREAM: &str = "application/octet-stream";
+
+/// Encodes the parts with a random boundary, and returns the
+/// "Content-Type" header value (with the boundary) and the body.
+pub async fn encode(parts: &[Part]) -> io::Result<(String, Bytes)> {
+    let boundary = format!("http-manager-{:032x}", rand::thread_rng().gen::<u128>());
+    let body = encode_with_boundary(parts, &boundary).await?;
+    Ok((format!("multipart/form-data; boundary={}", boundary), body))
+}
+
+async fn encode_with_boundary(parts: &[Part], boundary: &str) -> io::Result<Bytes> {
+    let mut body = Vec::new();
+    for part in parts.iter() {
+        body.extend_from_slice(format!("--{}\r\n", boundary).as_bytes());
+        body.extend_from_slice(
+            format!(
+                "Content-Disposition: form-data; name=\"{}\"",
+                escape_quoted(&part.name)
+            )
+            .as_bytes(),
+        );
+        if let Some(filename) = &part.filename {
+            body.extend_from_slice(
+                format!("; filename=\"{}\"", escape_quoted(filename)).as_bytes(),
+            );
+        }
+        body.extend_from_slice(b"\r\n");
+        if let Some(content_type) = &part.content_type {
+            body.extend_from_slice(format!("Content-Type: {}\r\n", content_type).as_bytes());
+        }
+        body.extend_from_slice(b"\r\n");
+        match &part.source {
+            Source::Bytes(b) => body.extend_from_slice(b),
+            Source::File(path) => body.extend_from_slice(&tokio::fs::read(path).await?),
+        }
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+    Ok(Bytes::from(body))
+}
+
+/// Escapes a quoted parameter value as browsers do, so that a name or
+/// filename cannot end the parameter or inject headers.
+/// ref. https://html.spec.whatwg.org/multipage/form-control-infrastructure.html#multipart-form-data
+fn escape_quoted(s: &str) -> String {
+    s.replace('\r', "%0D")
+        .replace('\n', "%0A")
+        .replace('"', "%22")
+}
+
+/// RUST_LOG=debug cargo test --lib -- multipart::test_encode --exact --show-output
+#[tokio::test]
+async fn test_encode() {
+    let file_path = std::env::temp_dir().join("http-manager-test-multipart.txt");
+    std::fs::write(&file_path, "file content").unwrap();
+
+    let parts = vec![
+        Part::text("name", "release"),
+        Part::file("artifact", &file_path).content_type("text/plain"),
+        Part::bytes("raw", vec![0u8, 1, 2]).filename("a\"b\r\n.bin"),
+    ];
+    let body = encode_with_boundary(&parts, "XYZ").await.unwrap();
+    let expected = [
+        &b"--XYZ\r\n"[..],
+        b"Content-Disposition: form-data; name=\"name\"\r\n\r\n",
+        b"release\r\n",
+        b"--XYZ\r\n",
+        b"Content-Disposition: form-data; name=\"artifact\"; filename=\"http-manager-test-multipart.txt\"\r\n",
+        b"Content-Type: text/plain\r\n\r\n",
+        b"file content\r\n",
+        b"--XYZ\r\n",
+        b"Content-Disposition: form-data; name=\"raw\"; filename=\"a%22b%0D%0A.bin\"\r\n",
+        b"Content-Type: application/octet-stream\r\n\r\n",
+        &[0u8, 1, 2],
+        b"\r\n--XYZ--\r\n",
+    ]
+    .concat();
+    assert_eq!(body, expected);
+
+    let (content_type, _) = encode(&parts).await.unwrap();
+    assert!(content_type.starts_with("multipart/form-data; boundary=http-manager-"));
+
+    std::fs::remove_file(&file_path).unwrap();
+    let e = encode(&parts).await.unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::NotFound);
+}
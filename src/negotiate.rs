@@ -0,0 +1,323 @@
+//! Content negotiation for APIs that serve several representations at
+//! the same path: a weighted "Accept" header built from the registered
+//! decoders, and the decoder picked by the response "Content-Type".
+
+use std::{
+    fmt,
+    io::{self, Error, ErrorKind},
+};
+
+use hyper::{
+    header::{ACCEPT, CONTENT_TYPE},
+    Body, Request,
+};
+use serde::de::DeserializeOwned;
+
+use crate::{logging::wire_debug, Manager};
+
+type Decoder<T> = Box<dyn Fn(&[u8]) -> io::Result<T> + Send + Sync>;
+
+struct Registered<T> {
+    media_type: String,
+    q: f32,
+    decoder: Decoder<T>,
+}
+
+/// Decoders of a uniformly typed value by media type, in order of
+/// preference.
+pub struct Negotiator<T> {
+    decoders: Vec<Registered<T>>,
+}
+
+impl<T> fmt::Debug for Negotiator<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Negotiator")
+            .field("accept", &self.accept())
+            .finish()
+    }
+}
+
+impl<T> Default for Negotiator<T> {
+    fn default() -> Self {
+        Self {
+            decoders: Vec::new(),
+        }
+    }
+}
+
+impl<T> Negotiator<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers the decoder of the media type (e.g., "application/xml"
+    /// or "text/*") with the quality value "q" in [0, 1].
+    pub fn register<F>(mut self, media_type: &str, q: f32, decoder: F) -> Self
+    where
+        F: Fn(&[u8]) -> io::Result<T> + Send + Sync + 'static,
+    {
+        self.decoders.push(Registered {
+            media_type: media_type.trim().to_ascii_lowercase(),
+            q: q.clamp(0.0, 1.0),
+            decoder: Box::new(decoder),
+        });
+        self
+    }
+
+    /// Registers a UTF-8 "text/plain" decoder.
+    pub fn text<F>(self, q: f32, f: F) -> Self
+    where
+        F: Fn(String) -> io::Result<T> + Send + Sync + 'static,
+    {
+        self.register("text/plain", q, move |b| {
+            let s = std::str::from_utf8(b).map_err(|e| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("failed to decode text {}", e),
+                )
+            })?;
+            f(s.to_string())
+        })
+    }
+
+    /// Returns the "Accept" header value, sorted by quality value
+    /// (e.g., "application/json, application/xml;q=0.9").
+    pub fn accept(&self) -> String {
+        let mut types: Vec<&Registered<T>> = self.decoders.iter().collect();
+        // stable, so that ties keep the registration order
+        types.sort_by(|a, b| b.q.total_cmp(&a.q));
+        types
+            .iter()
+            .map(|r| {
+                if r.q >= 1.0 {
+                    r.media_type.clone()
+                } else {
+                    // at most three decimals (RFC 9110 "qvalue")
+                    let q = format!("{:.3}", r.q);
+                    let q = q.trim_end_matches('0').trim_end_matches('.');
+                    format!("{};q={}", r.media_type, if q.is_empty() { "0" } else { q })
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Decodes the body with the decoder of the content type: an exact
+    /// match, then a structured syntax suffix (e.g., "+json" for
+    /// "application/json"), then a wildcard (e.g., "text/*"). A missing
+    /// content type is decoded by the first registered decoder.
+    pub fn decode(&self, content_type: Option<&str>, body: &[u8]) -> io::Result<T> {
+        let r = match content_type {
+            Some(ct) => self.find(ct),
+            None => self.decoders.first(),
+        };
+        match r {
+            Some(r) => {
+                wire_debug!("decoding {:?} as {}", content_type, r.media_type);
+                (r.decoder)(body)
+            }
+            None => Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "unsupported content type {:?} (accepts {})",
+                    content_type,
+                    self.accept()
+                ),
+            )),
+        }
+    }
+
+    fn find(&self, content_type: &str) -> Option<&Registered<T>> {
+        let ct = essence(content_type);
+        let (top, sub) = ct.split_once('/')?;
+        if let Some(r) = self.decoders.iter().find(|r| r.media_type == ct) {
+            return Some(r);
+        }
+        if let Some((_, suffix)) = sub.rsplit_once('+') {
+            let base = format!("{}/{}", top, suffix);
+            if let Some(r) = self.decoders.iter().find(|r| r.media_type == base) {
+                return Some(r);
+            }
+        }
+        self.decoders
+            .iter()
+            .find(|r| match r.media_type.split_once('/') {
+                Some((t, "*")) => t == top || t == "*",
+                _ => false,
+            })
+    }
+}
+
+impl<T: DeserializeOwned> Negotiator<T> {
+    /// Registers an "application/json" decoder (also used for "+json"
+    /// types such as "application/problem+json").
+    pub fn json(self, q: f32) -> Self {
+        self.register("application/json", q, |b| {
+            serde_json::from_slice(b).map_err(|e| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("failed to decode JSON {}", e),
+                )
+            })
+        })
+    }
+}
+
+/// Returns the lowercase media type without parameters.
+fn essence(content_type: &str) -> String {
+    content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase()
+}
+
+impl Manager {
+    /// Sends a GET request with the "Accept" header of the negotiator and
+    /// decodes the 2xx response by its "Content-Type".
+    pub async fn get_negotiated<T>(
+        &self,
+        url: &str,
+        path: &str,
+        negotiator: &Negotiator<T>,
+    ) -> io::Result<T> {
+        let uri = crate::join_uri(url, path)?;
+        let req = Request::get(uri.as_str())
+            .header(ACCEPT, negotiator.accept())
+            .body(Body::empty())
+            .map_err(|e| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("failed to create request {}", e),
+                )
+            })?;
+        let resp = self.read_response(req).await?;
+        if !resp.status().is_success() {
+            return Err(Error::new(
+                ErrorKind::Other,
+                format!(
+                    "unexpected HTTP response code {} from {}",
+                    resp.status(),
+                    crate::redact::url(uri.as_str())
+                ),
+            ));
+        }
+        let content_type = resp
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok());
+        negotiator.decode(content_type, resp.body())
+    }
+}
+
+#[test]
+fn test_negotiator() {
+    #[derive(Debug, PartialEq, serde::Deserialize)]
+    #[serde(untagged)]
+    enum Doc {
+        Json { name: String },
+        Text(String),
+    }
+
+    let n = Negotiator::<Doc>::new()
+        .text(0.5, |s| Ok(Doc::Text(s)))
+        .json(1.0)
+        .register("application/xml", 0.9, |b| {
+            let s = String::from_utf8_lossy(b);
+            let name = s
+                .trim()
+                .strip_prefix("<name>")
+                .and_then(|s| s.strip_suffix("</name>"))
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, "bad XML"))?;
+            Ok(Doc::Json {
+                name: name.to_string(),
+            })
+        })
+        .register("text/*", 0.125, |b| {
+            Ok(Doc::Text(String::from_utf8_lossy(b).to_string()))
+        });
+    assert_eq!(
+        n.accept(),
+        "application/json, application/xml;q=0.9, text/plain;q=0.5, text/*;q=0.125"
+    );
+
+    let json = Doc::Json {
+        name: "a".to_string(),
+    };
+    assert_eq!(
+        n.decode(Some("application/json; charset=utf-8"), br#"{"name":"a"}"#)
+            .unwrap(),
+        json
+    );
+    assert_eq!(
+        n.decode(Some("Application/Problem+JSON"), br#"{"name":"a"}"#)
+            .unwrap(),
+        json
+    );
+    assert_eq!(
+        n.decode(Some("application/xml"), b"<name>a</name>")
+            .unwrap(),
+        json
+    );
+    assert_eq!(
+        n.decode(Some("text/plain"), b"hi").unwrap(),
+        Doc::Text("hi".to_string())
+    );
+    assert_eq!(
+        n.decode(Some("text/csv"), b"a,b").unwrap(),
+        Doc::Text("a,b".to_string())
+    );
+    // the first registered decoder
+    assert_eq!(n.decode(None, b"hi").unwrap(), Doc::Text("hi".to_string()));
+
+    let e = n.decode(Some("image/png"), b"").unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidData);
+    assert!(n.decode(Some("application/json"), b"{").is_err());
+    assert!(n.decode(Some("text/plain"), &[0xff]).is_err());
+}
+
+/// RUST_LOG=debug cargo test --lib -- negotiate::test_get_negotiated --exact --show-output
+#[tokio::test]
+async fn test_get_negotiated() {
+    use crate::testing::Stub;
+    use hyper::Method;
+
+    let server = crate::testing::MockServer::start().await.unwrap();
+    server.register(
+        Stub::new(Method::GET, "/v")
+            .respond(200, r#"{"v":1}"#)
+            .respond_header("content-type", "application/json"),
+    );
+    server.register(
+        Stub::new(Method::GET, "/t")
+            .respond(200, "1")
+            .respond_header("content-type", "text/plain; charset=utf-8"),
+    );
+
+    let n = Negotiator::<serde_json::Value>::new()
+        .json(1.0)
+        .text(0.5, |s| {
+            Ok(serde_json::json!({ "v": s.parse::<u64>().unwrap_or(0) }))
+        });
+    let manager = Manager::new().unwrap();
+    let v = manager
+        .get_negotiated(&server.url(), "/v", &n)
+        .await
+        .unwrap();
+    assert_eq!(v["v"], 1);
+    let v = manager
+        .get_negotiated(&server.url(), "/t", &n)
+        .await
+        .unwrap();
+    assert_eq!(v["v"], 1);
+    server
+        .assert_received(Method::GET, "/v")
+        .once()
+        .with_header("accept", "application/json, text/plain;q=0.5");
+
+    assert!(manager
+        .get_negotiated(&server.url(), "/missing", &n)
+        .await
+        .is_err());
+}
@@ -0,0 +1,886 @@
+//! Pulls the blobs (config and layers) of a container image from an OCI
+//! distribution registry, without a container runtime. Blobs are written
+//! in the OCI image layout ("blobs/sha256/{hex}") under the output
+//! directory, verified by digest, and resumed if interrupted.
+//! ref. https://github.com/opencontainers/distribution-spec/blob/main/spec.md
+
+use std::{
+    io::{self, Error, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+use hyper::{
+    body::HttpBody,
+    header::{HeaderValue, ACCEPT, AUTHORIZATION, LOCATION, WWW_AUTHENTICATE},
+    Body, Request, Response, StatusCode,
+};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tokio::{
+    fs::{self, OpenOptions},
+    io::{AsyncReadExt, AsyncWriteExt},
+};
+use url::Url;
+
+use crate::{
+    crawl::MAX_REDIRECTS,
+    logging::{wire_debug, wire_info, wire_warn},
+    stall::SpeedMonitor,
+    Manager,
+};
+
+pub const MEDIA_TYPE_OCI_MANIFEST: &str = "application/vnd.oci.image.manifest.v1+json";
+pub const MEDIA_TYPE_OCI_INDEX: &str = "application/vnd.oci.image.index.v1+json";
+pub const MEDIA_TYPE_DOCKER_MANIFEST: &str = "application/vnd.docker.distribution.manifest.v2+json";
+pub const MEDIA_TYPE_DOCKER_MANIFEST_LIST: &str =
+    "application/vnd.docker.distribution.manifest.list.v2+json";
+
+/// Registry of references without one (Docker Hub).
+const DEFAULT_REGISTRY: &str = "registry-1.docker.io";
+
+/// Image reference (e.g., "ghcr.io/org/app:1.0", "alpine", or
+/// "quay.io/org/app@sha256:...").
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageRef {
+    pub registry: String,
+    pub repository: String,
+    /// Tag or digest.
+    pub reference: String,
+}
+
+impl ImageRef {
+    pub fn parse(s: &str) -> io::Result<Self> {
+        let s = s.trim();
+        if s.is_empty() || s.contains(char::is_whitespace) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid image reference '{}'", s),
+            ));
+        }
+        // the first component is a registry if it looks like a host
+        let (registry, rest) = match s.split_once('/') {
+            Some((first, rest))
+                if first.contains('.') || first.contains(':') || first == "localhost" =>
+            {
+                (first.to_string(), rest)
+            }
+            _ => (DEFAULT_REGISTRY.to_string(), s),
+        };
+        let (name, reference) = match rest.split_once('@') {
+            Some((name, digest)) => (name, digest.to_string()),
+            None => match rest.rsplit_once(':') {
+                Some((name, tag)) if !tag.contains('/') => (name, tag.to_string()),
+                _ => (rest, "latest".to_string()),
+            },
+        };
+        let repository = if registry == DEFAULT_REGISTRY && !name.contains('/') {
+            format!("library/{}", name)
+        } else {
+            name.to_string()
+        };
+        Ok(Self {
+            registry,
+            repository,
+            reference,
+        })
+    }
+
+    /// Returns the base URL of the registry API.
+    pub fn registry_url(&self) -> String {
+        format!("https://{}", self.registry)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OciPullConfig {
+    /// Platform picked from a multi-platform image index.
+    pub os: String,
+    pub architecture: String,
+    /// Pre-issued bearer token; otherwise an anonymous token is requested
+    /// when the registry challenges.
+    pub token: Option<String>,
+}
+
+impl Default for OciPullConfig {
+    fn default() -> Self {
+        Self {
+            os: "linux".to_string(),
+            architecture: "amd64".to_string(),
+            token: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Descriptor {
+    #[serde(default)]
+    pub media_type: String,
+    pub digest: String,
+    pub size: u64,
+    #[serde(default)]
+    pub platform: Option<Platform>,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+pub struct Platform {
+    pub os: String,
+    pub architecture: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Manifest {
+    #[serde(default)]
+    media_type: String,
+    config: Option<Descriptor>,
+    #[serde(default)]
+    layers: Vec<Descriptor>,
+    /// Set for an image index.
+    manifests: Option<Vec<Descriptor>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PulledBlob {
+    pub descriptor: Descriptor,
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PulledImage {
+    /// Digest of the (platform) manifest.
+    pub manifest_digest: String,
+    pub config: PulledBlob,
+    pub layers: Vec<PulledBlob>,
+}
+
+impl Manager {
+    /// Pulls the config and layer blobs of the image into "dir".
+    /// Blobs already present with the right digest are skipped, and
+    /// partially downloaded blobs are resumed with range requests.
+    pub async fn pull_image_blobs(
+        &self,
+        image: &ImageRef,
+        dir: impl AsRef<Path>,
+        cfg: &OciPullConfig,
+    ) -> io::Result<PulledImage> {
+        self.pull_image_blobs_from(
+            &image.registry_url(),
+            &image.repository,
+            &image.reference,
+            dir,
+            cfg,
+        )
+        .await
+    }
+
+    /// Same as "pull_image_blobs", with the registry base URL
+    /// (e.g., "https://ghcr.io" or a local mirror).
+    pub async fn pull_image_blobs_from(
+        &self,
+        registry_url: &str,
+        repository: &str,
+        reference: &str,
+        dir: impl AsRef<Path>,
+        cfg: &OciPullConfig,
+    ) -> io::Result<PulledImage> {
+        let mut s = Session {
+            manager: self,
+            registry_url: registry_url.trim_end_matches('/').to_string(),
+            repository: repository.to_string(),
+            token: cfg.token.clone(),
+        };
+
+        let (mut manifest, mut digest) = s.manifest(reference).await?;
+        if let Some(manifests) = &manifest.manifests {
+            let d = manifests
+                .iter()
+                .find(|d| {
+                    d.platform.as_ref().map_or(false, |p| {
+                        p.os == cfg.os && p.architecture == cfg.architecture
+                    })
+                })
+                .ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::NotFound,
+                        format!(
+                            "no manifest for {}/{} in the image index of {}",
+                            cfg.os, cfg.architecture, repository
+                        ),
+                    )
+                })?;
+            wire_debug!(
+                "selected {} manifest {} for {}/{}",
+                d.media_type,
+                d.digest,
+                cfg.os,
+                cfg.architecture
+            );
+            let d = d.digest.clone();
+            (manifest, digest) = s.manifest(&d).await?;
+        }
+        if manifest.manifests.is_some() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "image index refers to another image index",
+            ));
+        }
+        let config = manifest.config.take().ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "manifest {} ({}) has no config",
+                    digest, manifest.media_type
+                ),
+            )
+        })?;
+
+        let dir = dir.as_ref().join("blobs").join("sha256");
+        fs::create_dir_all(&dir).await?;
+        let config = s.blob(config, &dir).await?;
+        let mut layers = Vec::with_capacity(manifest.layers.len());
+        for d in manifest.layers.into_iter() {
+            layers.push(s.blob(d, &dir).await?);
+        }
+        wire_info!(
+            "pulled {} layer(s) of {}@{}",
+            layers.len(),
+            repository,
+            digest
+        );
+        Ok(PulledImage {
+            manifest_digest: digest,
+            config,
+            layers,
+        })
+    }
+}
+
+struct Session<'a> {
+    manager: &'a Manager,
+    registry_url: String,
+    repository: String,
+    token: Option<String>,
+}
+
+impl Session<'_> {
+    /// Fetches and parses the manifest, verifying it if referenced by
+    /// digest. Returns the manifest and its digest.
+    async fn manifest(&mut self, reference: &str) -> io::Result<(Manifest, String)> {
+        let path = format!("/v2/{}/manifests/{}", self.repository, reference);
+        let accept = [
+            MEDIA_TYPE_OCI_MANIFEST,
+            MEDIA_TYPE_OCI_INDEX,
+            MEDIA_TYPE_DOCKER_MANIFEST,
+            MEDIA_TYPE_DOCKER_MANIFEST_LIST,
+        ]
+        .join(", ");
+        let resp = self.get(&path, Some(&accept), None).await?;
+        let resp = check_status(resp, &path)?;
+        let header_digest = resp
+            .headers()
+            .get("docker-content-digest")
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string());
+        let body = crate::read_body_bytes(
+            resp.into_body(),
+            self.manager.timeout_for(&self.uri(&path)?),
+            self.manager.low_speed_limit().copied(),
+        )
+        .await?;
+        let digest = format!("sha256:{:x}", Sha256::digest(&body));
+        if reference.contains(':') && reference != digest {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("manifest digest {} does not match {}", digest, reference),
+            ));
+        }
+        if let Some(d) = header_digest {
+            if d.starts_with("sha256:") && d != digest {
+                wire_warn!("manifest digest {} differs from the header {}", digest, d);
+            }
+        }
+        let manifest: Manifest = serde_json::from_slice(&body).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("failed to parse manifest {}", e),
+            )
+        })?;
+        Ok((manifest, digest))
+    }
+
+    /// Downloads the blob to "{dir}/{hex}", unless already there.
+    async fn blob(&mut self, d: Descriptor, dir: &Path) -> io::Result<PulledBlob> {
+        let hex = match d.digest.strip_prefix("sha256:") {
+            Some(hex) if hex.len() == 64 && hex.bytes().all(|b| b.is_ascii_hexdigit()) => {
+                hex.to_ascii_lowercase()
+            }
+            Some(_) => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("invalid digest '{}'", d.digest),
+                ))
+            }
+            None => {
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    format!("unsupported digest algorithm '{}'", d.digest),
+                ))
+            }
+        };
+        let path = dir.join(&hex);
+        if fs::metadata(&path).await.is_ok() {
+            if file_sha256(&path).await? == (hex.clone(), d.size) {
+                wire_debug!("blob {} already pulled", d.digest);
+                return Ok(PulledBlob {
+                    descriptor: d,
+                    path,
+                });
+            }
+            wire_warn!("blob {} on disk is corrupted, pulling again", d.digest);
+            fs::remove_file(&path).await?;
+        }
+
+        let partial = dir.join(format!("{}.partial", hex));
+        let mut hasher = Sha256::new();
+        let mut written = 0;
+        if fs::metadata(&partial).await.is_ok() {
+            let mut f = fs::File::open(&partial).await?;
+            let mut buf = vec![0; 64 * 1024];
+            loop {
+                let n = f.read(&mut buf).await?;
+                if n == 0 {
+                    break;
+                }
+                hasher.update(&buf[..n]);
+                written += n as u64;
+            }
+        }
+        if written > d.size {
+            written = 0;
+            hasher = Sha256::new();
+        }
+
+        let path_v2 = format!("/v2/{}/blobs/{}", self.repository, d.digest);
+        let range = if written > 0 && written < d.size {
+            wire_info!("resuming blob {} from byte {}", d.digest, written);
+            Some(format!("bytes={}-", written))
+        } else {
+            None
+        };
+        let resp = if written == d.size && written > 0 {
+            None
+        } else {
+            Some(self.get(&path_v2, None, range.as_deref()).await?)
+        };
+
+        if let Some(resp) = resp {
+            let append = written > 0 && resp.status() == StatusCode::PARTIAL_CONTENT;
+            let resp = check_status(resp, &path_v2)?;
+            if !append {
+                // the registry may ignore the range and send the whole blob
+                written = 0;
+                hasher = Sha256::new();
+            }
+            let mut f = OpenOptions::new()
+                .create(true)
+                .write(true)
+                .append(append)
+                .truncate(!append)
+                .open(&partial)
+                .await?;
+            let idle_timeout = self.manager.timeout_for(&self.uri(&path_v2)?);
+            let mut body = resp.into_body();
+            let mut monitor = self
+                .manager
+                .low_speed_limit()
+                .copied()
+                .map(SpeedMonitor::new);
+            loop {
+                let next = async {
+                    match monitor.as_mut() {
+                        Some(m) => m.next_chunk(&mut body).await,
+                        None => body.data().await.transpose().map_err(|e| {
+                            Error::new(
+                                ErrorKind::Other,
+                                format!("failed to read blob {} {}", d.digest, e),
+                            )
+                        }),
+                    }
+                };
+                let chunk = match tokio::time::timeout(idle_timeout, next).await {
+                    Ok(Ok(Some(chunk))) => chunk,
+                    Ok(Ok(None)) => break,
+                    // keeps the partial blob for the next pull to resume
+                    Ok(Err(e)) => {
+                        f.flush().await?;
+                        wire_warn!(
+                            "failed to read blob {} at byte {} ({})",
+                            d.digest,
+                            written,
+                            e
+                        );
+                        return Err(e);
+                    }
+                    Err(_) => {
+                        f.flush().await?;
+                        return Err(Error::new(
+                            ErrorKind::TimedOut,
+                            format!("timed out reading blob {} at byte {}", d.digest, written),
+                        ));
+                    }
+                };
+                written += chunk.len() as u64;
+                if written > d.size {
+                    drop(f);
+                    fs::remove_file(&partial).await?;
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("blob {} is larger than {} bytes", d.digest, d.size),
+                    ));
+                }
+                hasher.update(&chunk);
+                f.write_all(&chunk).await?;
+            }
+            f.flush().await?;
+        }
+
+        let got = format!("{:x}", hasher.finalize());
+        if got != hex || written != d.size {
+            fs::remove_file(&partial).await?;
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "blob {} failed verification (sha256:{}, {} of {} bytes)",
+                    d.digest, got, written, d.size
+                ),
+            ));
+        }
+        fs::rename(&partial, &path).await?;
+        wire_debug!("pulled blob {} ({} bytes)", d.digest, d.size);
+        Ok(PulledBlob {
+            descriptor: d,
+            path,
+        })
+    }
+
+    fn uri(&self, path: &str) -> io::Result<hyper::Uri> {
+        let u = crate::join_uri(&self.registry_url, path)?;
+        u.as_str().parse().map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid registry URI {}", e),
+            )
+        })
+    }
+
+    /// Sends a GET request to the registry, requesting a token on a
+    /// "401" challenge and following redirects (e.g., to a blob storage
+    /// CDN, which does not get the registry token).
+    async fn get(
+        &mut self,
+        path: &str,
+        accept: Option<&str>,
+        range: Option<&str>,
+    ) -> io::Result<Response<Body>> {
+        let registry = crate::join_uri(&self.registry_url, path)?;
+        let mut url = registry.clone();
+        let mut redirects = 0;
+        let mut challenged = false;
+        loop {
+            let same_origin = url.origin() == registry.origin();
+            let mut builder = Request::builder().uri(url.as_str());
+            if let Some(a) = accept {
+                builder = builder.header(ACCEPT, a);
+            }
+            if let Some(r) = range {
+                builder = builder.header(hyper::header::RANGE, r);
+            }
+            if let (Some(t), true) = (&self.token, same_origin) {
+                builder = builder.header(AUTHORIZATION, format!("Bearer {}", t));
+            }
+            let req = builder.body(Body::empty()).map_err(|e| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("failed to create request {}", e),
+                )
+            })?;
+            let resp = self.manager.send(req).await?;
+
+            if resp.status() == StatusCode::UNAUTHORIZED && same_origin && !challenged {
+                challenged = true;
+                let challenge = resp.headers().get(WWW_AUTHENTICATE).cloned();
+                self.token = Some(self.fetch_token(challenge.as_ref()).await?);
+                continue;
+            }
+            if resp.status().is_redirection() {
+                let next = resp
+                    .headers()
+                    .get(LOCATION)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|loc| url.join(loc).ok());
+                if let Some(next) = next {
+                    if redirects >= MAX_REDIRECTS {
+                        return Err(Error::new(
+                            ErrorKind::Other,
+                            format!("stopped after {} redirects", MAX_REDIRECTS),
+                        ));
+                    }
+                    redirects += 1;
+                    wire_debug!(
+                        "following redirect to {}",
+                        crate::redact::url(next.as_str())
+                    );
+                    url = next;
+                    continue;
+                }
+            }
+            return Ok(resp);
+        }
+    }
+
+    /// Requests an (anonymous) pull token from the realm of a
+    /// "Bearer" challenge.
+    async fn fetch_token(&self, challenge: Option<&HeaderValue>) -> io::Result<String> {
+        let challenge = challenge.and_then(|v| v.to_str().ok()).unwrap_or("");
+        let params = parse_bearer_challenge(challenge).ok_or_else(|| {
+            Error::new(
+                ErrorKind::PermissionDenied,
+                format!(
+                    "registry requires unsupported authentication '{}'",
+                    challenge
+                ),
+            )
+        })?;
+        let realm = params
+            .iter()
+            .find(|(k, _)| k == "realm")
+            .map(|(_, v)| v.as_str())
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "bearer challenge without realm"))?;
+        let mut url = Url::parse(realm).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("invalid token realm '{}' {}", realm, e),
+            )
+        })?;
+        {
+            let mut q = url.query_pairs_mut();
+            for (k, v) in params
+                .iter()
+                .filter(|(k, _)| k == "service" || k == "scope")
+            {
+                q.append_pair(k, v);
+            }
+        }
+        if !params.iter().any(|(k, _)| k == "scope") {
+            url.query_pairs_mut()
+                .append_pair("scope", &format!("repository:{}:pull", self.repository));
+        }
+        wire_debug!(
+            "requesting registry token from {}",
+            crate::redact::url(url.as_str())
+        );
+
+        let req = crate::create_get(url.as_str(), "")?;
+        let resp = self.manager.read_response(req).await?;
+        if !resp.status().is_success() {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                format!("token request returned status code {}", resp.status()),
+            ));
+        }
+        #[derive(Deserialize)]
+        struct TokenResponse {
+            token: Option<String>,
+            access_token: Option<String>,
+        }
+        let t: TokenResponse = serde_json::from_slice(resp.body()).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("failed to parse token response {}", e),
+            )
+        })?;
+        t.token
+            .or(t.access_token)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "token response without a token"))
+    }
+}
+
+fn check_status(resp: Response<Body>, path: &str) -> io::Result<Response<Body>> {
+    let status = resp.status();
+    if status.is_success() {
+        return Ok(resp);
+    }
+    let kind = match status {
+        StatusCode::NOT_FOUND => ErrorKind::NotFound,
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => ErrorKind::PermissionDenied,
+        _ => ErrorKind::Other,
+    };
+    Err(Error::new(
+        kind,
+        format!("registry returned status code {} for {}", status, path),
+    ))
+}
+
+/// Parses the parameters of a "Bearer" challenge
+/// (e.g., 'Bearer realm="https://auth.docker.io/token",service="registry.docker.io"').
+fn parse_bearer_challenge(s: &str) -> Option<Vec<(String, String)>> {
+    let s = s.trim();
+    let rest = s
+        .get(..7)
+        .filter(|p| p.eq_ignore_ascii_case("bearer "))
+        .map(|_| &s[7..])?;
+    let mut params = Vec::new();
+    let mut chars = rest.chars().peekable();
+    loop {
+        while matches!(chars.peek(), Some(c) if *c == ',' || c.is_whitespace()) {
+            chars.next();
+        }
+        let key: String = chars.by_ref().take_while(|c| *c != '=').collect();
+        if key.is_empty() {
+            return Some(params);
+        }
+        let mut value = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' => break,
+                    '\\' => value.extend(chars.next()),
+                    c => value.push(c),
+                }
+            }
+        } else {
+            while let Some(c) = chars.peek() {
+                if *c == ',' {
+                    break;
+                }
+                value.push(*c);
+                chars.next();
+            }
+        }
+        params.push((key.trim().to_ascii_lowercase(), value.trim().to_string()));
+    }
+}
+
+/// Returns the hex-encoded SHA-256 and the size of the file.
+async fn file_sha256(path: &Path) -> io::Result<(String, u64)> {
+    let mut f = fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+    let mut size = 0;
+    loop {
+        let n = f.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        size += n as u64;
+    }
+    Ok((format!("{:x}", hasher.finalize()), size))
+}
+
+#[test]
+fn test_image_ref_parse() {
+    let r = ImageRef::parse("alpine").unwrap();
+    assert_eq!(
+        r,
+        ImageRef {
+            registry: DEFAULT_REGISTRY.to_string(),
+            repository: "library/alpine".to_string(),
+            reference: "latest".to_string(),
+        }
+    );
+    let r = ImageRef::parse("ghcr.io/org/app:1.0").unwrap();
+    assert_eq!(
+        (
+            r.registry.as_str(),
+            r.repository.as_str(),
+            r.reference.as_str()
+        ),
+        ("ghcr.io", "org/app", "1.0")
+    );
+    assert_eq!(r.registry_url(), "https://ghcr.io");
+    let r = ImageRef::parse("localhost:5000/app@sha256:abcd").unwrap();
+    assert_eq!(
+        (
+            r.registry.as_str(),
+            r.repository.as_str(),
+            r.reference.as_str()
+        ),
+        ("localhost:5000", "app", "sha256:abcd")
+    );
+    let r = ImageRef::parse("org/app").unwrap();
+    assert_eq!(r.repository, "org/app");
+    assert!(ImageRef::parse("").is_err());
+}
+
+#[test]
+fn test_parse_bearer_challenge() {
+    let params = parse_bearer_challenge(
+        r#"Bearer realm="https://auth.docker.io/token",service="registry.docker.io",scope="repository:a/b:pull,push""#,
+    )
+    .unwrap();
+    assert_eq!(
+        params,
+        vec![
+            (
+                "realm".to_string(),
+                "https://auth.docker.io/token".to_string()
+            ),
+            ("service".to_string(), "registry.docker.io".to_string()),
+            ("scope".to_string(), "repository:a/b:pull,push".to_string()),
+        ]
+    );
+    assert!(parse_bearer_challenge(r#"Basic realm="x""#).is_none());
+}
+
+/// RUST_LOG=debug cargo test --all-features --lib -- oci::test_pull_image_blobs --exact --show-output
+#[tokio::test]
+async fn test_pull_image_blobs() {
+    use hyper::Method;
+
+    use crate::testing::Stub;
+
+    let digest = |b: &[u8]| format!("sha256:{:x}", Sha256::digest(b));
+
+    let server = crate::testing::MockServer::start().await.unwrap();
+    let cdn = crate::testing::MockServer::start().await.unwrap();
+    let url = server.url();
+    let config = br#"{"architecture":"amd64","os":"linux"}"#.to_vec();
+    let layer: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8).collect();
+    let manifest = serde_json::json!({
+        "schemaVersion": 2,
+        "mediaType": MEDIA_TYPE_OCI_MANIFEST,
+        "config": {"mediaType": "application/vnd.oci.image.config.v1+json", "digest": digest(&config), "size": config.len()},
+        "layers": [{"mediaType": "application/vnd.oci.image.layer.v1.tar+gzip", "digest": digest(&layer), "size": layer.len()}],
+    })
+    .to_string();
+    let index = serde_json::json!({
+        "schemaVersion": 2,
+        "mediaType": MEDIA_TYPE_OCI_INDEX,
+        "manifests": [
+            {"mediaType": MEDIA_TYPE_OCI_MANIFEST, "digest": digest(b"other"), "size": 5, "platform": {"os": "linux", "architecture": "arm64"}},
+            {"mediaType": MEDIA_TYPE_OCI_MANIFEST, "digest": digest(manifest.as_bytes()), "size": manifest.len(), "platform": {"os": "linux", "architecture": "amd64"}},
+        ],
+    })
+    .to_string();
+
+    let authed = |path: String, body: Vec<u8>| {
+        Stub::new(Method::GET, &path)
+            .with_header("authorization", "Bearer t0k")
+            .respond(200, body)
+    };
+    server.register(
+        Stub::new(Method::GET, "/v2/org/app/manifests/1.0")
+            .respond(401, "")
+            .respond_header(
+                "www-authenticate",
+                &format!(
+                    r#"Bearer realm="{}/token",service="registry.test",scope="repository:org/app:pull""#,
+                    url
+                ),
+            ),
+    );
+    server.register(
+        Stub::new(Method::GET, "/token")
+            .with_query("service", "registry.test")
+            .with_query("scope", "repository:org/app:pull")
+            .respond(200, r#"{"token":"t0k"}"#),
+    );
+    server.register(authed(
+        "/v2/org/app/manifests/1.0".to_string(),
+        index.into_bytes(),
+    ));
+    server.register(authed(
+        format!("/v2/org/app/manifests/{}", digest(manifest.as_bytes())),
+        manifest.clone().into_bytes(),
+    ));
+    server.register(authed(
+        format!("/v2/org/app/blobs/{}", digest(&config)),
+        config.clone(),
+    ));
+    // layers are served from another location
+    server.register(
+        Stub::new(
+            Method::GET,
+            &format!("/v2/org/app/blobs/{}", digest(&layer)),
+        )
+        .respond(307, "")
+        .respond_header("location", &format!("{}/layer", cdn.url())),
+    );
+    cdn.stub(Method::GET, "/layer", 200, layer.clone());
+
+    let dir = std::env::temp_dir().join(format!("http-manager-test-oci-{}", server.port()));
+    let manager = Manager::new().unwrap();
+    let pulled = manager
+        .pull_image_blobs_from(&url, "org/app", "1.0", &dir, &OciPullConfig::default())
+        .await
+        .unwrap();
+    assert_eq!(pulled.manifest_digest, digest(manifest.as_bytes()));
+    assert_eq!(std::fs::read(&pulled.config.path).unwrap(), config);
+    assert_eq!(pulled.layers.len(), 1);
+    let layer_path = pulled.layers[0].path.clone();
+    assert_eq!(
+        layer_path,
+        dir.join("blobs/sha256").join(&digest(&layer)[7..])
+    );
+    assert_eq!(std::fs::read(&layer_path).unwrap(), layer);
+    // the registry token is not sent to the other location
+    assert!(!cdn.received_requests()[0]
+        .headers
+        .contains_key("authorization"));
+
+    // already pulled
+    manager
+        .pull_image_blobs_from(&url, "org/app", "1.0", &dir, &OciPullConfig::default())
+        .await
+        .unwrap();
+    cdn.assert_received(Method::GET, "/layer").once();
+
+    // resumes a partial download
+    std::fs::remove_file(&layer_path).unwrap();
+    let partial = dir
+        .join("blobs/sha256")
+        .join(format!("{}.partial", &digest(&layer)[7..]));
+    std::fs::write(&partial, &layer[..40_000]).unwrap();
+    cdn.register(
+        Stub::new(Method::GET, "/layer")
+            .with_header("range", "bytes=40000-")
+            .respond(206, layer[40_000..].to_vec())
+            .respond_header("content-range", "bytes 40000-99999/100000"),
+    );
+    manager
+        .pull_image_blobs_from(&url, "org/app", "1.0", &dir, &OciPullConfig::default())
+        .await
+        .unwrap();
+    assert_eq!(std::fs::read(&layer_path).unwrap(), layer);
+    assert!(!partial.exists());
+
+    // corrupted blobs are rejected
+    std::fs::remove_file(&layer_path).unwrap();
+    cdn.stub(Method::GET, "/layer", 200, vec![0; 100_000]);
+    let e = manager
+        .pull_image_blobs_from(&url, "org/app", "1.0", &dir, &OciPullConfig::default())
+        .await
+        .unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidData);
+    assert!(!layer_path.exists() && !partial.exists());
+
+    let e = manager
+        .pull_image_blobs_from(
+            &url,
+            "org/app",
+            "1.0",
+            &dir,
+            &OciPullConfig {
+                architecture: "s390x".to_string(),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::NotFound);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,298 @@
+//! Paginated list APIs, followed page by page: via "Link: <...>;
+//! rel="next"" headers (e.g., GitHub), or via a cursor in the JSON body
+//! that is sent back as a query parameter.
+//! ref. <https://www.rfc-editor.org/rfc/rfc8288>
+
+use std::{
+    collections::HashSet,
+    io::{self, Error, ErrorKind},
+};
+
+use futures_util::{stream, Stream};
+use hyper::{body::Bytes, header::LINK, Body, HeaderMap, Method, Request};
+use serde::de::DeserializeOwned;
+use url::Url;
+
+use crate::{error, informational::parse_links, logging::wire_debug, Manager};
+
+/// Page of a paginated response (of a 2xx status code).
+#[derive(Debug, Clone)]
+pub struct Page {
+    pub url: Url,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+impl Page {
+    pub fn json<T: DeserializeOwned>(&self) -> io::Result<T> {
+        serde_json::from_slice(&self.body).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("failed to parse JSON page {}", e),
+            )
+        })
+    }
+}
+
+type CursorFn = Box<dyn Fn(&serde_json::Value) -> Option<String> + Send + Sync>;
+
+struct Cursor {
+    param: String,
+    extract: CursorFn,
+}
+
+struct Pages {
+    manager: Manager,
+    next: Option<io::Result<Url>>,
+    cursor: Option<Cursor>,
+    /// Pages fetched so far, to stop on servers that link back.
+    visited: HashSet<Url>,
+}
+
+impl Manager {
+    /// Fetches the pages starting at the path joined to the URL, following
+    /// the "rel=next" link of every page (resolved against the page URL)
+    /// until the last page, which has none. A failed page (including
+    /// non-2xx status codes, as "error::Error::Status") ends the stream
+    /// after yielding the error. Use "StreamExt::take" to cap the pages.
+    pub fn paginate(
+        &self,
+        url: &str,
+        path: &str,
+    ) -> impl Stream<Item = io::Result<Page>> + Send + 'static {
+        self.pages(url, path, None)
+    }
+
+    /// Same as "paginate", but the next page is requested with the cursor
+    /// extracted from the JSON body of the current page, set as the query
+    /// parameter "param" (e.g., "?cursor=..." from '{"next_cursor": ...}').
+    /// The last page returns None (or an empty cursor).
+    pub fn paginate_with_cursor<F>(
+        &self,
+        url: &str,
+        path: &str,
+        param: &str,
+        extract: F,
+    ) -> impl Stream<Item = io::Result<Page>> + Send + 'static
+    where
+        F: Fn(&serde_json::Value) -> Option<String> + Send + Sync + 'static,
+    {
+        let cursor = Cursor {
+            param: param.to_string(),
+            extract: Box::new(extract),
+        };
+        self.pages(url, path, Some(cursor))
+    }
+
+    fn pages(
+        &self,
+        url: &str,
+        path: &str,
+        cursor: Option<Cursor>,
+    ) -> impl Stream<Item = io::Result<Page>> + Send + 'static {
+        let p = Pages {
+            manager: self.clone(),
+            next: Some(crate::join_uri(url, path)),
+            cursor,
+            visited: HashSet::new(),
+        };
+        stream::unfold(p, |mut p| async move {
+            let u = match p.next.take()? {
+                Ok(u) => u,
+                Err(e) => return Some((Err(e), p)),
+            };
+            let ret = p.fetch(u).await;
+            Some((ret, p))
+        })
+    }
+}
+
+impl Pages {
+    /// Fetches the page, and sets the URL of the next one, if any.
+    async fn fetch(&mut self, u: Url) -> io::Result<Page> {
+        let req = Request::builder()
+            .method(Method::GET)
+            .uri(u.as_str())
+            .body(Body::empty())
+            .map_err(|e| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("failed to create page request {}", e),
+                )
+            })?;
+        let resp = self.manager.read_response(req).await?;
+        let (parts, body) = resp.into_parts();
+        if !parts.status.is_success() {
+            return Err(error::Error::Status(parts.status, body).into());
+        }
+        let page = Page {
+            url: u,
+            headers: parts.headers,
+            body,
+        };
+
+        let next = match &self.cursor {
+            None => next_link(&page)?,
+            Some(c) => match (c.extract)(&page.json()?) {
+                Some(cursor) if !cursor.is_empty() => {
+                    Some(with_query_param(&page.url, &c.param, &cursor))
+                }
+                _ => None,
+            },
+        };
+        self.visited.insert(page.url.clone());
+        self.next = match next {
+            Some(n) if self.visited.contains(&n) => Some(Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "next page {} was already fetched",
+                    crate::redact::url(n.as_str())
+                ),
+            ))),
+            Some(n) => {
+                wire_debug!("next page {}", crate::redact::url(n.as_str()));
+                Some(Ok(n))
+            }
+            None => None,
+        };
+        Ok(page)
+    }
+}
+
+/// Returns the "rel=next" link of the page, if any.
+fn next_link(page: &Page) -> io::Result<Option<Url>> {
+    let target = page
+        .headers
+        .get_all(LINK)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(parse_links)
+        .find(|(_, rels)| rels.iter().any(|r| r.eq_ignore_ascii_case("next")))
+        .map(|(target, _)| target);
+    let Some(target) = target else {
+        return Ok(None);
+    };
+    page.url.join(&target).map(Some).map_err(|e| {
+        error::Error::UrlParse(format!("failed to parse next page link '{}' {}", target, e)).into()
+    })
+}
+
+/// Returns the URL with the query parameter set (replacing any value).
+fn with_query_param(u: &Url, param: &str, value: &str) -> Url {
+    let pairs: Vec<(String, String)> = u
+        .query_pairs()
+        .filter(|(k, _)| k != param)
+        .map(|(k, v)| (k.into_owned(), v.into_owned()))
+        .collect();
+    let mut next = u.clone();
+    next.query_pairs_mut()
+        .clear()
+        .extend_pairs(pairs)
+        .append_pair(param, value);
+    next
+}
+
+/// RUST_LOG=debug cargo test --lib -- paginate::test_paginate --exact --show-output
+#[tokio::test]
+async fn test_paginate() {
+    use futures_util::StreamExt;
+
+    use crate::testing::Stub;
+
+    let server = crate::testing::MockServer::start().await.unwrap();
+    let last = format!("{}/items?page=3", server.url());
+    server.register(
+        Stub::new(Method::GET, "/items")
+            .respond(200, "[1,2]")
+            .respond_header("link", "</items?page=2>; rel=\"next\", <?page=3>; rel=last"),
+    );
+    server.register(
+        Stub::new(Method::GET, "/items")
+            .with_query("page", "2")
+            .respond(200, "[3,4]")
+            .respond_header(
+                "link",
+                &format!("</items>; rel=\"prev first\", <{}>; rel=NEXT", last),
+            ),
+    );
+    server.register(
+        Stub::new(Method::GET, "/items")
+            .with_query("page", "3")
+            .respond(200, "[5]")
+            .respond_header("link", "</items?page=2>; rel=prev"),
+    );
+
+    let manager = Manager::new().unwrap();
+    let pages: Vec<_> = manager
+        .paginate(&server.url(), "/items")
+        .map(|p| p.unwrap())
+        .collect()
+        .await;
+    let items: Vec<Vec<u32>> = pages.iter().map(|p| p.json().unwrap()).collect();
+    assert_eq!(items, vec![vec![1, 2], vec![3, 4], vec![5]]);
+    assert_eq!(pages[2].url.as_str(), last);
+    server.assert_received(Method::GET, "/items").times(3);
+
+    // links back to a fetched page
+    server.register(
+        Stub::new(Method::GET, "/loop")
+            .respond(200, "[]")
+            .respond_header("link", "</loop>; rel=next"),
+    );
+    let ret: Vec<_> = manager.paginate(&server.url(), "/loop").collect().await;
+    assert_eq!(ret.len(), 2);
+    assert!(ret[0].is_ok());
+    assert_eq!(ret[1].as_ref().unwrap_err().kind(), ErrorKind::InvalidData);
+
+    // the failed page ends the stream
+    let ret: Vec<_> = manager.paginate(&server.url(), "/missing").collect().await;
+    assert_eq!(ret.len(), 1);
+    assert!(matches!(
+        error::Error::from(ret.into_iter().next().unwrap().unwrap_err()),
+        error::Error::Status(s, _) if s == hyper::StatusCode::NOT_FOUND
+    ));
+}
+
+/// RUST_LOG=debug cargo test --lib -- paginate::test_paginate_with_cursor --exact --show-output
+#[tokio::test]
+async fn test_paginate_with_cursor() {
+    use futures_util::StreamExt;
+
+    use crate::testing::Stub;
+
+    let server = crate::testing::MockServer::start().await.unwrap();
+    server.stub(
+        Method::GET,
+        "/events",
+        200,
+        r#"{"items":[1,2],"next_cursor":"c2"}"#,
+    );
+    server.register(
+        Stub::new(Method::GET, "/events")
+            .with_query("cursor", "c2")
+            .with_query("limit", "2")
+            .respond(200, r#"{"items":[3],"next_cursor":null}"#),
+    );
+
+    let manager = Manager::new().unwrap();
+    let pages: Vec<_> = manager
+        .paginate_with_cursor(&server.url(), "/events?limit=2", "cursor", |v| {
+            v["next_cursor"].as_str().map(|s| s.to_string())
+        })
+        .map(|p| p.unwrap())
+        .collect()
+        .await;
+    assert_eq!(pages.len(), 2);
+    let v: serde_json::Value = pages[1].json().unwrap();
+    assert_eq!(v["items"], serde_json::json!([3]));
+    assert_eq!(pages[1].url.query(), Some("limit=2&cursor=c2"));
+
+    // not JSON
+    server.stub(Method::GET, "/text", 200, "hello");
+    let ret: Vec<_> = manager
+        .paginate_with_cursor(&server.url(), "/text", "cursor", |_| None)
+        .collect()
+        .await;
+    assert_eq!(ret.len(), 1);
+    assert_eq!(ret[0].as_ref().unwrap_err().kind(), ErrorKind::InvalidData);
+}
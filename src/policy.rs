@@ -0,0 +1,147 @@
+//! Egress policies on request destinations.
+
+use std::io::{self, Error, ErrorKind};
+
+use url::Url;
+
+/// Allowed and denied host patterns.
+/// A pattern is either an exact host ("api.example.com", "10.0.0.1"),
+/// a wildcard for all subdomains ("*.example.com", which does not match
+/// "example.com" itself), or "*" for any host. Matching is
+/// case-insensitive. Denied patterns take precedence, and a non-empty
+/// allowlist rejects every host it does not match.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HostPolicy {
+    allow: Vec<String>,
+    deny: Vec<String>,
+}
+
+impl HostPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn allow(mut self, patterns: &[&str]) -> Self {
+        self.allow
+            .extend(patterns.iter().map(|p| normalize(p).to_string()));
+        self
+    }
+
+    pub fn deny(mut self, patterns: &[&str]) -> Self {
+        self.deny
+            .extend(patterns.iter().map(|p| normalize(p).to_string()));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    /// Fails if any pattern is malformed (e.g., "", "api.*", "*example.com").
+    pub fn validate(&self) -> io::Result<()> {
+        for p in self.allow.iter().chain(self.deny.iter()) {
+            check_pattern(p)?;
+        }
+        Ok(())
+    }
+
+    /// Returns true if requests to the host are permitted.
+    pub fn is_allowed(&self, host: &str) -> bool {
+        let host = normalize(host);
+        if self.deny.iter().any(|p| matches(p, &host)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|p| matches(p, &host))
+    }
+
+    /// Fails with "PermissionDenied" if the URL host violates the policy.
+    pub fn check(&self, url: &Url) -> io::Result<()> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        let host = url.host_str().unwrap_or("");
+        if !self.is_allowed(host) {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                format!("host '{}' is not permitted by the host policy", host),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Fails if the pattern is not an exact host, "*.suffix", or "*".
+pub fn check_pattern(pattern: &str) -> io::Result<()> {
+    let host = pattern.strip_prefix("*.").unwrap_or(pattern);
+    if host.is_empty() || (pattern != "*" && host.contains('*')) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "invalid host pattern '{}' (expected an exact host, '*.suffix', or '*')",
+                pattern
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Returns true if the host matches the pattern (see "HostPolicy").
+pub fn host_matches(pattern: &str, host: &str) -> bool {
+    matches(&normalize(pattern), &normalize(host))
+}
+
+/// Lowercases and strips IPv6 brackets and the trailing root dot.
+fn normalize(host: &str) -> String {
+    host.trim_start_matches('[')
+        .trim_end_matches(']')
+        .trim_end_matches('.')
+        .to_ascii_lowercase()
+}
+
+fn matches(pattern: &str, host: &str) -> bool {
+    if pattern == "*" {
+        return true;
+    }
+    match pattern.strip_prefix("*.") {
+        Some(suffix) => host.len() > suffix.len() && host.ends_with(&format!(".{}", suffix)),
+        None => pattern == host,
+    }
+}
+
+#[test]
+fn test_host_policy() {
+    let policy = HostPolicy::new();
+    assert!(policy.is_allowed("anything.com"));
+
+    let policy = HostPolicy::new()
+        .allow(&["api.example.com", "*.internal.example.com"])
+        .deny(&["secret.internal.example.com"]);
+    assert!(policy.is_allowed("api.example.com"));
+    assert!(policy.is_allowed("API.Example.com."));
+    assert!(policy.is_allowed("a.internal.example.com"));
+    assert!(policy.is_allowed("a.b.internal.example.com"));
+    assert!(!policy.is_allowed("internal.example.com"));
+    assert!(!policy.is_allowed("secret.internal.example.com"));
+    assert!(!policy.is_allowed("example.com"));
+    assert!(!policy.is_allowed("evilapi.example.com"));
+
+    let policy = HostPolicy::new().deny(&["169.254.169.254", "::1"]);
+    assert!(policy.is_allowed("example.com"));
+    assert!(policy
+        .check(&Url::parse("http://169.254.169.254/latest").unwrap())
+        .is_err());
+    let ret = policy.check(&Url::parse("http://[::1]:9650/").unwrap());
+    assert_eq!(ret.unwrap_err().kind(), ErrorKind::PermissionDenied);
+}
+
+#[test]
+fn test_check_pattern() {
+    for p in ["api.example.com", "*.example.com", "*", "10.0.0.1", "::1"] {
+        assert!(check_pattern(p).is_ok(), "{}", p);
+    }
+    for p in ["", "*.", "api.*", "*example.com", "*.*.com"] {
+        assert!(check_pattern(p).is_err(), "{}", p);
+    }
+    assert!(HostPolicy::new().allow(&["*.a.com"]).validate().is_ok());
+    assert!(HostPolicy::new().deny(&["a.*"]).validate().is_err());
+}
@@ -0,0 +1,139 @@
+//! Connection limits for the "Manager" connection pool.
+
+use std::{
+    collections::HashMap,
+    error::Error as StdError,
+    future::Future,
+    io,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use hyper::{
+    client::connect::{Connected, Connection},
+    service::Service,
+    Uri,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    sync::{OwnedSemaphorePermit, Semaphore},
+};
+
+type BoxError = Box<dyn StdError + Send + Sync>;
+
+/// Wraps a connector to cap the number of open connections per host.
+/// Each connection holds a permit until it is closed, so a request that
+/// needs a new connection beyond the cap waits for a pooled connection to
+/// become idle (which hyper reuses) or for one to close.
+#[derive(Debug, Clone)]
+pub(crate) struct LimitedConnector<C> {
+    inner: C,
+    max_per_host: Option<usize>,
+    hosts: Arc<Mutex<HashMap<String, Arc<Semaphore>>>>,
+}
+
+impl<C> LimitedConnector<C> {
+    pub(crate) fn new(inner: C, max_per_host: Option<usize>) -> Self {
+        Self {
+            inner,
+            max_per_host,
+            hosts: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    fn semaphore(&self, uri: &Uri) -> Option<Arc<Semaphore>> {
+        let max = self.max_per_host?;
+        let key = uri.authority().map(|a| a.as_str()).unwrap_or("");
+        let mut hosts = self.hosts.lock().unwrap();
+        let sem = hosts
+            .entry(key.to_ascii_lowercase())
+            .or_insert_with(|| Arc::new(Semaphore::new(max)));
+        Some(sem.clone())
+    }
+}
+
+impl<C> Service<Uri> for LimitedConnector<C>
+where
+    C: Service<Uri>,
+    C::Response: AsyncRead + AsyncWrite + Connection + Unpin + Send + 'static,
+    C::Error: Into<BoxError>,
+    C::Future: Send + 'static,
+{
+    type Response = LimitedStream<C::Response>;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let sem = self.semaphore(&uri);
+        let connecting = self.inner.call(uri);
+        Box::pin(async move {
+            let permit = match sem {
+                Some(sem) => Some(sem.acquire_owned().await?),
+                None => None,
+            };
+            let stream = connecting.await.map_err(Into::into)?;
+            Ok(LimitedStream {
+                inner: stream,
+                _permit: permit,
+            })
+        })
+    }
+}
+
+/// Connection that releases its permit when dropped.
+#[derive(Debug)]
+pub(crate) struct LimitedStream<T> {
+    inner: T,
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl<T: Connection> Connection for LimitedStream<T> {
+    fn connected(&self) -> Connected {
+        self.inner.connected()
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for LimitedStream<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for LimitedStream<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+}
@@ -0,0 +1,662 @@
+//! Endpoint latency probes, for choosing the nearest (or fastest) of
+//! several regional endpoints at startup, and layered reachability checks
+//! that tell which layer of a down endpoint failed.
+
+use std::{
+    fmt,
+    io::{self, Error, ErrorKind},
+    net::{IpAddr, SocketAddr},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use futures_util::future;
+use hyper::{client::conn, header::HOST, Body, Request, StatusCode};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+    time::timeout,
+};
+use url::{Host, Url};
+
+use crate::{
+    loadtest::Percentiles,
+    logging::{wire_debug, wire_info},
+    ssrf,
+    tls::{check_pins, der_next, TlsStream},
+    IntoUrl, Manager,
+};
+
+/// Timings of a single request over a new connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ProbeSample {
+    pub dns: Duration,
+    pub connect: Duration,
+    /// Zero for plain HTTP.
+    pub tls: Duration,
+    /// From sending the request to receiving the response headers.
+    pub first_byte: Duration,
+    /// Including reading the response body.
+    pub total: Duration,
+}
+
+/// Latency distribution of the successful probe samples.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProbeReport {
+    pub url: String,
+    pub samples: Vec<ProbeSample>,
+    /// Number of failed samples.
+    pub failed: usize,
+    pub total: Percentiles,
+    pub dns: Percentiles,
+    pub connect: Percentiles,
+    pub tls: Percentiles,
+    pub first_byte: Percentiles,
+}
+
+impl ProbeReport {
+    fn new(url: &str, samples: Vec<ProbeSample>, failed: usize) -> Self {
+        let component = |f: fn(&ProbeSample) -> Duration| {
+            let v: Vec<Duration> = samples.iter().map(f).collect();
+            Percentiles::from_samples(&v)
+        };
+        Self {
+            url: url.to_string(),
+            total: component(|s| s.total),
+            dns: component(|s| s.dns),
+            connect: component(|s| s.connect),
+            tls: component(|s| s.tls),
+            first_byte: component(|s| s.first_byte),
+            samples,
+            failed,
+        }
+    }
+}
+
+impl Manager {
+    /// Sends "samples" sequential GET requests to the URL, each over a new
+    /// connection (bypassing the pool) so that every sample includes the
+    /// DNS lookup, TCP connect, and TLS handshake. Failed samples are
+    /// counted in the report; fails only if every sample fails.
+    pub async fn probe(&self, url: impl IntoUrl, samples: usize) -> io::Result<ProbeReport> {
+        let u = url.into_url()?;
+        self.check_url(&u)?;
+        let url = u.as_str();
+        let timeout_dur = self.timeout_for(&u.as_str().parse().unwrap_or_default());
+
+        let mut ok = Vec::with_capacity(samples);
+        let mut last_err = None;
+        for _ in 0..samples.max(1) {
+            let ret = match timeout(timeout_dur, self.probe_once(&u)).await {
+                Ok(ret) => ret,
+                Err(e) => Err(Error::new(
+                    ErrorKind::TimedOut,
+                    format!("failed to probe within {:?} {}", timeout_dur, e),
+                )),
+            };
+            match ret {
+                Ok(s) => {
+                    wire_debug!("probe sample {:?}", s);
+                    ok.push(s);
+                }
+                Err(e) => {
+                    wire_debug!("probe sample failed {}", e);
+                    last_err = Some(e);
+                }
+            }
+        }
+
+        let failed = samples.max(1) - ok.len();
+        if ok.is_empty() {
+            return Err(last_err.expect("failed probes must have an error"));
+        }
+        let report = ProbeReport::new(url, ok, failed);
+        wire_info!(
+            "probed {} (p50 {:?}, p95 {:?}, failed {})",
+            crate::redact::url(url),
+            report.total.p50,
+            report.total.p95,
+            failed
+        );
+        Ok(report)
+    }
+
+    /// Probes the URLs concurrently and returns the index and report of
+    /// the URL with the lowest median latency.
+    pub async fn probe_fastest(
+        &self,
+        urls: &[&str],
+        samples: usize,
+    ) -> io::Result<(usize, ProbeReport)> {
+        let reports = future::join_all(urls.iter().map(|u| self.probe(u, samples))).await;
+
+        let mut fastest: Option<(usize, ProbeReport)> = None;
+        let mut last_err = None;
+        for (i, ret) in reports.into_iter().enumerate() {
+            match ret {
+                Ok(r) => {
+                    if fastest
+                        .as_ref()
+                        .map_or(true, |(_, f)| r.total.p50 < f.total.p50)
+                    {
+                        fastest = Some((i, r));
+                    }
+                }
+                Err(e) => last_err = Some(e),
+            }
+        }
+        match (fastest, last_err) {
+            (Some(f), _) => Ok(f),
+            (None, Some(e)) => Err(e),
+            (None, None) => Err(Error::new(ErrorKind::InvalidInput, "no URL to probe")),
+        }
+    }
+
+    async fn probe_once(&self, url: &Url) -> io::Result<ProbeSample> {
+        let c = self.connect(url).await?;
+        let host = url.host_str().unwrap_or("");
+        let req = self.probe_request(url, host)?;
+        let (_, first_byte, total) = exchange(c.stream, req).await?;
+        Ok(ProbeSample {
+            dns: c.dns,
+            connect: c.connect,
+            tls: c.tls,
+            first_byte,
+            total: c.dns + c.connect + c.tls + total,
+        })
+    }
+
+    /// Opens a new (unpooled) connection to the host of the URL, with TLS
+    /// for "https".
+    pub(crate) async fn connect(&self, url: &Url) -> io::Result<Connected> {
+        let start = Instant::now();
+        let addrs = self.resolve(url).await?;
+        let dns = start.elapsed();
+
+        let start = Instant::now();
+        let tcp = TcpStream::connect(&addrs[..]).await?;
+        let connect = start.elapsed();
+
+        if url.scheme() != "https" {
+            return Ok(Connected {
+                stream: Box::new(tcp),
+                dns,
+                connect,
+                tls: Duration::ZERO,
+            });
+        }
+
+        let start = Instant::now();
+        let stream = self.tls_handshake(url, tcp).await?;
+        Ok(Connected {
+            stream: Box::new(stream),
+            dns,
+            connect,
+            tls: start.elapsed(),
+        })
+    }
+
+    /// Resolves the host of the URL, without the restricted addresses if
+    /// the SSRF guard is enabled.
+    async fn resolve(&self, url: &Url) -> io::Result<Vec<SocketAddr>> {
+        let port = url.port_or_known_default().unwrap_or(80);
+        let mut addrs: Vec<SocketAddr> = match url.host() {
+            Some(Host::Domain(d)) => tokio::net::lookup_host((d, port)).await?.collect(),
+            Some(Host::Ipv4(ip)) => vec![SocketAddr::new(ip.into(), port)],
+            Some(Host::Ipv6(ip)) => vec![SocketAddr::new(ip.into(), port)],
+            None => return Err(Error::new(ErrorKind::InvalidInput, "URL has no host")),
+        };
+        if self.blocks_restricted_destinations() {
+            addrs.retain(|a| !ssrf::is_restricted_ip(a.ip()));
+        }
+        if addrs.is_empty() {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                format!("no allowed address for '{}'", url.host_str().unwrap_or("")),
+            ));
+        }
+        Ok(addrs)
+    }
+
+    /// Performs the TLS handshake, and checks the server certificates
+    /// against the pins (if any), as the HTTPS connector of the clients.
+    async fn tls_handshake(&self, url: &Url, tcp: TcpStream) -> io::Result<TlsStream<TcpStream>> {
+        let host = url.host_str().unwrap_or("");
+        let stream = self
+            .tls_config_for(host)
+            .tls_connector(self.accepts_invalid_certs(host), false)?
+            .connect(host, tcp)
+            .await?;
+        let pins = self.tls_config_for(host).pins();
+        if !pins.is_empty() {
+            check_pins(host, pins, &stream.peer_certificates())
+                .map_err(crate::error::Error::PinMismatch)?;
+        }
+        Ok(stream)
+    }
+
+    /// Checks the URL layer by layer over a new connection (DNS, TCP
+    /// connect, TLS handshake, then a GET request), stopping at the first
+    /// failed layer. Fails only on a URL rejected by the manager policies;
+    /// any other failure is in the report.
+    pub async fn probe_endpoint(&self, url: impl IntoUrl) -> io::Result<EndpointReport> {
+        let u = url.into_url()?;
+        self.check_url(&u)?;
+        let url = u.as_str();
+        let timeout_dur = self.timeout_for(&u.as_str().parse().unwrap_or_default());
+        let step = |ret: Result<io::Result<()>, _>, start: Instant| match ret {
+            Ok(Ok(())) => Step::Ok(start.elapsed()),
+            Ok(Err(e)) => Step::Failed(e.to_string()),
+            Err(_) => Step::Failed(format!("timed out after {:?}", timeout_dur)),
+        };
+
+        let mut report = EndpointReport {
+            url: url.to_string(),
+            ..Default::default()
+        };
+        let done = |report: EndpointReport| {
+            wire_info!("probed {}: {}", crate::redact::url(url), report);
+            Ok(report)
+        };
+
+        let start = Instant::now();
+        let mut addrs = Vec::new();
+        let ret = timeout(timeout_dur, async {
+            addrs = self.resolve(&u).await?;
+            Ok(())
+        })
+        .await;
+        report.dns = step(ret, start);
+        report.addrs = addrs.iter().map(|a| a.ip()).collect();
+        if !report.dns.is_ok() {
+            return done(report);
+        }
+
+        let start = Instant::now();
+        let mut tcp = None;
+        let ret = timeout(timeout_dur, async {
+            tcp = Some(TcpStream::connect(&addrs[..]).await?);
+            Ok(())
+        })
+        .await;
+        report.tcp = step(ret, start);
+        let tcp = match tcp {
+            Some(tcp) => tcp,
+            None => return done(report),
+        };
+
+        let stream: Box<dyn Io> = if u.scheme() == "https" {
+            let start = Instant::now();
+            let mut tls = None;
+            let ret = timeout(timeout_dur, async {
+                tls = Some(self.tls_handshake(&u, tcp).await?);
+                Ok(())
+            })
+            .await;
+            let s = step(ret, start);
+            report.tls = Some(s);
+            match tls {
+                Some(tls) => {
+                    report.cert_not_after = tls
+                        .peer_certificates()
+                        .first()
+                        .and_then(|der| cert_not_after(der));
+                    Box::new(tls)
+                }
+                None => return done(report),
+            }
+        } else {
+            Box::new(tcp)
+        };
+
+        let start = Instant::now();
+        let host = u.host_str().unwrap_or("");
+        let req = self.probe_request(&u, host)?;
+        let mut status = None;
+        let ret = timeout(timeout_dur, async {
+            status = Some(exchange(stream, req).await?.0);
+            Ok(())
+        })
+        .await;
+        report.http = step(ret, start);
+        report.status = status;
+        done(report)
+    }
+
+    fn probe_request(&self, url: &Url, host: &str) -> io::Result<Request<Body>> {
+        let mut path = url.path().to_string();
+        if let Some(q) = url.query() {
+            path.push('?');
+            path.push_str(q);
+        }
+        let authority = match url.port() {
+            Some(p) => format!("{}:{}", host, p),
+            None => host.to_string(),
+        };
+        let mut req = Request::builder()
+            .uri(path)
+            .header(HOST, authority)
+            .body(Body::empty())
+            .map_err(|e| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("failed to create probe request {}", e),
+                )
+            })?;
+        for (k, v) in self.default_headers_for(host).iter() {
+            req.headers_mut().append(k, v.clone());
+        }
+        Ok(req)
+    }
+}
+
+pub(crate) trait Io: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Io for T {}
+
+/// New connection, with the time of each step.
+pub(crate) struct Connected {
+    pub(crate) stream: Box<dyn Io>,
+    pub(crate) dns: Duration,
+    pub(crate) connect: Duration,
+    /// Zero for plain HTTP.
+    pub(crate) tls: Duration,
+}
+
+/// Sends the request over the connection and returns the response status
+/// and the time to the response headers and to the end of the body.
+async fn exchange(
+    stream: Box<dyn Io>,
+    req: Request<Body>,
+) -> io::Result<(StatusCode, Duration, Duration)> {
+    let (mut sender, connection) = conn::handshake(stream)
+        .await
+        .map_err(|e| Error::new(ErrorKind::Other, format!("failed HTTP handshake {}", e)))?;
+    let connection = tokio::spawn(connection);
+
+    let start = Instant::now();
+    let ret = async {
+        let resp = sender
+            .send_request(req)
+            .await
+            .map_err(|e| Error::new(ErrorKind::Other, format!("failed to send probe {}", e)))?;
+        let status = resp.status();
+        let first_byte = start.elapsed();
+        crate::buffer::collect(resp.into_body())
+            .await
+            .map_err(|e| {
+                Error::new(
+                    ErrorKind::Other,
+                    format!("failed to read probe response {}", e),
+                )
+            })?;
+        Ok((status, first_byte, start.elapsed()))
+    }
+    .await;
+    connection.abort();
+    ret
+}
+
+/// Result of a single layer of "probe_endpoint".
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum Step {
+    /// Not reached, since a lower layer failed (or not applicable).
+    #[default]
+    Skipped,
+    Ok(Duration),
+    Failed(String),
+}
+
+impl Step {
+    pub fn is_ok(&self) -> bool {
+        matches!(self, Step::Ok(_))
+    }
+}
+
+/// Layers checked by "probe_endpoint", from the bottom.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layer {
+    Dns,
+    Tcp,
+    Tls,
+    Http,
+}
+
+impl fmt::Display for Layer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Layer::Dns => "dns",
+            Layer::Tcp => "tcp",
+            Layer::Tls => "tls",
+            Layer::Http => "http",
+        })
+    }
+}
+
+/// Layer-by-layer reachability of an endpoint.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct EndpointReport {
+    pub url: String,
+    /// Resolved (and allowed) addresses.
+    pub addrs: Vec<IpAddr>,
+    pub dns: Step,
+    pub tcp: Step,
+    /// None for plain HTTP.
+    pub tls: Option<Step>,
+    /// Expiry of the server certificate, if the TLS handshake succeeded.
+    pub cert_not_after: Option<SystemTime>,
+    /// Time to the end of the GET response.
+    pub http: Step,
+    pub status: Option<StatusCode>,
+}
+
+impl EndpointReport {
+    /// Returns the lowest failed layer; a non-2xx status counts as a
+    /// failed HTTP layer.
+    pub fn failed_layer(&self) -> Option<Layer> {
+        if !self.dns.is_ok() {
+            return Some(Layer::Dns);
+        }
+        if !self.tcp.is_ok() {
+            return Some(Layer::Tcp);
+        }
+        if matches!(&self.tls, Some(s) if !s.is_ok()) {
+            return Some(Layer::Tls);
+        }
+        if !self.http.is_ok() || !self.status.map_or(false, |s| s.is_success()) {
+            return Some(Layer::Http);
+        }
+        None
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.failed_layer().is_none()
+    }
+
+    /// Returns the time left until the certificate expires (zero if
+    /// expired).
+    pub fn cert_expires_in(&self) -> Option<Duration> {
+        self.cert_not_after
+            .map(|t| t.duration_since(SystemTime::now()).unwrap_or_default())
+    }
+}
+
+/// e.g., "dns ok (1ms), tcp ok (2ms), tls ok (5ms), http 200 OK (3ms)".
+impl fmt::Display for EndpointReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut steps = vec![(Layer::Dns, &self.dns), (Layer::Tcp, &self.tcp)];
+        if let Some(tls) = &self.tls {
+            steps.push((Layer::Tls, tls));
+        }
+        steps.push((Layer::Http, &self.http));
+
+        let mut first = true;
+        for (layer, step) in steps {
+            if !first {
+                f.write_str(", ")?;
+            }
+            first = false;
+            match step {
+                Step::Skipped => write!(f, "{} skipped", layer)?,
+                Step::Failed(e) => write!(f, "{} failed ({})", layer, e)?,
+                Step::Ok(d) => match (layer, self.status) {
+                    (Layer::Http, Some(s)) => write!(f, "http {} ({:?})", s, d)?,
+                    _ => write!(f, "{} ok ({:?})", layer, d)?,
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the "notAfter" time of a DER-encoded X.509 certificate.
+fn cert_not_after(der: &[u8]) -> Option<SystemTime> {
+    let (_, cert, _) = der_next(der)?;
+    let (_, tbs, _) = der_next(cert)?;
+    // the version is an optional explicit [0] tag
+    let mut rest = tbs;
+    let (tag, _, after_version) = der_next(rest)?;
+    if tag == 0xa0 {
+        rest = after_version;
+    }
+    // serial number, signature algorithm, issuer
+    for _ in 0..3 {
+        rest = der_next(rest)?.2;
+    }
+    let (_, validity, _) = der_next(rest)?;
+    let (_, _, not_after) = der_next(validity)?;
+    let (tag, t, _) = der_next(not_after)?;
+    let t = std::str::from_utf8(t).ok()?.strip_suffix('Z')?;
+    let (year, t) = match tag {
+        // UTCTime "YYMMDDHHMMSSZ", years 1950 to 2049
+        0x17 => {
+            let yy: i64 = t.get(..2)?.parse().ok()?;
+            (if yy < 50 { 2000 + yy } else { 1900 + yy }, &t[2..])
+        }
+        // GeneralizedTime "YYYYMMDDHHMMSSZ"
+        0x18 => (t.get(..4)?.parse().ok()?, &t[4..]),
+        _ => return None,
+    };
+    let field = |i: usize| -> Option<i64> { t.get(i..i + 2)?.parse().ok() };
+    let days = days_from_civil(year, field(0)?, field(2)?);
+    let secs = days * 86400 + field(4)? * 3600 + field(6)? * 60 + field(8)?;
+    Some(UNIX_EPOCH + Duration::from_secs(u64::try_from(secs).ok()?))
+}
+
+/// Days since 1970-01-01 of the proleptic Gregorian date.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// RUST_LOG=debug cargo test --lib -- probe::test_probe --exact --show-output
+#[tokio::test]
+async fn test_probe() {
+    use hyper::Method;
+
+    let server = crate::testing::MockServer::start().await.unwrap();
+    server.stub(Method::GET, "/health", 200, "ok");
+
+    let manager = Manager::builder().build().unwrap();
+    let url = format!("{}/health", server.url());
+    let report = manager.probe(&url, 5).await.unwrap();
+    assert_eq!(report.samples.len(), 5);
+    assert_eq!(report.failed, 0);
+    assert_eq!(report.tls.max, Duration::ZERO);
+    assert!(report.total.min > Duration::ZERO);
+    assert!(report.total.p50 >= report.first_byte.p50);
+    // every sample uses a new connection
+    assert_eq!(server.accepted_connections(), 5);
+    server.assert_received(Method::GET, "/health").times(5);
+
+    let tls_server = crate::testing::MockServer::start_https().await.unwrap();
+    tls_server.stub(Method::GET, "/", 200, "ok");
+    let insecure = Manager::builder()
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap();
+    let report = insecure.probe(&tls_server.url(), 2).await.unwrap();
+    assert!(report.tls.min > Duration::ZERO);
+    // self-signed certificates are rejected by default
+    assert!(manager.probe(&tls_server.url(), 1).await.is_err());
+
+    let (i, report) = manager
+        .probe_fastest(&["http://127.0.0.1:1/", &url], 2)
+        .await
+        .unwrap();
+    assert_eq!(i, 1);
+    assert_eq!(report.url, url);
+}
+
+#[test]
+fn test_cert_not_after() {
+    for (y, m, d) in [(2030, 1, 2), (2060, 7, 31)] {
+        let mut params = rcgen::CertificateParams::new(vec!["localhost".to_string()]);
+        params.not_after = rcgen::date_time_ymd(y, m, d);
+        let der = rcgen::Certificate::from_params(params)
+            .unwrap()
+            .serialize_der()
+            .unwrap();
+        let days = days_from_civil(y as i64, m as i64, d as i64) as u64;
+        assert_eq!(
+            cert_not_after(&der).unwrap(),
+            UNIX_EPOCH + Duration::from_secs(days * 86400)
+        );
+    }
+    assert_eq!(days_from_civil(1970, 1, 1), 0);
+    assert_eq!(days_from_civil(2000, 3, 1), 11017);
+    assert!(cert_not_after(b"\x30\x03\x02\x01").is_none());
+}
+
+/// RUST_LOG=debug cargo test --lib -- probe::test_probe_endpoint --exact --show-output
+#[tokio::test]
+async fn test_probe_endpoint() {
+    use hyper::Method;
+
+    let tls_server = crate::testing::MockServer::start_https().await.unwrap();
+    tls_server.stub(Method::GET, "/health", 200, "ok");
+    let insecure = Manager::builder()
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap();
+    let url = format!("{}/health", tls_server.url());
+    let report = insecure.probe_endpoint(&url).await.unwrap();
+    assert!(report.is_ok(), "{}", report);
+    assert!(!report.addrs.is_empty());
+    assert!(matches!(report.tls, Some(Step::Ok(_))));
+    assert_eq!(report.status, Some(StatusCode::OK));
+    // self-signed certificates from rcgen expire in 4096
+    assert!(report.cert_expires_in().unwrap() > Duration::from_secs(365 * 86400));
+    assert!(report.to_string().contains("http 200 OK"));
+
+    // the TLS layer fails on a self-signed certificate by default
+    let manager = Manager::new().unwrap();
+    let report = manager.probe_endpoint(&url).await.unwrap();
+    assert_eq!(report.failed_layer(), Some(Layer::Tls));
+    assert_eq!(report.http, Step::Skipped);
+    assert!(report.cert_not_after.is_none());
+
+    let server = crate::testing::MockServer::start().await.unwrap();
+    server.stub(Method::GET, "/", 503, "down");
+    let report = manager.probe_endpoint(&server.url()).await.unwrap();
+    assert_eq!(report.failed_layer(), Some(Layer::Http));
+    assert_eq!(report.status, Some(StatusCode::SERVICE_UNAVAILABLE));
+    assert!(report.tls.is_none());
+
+    let report = manager.probe_endpoint("http://127.0.0.1:1/").await.unwrap();
+    assert_eq!(report.failed_layer(), Some(Layer::Tcp));
+    assert!(report.to_string().starts_with("dns ok"));
+    assert!(report.to_string().contains("tcp failed"));
+
+    let report = manager
+        .probe_endpoint("http://does-not-exist.invalid/")
+        .await
+        .unwrap();
+    assert_eq!(report.failed_layer(), Some(Layer::Dns));
+    assert!(report.addrs.is_empty());
+
+    assert!(manager.probe_endpoint("ftp://127.0.0.1/").await.is_err());
+}
@@ -0,0 +1,370 @@
+//! Parser of the Prometheus text exposition format, so that health tooling
+//! can read counters from "/metrics" endpoints without regexes.
+//! ref. https://prometheus.io/docs/instrumenting/exposition_formats/
+
+use std::{
+    collections::BTreeMap,
+    io::{self, Error, ErrorKind},
+};
+
+use crate::Manager;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricType {
+    Counter,
+    Gauge,
+    Histogram,
+    Summary,
+    Untyped,
+}
+
+impl MetricType {
+    fn parse(s: &str) -> Self {
+        match s {
+            "counter" => MetricType::Counter,
+            "gauge" => MetricType::Gauge,
+            "histogram" => MetricType::Histogram,
+            "summary" => MetricType::Summary,
+            _ => MetricType::Untyped,
+        }
+    }
+
+    /// Returns the sample name suffixes that belong to the family.
+    fn suffixes(&self) -> &'static [&'static str] {
+        match self {
+            MetricType::Counter => &["_total", "_created"],
+            MetricType::Histogram => &["_bucket", "_sum", "_count", "_created"],
+            MetricType::Summary => &["_sum", "_count", "_created"],
+            _ => &[],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    /// Full sample name (e.g., "http_request_duration_seconds_bucket").
+    pub name: String,
+    pub labels: BTreeMap<String, String>,
+    pub value: f64,
+    /// Milliseconds since the epoch, if exposed.
+    pub timestamp_ms: Option<i64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricFamily {
+    pub name: String,
+    pub help: Option<String>,
+    pub metric_type: MetricType,
+    pub samples: Vec<Sample>,
+}
+
+impl MetricFamily {
+    /// Returns the first sample that has all the labels (and possibly
+    /// others), e.g., "find(&[("code", "200")])".
+    pub fn find(&self, labels: &[(&str, &str)]) -> Option<&Sample> {
+        self.samples.iter().find(|s| {
+            labels
+                .iter()
+                .all(|(k, v)| s.labels.get(*k).map(|x| x.as_str()) == Some(*v))
+        })
+    }
+
+    /// Returns the value of the first sample that has all the labels.
+    pub fn value(&self, labels: &[(&str, &str)]) -> Option<f64> {
+        self.find(labels).map(|s| s.value)
+    }
+}
+
+/// Metric families by name.
+pub type Metrics = BTreeMap<String, MetricFamily>;
+
+/// Parses the text exposition format. Samples without a "# TYPE" line
+/// form untyped families of their own name.
+pub fn parse(text: &str) -> io::Result<Metrics> {
+    let mut families = Metrics::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let invalid = |msg: &str| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("invalid metrics line {} '{}' ({})", i + 1, line, msg),
+            )
+        };
+
+        if let Some(comment) = line.strip_prefix('#') {
+            let mut parts = comment.trim_start().splitn(3, ' ');
+            let (keyword, name, rest) = (parts.next(), parts.next(), parts.next());
+            match (keyword, name) {
+                (Some("HELP"), Some(name)) => {
+                    family(&mut families, name).help = Some(unescape(rest.unwrap_or(""), false));
+                }
+                (Some("TYPE"), Some(name)) => {
+                    let t = rest.ok_or_else(|| invalid("missing type"))?.trim();
+                    family(&mut families, name).metric_type = MetricType::parse(t);
+                }
+                // other comments
+                _ => {}
+            }
+            continue;
+        }
+
+        let sample = parse_sample(line).map_err(|e| invalid(&e))?;
+        let name = family_name(&families, &sample.name);
+        family(&mut families, &name).samples.push(sample);
+    }
+    Ok(families)
+}
+
+fn family<'a>(families: &'a mut Metrics, name: &str) -> &'a mut MetricFamily {
+    families
+        .entry(name.to_string())
+        .or_insert_with(|| MetricFamily {
+            name: name.to_string(),
+            help: None,
+            metric_type: MetricType::Untyped,
+            samples: Vec::new(),
+        })
+}
+
+/// Maps e.g. "latency_bucket" to the "latency" histogram family.
+fn family_name(families: &Metrics, sample_name: &str) -> String {
+    if families.contains_key(sample_name) {
+        return sample_name.to_string();
+    }
+    for (name, f) in families.iter() {
+        if let Some(suffix) = sample_name.strip_prefix(name.as_str()) {
+            if f.metric_type.suffixes().contains(&suffix) {
+                return name.clone();
+            }
+        }
+    }
+    sample_name.to_string()
+}
+
+fn parse_sample(line: &str) -> Result<Sample, String> {
+    let name_end = line
+        .find(|c: char| c == '{' || c.is_whitespace())
+        .ok_or("missing value")?;
+    let name = &line[..name_end];
+    if name.is_empty() {
+        return Err("missing metric name".to_string());
+    }
+
+    let mut labels = BTreeMap::new();
+    let mut rest = &line[name_end..];
+    if let Some(after) = rest.strip_prefix('{') {
+        let (parsed, after) = parse_labels(after)?;
+        labels = parsed;
+        rest = after;
+    }
+
+    let mut fields = rest.split_whitespace();
+    let value = parse_value(fields.next().ok_or("missing value")?)?;
+    let timestamp_ms = match fields.next() {
+        Some(t) => Some(
+            t.parse::<i64>()
+                .map_err(|e| format!("invalid timestamp '{}' {}", t, e))?,
+        ),
+        None => None,
+    };
+    if fields.next().is_some() {
+        return Err("unexpected trailing fields".to_string());
+    }
+
+    Ok(Sample {
+        name: name.to_string(),
+        labels,
+        value,
+        timestamp_ms,
+    })
+}
+
+/// Parses 'a="1",b="2"}' and returns the labels and the rest of the line.
+fn parse_labels(s: &str) -> Result<(BTreeMap<String, String>, &str), String> {
+    let mut labels = BTreeMap::new();
+    let mut rest = s.trim_start();
+    loop {
+        if let Some(after) = rest.strip_prefix('}') {
+            return Ok((labels, after));
+        }
+        let eq = rest.find('=').ok_or("missing '=' in labels")?;
+        let key = rest[..eq].trim();
+        rest = rest[eq + 1..]
+            .trim_start()
+            .strip_prefix('"')
+            .ok_or("label value must be quoted")?;
+
+        // finds the closing quote, skipping escaped characters
+        let mut end = None;
+        let mut escaped = false;
+        for (i, c) in rest.char_indices() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => {
+                    end = Some(i);
+                    break;
+                }
+                _ => {}
+            }
+        }
+        let end = end.ok_or("unterminated label value")?;
+        labels.insert(key.to_string(), unescape(&rest[..end], true));
+
+        rest = rest[end + 1..].trim_start();
+        if let Some(after) = rest.strip_prefix(',') {
+            rest = after.trim_start();
+        } else if !rest.starts_with('}') {
+            return Err("expected ',' or '}' in labels".to_string());
+        }
+    }
+}
+
+fn parse_value(s: &str) -> Result<f64, String> {
+    match s {
+        "+Inf" | "Inf" => Ok(f64::INFINITY),
+        "-Inf" => Ok(f64::NEG_INFINITY),
+        "NaN" => Ok(f64::NAN),
+        _ => s
+            .parse::<f64>()
+            .map_err(|e| format!("invalid value '{}' {}", s, e)),
+    }
+}
+
+/// Unescapes "\\" and "\n" (and '\"' in label values).
+fn unescape(s: &str, quotes: bool) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('\\') => out.push('\\'),
+            Some('"') if quotes => out.push('"'),
+            Some(other) => {
+                out.push('\\');
+                out.push(other);
+            }
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+/// Scrapes the metrics endpoint with a default manager.
+pub async fn scrape_metrics(url: &str, path: &str) -> io::Result<Metrics> {
+    Manager::new()?.scrape_metrics(url, path).await
+}
+
+impl Manager {
+    /// GETs the metrics endpoint (e.g., "/metrics") and parses the
+    /// response, failing on a non-2xx status code.
+    pub async fn scrape_metrics(&self, url: &str, path: &str) -> io::Result<Metrics> {
+        let req = crate::create_get(url, path)?;
+        let body = self.read_bytes(req, true).await?;
+        let text = std::str::from_utf8(&body).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("metrics response is not UTF-8 {}", e),
+            )
+        })?;
+        parse(text)
+    }
+}
+
+#[test]
+fn test_parse() {
+    let text = r#"
+# HELP http_requests_total The total number of HTTP requests.
+# TYPE http_requests_total counter
+http_requests_total{method="post",code="200"} 1027 1395066363000
+http_requests_total{method="post",code="400"}    3 1395066363000
+
+# A comment
+msdos_file_access_time_seconds{path="C:\\DIR\\FILE.TXT",error="Cannot find file:\n\"FILE.TXT\""} 1.458255915e9
+
+metric_without_timestamp_and_labels 12.47
+
+# HELP http_request_duration_seconds A histogram of the request duration.
+# TYPE http_request_duration_seconds histogram
+http_request_duration_seconds_bucket{le="0.05"} 24054
+http_request_duration_seconds_bucket{le="+Inf",} 144320
+http_request_duration_seconds_sum 53423
+http_request_duration_seconds_count 144320
+
+# TYPE rpc_duration_seconds summary
+rpc_duration_seconds{quantile="0.99"} NaN
+rpc_duration_seconds_count 2693
+"#;
+    let metrics = parse(text).unwrap();
+    assert_eq!(metrics.len(), 5);
+
+    let requests = &metrics["http_requests_total"];
+    assert_eq!(requests.metric_type, MetricType::Counter);
+    assert_eq!(
+        requests.help.as_deref(),
+        Some("The total number of HTTP requests.")
+    );
+    assert_eq!(requests.value(&[("code", "400")]), Some(3.0));
+    assert_eq!(requests.samples[0].timestamp_ms, Some(1395066363000));
+    assert_eq!(requests.value(&[("code", "500")]), None);
+
+    let access = &metrics["msdos_file_access_time_seconds"];
+    assert_eq!(access.metric_type, MetricType::Untyped);
+    let labels = &access.samples[0].labels;
+    assert_eq!(labels["path"], "C:\\DIR\\FILE.TXT");
+    assert_eq!(labels["error"], "Cannot find file:\n\"FILE.TXT\"");
+    assert_eq!(access.samples[0].value, 1.458255915e9);
+
+    assert_eq!(
+        metrics["metric_without_timestamp_and_labels"].value(&[]),
+        Some(12.47)
+    );
+
+    let duration = &metrics["http_request_duration_seconds"];
+    assert_eq!(duration.metric_type, MetricType::Histogram);
+    assert_eq!(duration.samples.len(), 4);
+    assert_eq!(duration.value(&[("le", "+Inf")]), Some(144320.0));
+    let count = duration
+        .samples
+        .iter()
+        .find(|s| s.name == "http_request_duration_seconds_count")
+        .unwrap();
+    assert_eq!(count.value, 144320.0);
+
+    let rpc = &metrics["rpc_duration_seconds"];
+    assert_eq!(rpc.metric_type, MetricType::Summary);
+    assert!(rpc.value(&[("quantile", "0.99")]).unwrap().is_nan());
+    assert_eq!(rpc.samples.len(), 2);
+
+    assert!(parse("broken{a=\"1\" 1").is_err());
+    assert!(parse("no_value").is_err());
+    assert!(parse("bad_value abc").is_err());
+    assert!(parse("unquoted{a=1} 1").is_err());
+}
+
+/// RUST_LOG=debug cargo test --lib -- prometheus::test_scrape_metrics --exact --show-output
+#[tokio::test]
+async fn test_scrape_metrics() {
+    use hyper::Method;
+
+    let server = crate::testing::MockServer::start().await.unwrap();
+    server.stub(
+        Method::GET,
+        "/metrics",
+        200,
+        "# TYPE up gauge\nup{job=\"api\"} 1\n",
+    );
+
+    let metrics = scrape_metrics(&server.url(), "/metrics").await.unwrap();
+    assert_eq!(metrics["up"].metric_type, MetricType::Gauge);
+    assert_eq!(metrics["up"].value(&[("job", "api")]), Some(1.0));
+
+    assert!(scrape_metrics(&server.url(), "/missing").await.is_err());
+}
@@ -0,0 +1,483 @@
+//! Range requests, for sparse reads of large remote files.
+
+use std::io::{self, Error, ErrorKind};
+
+use hyper::{
+    body::Bytes,
+    header::{HeaderValue, CONTENT_RANGE, CONTENT_TYPE, RANGE},
+    Body, HeaderMap, Request, Response, StatusCode,
+};
+
+use crate::Manager;
+
+/// Inclusive byte range, as in "Content-Range: bytes 0-499/1234".
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    /// Inclusive.
+    pub end: u64,
+    /// Complete length of the resource, if known.
+    pub total: Option<u64>,
+}
+
+impl ByteRange {
+    pub fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+}
+
+/// Parses a "Content-Range" value (e.g., "bytes 0-499/1234" or
+/// "bytes 0-499/*").
+pub fn parse_content_range(s: &str) -> io::Result<ByteRange> {
+    let invalid = || {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("invalid Content-Range '{}'", s),
+        )
+    };
+    let spec = s.trim().strip_prefix("bytes ").ok_or_else(invalid)?;
+    let (range, total) = spec.split_once('/').ok_or_else(invalid)?;
+    let (start, end) = range.split_once('-').ok_or_else(invalid)?;
+    let start: u64 = start.trim().parse().map_err(|_| invalid())?;
+    let end: u64 = end.trim().parse().map_err(|_| invalid())?;
+    let total = match total.trim() {
+        "*" => None,
+        t => Some(t.parse::<u64>().map_err(|_| invalid())?),
+    };
+    if end < start || total.map_or(false, |t| end >= t) {
+        return Err(invalid());
+    }
+    Ok(ByteRange { start, end, total })
+}
+
+/// Parses a "multipart/byteranges" body into its ranges and parts,
+/// without copying the part data.
+/// ref. https://www.rfc-editor.org/rfc/rfc9110#name-media-type-multipart-byteran
+pub fn parse_byteranges(content_type: &str, body: &Bytes) -> io::Result<Vec<(ByteRange, Bytes)>> {
+    let invalid = |msg: &str| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("invalid multipart/byteranges body ({})", msg),
+        )
+    };
+    if !content_type
+        .trim_start()
+        .to_ascii_lowercase()
+        .starts_with("multipart/byteranges")
+    {
+        return Err(invalid("not multipart/byteranges"));
+    }
+    let boundary = content_type
+        .split(';')
+        .filter_map(|p| p.trim().split_once('='))
+        .find(|(k, _)| k.trim().eq_ignore_ascii_case("boundary"))
+        .map(|(_, v)| v.trim().trim_matches('"'))
+        .ok_or_else(|| invalid("missing boundary"))?;
+    let delimiter = format!("--{}", boundary);
+    let delimiter = delimiter.as_bytes();
+
+    let mut parts = Vec::new();
+    let mut pos = find(body, delimiter, 0).ok_or_else(|| invalid("missing boundary"))?;
+    loop {
+        pos += delimiter.len();
+        if body[pos..].starts_with(b"--") {
+            return Ok(parts);
+        }
+        // skips the rest of the delimiter line
+        pos = find(body, b"\n", pos).ok_or_else(|| invalid("truncated"))? + 1;
+
+        let mut range = None;
+        loop {
+            let eol = find(body, b"\n", pos).ok_or_else(|| invalid("truncated headers"))?;
+            let line = std::str::from_utf8(&body[pos..eol])
+                .map_err(|_| invalid("non-UTF-8 headers"))?
+                .trim_end_matches('\r');
+            pos = eol + 1;
+            if line.is_empty() {
+                break;
+            }
+            if let Some((k, v)) = line.split_once(':') {
+                if k.trim().eq_ignore_ascii_case("content-range") {
+                    range = Some(parse_content_range(v)?);
+                }
+            }
+        }
+        let range = range.ok_or_else(|| invalid("part without Content-Range"))?;
+
+        let next = find(body, delimiter, pos).ok_or_else(|| invalid("missing closing boundary"))?;
+        // the line break before the delimiter belongs to the delimiter
+        let mut end = next;
+        if end > pos && body[end - 1] == b'\n' {
+            end -= 1;
+            if end > pos && body[end - 1] == b'\r' {
+                end -= 1;
+            }
+        }
+        let data = body.slice(pos..end);
+        if data.len() as u64 != range.len() {
+            return Err(invalid(&format!(
+                "part of {} bytes for a range of {} bytes",
+                data.len(),
+                range.len()
+            )));
+        }
+        parts.push((range, data));
+        pos = next;
+    }
+}
+
+fn find(hay: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    if from > hay.len() {
+        return None;
+    }
+    hay[from..]
+        .windows(needle.len())
+        .position(|w| w == needle)
+        .map(|i| from + i)
+}
+
+impl Manager {
+    /// Requests the inclusive byte ranges in a single request and returns
+    /// the parts in the order of the response. A server that ignores the
+    /// ranges (200) has its full response sliced instead.
+    pub async fn get_ranges(
+        &self,
+        url: &str,
+        path: &str,
+        ranges: &[(u64, u64)],
+    ) -> io::Result<Vec<(ByteRange, Bytes)>> {
+        if ranges.is_empty() || ranges.iter().any(|(s, e)| e < s) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid byte ranges {:?}", ranges),
+            ));
+        }
+        let spec: Vec<String> = ranges.iter().map(|(s, e)| format!("{}-{}", s, e)).collect();
+        let req = create_range_get(url, path, &spec.join(","))?;
+        let resp = self.read_response(req).await?;
+        match resp.status() {
+            StatusCode::PARTIAL_CONTENT => {
+                let content_type = header_str(resp.headers(), CONTENT_TYPE.as_str());
+                if content_type
+                    .to_ascii_lowercase()
+                    .starts_with("multipart/byteranges")
+                {
+                    return parse_byteranges(&content_type, resp.body());
+                }
+                // a single (or coalesced) range
+                Ok(vec![single_part(&resp)?])
+            }
+            StatusCode::OK => {
+                let body = resp.into_body();
+                let total = body.len() as u64;
+                ranges
+                    .iter()
+                    .map(|(s, e)| {
+                        if *s >= total {
+                            return Err(Error::new(
+                                ErrorKind::InvalidInput,
+                                format!("range {}-{} beyond {} bytes", s, e, total),
+                            ));
+                        }
+                        let end = (*e).min(total - 1);
+                        let range = ByteRange {
+                            start: *s,
+                            end,
+                            total: Some(total),
+                        };
+                        Ok((range, body.slice(*s as usize..=end as usize)))
+                    })
+                    .collect()
+            }
+            s => Err(Error::new(
+                ErrorKind::Other,
+                format!(
+                    "{} returned unexpected status code {} for a range request",
+                    crate::redact::url(url),
+                    s
+                ),
+            )),
+        }
+    }
+
+    /// Reads the inclusive byte range "start..=end" and returns exactly
+    /// those bytes (fewer if the resource ends before "end"). Fails unless
+    /// the server answers 206 with a matching "Content-Range".
+    pub async fn get_range(
+        &self,
+        url: &str,
+        path: &str,
+        start: u64,
+        end: u64,
+    ) -> io::Result<Bytes> {
+        if end < start {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid byte range {}-{}", start, end),
+            ));
+        }
+        let req = create_range_get(url, path, &format!("{}-{}", start, end))?;
+        let resp = self.read_response(req).await?;
+        match resp.status() {
+            StatusCode::PARTIAL_CONTENT => {}
+            StatusCode::RANGE_NOT_SATISFIABLE => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "range {}-{} not satisfiable for {} (Content-Range {:?})",
+                        start,
+                        end,
+                        crate::redact::url(url),
+                        header_str(resp.headers(), CONTENT_RANGE.as_str())
+                    ),
+                ))
+            }
+            s => {
+                return Err(Error::new(
+                    ErrorKind::Other,
+                    format!(
+                        "{} returned status code {} for range {}-{}, expected 206",
+                        crate::redact::url(url),
+                        s,
+                        start,
+                        end
+                    ),
+                ))
+            }
+        }
+        let (range, body) = single_part(&resp)?;
+        // the server may only shorten the range at the end of the resource
+        let truncated = range.end < end && range.total == Some(range.end + 1);
+        if range.start != start || (range.end != end && !truncated) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "requested range {}-{}, received {}-{}",
+                    start, end, range.start, range.end
+                ),
+            ));
+        }
+        Ok(body)
+    }
+}
+
+/// Creates a GET request with "Range: bytes={spec}" (e.g., "0-499",
+/// "500-", or "0-1,5-9").
+pub(crate) fn create_range_get(url: &str, path: &str, spec: &str) -> io::Result<Request<Body>> {
+    let mut req = crate::create_get(url, path)?;
+    let v = HeaderValue::from_str(&format!("bytes={}", spec)).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid byte range '{}' {}", spec, e),
+        )
+    })?;
+    req.headers_mut().insert(RANGE, v);
+    Ok(req)
+}
+
+/// Returns the complete length from the "Content-Range" of a 416
+/// response (e.g., "bytes */1234").
+pub(crate) fn unsatisfied_length(headers: &HeaderMap) -> Option<u64> {
+    header_str(headers, CONTENT_RANGE.as_str())
+        .trim()
+        .strip_prefix("bytes */")
+        .and_then(|t| t.trim().parse().ok())
+}
+
+pub(crate) fn header_str(headers: &HeaderMap, name: &str) -> String {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("")
+        .to_string()
+}
+
+/// Returns the range and body of a single-part 206 response.
+pub(crate) fn single_part(resp: &Response<Bytes>) -> io::Result<(ByteRange, Bytes)> {
+    let range = parse_content_range(&header_str(resp.headers(), CONTENT_RANGE.as_str()))?;
+    if resp.body().len() as u64 != range.len() {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "received {} bytes for Content-Range {}-{}",
+                resp.body().len(),
+                range.start,
+                range.end
+            ),
+        ));
+    }
+    Ok((range, resp.body().clone()))
+}
+
+#[test]
+fn test_parse_content_range() {
+    assert_eq!(
+        parse_content_range("bytes 0-499/1234").unwrap(),
+        ByteRange {
+            start: 0,
+            end: 499,
+            total: Some(1234)
+        }
+    );
+    assert_eq!(parse_content_range("bytes 10-19/*").unwrap().total, None);
+    assert_eq!(parse_content_range("bytes 10-19/*").unwrap().len(), 10);
+    assert!(parse_content_range("bytes 20-10/100").is_err());
+    assert!(parse_content_range("bytes 0-100/100").is_err());
+    assert!(parse_content_range("bytes */100").is_err());
+    assert!(parse_content_range("items 0-1/2").is_err());
+}
+
+#[test]
+fn test_parse_byteranges() {
+    let body = Bytes::from_static(
+        b"preamble\r\n\
+--THIS_STRING_SEPARATES\r\n\
+Content-Type: application/octet-stream\r\n\
+Content-Range: bytes 500-509/8000\r\n\
+\r\n\
+0123456789\r\n\
+--THIS_STRING_SEPARATES\r\n\
+content-range: bytes 7000-7004/8000\r\n\
+\r\n\
+ab\r\nc\r\n\
+--THIS_STRING_SEPARATES--\r\n",
+    );
+    let parts = parse_byteranges(
+        "multipart/byteranges; boundary=\"THIS_STRING_SEPARATES\"",
+        &body,
+    )
+    .unwrap();
+    assert_eq!(parts.len(), 2);
+    assert_eq!(parts[0].0.start, 500);
+    assert_eq!(parts[0].1, "0123456789");
+    assert_eq!(parts[1].0.end, 7004);
+    assert_eq!(parts[1].1, "ab\r\nc");
+
+    assert!(parse_byteranges("text/plain", &body).is_err());
+    assert!(parse_byteranges("multipart/byteranges", &body).is_err());
+    // truncated body
+    let truncated = body.slice(..body.len() - 30);
+    assert!(parse_byteranges(
+        "multipart/byteranges; boundary=THIS_STRING_SEPARATES",
+        &truncated
+    )
+    .is_err());
+}
+
+/// RUST_LOG=debug cargo test --lib -- range::test_get_ranges --exact --show-output
+#[tokio::test]
+async fn test_get_ranges() {
+    use hyper::Method;
+
+    use crate::testing::Stub;
+
+    let server = crate::testing::MockServer::start().await.unwrap();
+    server.register(
+        Stub::new(Method::GET, "/index")
+            .with_header("range", "bytes=0-3,10-11")
+            .respond(
+                206,
+                "--b\r\nContent-Range: bytes 0-3/20\r\n\r\nHEAD\r\n\
+                 --b\r\nContent-Range: bytes 10-11/20\r\n\r\nxy\r\n--b--\r\n",
+            )
+            .respond_header("content-type", "multipart/byteranges; boundary=b"),
+    );
+    server.register(
+        Stub::new(Method::GET, "/index")
+            .with_header("range", "bytes=0-3")
+            .respond(206, "HEAD")
+            .respond_header("content-range", "bytes 0-3/20"),
+    );
+    server.stub(Method::GET, "/full", 200, "0123456789");
+
+    let manager = Manager::new().unwrap();
+    let parts = manager
+        .get_ranges(&server.url(), "/index", &[(0, 3), (10, 11)])
+        .await
+        .unwrap();
+    assert_eq!(parts.len(), 2);
+    assert_eq!(parts[0].1, "HEAD");
+    assert_eq!(parts[1].0.start, 10);
+    assert_eq!(parts[1].1, "xy");
+
+    let parts = manager
+        .get_ranges(&server.url(), "/index", &[(0, 3)])
+        .await
+        .unwrap();
+    assert_eq!(
+        parts,
+        vec![(
+            ByteRange {
+                start: 0,
+                end: 3,
+                total: Some(20)
+            },
+            Bytes::from("HEAD")
+        )]
+    );
+
+    let parts = manager
+        .get_ranges(&server.url(), "/full", &[(2, 4), (8, 100)])
+        .await
+        .unwrap();
+    assert_eq!(parts[0].1, "234");
+    assert_eq!(parts[1].1, "89");
+
+    assert!(manager
+        .get_ranges(&server.url(), "/full", &[(5, 1)])
+        .await
+        .is_err());
+    assert!(manager
+        .get_ranges(&server.url(), "/missing", &[(0, 1)])
+        .await
+        .is_err());
+}
+
+/// RUST_LOG=debug cargo test --lib -- range::test_get_range --exact --show-output
+#[tokio::test]
+async fn test_get_range() {
+    use hyper::Method;
+
+    use crate::testing::Stub;
+
+    let server = crate::testing::MockServer::start().await.unwrap();
+    let stub = |range: &str, status: u16, body: &'static str, content_range: &str| {
+        Stub::new(Method::GET, "/blob")
+            .with_header("range", range)
+            .respond(status, body)
+            .respond_header("content-range", content_range)
+    };
+    server.register(stub("bytes=0-3", 206, "MAGI", "bytes 0-3/10"));
+    // at the end of the resource
+    server.register(stub("bytes=8-15", 206, "89", "bytes 8-9/10"));
+    // a misbehaving server
+    server.register(stub("bytes=4-5", 206, "0123", "bytes 0-3/10"));
+    server.register(stub("bytes=2-4", 206, "23", "bytes 2-4/10"));
+    server.register(stub("bytes=20-30", 416, "", "bytes */10"));
+    server.stub(Method::GET, "/full", 200, "0123456789");
+
+    let manager = Manager::new().unwrap();
+    let url = server.url();
+    assert_eq!(
+        manager.get_range(&url, "/blob", 0, 3).await.unwrap(),
+        "MAGI"
+    );
+    assert_eq!(manager.get_range(&url, "/blob", 8, 15).await.unwrap(), "89");
+    server
+        .assert_received(Method::GET, "/blob")
+        .with_header("range", "bytes=8-15");
+
+    let e = manager.get_range(&url, "/blob", 4, 5).await.unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidData);
+    let e = manager.get_range(&url, "/blob", 2, 4).await.unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidData);
+    let e = manager.get_range(&url, "/blob", 20, 30).await.unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidInput);
+    let e = manager.get_range(&url, "/blob", 3, 1).await.unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidInput);
+    assert!(manager.get_range(&url, "/full", 0, 3).await.is_err());
+}
@@ -0,0 +1,259 @@
+//! Retries of transient failures (connection errors, timeouts, and
+//! retryable status codes) with exponential backoff and jitter.
+
+use std::{future::Future, io, time::Duration};
+
+use hyper::StatusCode;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    attempt::{AttemptError, Attempted},
+    clock::Clock,
+    error::Error,
+    logging::{wire_debug, wire_warn},
+};
+
+/// Unset fields of a config file (see "config::Config") keep the defaults.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryPolicy {
+    /// Attempts including the first one (at least 1).
+    pub max_attempts: usize,
+    /// Delay before the first retry, doubled for every further retry.
+    #[serde(with = "crate::config::duration")]
+    pub base_delay: Duration,
+    #[serde(with = "crate::config::duration")]
+    pub max_delay: Duration,
+    /// Fraction of each delay that is randomized, in [0, 1]: 0 waits the
+    /// exact backoff, 1 waits anywhere between zero and the backoff
+    /// ("full jitter"), so that clients do not retry in lockstep.
+    pub jitter: f64,
+    /// Status codes that are retried (others are returned as is).
+    pub retry_on_status: Vec<u16>,
+    /// Retries non-idempotent methods (e.g., POST) too, which is only
+    /// safe if the server has no side effects for the request (e.g., a
+    /// read-only JSON-RPC call).
+    pub retry_non_idempotent: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(10),
+            jitter: 0.5,
+            retry_on_status: vec![429, 500, 502, 503, 504],
+            retry_non_idempotent: false,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Returns the backoff after "failures" failed attempts (1 for the
+    /// delay before the first retry), with jitter.
+    pub fn delay(&self, failures: usize) -> Duration {
+        let exp = failures.saturating_sub(1).min(31) as u32;
+        let backoff = self
+            .base_delay
+            .saturating_mul(1u32 << exp)
+            .min(self.max_delay);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        if jitter == 0.0 {
+            return backoff;
+        }
+        backoff.mul_f64(1.0 - jitter * rand::thread_rng().gen::<f64>())
+    }
+
+    pub fn is_retryable_status(&self, status: StatusCode) -> bool {
+        self.retry_on_status.contains(&status.as_u16())
+    }
+}
+
+/// Returns true for the errors worth retrying: timeouts (including
+/// stalled transfers), connection failures, and requests that failed on
+/// an established connection (e.g., closed by the server). DNS failures,
+/// proxy failures (e.g., refused tunnels), and invalid requests are final.
+pub fn is_retryable_error(e: &io::Error) -> bool {
+    if e.kind() == io::ErrorKind::TimedOut {
+        return true;
+    }
+    matches!(
+        e.get_ref().and_then(|inner| inner.downcast_ref::<Error>()),
+        Some(Error::Timeout(_) | Error::Connect(_) | Error::Request(_))
+    )
+}
+
+/// Runs "f" until it returns a non-retryable status or error, or the
+/// attempts run out. The last attempt is returned as is (including a
+/// retryable status code). Only a single attempt is made if the request
+/// is not idempotent, unless the policy allows it.
+pub(crate) async fn run<T, F, Fut>(
+    policy: &RetryPolicy,
+    idempotent: bool,
+    clock: &dyn Clock,
+    endpoint: &str,
+    mut f: F,
+) -> io::Result<Attempted<(StatusCode, T)>>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = io::Result<(StatusCode, T)>>,
+{
+    let max_attempts = if idempotent || policy.retry_non_idempotent {
+        policy.max_attempts.max(1)
+    } else {
+        1
+    };
+
+    let start = clock.now();
+    let mut errors = Vec::new();
+    let mut attempt = 0;
+    loop {
+        if attempt > 0 {
+            let delay = policy.delay(attempt);
+            wire_debug!(
+                "retrying {} in {:?} (attempt {})",
+                endpoint,
+                delay,
+                attempt + 1
+            );
+            clock.sleep(delay).await;
+        }
+        attempt += 1;
+
+        let attempt_start = clock.now();
+        let ret = f().await;
+        let last = attempt >= max_attempts;
+        let error = match ret {
+            Ok((status, _)) if !last && policy.is_retryable_status(status) => {
+                io::Error::from(Error::Status(status, hyper::body::Bytes::new()))
+            }
+            Ok(value) => {
+                return Ok(Attempted {
+                    value,
+                    attempts: attempt,
+                    errors,
+                    endpoint: endpoint.to_string(),
+                    elapsed: clock.now() - start,
+                })
+            }
+            Err(e) if !last && is_retryable_error(&e) => e,
+            Err(e) => return Err(e),
+        };
+        wire_warn!("attempt {} to {} failed: {}", attempt, endpoint, error);
+        errors.push(AttemptError {
+            endpoint: endpoint.to_string(),
+            error,
+            elapsed: clock.now() - attempt_start,
+        });
+    }
+}
+
+#[test]
+fn test_retry_delay() {
+    let policy = RetryPolicy {
+        base_delay: Duration::from_millis(100),
+        max_delay: Duration::from_millis(500),
+        jitter: 0.0,
+        ..Default::default()
+    };
+    let delays: Vec<Duration> = (1..=5).map(|n| policy.delay(n)).collect();
+    assert_eq!(
+        delays,
+        [100, 200, 400, 500, 500]
+            .map(Duration::from_millis)
+            .to_vec()
+    );
+    assert_eq!(policy.delay(1000), Duration::from_millis(500));
+
+    let policy = RetryPolicy {
+        jitter: 1.0,
+        ..policy
+    };
+    for _ in 0..100 {
+        assert!(policy.delay(3) <= Duration::from_millis(400));
+    }
+
+    assert!(policy.is_retryable_status(StatusCode::SERVICE_UNAVAILABLE));
+    assert!(!policy.is_retryable_status(StatusCode::NOT_FOUND));
+    assert!(is_retryable_error(&io::Error::from(Error::Connect(
+        "refused".to_string()
+    ))));
+    assert!(is_retryable_error(&io::Error::from(
+        io::ErrorKind::TimedOut
+    )));
+    assert!(!is_retryable_error(&io::Error::from(Error::Dns(
+        "no such host".to_string()
+    ))));
+    assert!(!is_retryable_error(&io::Error::from(
+        io::ErrorKind::InvalidInput
+    )));
+}
+
+/// RUST_LOG=debug cargo test --lib -- retry::test_retry_run --exact --show-output
+#[tokio::test]
+async fn test_retry_run() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use crate::clock::MockClock;
+
+    let policy = RetryPolicy {
+        max_attempts: 4,
+        base_delay: Duration::from_millis(100),
+        jitter: 0.0,
+        ..Default::default()
+    };
+
+    // connection error, then 503, then 200
+    let clock = MockClock::new();
+    let calls = AtomicUsize::new(0);
+    let attempted = run(&policy, true, &clock, "http://a", || async {
+        match calls.fetch_add(1, Ordering::SeqCst) {
+            0 => Err(Error::Connect("refused".to_string()).into()),
+            1 => Ok((StatusCode::SERVICE_UNAVAILABLE, "busy")),
+            _ => Ok((StatusCode::OK, "ok")),
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(attempted.value, (StatusCode::OK, "ok"));
+    assert_eq!(attempted.attempts, 3);
+    assert_eq!(attempted.errors.len(), 2);
+    assert_eq!(
+        clock.sleeps(),
+        [100, 200].map(Duration::from_millis).to_vec()
+    );
+
+    // the last retryable status is returned as is
+    let clock = MockClock::new();
+    let attempted = run(&policy, true, &clock, "http://a", || async {
+        Ok((StatusCode::BAD_GATEWAY, ()))
+    })
+    .await
+    .unwrap();
+    assert_eq!(attempted.value.0, StatusCode::BAD_GATEWAY);
+    assert_eq!(attempted.attempts, 4);
+
+    // final errors, and non-idempotent requests
+    let clock = MockClock::new();
+    let calls = AtomicUsize::new(0);
+    let e = run(&policy, true, &clock, "http://a", || async {
+        calls.fetch_add(1, Ordering::SeqCst);
+        Err::<(StatusCode, ()), _>(Error::Dns("no such host".to_string()).into())
+    })
+    .await
+    .unwrap_err();
+    assert!(matches!(Error::from(e), Error::Dns(_)));
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+    let calls = AtomicUsize::new(0);
+    let attempted = run(&policy, false, &clock, "http://a", || async {
+        calls.fetch_add(1, Ordering::SeqCst);
+        Ok((StatusCode::SERVICE_UNAVAILABLE, ()))
+    })
+    .await
+    .unwrap();
+    assert_eq!(attempted.attempts, 1);
+    assert!(clock.sleeps().is_empty());
+}
@@ -0,0 +1,288 @@
+//! Request signing hook (see "ManagerBuilder::request_signer"), for APIs
+//! that authenticate requests with their own signature scheme (e.g.,
+//! exchange and webhook APIs signing a timestamp, nonce, and the body).
+
+use std::{
+    fmt,
+    io::{self, Error, ErrorKind},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use hmac::{Hmac, Mac};
+use hyper::{
+    body::HttpBody,
+    header::{HeaderName, HeaderValue},
+    Body, HeaderMap, Method, Request, Uri,
+};
+use rand::Rng;
+use sha2::Sha256;
+
+/// Signs a request just before it is sent, after the default headers and
+/// cookies are added, by adding or replacing headers.
+pub trait RequestSigner: Send + Sync + fmt::Debug {
+    /// "body" is None for streaming bodies (unknown size), which are not
+    /// buffered to be signed.
+    fn sign(
+        &self,
+        method: &Method,
+        uri: &Uri,
+        headers: &mut HeaderMap,
+        body: Option<&[u8]>,
+    ) -> io::Result<()>;
+}
+
+/// Signs the request, buffering the body if its size is known.
+pub async fn sign_request(
+    signer: &dyn RequestSigner,
+    req: Request<Body>,
+) -> io::Result<Request<Body>> {
+    let (mut parts, body) = req.into_parts();
+    let body = if body.size_hint().exact().is_some() {
+        let b = crate::buffer::collect(body).await.map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("failed to read request body to sign {}", e),
+            )
+        })?;
+        signer.sign(&parts.method, &parts.uri, &mut parts.headers, Some(&b))?;
+        Body::from(b)
+    } else {
+        signer.sign(&parts.method, &parts.uri, &mut parts.headers, None)?;
+        body
+    };
+    Ok(Request::from_parts(parts, body))
+}
+
+pub const DEFAULT_SIGNATURE_HEADER: &str = "x-signature";
+pub const DEFAULT_TIMESTAMP_HEADER: &str = "x-timestamp";
+pub const DEFAULT_NONCE_HEADER: &str = "x-nonce";
+
+/// Signs requests with the hex-encoded HMAC-SHA256 of
+/// "{timestamp}\n{nonce}\n{method}\n{path and query}\n{body}", and sends
+/// the Unix timestamp (in seconds) and a random nonce as headers, so that
+/// servers can reject replayed requests. Streaming bodies are signed as
+/// empty.
+#[derive(Clone)]
+pub struct HmacSha256Signer {
+    secret: Vec<u8>,
+    key_id: Option<(HeaderName, HeaderValue)>,
+    signature_header: HeaderName,
+    timestamp_header: HeaderName,
+    nonce_header: Option<HeaderName>,
+}
+
+impl fmt::Debug for HmacSha256Signer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HmacSha256Signer")
+            .field("key_id", &self.key_id)
+            .field("signature_header", &self.signature_header)
+            .field("timestamp_header", &self.timestamp_header)
+            .field("nonce_header", &self.nonce_header)
+            .finish_non_exhaustive()
+    }
+}
+
+impl HmacSha256Signer {
+    pub fn new(secret: impl Into<Vec<u8>>) -> Self {
+        Self {
+            secret: secret.into(),
+            key_id: None,
+            signature_header: HeaderName::from_static(DEFAULT_SIGNATURE_HEADER),
+            timestamp_header: HeaderName::from_static(DEFAULT_TIMESTAMP_HEADER),
+            nonce_header: Some(HeaderName::from_static(DEFAULT_NONCE_HEADER)),
+        }
+    }
+
+    /// Sends the API key ID as the header (e.g., "x-api-key").
+    pub fn key_id(mut self, header: &str, key_id: &str) -> io::Result<Self> {
+        self.key_id = Some((header_name(header)?, header_value(key_id)?));
+        Ok(self)
+    }
+
+    pub fn signature_header(mut self, header: &str) -> io::Result<Self> {
+        self.signature_header = header_name(header)?;
+        Ok(self)
+    }
+
+    pub fn timestamp_header(mut self, header: &str) -> io::Result<Self> {
+        self.timestamp_header = header_name(header)?;
+        Ok(self)
+    }
+
+    /// Sets the nonce header, or leaves the nonce out (and signs it as
+    /// empty) if None.
+    pub fn nonce_header(mut self, header: Option<&str>) -> io::Result<Self> {
+        self.nonce_header = header.map(header_name).transpose()?;
+        Ok(self)
+    }
+
+    /// Returns the hex-encoded signature, for servers to verify requests
+    /// (compare in constant time).
+    pub fn signature(
+        &self,
+        timestamp: u64,
+        nonce: &str,
+        method: &Method,
+        uri: &Uri,
+        body: &[u8],
+    ) -> String {
+        let path_and_query = uri.path_and_query().map(|p| p.as_str()).unwrap_or("/");
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC accepts any key length");
+        mac.update(
+            format!("{}\n{}\n{}\n{}\n", timestamp, nonce, method, path_and_query).as_bytes(),
+        );
+        mac.update(body);
+        format!("{:x}", mac.finalize().into_bytes())
+    }
+
+    /// Signs with the timestamp and nonce (e.g., to reproduce a signature).
+    pub fn sign_at(
+        &self,
+        method: &Method,
+        uri: &Uri,
+        headers: &mut HeaderMap,
+        body: Option<&[u8]>,
+        timestamp: u64,
+        nonce: &str,
+    ) -> io::Result<()> {
+        let nonce = if self.nonce_header.is_some() {
+            nonce
+        } else {
+            ""
+        };
+        let signature = self.signature(timestamp, nonce, method, uri, body.unwrap_or_default());
+
+        if let Some((name, v)) = &self.key_id {
+            headers.insert(name.clone(), v.clone());
+        }
+        headers.insert(self.timestamp_header.clone(), HeaderValue::from(timestamp));
+        if let Some(name) = &self.nonce_header {
+            headers.insert(name.clone(), header_value(nonce)?);
+        }
+        headers.insert(self.signature_header.clone(), header_value(&signature)?);
+        Ok(())
+    }
+}
+
+impl RequestSigner for HmacSha256Signer {
+    fn sign(
+        &self,
+        method: &Method,
+        uri: &Uri,
+        headers: &mut HeaderMap,
+        body: Option<&[u8]>,
+    ) -> io::Result<()> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let nonce = format!("{:032x}", rand::thread_rng().gen::<u128>());
+        self.sign_at(method, uri, headers, body, timestamp, &nonce)
+    }
+}
+
+fn header_name(name: &str) -> io::Result<HeaderName> {
+    HeaderName::from_bytes(name.as_bytes()).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid signature header name {}", e),
+        )
+    })
+}
+
+fn header_value(v: &str) -> io::Result<HeaderValue> {
+    HeaderValue::from_str(v).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid signature header value {}", e),
+        )
+    })
+}
+
+/// RUST_LOG=debug cargo test --lib -- signer::test_hmac_sha256_signer --exact --show-output
+#[test]
+fn test_hmac_sha256_signer() {
+    let signer = HmacSha256Signer::new("secret")
+        .key_id("x-api-key", "k1")
+        .unwrap();
+    let uri: Uri = "https://api.example.com/v1/order?symbol=BTC"
+        .parse()
+        .unwrap();
+    let mut headers = HeaderMap::new();
+    signer
+        .sign_at(
+            &Method::POST,
+            &uri,
+            &mut headers,
+            Some(b"{\"qty\":1}"),
+            1700000000,
+            "n1",
+        )
+        .unwrap();
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(b"secret").unwrap();
+    mac.update(b"1700000000\nn1\nPOST\n/v1/order?symbol=BTC\n{\"qty\":1}");
+    let expected = format!("{:x}", mac.finalize().into_bytes());
+    assert_eq!(headers[DEFAULT_SIGNATURE_HEADER], expected.as_str());
+    assert_eq!(headers[DEFAULT_TIMESTAMP_HEADER], "1700000000");
+    assert_eq!(headers[DEFAULT_NONCE_HEADER], "n1");
+    assert_eq!(headers["x-api-key"], "k1");
+
+    // without a nonce, and with custom header names
+    let signer = HmacSha256Signer::new("secret")
+        .nonce_header(None)
+        .unwrap()
+        .signature_header("X-Sig")
+        .unwrap();
+    let mut headers = HeaderMap::new();
+    signer
+        .sign_at(&Method::GET, &uri, &mut headers, None, 1700000000, "n1")
+        .unwrap();
+    assert_eq!(
+        headers["x-sig"],
+        signer
+            .signature(1700000000, "", &Method::GET, &uri, b"")
+            .as_str()
+    );
+    assert!(headers.get(DEFAULT_NONCE_HEADER).is_none());
+
+    assert!(HmacSha256Signer::new("s")
+        .signature_header("bad header")
+        .is_err());
+    assert!(!format!("{:?}", HmacSha256Signer::new("secret")).contains("secret"));
+}
+
+/// RUST_LOG=debug cargo test --lib -- signer::test_manager_request_signer --exact --show-output
+#[tokio::test]
+async fn test_manager_request_signer() {
+    use std::sync::Arc;
+
+    let server = crate::testing::MockServer::start().await.unwrap();
+    server.stub(Method::POST, "/v1/order", 200, "ok");
+
+    let signer = HmacSha256Signer::new("secret");
+    let manager = crate::Manager::builder()
+        .request_signer(Arc::new(signer.clone()))
+        .build()
+        .unwrap();
+    let req = crate::create_json_post(server.url(), "/v1/order", "{\"qty\":1}").unwrap();
+    assert_eq!(manager.read_bytes(req, true).await.unwrap(), "ok");
+
+    let received = server.assert_received(Method::POST, "/v1/order").once();
+    let r = &received.requests()[0];
+    assert_eq!(r.body, "{\"qty\":1}");
+    let timestamp: u64 = r.headers[DEFAULT_TIMESTAMP_HEADER]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    let nonce = r.headers[DEFAULT_NONCE_HEADER].to_str().unwrap();
+    assert_eq!(nonce.len(), 32);
+    assert_eq!(
+        r.headers[DEFAULT_SIGNATURE_HEADER],
+        signer
+            .signature(timestamp, nonce, &Method::POST, &r.uri, b"{\"qty\":1}")
+            .as_str()
+    );
+}
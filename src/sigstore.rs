@@ -0,0 +1,1091 @@
+//! Verification of downloaded artifacts against cosign signatures, so that
+//! binaries fetched by provisioning pipelines are supply-chain verified
+//! without shelling out to the cosign CLI.
+//!
+//! Supports the bundles of "cosign sign-blob --bundle" (or the entries of
+//! a Rekor transparency log), signed with a key ("--key") or keyless with
+//! a short-lived Fulcio certificate bound to an OIDC identity.
+//! ref. https://docs.sigstore.dev/cosign/verifying/verify/
+
+use std::{
+    io::{self, Error, ErrorKind},
+    path::Path,
+    time::Duration,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hyper::{
+    header::{ACCEPT, CONTENT_TYPE},
+    Body, Request,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha384};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use x509_cert::{
+    der::{
+        asn1::Utf8StringRef,
+        oid::{db::rfc5280::ID_CE_SUBJECT_ALT_NAME, ObjectIdentifier},
+        Decode, DecodePem, Encode,
+    },
+    ext::pkix::{name::GeneralName, SubjectAltName},
+    Certificate,
+};
+
+use crate::{
+    logging::{wire_debug, wire_info, wire_warn},
+    Manager,
+};
+
+const ECDSA_WITH_SHA256: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.4.3.2");
+const ECDSA_WITH_SHA384: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.4.3.3");
+/// Fulcio OIDC issuer extension, raw string value (deprecated).
+const FULCIO_ISSUER_V1: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.6.1.4.1.57264.1.1");
+/// Fulcio OIDC issuer extension, DER-encoded UTF8String value.
+const FULCIO_ISSUER_V2: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.6.1.4.1.57264.1.8");
+
+/// Bundle written by "cosign sign-blob --bundle".
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CosignBundle {
+    /// Base64-encoded ASN.1 DER ECDSA signature over the artifact.
+    #[serde(rename = "base64Signature")]
+    pub base64_signature: String,
+    /// Base64-encoded PEM signing certificate, for keyless signatures.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cert: Option<String>,
+    #[serde(
+        rename = "rekorBundle",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub rekor_bundle: Option<RekorBundle>,
+}
+
+/// Transparency log entry with the log's promise to include it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RekorBundle {
+    /// Base64-encoded signature of the log over the payload.
+    #[serde(rename = "SignedEntryTimestamp")]
+    pub signed_entry_timestamp: String,
+    #[serde(rename = "Payload")]
+    pub payload: RekorPayload,
+}
+
+/// Signed fields of a transparency log entry. The fields are declared in
+/// the lexicographic order of their JSON keys, so that the serialized
+/// payload is the canonical JSON signed by the log.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RekorPayload {
+    /// Base64-encoded entry (e.g., "hashedrekord").
+    pub body: String,
+    /// Unix time the log integrated the entry.
+    #[serde(rename = "integratedTime")]
+    pub integrated_time: i64,
+    /// Hex-encoded SHA-256 of the log's public key.
+    #[serde(rename = "logID")]
+    pub log_id: String,
+    #[serde(rename = "logIndex")]
+    pub log_index: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct HashedRekord {
+    kind: String,
+    spec: HashedRekordSpec,
+}
+
+#[derive(Debug, Deserialize)]
+struct HashedRekordSpec {
+    data: HashedRekordData,
+    signature: HashedRekordSignature,
+}
+
+#[derive(Debug, Deserialize)]
+struct HashedRekordData {
+    hash: HashedRekordHash,
+}
+
+#[derive(Debug, Deserialize)]
+struct HashedRekordHash {
+    algorithm: String,
+    value: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct HashedRekordSignature {
+    content: String,
+    #[serde(rename = "publicKey")]
+    public_key: HashedRekordPublicKey,
+}
+
+#[derive(Debug, Deserialize)]
+struct HashedRekordPublicKey {
+    /// Base64-encoded PEM public key or certificate.
+    content: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct RekorLogEntry {
+    body: String,
+    #[serde(rename = "integratedTime")]
+    integrated_time: i64,
+    #[serde(rename = "logID")]
+    log_id: String,
+    #[serde(rename = "logIndex")]
+    log_index: i64,
+    verification: Option<RekorVerification>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RekorVerification {
+    #[serde(rename = "signedEntryTimestamp")]
+    signed_entry_timestamp: Option<String>,
+}
+
+impl CosignBundle {
+    pub fn from_json(b: &[u8]) -> io::Result<Self> {
+        serde_json::from_slice(b).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("failed to parse cosign bundle {}", e),
+            )
+        })
+    }
+
+    pub async fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let b = tokio::fs::read(path.as_ref()).await?;
+        Self::from_json(&b)
+    }
+
+    /// Builds the bundle of a "hashedrekord" transparency log entry: the
+    /// signature and the signing certificate are those recorded in the log.
+    pub fn from_rekor_bundle(rekor: RekorBundle) -> io::Result<Self> {
+        let entry = decode_hashed_rekord(&rekor.payload.body)?;
+        let key = decode_b64("entry public key", &entry.spec.signature.public_key.content)?;
+        let cert = if String::from_utf8_lossy(&key).contains("CERTIFICATE") {
+            Some(entry.spec.signature.public_key.content.clone())
+        } else {
+            None
+        };
+        Ok(Self {
+            base64_signature: entry.spec.signature.content,
+            cert,
+            rekor_bundle: Some(rekor),
+        })
+    }
+
+    fn signature(&self) -> io::Result<Vec<u8>> {
+        decode_b64("signature", self.base64_signature.trim())
+    }
+
+    fn certificate(&self) -> io::Result<Option<(Vec<u8>, Certificate)>> {
+        let cert = match self.cert.as_deref().map(str::trim) {
+            Some(c) if !c.is_empty() => c,
+            _ => return Ok(None),
+        };
+        let pem = decode_b64("certificate", cert)?;
+        let c = Certificate::from_pem(&pem).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("failed to parse signing certificate {}", e),
+            )
+        })?;
+        Ok(Some((pem, c)))
+    }
+}
+
+/// ECDSA public key of a signer, a transparency log, or a CA.
+#[derive(Debug, Clone)]
+enum PublicKey {
+    P256(p256::ecdsa::VerifyingKey),
+    P384(p384::ecdsa::VerifyingKey),
+}
+
+impl PublicKey {
+    /// Parses a "PUBLIC KEY" (SubjectPublicKeyInfo) PEM.
+    fn from_pem(pem: &str) -> io::Result<Self> {
+        use p256::pkcs8::DecodePublicKey;
+
+        if let Ok(k) = p256::ecdsa::VerifyingKey::from_public_key_pem(pem.trim()) {
+            return Ok(PublicKey::P256(k));
+        }
+        p384::ecdsa::VerifyingKey::from_public_key_pem(pem.trim())
+            .map(PublicKey::P384)
+            .map_err(|e| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("failed to parse ECDSA P-256/P-384 public key {}", e),
+                )
+            })
+    }
+
+    fn from_certificate(cert: &Certificate) -> io::Result<Self> {
+        let point = cert
+            .tbs_certificate
+            .subject_public_key_info
+            .subject_public_key
+            .raw_bytes();
+        // uncompressed SEC1 points of P-256 and P-384
+        let key = match point.len() {
+            65 => p256::ecdsa::VerifyingKey::from_sec1_bytes(point).map(PublicKey::P256),
+            97 => p384::ecdsa::VerifyingKey::from_sec1_bytes(point).map(PublicKey::P384),
+            n => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("unsupported certificate public key ({} bytes)", n),
+                ))
+            }
+        };
+        key.map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("failed to parse certificate public key {}", e),
+            )
+        })
+    }
+
+    /// Verifies the DER-encoded signature over the message digest.
+    fn verify_prehash(&self, digest: &[u8], sig: &[u8]) -> io::Result<()> {
+        use p256::ecdsa::signature::hazmat::PrehashVerifier;
+
+        let ret = match self {
+            PublicKey::P256(k) => {
+                p256::ecdsa::Signature::from_der(sig).and_then(|s| k.verify_prehash(digest, &s))
+            }
+            PublicKey::P384(k) => {
+                p384::ecdsa::Signature::from_der(sig).and_then(|s| k.verify_prehash(digest, &s))
+            }
+        };
+        ret.map_err(|e| Error::new(ErrorKind::InvalidData, format!("invalid signature {}", e)))
+    }
+}
+
+/// What was verified about an artifact.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Verified {
+    /// Hex-encoded SHA-256 of the artifact.
+    pub sha256: String,
+    /// Identity (email or URI) of the keyless signing certificate.
+    pub identity: Option<String>,
+    /// OIDC issuer of the keyless signing certificate.
+    pub issuer: Option<String>,
+    /// Transparency log index, if the log entry was verified.
+    pub log_index: Option<i64>,
+    /// Unix time the log integrated the entry, if verified.
+    pub integrated_time: Option<i64>,
+}
+
+/// Verification policy of cosign signatures.
+///
+/// Keyless signatures require a verified transparency log entry, since
+/// the signing certificate is only valid for minutes and the log time
+/// proves the signature was made while it was.
+#[derive(Debug, Clone)]
+pub struct CosignVerifier {
+    key: Option<PublicKey>,
+    roots: Vec<Certificate>,
+    identity: Option<String>,
+    issuer: Option<String>,
+    rekor_key: Option<PublicKey>,
+    require_tlog: bool,
+}
+
+impl CosignVerifier {
+    /// Verifies signatures made with the key ("cosign.pub").
+    pub fn with_public_key(pem: &str) -> io::Result<Self> {
+        Ok(Self {
+            key: Some(PublicKey::from_pem(pem)?),
+            roots: Vec::new(),
+            identity: None,
+            issuer: None,
+            rekor_key: None,
+            require_tlog: false,
+        })
+    }
+
+    /// Verifies keyless signatures, whose certificates must be issued by
+    /// one of the CA certificates (e.g., the Fulcio intermediate) to the
+    /// identity (certificate email or URI) by the OIDC issuer (e.g.,
+    /// "https://token.actions.githubusercontent.com").
+    pub fn keyless(ca_pem: &str, identity: &str, issuer: &str) -> io::Result<Self> {
+        let roots = Certificate::load_pem_chain(ca_pem.as_bytes()).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("failed to parse CA certificates {}", e),
+            )
+        })?;
+        if roots.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "no CA certificate for keyless verification",
+            ));
+        }
+        Ok(Self {
+            key: None,
+            roots,
+            identity: Some(identity.to_string()),
+            issuer: Some(issuer.to_string()),
+            rekor_key: None,
+            require_tlog: true,
+        })
+    }
+
+    /// Sets the public key of the transparency log (e.g., "rekor.pub"),
+    /// to verify the signed entry timestamps of bundles.
+    pub fn rekor_public_key(mut self, pem: &str) -> io::Result<Self> {
+        self.rekor_key = Some(PublicKey::from_pem(pem)?);
+        Ok(self)
+    }
+
+    /// Requires a verified transparency log entry for key-based
+    /// signatures too (always required for keyless signatures).
+    pub fn require_transparency_log(mut self, require: bool) -> Self {
+        self.require_tlog = require || self.key.is_none();
+        self
+    }
+
+    /// Verifies the bundle against the SHA-256 of the artifact.
+    pub fn verify(&self, sha256: &[u8], bundle: &CosignBundle) -> io::Result<Verified> {
+        let sig = bundle.signature()?;
+        let digest_hex: String = sha256.iter().map(|b| format!("{:02x}", b)).collect();
+
+        let mut verified = Verified {
+            sha256: digest_hex.clone(),
+            identity: None,
+            issuer: None,
+            log_index: None,
+            integrated_time: None,
+        };
+
+        // signature over the artifact
+        let cert = match &self.key {
+            Some(key) => {
+                key.verify_prehash(sha256, &sig)
+                    .map_err(|e| failed(format!("artifact signature: {}", e)))?;
+                None
+            }
+            None => {
+                let (pem, cert) = bundle
+                    .certificate()?
+                    .ok_or_else(|| failed("keyless bundle has no signing certificate"))?;
+                let (identity, issuer) = self.verify_certificate(&cert)?;
+                PublicKey::from_certificate(&cert)?
+                    .verify_prehash(sha256, &sig)
+                    .map_err(|e| failed(format!("artifact signature: {}", e)))?;
+                verified.identity = Some(identity);
+                verified.issuer = Some(issuer);
+                Some((pem, cert))
+            }
+        };
+
+        // transparency log entry
+        let rekor = match &bundle.rekor_bundle {
+            Some(rekor) => rekor,
+            None if !self.require_tlog => return Ok(verified),
+            None => return Err(failed("bundle has no transparency log entry")),
+        };
+        let rekor_key = match &self.rekor_key {
+            Some(k) => k,
+            None if !self.require_tlog => {
+                wire_warn!("no transparency log public key, skipping the log entry");
+                return Ok(verified);
+            }
+            None => {
+                return Err(failed(
+                    "transparency log entry required, but no log public key",
+                ))
+            }
+        };
+        let set = decode_b64(
+            "signed entry timestamp",
+            rekor.signed_entry_timestamp.trim(),
+        )?;
+        let payload = serde_json::to_vec(&rekor.payload).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("failed to serialize log payload {}", e),
+            )
+        })?;
+        rekor_key
+            .verify_prehash(&Sha256::digest(&payload), &set)
+            .map_err(|e| failed(format!("signed entry timestamp: {}", e)))?;
+
+        // the log entry must be of this artifact and signature
+        let entry = decode_hashed_rekord(&rekor.payload.body)?;
+        if !entry
+            .spec
+            .data
+            .hash
+            .algorithm
+            .eq_ignore_ascii_case("sha256")
+            || !entry.spec.data.hash.value.eq_ignore_ascii_case(&digest_hex)
+        {
+            return Err(failed(format!(
+                "log entry is of another artifact ({}:{})",
+                entry.spec.data.hash.algorithm, entry.spec.data.hash.value
+            )));
+        }
+        if decode_b64("entry signature", &entry.spec.signature.content)? != sig {
+            return Err(failed("log entry has another signature"));
+        }
+
+        if let Some((pem, cert)) = cert {
+            let logged = decode_b64("entry public key", &entry.spec.signature.public_key.content)?;
+            if logged != pem {
+                return Err(failed("log entry has another signing certificate"));
+            }
+            // the certificate must have been valid when the log integrated
+            // the signature
+            let validity = &cert.tbs_certificate.validity;
+            let t = Duration::from_secs(rekor.payload.integrated_time.max(0) as u64);
+            if t < validity.not_before.to_unix_duration()
+                || t > validity.not_after.to_unix_duration()
+            {
+                return Err(failed(format!(
+                    "signing certificate was not valid at the log time {}",
+                    rekor.payload.integrated_time
+                )));
+            }
+        }
+
+        wire_debug!(
+            "verified transparency log entry {} at {}",
+            rekor.payload.log_index,
+            rekor.payload.integrated_time
+        );
+        verified.log_index = Some(rekor.payload.log_index);
+        verified.integrated_time = Some(rekor.payload.integrated_time);
+        Ok(verified)
+    }
+
+    /// Verifies the artifact file against the bundle.
+    pub async fn verify_file(
+        &self,
+        path: impl AsRef<Path>,
+        bundle: &CosignBundle,
+    ) -> io::Result<Verified> {
+        let mut f = tokio::fs::File::open(path.as_ref()).await?;
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            let n = f.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+        }
+        self.verify(&hasher.finalize(), bundle)
+    }
+
+    /// Verifies that the certificate was issued by one of the CAs to the
+    /// expected identity, and returns its identity and issuer.
+    fn verify_certificate(&self, cert: &Certificate) -> io::Result<(String, String)> {
+        let ca = self
+            .roots
+            .iter()
+            .find(|ca| ca.tbs_certificate.subject == cert.tbs_certificate.issuer)
+            .ok_or_else(|| {
+                failed(format!(
+                    "signing certificate issuer '{}' is not trusted",
+                    cert.tbs_certificate.issuer
+                ))
+            })?;
+        let tbs = cert.tbs_certificate.to_der().map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("failed to encode certificate {}", e),
+            )
+        })?;
+        let digest = match cert.signature_algorithm.oid {
+            ECDSA_WITH_SHA256 => Sha256::digest(&tbs).to_vec(),
+            ECDSA_WITH_SHA384 => Sha384::digest(&tbs).to_vec(),
+            oid => {
+                return Err(failed(format!(
+                    "unsupported certificate signature algorithm {}",
+                    oid
+                )))
+            }
+        };
+        PublicKey::from_certificate(ca)?
+            .verify_prehash(&digest, cert.signature.raw_bytes())
+            .map_err(|e| failed(format!("signing certificate: {}", e)))?;
+
+        let (identities, issuer) = certificate_identity(cert)?;
+        let expected = self.identity.as_deref().unwrap_or("");
+        let identity = identities
+            .into_iter()
+            .find(|id| id == expected)
+            .ok_or_else(|| {
+                failed(format!(
+                    "signing certificate is not issued to '{}'",
+                    expected
+                ))
+            })?;
+        let expected = self.issuer.as_deref().unwrap_or("");
+        match issuer {
+            Some(issuer) if issuer == expected => Ok((identity, issuer)),
+            issuer => Err(failed(format!(
+                "signing certificate is issued by {:?}, expected '{}'",
+                issuer, expected
+            ))),
+        }
+    }
+}
+
+/// Returns the subject alternative names (emails and URIs) and the
+/// Fulcio OIDC issuer of the certificate.
+fn certificate_identity(cert: &Certificate) -> io::Result<(Vec<String>, Option<String>)> {
+    let mut identities = Vec::new();
+    let mut issuer = None;
+    for ext in cert.tbs_certificate.extensions.iter().flatten() {
+        let value = ext.extn_value.as_bytes();
+        if ext.extn_id == ID_CE_SUBJECT_ALT_NAME {
+            let san = SubjectAltName::from_der(value).map_err(|e| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("failed to parse subject alternative names {}", e),
+                )
+            })?;
+            for name in san.0.iter() {
+                match name {
+                    GeneralName::Rfc822Name(s) => identities.push(s.to_string()),
+                    GeneralName::UniformResourceIdentifier(s) => identities.push(s.to_string()),
+                    _ => {}
+                }
+            }
+        } else if ext.extn_id == FULCIO_ISSUER_V2 {
+            let s = Utf8StringRef::from_der(value).map_err(|e| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("failed to parse OIDC issuer {}", e),
+                )
+            })?;
+            issuer = Some(s.to_string());
+        } else if ext.extn_id == FULCIO_ISSUER_V1 && issuer.is_none() {
+            issuer = Some(String::from_utf8_lossy(value).to_string());
+        }
+    }
+    Ok((identities, issuer))
+}
+
+fn decode_hashed_rekord(body: &str) -> io::Result<HashedRekord> {
+    let b = decode_b64("log entry body", body.trim())?;
+    let entry: HashedRekord = serde_json::from_slice(&b).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("failed to parse log entry body {}", e),
+        )
+    })?;
+    if entry.kind != "hashedrekord" {
+        return Err(failed(format!(
+            "unsupported log entry kind '{}'",
+            entry.kind
+        )));
+    }
+    Ok(entry)
+}
+
+fn decode_b64(what: &str, s: &str) -> io::Result<Vec<u8>> {
+    STANDARD.decode(s).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("failed to decode {} {}", what, e),
+        )
+    })
+}
+
+fn failed(msg: impl std::fmt::Display) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("cosign verification failed: {}", msg),
+    )
+}
+
+impl Manager {
+    /// Looks up the transparency log entries of the artifact SHA-256 and
+    /// returns their UUIDs.
+    pub async fn find_rekor_entries(
+        &self,
+        rekor_url: &str,
+        sha256: &[u8],
+    ) -> io::Result<Vec<String>> {
+        let hex: String = sha256.iter().map(|b| format!("{:02x}", b)).collect();
+        let uri = crate::join_uri(rekor_url, "api/v1/index/retrieve")?;
+        let req = Request::post(uri.as_str())
+            .header(CONTENT_TYPE, "application/json")
+            .header(ACCEPT, "application/json")
+            .body(Body::from(
+                serde_json::json!({ "hash": format!("sha256:{}", hex) }).to_string(),
+            ))
+            .map_err(|e| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("failed to create request {}", e),
+                )
+            })?;
+        let resp = self.read_response(req).await?;
+        if !resp.status().is_success() {
+            return Err(Error::new(
+                ErrorKind::Other,
+                format!(
+                    "unexpected HTTP response code {} from {}",
+                    resp.status(),
+                    crate::redact::url(uri.as_str())
+                ),
+            ));
+        }
+        serde_json::from_slice(resp.body()).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("failed to parse log index response {}", e),
+            )
+        })
+    }
+
+    /// Fetches the transparency log entry as a cosign bundle, to verify
+    /// artifacts that were signed without writing a bundle.
+    pub async fn fetch_rekor_entry(&self, rekor_url: &str, uuid: &str) -> io::Result<CosignBundle> {
+        let uri = crate::join_uri(rekor_url, &format!("api/v1/log/entries/{}", uuid))?;
+        let req = Request::get(uri.as_str())
+            .header(ACCEPT, "application/json")
+            .body(Body::empty())
+            .map_err(|e| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("failed to create request {}", e),
+                )
+            })?;
+        let resp = self.read_response(req).await?;
+        if !resp.status().is_success() {
+            return Err(Error::new(
+                ErrorKind::Other,
+                format!(
+                    "unexpected HTTP response code {} from {}",
+                    resp.status(),
+                    crate::redact::url(uri.as_str())
+                ),
+            ));
+        }
+        // keyed by the entry UUID
+        let entries: std::collections::HashMap<String, RekorLogEntry> =
+            serde_json::from_slice(resp.body()).map_err(|e| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("failed to parse log entry {}", e),
+                )
+            })?;
+        let entry = entries
+            .into_values()
+            .next()
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("no log entry '{}'", uuid)))?;
+        let set = entry
+            .verification
+            .and_then(|v| v.signed_entry_timestamp)
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("log entry '{}' has no signed entry timestamp", uuid),
+                )
+            })?;
+        CosignBundle::from_rekor_bundle(RekorBundle {
+            signed_entry_timestamp: set,
+            payload: RekorPayload {
+                body: entry.body,
+                integrated_time: entry.integrated_time,
+                log_id: entry.log_id,
+                log_index: entry.log_index,
+            },
+        })
+    }
+}
+
+/// Same as "download_file", but the file is only written to "file_path"
+/// once the downloaded bytes are verified against the cosign bundle. An
+/// unverified download is removed.
+pub async fn download_file_verified(
+    ep: &str,
+    file_path: &str,
+    bundle: &CosignBundle,
+    verifier: &CosignVerifier,
+) -> io::Result<Verified> {
+    wire_info!("downloading the file via {}", crate::redact::url(ep));
+    let mut resp = crate::download_client()
+        .get(ep)
+        .send()
+        .await
+        .map_err(|e| crate::error::Error::from_reqwest("failed get", e))?;
+
+    let unverified = format!("{}.unverified", file_path);
+    let mut f = tokio::fs::File::create(&unverified).await?;
+    let mut hasher = Sha256::new();
+    let ret = async {
+        while let Some(chunk) = resp.chunk().await.map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("failed chunk {}", e.without_url()),
+            )
+        })? {
+            hasher.update(&chunk);
+            f.write_all(&chunk).await?;
+        }
+        f.flush().await?;
+        verifier.verify(&hasher.finalize(), bundle)
+    }
+    .await;
+
+    match ret {
+        Ok(verified) => {
+            tokio::fs::rename(&unverified, file_path).await?;
+            wire_info!("verified {} (sha256:{})", file_path, verified.sha256);
+            Ok(verified)
+        }
+        Err(e) => {
+            wire_warn!("removing unverified download {}", unverified);
+            let _ = tokio::fs::remove_file(&unverified).await;
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+fn test_rekor_bundle(
+    rekor: &p256::ecdsa::SigningKey,
+    sha256: &[u8],
+    sig: &[u8],
+    public_key_pem: &str,
+    integrated_time: i64,
+) -> RekorBundle {
+    use p256::ecdsa::signature::hazmat::PrehashSigner;
+
+    let hex: String = sha256.iter().map(|b| format!("{:02x}", b)).collect();
+    let body = serde_json::json!({
+        "apiVersion": "0.0.1",
+        "kind": "hashedrekord",
+        "spec": {
+            "data": { "hash": { "algorithm": "sha256", "value": hex } },
+            "signature": {
+                "content": STANDARD.encode(sig),
+                "publicKey": { "content": STANDARD.encode(public_key_pem) },
+            },
+        },
+    });
+    let payload = RekorPayload {
+        body: STANDARD.encode(body.to_string()),
+        integrated_time,
+        log_id: "c0d23d6ad406973f9559f3ba2d1ca01f84147d8ffc5b8445c224f98b9591801d".to_string(),
+        log_index: 42,
+    };
+    let set: p256::ecdsa::Signature = rekor
+        .sign_prehash(&Sha256::digest(serde_json::to_vec(&payload).unwrap()))
+        .unwrap();
+    RekorBundle {
+        signed_entry_timestamp: STANDARD.encode(set.to_der()),
+        payload,
+    }
+}
+
+#[test]
+fn test_verify_key_based() {
+    use p256::{
+        ecdsa::{signature::hazmat::PrehashSigner, SigningKey},
+        pkcs8::{EncodePublicKey, LineEnding},
+    };
+
+    let signer = SigningKey::from_slice(&[7u8; 32]).unwrap();
+    let signer_pem = signer
+        .verifying_key()
+        .to_public_key_pem(LineEnding::LF)
+        .unwrap();
+    let rekor = SigningKey::from_slice(&[9u8; 32]).unwrap();
+    let rekor_pem = rekor
+        .verifying_key()
+        .to_public_key_pem(LineEnding::LF)
+        .unwrap();
+
+    let artifact = b"#!/bin/sh\necho hello\n";
+    let digest = Sha256::digest(artifact);
+    let sig: p256::ecdsa::Signature = signer.sign_prehash(&digest).unwrap();
+    let sig = sig.to_der().as_bytes().to_vec();
+    let bundle = CosignBundle {
+        base64_signature: STANDARD.encode(&sig),
+        cert: None,
+        rekor_bundle: Some(test_rekor_bundle(
+            &rekor,
+            &digest,
+            &sig,
+            &signer_pem,
+            1_700_000_000,
+        )),
+    };
+    let bundle = CosignBundle::from_json(&serde_json::to_vec(&bundle).unwrap()).unwrap();
+
+    // without the log key, only the signature is verified
+    let verifier = CosignVerifier::with_public_key(&signer_pem).unwrap();
+    let v = verifier.verify(&digest, &bundle).unwrap();
+    assert_eq!(v.sha256, format!("{:x}", digest));
+    assert_eq!(v.log_index, None);
+    assert!(verifier
+        .clone()
+        .require_transparency_log(true)
+        .verify(&digest, &bundle)
+        .is_err());
+
+    let verifier = verifier.rekor_public_key(&rekor_pem).unwrap();
+    let v = verifier.verify(&digest, &bundle).unwrap();
+    assert_eq!(v.log_index, Some(42));
+    assert_eq!(v.integrated_time, Some(1_700_000_000));
+
+    // another artifact
+    let e = verifier
+        .verify(&Sha256::digest(b"tampered"), &bundle)
+        .unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidData);
+
+    // another signer
+    let other = SigningKey::from_slice(&[8u8; 32]).unwrap();
+    let other_pem = other
+        .verifying_key()
+        .to_public_key_pem(LineEnding::LF)
+        .unwrap();
+    assert!(CosignVerifier::with_public_key(&other_pem)
+        .unwrap()
+        .verify(&digest, &bundle)
+        .is_err());
+
+    // a tampered log entry
+    let mut tampered = bundle.clone();
+    tampered.rekor_bundle.as_mut().unwrap().payload.log_index = 43;
+    assert!(verifier.verify(&digest, &tampered).is_err());
+
+    // a log entry of another artifact, signed by the log
+    let mut other_entry = bundle.clone();
+    other_entry.rekor_bundle = Some(test_rekor_bundle(
+        &rekor,
+        &Sha256::digest(b"other"),
+        &sig,
+        &signer_pem,
+        1_700_000_000,
+    ));
+    assert!(verifier.verify(&digest, &other_entry).is_err());
+
+    // the signature and key recorded in the log
+    let from_log = CosignBundle::from_rekor_bundle(bundle.rekor_bundle.clone().unwrap()).unwrap();
+    assert_eq!(from_log.base64_signature, bundle.base64_signature);
+    assert_eq!(from_log.cert, None);
+    assert!(verifier.verify(&digest, &from_log).is_ok());
+
+    assert!(CosignVerifier::with_public_key("not a key").is_err());
+    assert!(CosignBundle::from_json(b"{}").is_err());
+}
+
+#[test]
+fn test_verify_keyless() {
+    use p256::{
+        ecdsa::{signature::hazmat::PrehashSigner, SigningKey},
+        pkcs8::{DecodePrivateKey, EncodePublicKey, LineEnding},
+    };
+    use rcgen::{BasicConstraints, CertificateParams, CustomExtension, DnType, IsCa, SanType};
+
+    let mut params = CertificateParams::new(vec![]);
+    params
+        .distinguished_name
+        .push(DnType::CommonName, "sigstore-intermediate");
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let ca = rcgen::Certificate::from_params(params).unwrap();
+    let ca_pem = ca.serialize_pem().unwrap();
+
+    let leaf = |email: &str, issuer: &str, not_after: Option<(i32, u8, u8)>| {
+        let mut params = CertificateParams::new(vec![]);
+        params.subject_alt_names = vec![SanType::Rfc822Name(email.to_string())];
+        params.custom_extensions = vec![CustomExtension::from_oid_content(
+            &[1, 3, 6, 1, 4, 1, 57264, 1, 1],
+            issuer.as_bytes().to_vec(),
+        )];
+        if let Some((y, m, d)) = not_after {
+            params.not_after = rcgen::date_time_ymd(y, m, d);
+        }
+        let cert = rcgen::Certificate::from_params(params).unwrap();
+        let pem = cert.serialize_pem_with_signer(&ca).unwrap();
+        let key = SigningKey::from_pkcs8_der(&cert.serialize_private_key_der()).unwrap();
+        (pem, key)
+    };
+
+    let rekor = SigningKey::from_slice(&[9u8; 32]).unwrap();
+    let rekor_pem = rekor
+        .verifying_key()
+        .to_public_key_pem(LineEnding::LF)
+        .unwrap();
+    let artifact = b"binary";
+    let digest = Sha256::digest(artifact);
+    let sign = |cert_pem: &str, key: &SigningKey| {
+        let sig: p256::ecdsa::Signature = key.sign_prehash(&digest).unwrap();
+        let sig = sig.to_der().as_bytes().to_vec();
+        CosignBundle {
+            base64_signature: STANDARD.encode(&sig),
+            cert: Some(STANDARD.encode(cert_pem)),
+            rekor_bundle: Some(test_rekor_bundle(
+                &rekor,
+                &digest,
+                &sig,
+                cert_pem,
+                1_700_000_000,
+            )),
+        }
+    };
+
+    let issuer = "https://token.actions.githubusercontent.com";
+    let verifier = CosignVerifier::keyless(&ca_pem, "ci@example.com", issuer)
+        .unwrap()
+        .rekor_public_key(&rekor_pem)
+        .unwrap();
+
+    let (pem, key) = leaf("ci@example.com", issuer, None);
+    let bundle = sign(&pem, &key);
+    let v = verifier.verify(&digest, &bundle).unwrap();
+    assert_eq!(v.identity.as_deref(), Some("ci@example.com"));
+    assert_eq!(v.issuer.as_deref(), Some(issuer));
+    assert_eq!(v.log_index, Some(42));
+
+    // keyless signatures require the log entry
+    let mut no_log = bundle.clone();
+    no_log.rekor_bundle = None;
+    assert!(verifier.verify(&digest, &no_log).is_err());
+    assert!(CosignVerifier::keyless(&ca_pem, "ci@example.com", issuer)
+        .unwrap()
+        .require_transparency_log(false)
+        .verify(&digest, &bundle)
+        .is_err());
+
+    // another identity, or another issuer
+    let (pem, key) = leaf("attacker@example.com", issuer, None);
+    assert!(verifier.verify(&digest, &sign(&pem, &key)).is_err());
+    let (pem, key) = leaf("ci@example.com", "https://accounts.example.com", None);
+    assert!(verifier.verify(&digest, &sign(&pem, &key)).is_err());
+
+    // expired before the log time
+    let (pem, key) = leaf("ci@example.com", issuer, Some((2000, 1, 1)));
+    assert!(verifier.verify(&digest, &sign(&pem, &key)).is_err());
+
+    // issued by another CA
+    let other_ca = rcgen::generate_simple_self_signed(vec!["localhost".to_string()])
+        .unwrap()
+        .serialize_pem()
+        .unwrap();
+    let other = CosignVerifier::keyless(&other_ca, "ci@example.com", issuer)
+        .unwrap()
+        .rekor_public_key(&rekor_pem)
+        .unwrap();
+    assert!(other.verify(&digest, &bundle).is_err());
+
+    // the certificate recorded in the log
+    let from_log = CosignBundle::from_rekor_bundle(bundle.rekor_bundle.clone().unwrap()).unwrap();
+    assert_eq!(from_log.cert, bundle.cert);
+    assert!(verifier.verify(&digest, &from_log).is_ok());
+}
+
+/// RUST_LOG=debug cargo test --all-features --lib -- sigstore::test_download_file_verified --exact --show-output
+#[tokio::test]
+async fn test_download_file_verified() {
+    use hyper::Method;
+    use p256::{
+        ecdsa::{signature::hazmat::PrehashSigner, SigningKey},
+        pkcs8::{EncodePublicKey, LineEnding},
+    };
+
+    let signer = SigningKey::from_slice(&[7u8; 32]).unwrap();
+    let signer_pem = signer
+        .verifying_key()
+        .to_public_key_pem(LineEnding::LF)
+        .unwrap();
+    let rekor = SigningKey::from_slice(&[9u8; 32]).unwrap();
+    let rekor_pem = rekor
+        .verifying_key()
+        .to_public_key_pem(LineEnding::LF)
+        .unwrap();
+
+    let body: Vec<u8> = (0..200_000).map(|i| (i % 251) as u8).collect();
+    let digest = Sha256::digest(&body);
+    let sig: p256::ecdsa::Signature = signer.sign_prehash(&digest).unwrap();
+    let sig = sig.to_der().as_bytes().to_vec();
+    let rekor_bundle = test_rekor_bundle(&rekor, &digest, &sig, &signer_pem, 1_700_000_000);
+    let bundle = CosignBundle {
+        base64_signature: STANDARD.encode(&sig),
+        cert: None,
+        rekor_bundle: Some(rekor_bundle.clone()),
+    };
+
+    let server = crate::testing::MockServer::start().await.unwrap();
+    server.stub(Method::GET, "/file.bin", 200, body.clone());
+    server.stub(Method::GET, "/tampered.bin", 200, b"tampered".to_vec());
+    server.stub(
+        Method::POST,
+        "/api/v1/index/retrieve",
+        200,
+        r#"["24296fb24b8ad77a"]"#,
+    );
+    server.stub(
+        Method::GET,
+        "/api/v1/log/entries/24296fb24b8ad77a",
+        200,
+        serde_json::json!({
+            "24296fb24b8ad77a": {
+                "body": rekor_bundle.payload.body,
+                "integratedTime": rekor_bundle.payload.integrated_time,
+                "logID": rekor_bundle.payload.log_id,
+                "logIndex": rekor_bundle.payload.log_index,
+                "verification": {
+                    "signedEntryTimestamp": rekor_bundle.signed_entry_timestamp,
+                },
+            },
+        })
+        .to_string(),
+    );
+
+    let verifier = CosignVerifier::with_public_key(&signer_pem)
+        .unwrap()
+        .rekor_public_key(&rekor_pem)
+        .unwrap()
+        .require_transparency_log(true);
+    let file_path = std::env::temp_dir().join("http-manager-test-download-verified.bin");
+    let file_path = file_path.to_str().unwrap();
+    let v = download_file_verified(
+        &format!("{}/file.bin", server.url()),
+        file_path,
+        &bundle,
+        &verifier,
+    )
+    .await
+    .unwrap();
+    assert_eq!(v.log_index, Some(42));
+    assert_eq!(std::fs::read(file_path).unwrap(), body);
+    assert!(verifier.verify_file(file_path, &bundle).await.is_ok());
+    std::fs::remove_file(file_path).unwrap();
+
+    let e = download_file_verified(
+        &format!("{}/tampered.bin", server.url()),
+        file_path,
+        &bundle,
+        &verifier,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidData);
+    assert!(!Path::new(file_path).exists());
+    assert!(!Path::new(&format!("{}.unverified", file_path)).exists());
+
+    // verified against the transparency log entry, without a bundle
+    let manager = Manager::new().unwrap();
+    let uuids = manager
+        .find_rekor_entries(&server.url(), &digest)
+        .await
+        .unwrap();
+    assert_eq!(uuids, vec!["24296fb24b8ad77a".to_string()]);
+    let from_log = manager
+        .fetch_rekor_entry(&server.url(), &uuids[0])
+        .await
+        .unwrap();
+    assert_eq!(from_log, bundle);
+    assert!(verifier.verify(&digest, &from_log).is_ok());
+    server
+        .assert_received(Method::POST, "/api/v1/index/retrieve")
+        .once();
+}
@@ -0,0 +1,409 @@
+//! AWS Signature Version 4 request signing (e.g., S3 or API Gateway with
+//! IAM authorization), see "ManagerBuilder::sigv4_signer".
+//! ref. https://docs.aws.amazon.com/IAM/latest/UserGuide/create-signed-request.html
+
+use std::{
+    fmt,
+    io::{self, Error, ErrorKind},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use hmac::{Hmac, Mac};
+use hyper::{
+    header::{HeaderName, HeaderValue, AUTHORIZATION, HOST},
+    Body, HeaderMap, Method, Request, Uri,
+};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use sha2::{Digest, Sha256};
+
+use crate::signer::{self, RequestSigner};
+
+const ALGORITHM: &str = "AWS4-HMAC-SHA256";
+
+/// Payload hash of bodies that are not buffered to be hashed (streaming
+/// bodies), which only S3 accepts.
+pub const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+
+const AMZ_DATE: &str = "x-amz-date";
+const AMZ_CONTENT_SHA256: &str = "x-amz-content-sha256";
+const AMZ_SECURITY_TOKEN: &str = "x-amz-security-token";
+
+/// Everything but the unreserved characters of the SigV4 "UriEncode".
+const URI_ENCODE: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Signs requests with the credentials of the region and service. Also
+/// usable as a "RequestSigner".
+#[derive(Clone)]
+pub struct SigV4Signer {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+    region: String,
+    service: String,
+}
+
+impl fmt::Debug for SigV4Signer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SigV4Signer")
+            .field("access_key_id", &self.access_key_id)
+            .field("region", &self.region)
+            .field("service", &self.service)
+            .finish()
+    }
+}
+
+impl SigV4Signer {
+    /// Creates a signer for the service in the region
+    /// (e.g., "us-east-1" and "s3", or "execute-api").
+    pub fn new(access_key_id: &str, secret_access_key: &str, region: &str, service: &str) -> Self {
+        Self {
+            access_key_id: access_key_id.to_string(),
+            secret_access_key: secret_access_key.to_string(),
+            session_token: None,
+            region: region.to_string(),
+            service: service.to_string(),
+        }
+    }
+
+    /// Sets the session token of temporary credentials, sent as
+    /// "X-Amz-Security-Token".
+    pub fn session_token(mut self, token: &str) -> Self {
+        self.session_token = Some(token.to_string());
+        self
+    }
+
+    /// Signs the request, hashing the body if its size is known (in-memory
+    /// bodies). Streaming bodies are signed with "UNSIGNED-PAYLOAD".
+    pub async fn sign(&self, req: Request<Body>) -> io::Result<Request<Body>> {
+        signer::sign_request(self, req).await
+    }
+
+    /// Signs the request whose body has the hex-encoded SHA-256 (or
+    /// "UNSIGNED-PAYLOAD"), as sent at the Unix time (in seconds).
+    pub fn sign_at<B>(
+        &self,
+        req: &mut Request<B>,
+        payload_hash: &str,
+        time: u64,
+    ) -> io::Result<()> {
+        let (method, uri) = (req.method().clone(), req.uri().clone());
+        self.sign_headers(&method, &uri, req.headers_mut(), payload_hash, time)
+    }
+
+    fn sign_headers(
+        &self,
+        method: &Method,
+        uri: &Uri,
+        headers: &mut HeaderMap,
+        payload_hash: &str,
+        time: u64,
+    ) -> io::Result<()> {
+        let (date, datetime) = amz_date(time);
+        headers.insert(AMZ_DATE, header_value(&datetime)?);
+        // S3 requires the payload hash header, other services ignore it
+        if self.service == "s3" {
+            headers.insert(AMZ_CONTENT_SHA256, header_value(payload_hash)?);
+        }
+        if let Some(t) = &self.session_token {
+            let mut v = header_value(t)?;
+            v.set_sensitive(true);
+            headers.insert(AMZ_SECURITY_TOKEN, v);
+        }
+
+        let (canonical_request, signed_headers) =
+            self.canonical_request(method, uri, headers, payload_hash)?;
+        let scope = format!("{}/{}/{}/aws4_request", date, self.region, self.service);
+        let string_to_sign = format!(
+            "{}\n{}\n{}\n{}",
+            ALGORITHM,
+            datetime,
+            scope,
+            hex(&Sha256::digest(canonical_request.as_bytes()))
+        );
+
+        let k = hmac(
+            format!("AWS4{}", self.secret_access_key).as_bytes(),
+            date.as_bytes(),
+        );
+        let k = hmac(&k, self.region.as_bytes());
+        let k = hmac(&k, self.service.as_bytes());
+        let k = hmac(&k, b"aws4_request");
+        let signature = hex(&hmac(&k, string_to_sign.as_bytes()));
+
+        let mut v = header_value(&format!(
+            "{} Credential={}/{}, SignedHeaders={}, Signature={}",
+            ALGORITHM, self.access_key_id, scope, signed_headers, signature
+        ))?;
+        v.set_sensitive(true);
+        headers.insert(AUTHORIZATION, v);
+        Ok(())
+    }
+
+    /// Returns the canonical request and its signed header names.
+    fn canonical_request(
+        &self,
+        method: &Method,
+        uri: &Uri,
+        req_headers: &HeaderMap,
+        payload_hash: &str,
+    ) -> io::Result<(String, String)> {
+        // the path as sent is URI-encoded once, and S3 signs it as is
+        let path = match uri.path() {
+            "" => "/",
+            p => p,
+        };
+        let path = if self.service == "s3" {
+            path.to_string()
+        } else {
+            path.split('/')
+                .map(|s| utf8_percent_encode(s, URI_ENCODE).to_string())
+                .collect::<Vec<_>>()
+                .join("/")
+        };
+
+        let mut query: Vec<(String, String)> = uri
+            .query()
+            .unwrap_or("")
+            .split('&')
+            .filter(|p| !p.is_empty())
+            .map(|p| {
+                let (k, v) = p.split_once('=').unwrap_or((p, ""));
+                (uri_encode(k), uri_encode(v))
+            })
+            .collect();
+        query.sort();
+        let query: Vec<String> = query.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+
+        // "host", "content-type", "content-md5", and "x-amz-*"
+        let mut headers: Vec<(String, String)> = Vec::new();
+        if !req_headers.contains_key(HOST) {
+            let host = uri.authority().map(|a| a.as_str()).ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    "request URI without a host to sign",
+                )
+            })?;
+            let host = host.rsplit('@').next().unwrap_or(host);
+            headers.push(("host".to_string(), host.to_ascii_lowercase()));
+        }
+        for name in req_headers.keys() {
+            if !is_signed_header(name) {
+                continue;
+            }
+            let values: Vec<String> = req_headers
+                .get_all(name)
+                .iter()
+                .map(|v| {
+                    String::from_utf8_lossy(v.as_bytes())
+                        .split_whitespace()
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                })
+                .collect();
+            headers.push((name.as_str().to_string(), values.join(",")));
+        }
+        headers.sort();
+        let signed_headers = headers
+            .iter()
+            .map(|(k, _)| k.as_str())
+            .collect::<Vec<_>>()
+            .join(";");
+        let canonical_headers: String = headers
+            .iter()
+            .map(|(k, v)| format!("{}:{}\n", k, v))
+            .collect();
+
+        let canonical_request = format!(
+            "{}\n{}\n{}\n{}\n{}\n{}",
+            method,
+            path,
+            query.join("&"),
+            canonical_headers,
+            signed_headers,
+            payload_hash
+        );
+        Ok((canonical_request, signed_headers))
+    }
+}
+
+impl RequestSigner for SigV4Signer {
+    /// Hashes the body, or signs streaming bodies as "UNSIGNED-PAYLOAD".
+    fn sign(
+        &self,
+        method: &Method,
+        uri: &Uri,
+        headers: &mut HeaderMap,
+        body: Option<&[u8]>,
+    ) -> io::Result<()> {
+        let payload_hash = match body {
+            Some(b) => hex(&Sha256::digest(b)),
+            None => UNSIGNED_PAYLOAD.to_string(),
+        };
+        self.sign_headers(method, uri, headers, &payload_hash, unix_now())
+    }
+}
+
+fn is_signed_header(name: &HeaderName) -> bool {
+    let name = name.as_str();
+    name == "host" || name == "content-type" || name == "content-md5" || name.starts_with("x-amz-")
+}
+
+/// Decodes a query component as sent, and encodes it as SigV4 does.
+fn uri_encode(s: &str) -> String {
+    let decoded = percent_decode_str(s).decode_utf8_lossy();
+    utf8_percent_encode(&decoded, URI_ENCODE).to_string()
+}
+
+fn header_value(v: &str) -> io::Result<HeaderValue> {
+    HeaderValue::from_str(v).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid SigV4 header value {}", e),
+        )
+    })
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex(b: &[u8]) -> String {
+    b.iter().map(|x| format!("{:02x}", x)).collect()
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Returns the "YYYYMMDD" date and "YYYYMMDD'T'HHMMSS'Z'" time in UTC.
+fn amz_date(time: u64) -> (String, String) {
+    let days = (time / 86400) as i64;
+    let secs = time % 86400;
+
+    // civil date from days since 1970-01-01
+    // ref. https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    let date = format!("{:04}{:02}{:02}", year, month, day);
+    let datetime = format!(
+        "{}T{:02}{:02}{:02}Z",
+        date,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    );
+    (date, datetime)
+}
+
+/// RUST_LOG=debug cargo test --all-features --lib -- sigv4::test_sigv4 --exact --show-output
+#[test]
+fn test_sigv4() {
+    assert_eq!(
+        amz_date(1440938160),
+        ("20150830".to_string(), "20150830T123600Z".to_string())
+    );
+    assert_eq!(amz_date(951782400).1, "20000229T000000Z");
+
+    // ref. https://github.com/awslabs/aws-c-auth/tree/main/tests/aws-signing-test-suite/v4
+    let signer = SigV4Signer::new(
+        "AKIDEXAMPLE",
+        "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+        "us-east-1",
+        "service",
+    );
+    let empty_hash = hex(&Sha256::digest(b""));
+
+    // "get-vanilla"
+    let mut req = Request::get("https://example.amazonaws.com/")
+        .body(())
+        .unwrap();
+    signer.sign_at(&mut req, &empty_hash, 1440938160).unwrap();
+    assert_eq!(
+        req.headers()[AUTHORIZATION],
+        "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, SignedHeaders=host;x-amz-date, Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31"
+    );
+    assert_eq!(req.headers()[AMZ_DATE], "20150830T123600Z");
+    assert!(req.headers().get(AMZ_CONTENT_SHA256).is_none());
+
+    // "get-vanilla-query-order-key-case"
+    let mut req = Request::get("https://example.amazonaws.com/?Param2=value2&Param1=value1")
+        .body(())
+        .unwrap();
+    signer.sign_at(&mut req, &empty_hash, 1440938160).unwrap();
+    assert!(req.headers()[AUTHORIZATION]
+        .to_str()
+        .unwrap()
+        .ends_with("Signature=b97d918cfa904a5beff61c982a1b6f458b799221646efd99d3219ec94cdf2500"));
+
+    // S3 sends the payload hash, and the session token is signed
+    let signer = SigV4Signer::new("AKIDEXAMPLE", "secret", "us-west-2", "s3").session_token("tok");
+    let mut req = Request::put("https://bucket.s3.amazonaws.com/a%20b.txt")
+        .header("content-type", "text/plain")
+        .header("user-agent", "not signed")
+        .body(())
+        .unwrap();
+    signer
+        .sign_at(&mut req, UNSIGNED_PAYLOAD, 1440938160)
+        .unwrap();
+    assert_eq!(req.headers()[AMZ_CONTENT_SHA256], UNSIGNED_PAYLOAD);
+    assert_eq!(req.headers()[AMZ_SECURITY_TOKEN], "tok");
+    let auth = req.headers()[AUTHORIZATION].to_str().unwrap().to_string();
+    assert!(auth.contains(
+        "SignedHeaders=content-type;host;x-amz-content-sha256;x-amz-date;x-amz-security-token,"
+    ));
+    assert!(!format!("{:?}", signer).contains("secret"));
+}
+
+/// RUST_LOG=debug cargo test --all-features --lib -- sigv4::test_sigv4_manager --exact --show-output
+#[tokio::test]
+async fn test_sigv4_manager() {
+    use hyper::Method;
+
+    let server = crate::testing::MockServer::start().await.unwrap();
+    server.stub(Method::POST, "/prod/items", 200, "ok");
+
+    let signer = SigV4Signer::new("AKIDEXAMPLE", "secret", "us-east-1", "execute-api");
+    let manager = crate::Manager::builder()
+        .sigv4_signer(signer.clone())
+        .build()
+        .unwrap();
+    let req = crate::create_json_post(server.url(), "/prod/items", "{\"a\":1}").unwrap();
+    assert_eq!(manager.read_bytes(req, true).await.unwrap(), "ok");
+
+    let received = server.assert_received(Method::POST, "/prod/items").once();
+    let r = &received.requests()[0];
+    assert_eq!(r.body, "{\"a\":1}");
+    let auth = r.headers[AUTHORIZATION].to_str().unwrap();
+    assert!(auth.starts_with("AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/"));
+    assert!(auth.contains("SignedHeaders=content-type;host;x-amz-date,"));
+
+    // the same signature for the same request and time
+    let time = r.headers[AMZ_DATE].to_str().unwrap();
+    let now = unix_now();
+    let time = (now - 60..=now).find(|t| amz_date(*t).1 == time).unwrap();
+    let mut expected = Request::post(format!("{}/prod/items", server.url()))
+        .header("content-type", "application/json")
+        .body(())
+        .unwrap();
+    signer
+        .sign_at(&mut expected, &hex(&Sha256::digest(b"{\"a\":1}")), time)
+        .unwrap();
+    assert_eq!(expected.headers()[AUTHORIZATION], auth);
+}
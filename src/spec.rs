@@ -0,0 +1,152 @@
+//! Serializable request specifications, for storing requests in job
+//! queues or config files and executing them later.
+
+use std::{
+    collections::BTreeMap,
+    io::{self, Error, ErrorKind},
+    time::Duration,
+};
+
+use hyper::{body::Bytes, Body, Method, Request, Response};
+use serde::{Deserialize, Serialize};
+
+use crate::{config::optional_duration, Manager};
+
+/// Declarative HTTP request, e.g., in YAML:
+///
+/// ```yaml
+/// method: POST
+/// url: http://localhost:9650
+/// path: /ext/info
+/// headers:
+///   content-type: application/json
+/// body: '{"jsonrpc":"2.0","id":1,"method":"info.getNodeID"}'
+/// timeout: 10s
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RequestSpec {
+    /// HTTP method (default "GET").
+    #[serde(default = "default_method")]
+    pub method: String,
+    /// Base URL.
+    pub url: String,
+    /// Path joined to the base URL (see "crate::join_uri").
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub path: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    /// Overrides the manager timeout for this request.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "optional_duration"
+    )]
+    pub timeout: Option<Duration>,
+}
+
+fn default_method() -> String {
+    "GET".to_string()
+}
+
+impl RequestSpec {
+    /// Creates a GET spec with no header and no body.
+    pub fn new(url: &str, path: &str) -> Self {
+        Self {
+            method: default_method(),
+            url: url.to_string(),
+            path: path.to_string(),
+            headers: BTreeMap::new(),
+            body: None,
+            timeout: None,
+        }
+    }
+
+    /// Builds the hyper request.
+    pub fn to_request(&self) -> io::Result<Request<Body>> {
+        let method =
+            Method::from_bytes(self.method.to_ascii_uppercase().as_bytes()).map_err(|e| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("invalid method '{}' {}", self.method, e),
+                )
+            })?;
+        let uri = crate::join_uri(&self.url, &self.path)?;
+
+        let mut builder = Request::builder().method(method).uri(uri.as_str());
+        for (k, v) in self.headers.iter() {
+            builder = builder.header(k.as_str(), v.as_str());
+        }
+        let body = match &self.body {
+            Some(b) => Body::from(b.clone()),
+            None => Body::empty(),
+        };
+        builder.body(body).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("failed to create request {}", e),
+            )
+        })
+    }
+}
+
+impl Manager {
+    /// Executes the spec and returns the response with its whole body.
+    /// Non-2xx responses are returned as is, for the caller to inspect.
+    pub async fn execute(&self, spec: &RequestSpec) -> io::Result<Response<Bytes>> {
+        let req = spec.to_request()?;
+        let timeout_dur = spec.timeout.unwrap_or_else(|| self.timeout_for(req.uri()));
+
+        self.fetch(req, timeout_dur).await
+    }
+}
+
+/// RUST_LOG=debug cargo test --lib -- spec::test_request_spec --exact --show-output
+#[tokio::test]
+async fn test_request_spec() {
+    let server = crate::testing::MockServer::start().await.unwrap();
+    server.register(
+        crate::testing::Stub::new(Method::POST, "/ext/info")
+            .with_header("x-tenant", "a")
+            .respond(200, "ok"),
+    );
+
+    let yaml = format!(
+        r#"
+method: post
+url: {}
+path: /ext/info
+headers:
+  x-tenant: a
+body: '{{"id":1}}'
+timeout: 5s
+"#,
+        server.url()
+    );
+    let spec: RequestSpec = serde_yaml::from_str(&yaml).unwrap();
+    assert_eq!(spec.timeout, Some(Duration::from_secs(5)));
+
+    let manager = Manager::new().unwrap();
+    let resp = manager.execute(&spec).await.unwrap();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.body(), "ok");
+    server
+        .assert_received(Method::POST, "/ext/info")
+        .with_json_body(&serde_json::json!({"id": 1}))
+        .once();
+
+    let resp = manager
+        .execute(&RequestSpec::new(&server.url(), "/missing"))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 404);
+
+    let mut spec = RequestSpec::new(&server.url(), "/");
+    spec.method = "NOT A METHOD".to_string();
+    assert!(manager.execute(&spec).await.is_err());
+
+    let encoded = serde_yaml::to_string(&RequestSpec::new("http://a", "/b")).unwrap();
+    assert_eq!(encoded, "method: GET\nurl: http://a\npath: /b\n");
+}
@@ -0,0 +1,484 @@
+//! Server-Sent Events ("text/event-stream") subscriptions, parsed as the
+//! body arrives and reconnected with "Last-Event-ID" when the connection
+//! drops.
+//! ref. <https://html.spec.whatwg.org/multipage/server-sent-events.html>
+
+use std::{
+    collections::VecDeque,
+    io::{self, ErrorKind},
+    mem,
+    time::Duration,
+};
+
+use futures_util::{stream, Stream};
+use hyper::{
+    body::HttpBody,
+    header::{HeaderValue, ACCEPT, CACHE_CONTROL, CONTENT_TYPE},
+    Body, Method, Request, StatusCode,
+};
+
+use crate::{
+    error::Error,
+    logging::{wire_debug, wire_warn},
+    retry::{self, RetryPolicy},
+    traffic, Manager,
+};
+
+pub const LAST_EVENT_ID: &str = "last-event-id";
+
+/// Event dispatched by the server (a block of fields ending with a blank
+/// line).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    /// "event" field, or "message" if not set.
+    pub event: String,
+    /// "data" fields, joined with "\n".
+    pub data: String,
+    /// Last event ID received so far (sticky across events), if any.
+    pub id: Option<String>,
+}
+
+/// Incremental "text/event-stream" parser: lines may be split across
+/// chunks, and end with "\r\n", "\n", or "\r".
+#[derive(Debug, Default)]
+struct Parser {
+    line: Vec<u8>,
+    /// Whether the last byte was "\r", so that a following "\n" (possibly
+    /// in the next chunk) ends the same line.
+    after_cr: bool,
+    /// Whether the first line (which may start with a BOM) was read.
+    started: bool,
+    event: String,
+    data: String,
+    last_event_id: String,
+    /// Reconnection delay set by the server.
+    retry: Option<Duration>,
+}
+
+impl Parser {
+    /// Returns the events completed by the chunk.
+    fn feed(&mut self, chunk: &[u8]) -> Vec<Event> {
+        let mut events = Vec::new();
+        for &b in chunk {
+            match b {
+                b'\n' if self.after_cr => self.after_cr = false,
+                b'\r' | b'\n' => {
+                    self.after_cr = b == b'\r';
+                    let line = mem::take(&mut self.line);
+                    if let Some(ev) = self.process_line(&line) {
+                        events.push(ev);
+                    }
+                }
+                _ => {
+                    self.after_cr = false;
+                    self.line.push(b);
+                }
+            }
+        }
+        events
+    }
+
+    fn process_line(&mut self, line: &[u8]) -> Option<Event> {
+        let mut line = line;
+        if !self.started {
+            self.started = true;
+            line = line.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(line);
+        }
+        if line.is_empty() {
+            return self.dispatch();
+        }
+        let line = String::from_utf8_lossy(line);
+        if line.starts_with(':') {
+            // comment (e.g., keep-alive)
+            return None;
+        }
+        let (field, value) = match line.find(':') {
+            Some(i) => {
+                let v = &line[i + 1..];
+                (&line[..i], v.strip_prefix(' ').unwrap_or(v))
+            }
+            None => (line.as_ref(), ""),
+        };
+        match field {
+            "event" => self.event = value.to_string(),
+            "data" => {
+                self.data.push_str(value);
+                self.data.push('\n');
+            }
+            "id" if !value.contains('\0') => self.last_event_id = value.to_string(),
+            "retry" if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) => {
+                if let Ok(ms) = value.parse() {
+                    self.retry = Some(Duration::from_millis(ms));
+                }
+            }
+            _ => {}
+        }
+        None
+    }
+
+    /// Returns the buffered event, if it has any data.
+    fn dispatch(&mut self) -> Option<Event> {
+        let event = mem::take(&mut self.event);
+        if self.data.is_empty() {
+            return None;
+        }
+        let mut data = mem::take(&mut self.data);
+        data.pop();
+        Some(Event {
+            event: if event.is_empty() {
+                "message".to_string()
+            } else {
+                event
+            },
+            data,
+            id: if self.last_event_id.is_empty() {
+                None
+            } else {
+                Some(self.last_event_id.clone())
+            },
+        })
+    }
+
+    /// Discards the incomplete event of a dropped connection, keeping the
+    /// last event ID and the reconnection delay.
+    fn reset(&mut self) {
+        self.line.clear();
+        self.after_cr = false;
+        self.started = false;
+        self.event.clear();
+        self.data.clear();
+    }
+}
+
+struct Subscription {
+    manager: Manager,
+    url: String,
+    path: String,
+    /// Host of the last connection, for the traffic counters.
+    host: String,
+    policy: RetryPolicy,
+    parser: Parser,
+    body: Option<Body>,
+    pending: VecDeque<Event>,
+    /// Consecutive failed connection attempts.
+    failures: usize,
+    /// Delay before the next connection attempt, if reconnecting.
+    wait: Option<Duration>,
+    done: bool,
+}
+
+impl Manager {
+    /// Subscribes to the event stream at the path joined to the URL, with
+    /// the retry policy of the host (see "Manager::retry_policy_for"). See
+    /// "get_sse_with_retry".
+    pub fn get_sse(
+        &self,
+        url: &str,
+        path: &str,
+    ) -> impl Stream<Item = io::Result<Event>> + Send + 'static {
+        let policy = match crate::join_uri(url, path) {
+            Ok(u) => self
+                .retry_policy_for(&u.as_str().parse().unwrap_or_default())
+                .clone(),
+            Err(_) => RetryPolicy::default(),
+        };
+        self.get_sse_with_retry(url, path, None, &policy)
+    }
+
+    /// Subscribes to the event stream, resuming after "last_event_id" if
+    /// any. The manager timeout applies to each connection until the
+    /// response headers arrive, after which events are yielded as they are
+    /// parsed. When the server closes the stream (or the connection drops),
+    /// the stream reconnects after the "retry" delay set by the server (or
+    /// the policy backoff), sending the last event ID received. Failed
+    /// connections (errors and retryable status codes) are retried up to
+    /// the policy attempts, and the last error is yielded before the stream
+    /// ends. The stream also ends when the server responds with 204 (No
+    /// Content), which asks clients not to reconnect.
+    pub fn get_sse_with_retry(
+        &self,
+        url: &str,
+        path: &str,
+        last_event_id: Option<&str>,
+        policy: &RetryPolicy,
+    ) -> impl Stream<Item = io::Result<Event>> + Send + 'static {
+        let s = Subscription {
+            manager: self.clone(),
+            url: url.to_string(),
+            path: path.to_string(),
+            host: String::new(),
+            policy: policy.clone(),
+            parser: Parser {
+                last_event_id: last_event_id.unwrap_or_default().to_string(),
+                ..Default::default()
+            },
+            body: None,
+            pending: VecDeque::new(),
+            failures: 0,
+            wait: None,
+            done: false,
+        };
+        stream::unfold(s, |mut s| async move {
+            loop {
+                if let Some(ev) = s.pending.pop_front() {
+                    return Some((Ok(ev), s));
+                }
+                if s.done {
+                    return None;
+                }
+
+                let body = match s.body.as_mut() {
+                    Some(b) => b,
+                    None => {
+                        if let Some(d) = s.wait.take() {
+                            tokio::time::sleep(d).await;
+                        }
+                        match s.connect().await {
+                            Ok(Some(body)) => {
+                                s.failures = 0;
+                                s.body.insert(body)
+                            }
+                            Ok(None) => return None,
+                            Err(e) => {
+                                s.failures += 1;
+                                if !s.is_retryable(&e) || s.failures >= s.policy.max_attempts {
+                                    s.done = true;
+                                    return Some((Err(e), s));
+                                }
+                                wire_warn!("failed to connect to event stream {}, retrying", e);
+                                s.wait = Some(s.reconnect_delay());
+                                continue;
+                            }
+                        }
+                    }
+                };
+                match body.data().await {
+                    Some(Ok(chunk)) => {
+                        s.manager
+                            .traffic()
+                            .record_received(&s.host, chunk.len() as u64);
+                        let events = s.parser.feed(&chunk);
+                        s.pending.extend(events);
+                    }
+                    Some(Err(e)) => {
+                        wire_warn!("event stream failed {}, reconnecting", e);
+                        s.disconnected();
+                    }
+                    None => {
+                        wire_debug!("event stream closed by the server, reconnecting");
+                        s.disconnected();
+                    }
+                }
+            }
+        })
+    }
+}
+
+impl Subscription {
+    /// Returns the event stream body, or None if the server asks not to
+    /// reconnect.
+    async fn connect(&mut self) -> io::Result<Option<Body>> {
+        let u = crate::join_uri(self.url.as_str(), &self.path)?;
+        let mut builder = Request::builder()
+            .method(Method::GET)
+            .uri(u.as_str())
+            .header(ACCEPT, "text/event-stream")
+            .header(CACHE_CONTROL, "no-cache");
+        if !self.parser.last_event_id.is_empty() {
+            let v = HeaderValue::from_str(&self.parser.last_event_id).map_err(|e| {
+                io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("invalid last event ID {}", e),
+                )
+            })?;
+            builder = builder.header(LAST_EVENT_ID, v);
+        }
+        let req = builder.body(Body::empty()).map_err(|e| {
+            io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("failed to create event stream request {}", e),
+            )
+        })?;
+
+        self.host = traffic::host_key(req.uri());
+        let timeout_dur = self.manager.timeout_for(req.uri());
+        let resp = self.manager.send_with_timeout(req, timeout_dur).await?;
+        match resp.status() {
+            StatusCode::OK => {}
+            StatusCode::NO_CONTENT => {
+                wire_debug!("{} closed the event stream", crate::redact::url(u.as_str()));
+                return Ok(None);
+            }
+            status => {
+                let body = crate::read_body_bytes(resp.into_body(), timeout_dur, None)
+                    .await
+                    .unwrap_or_default();
+                return Err(Error::Status(status, body).into());
+            }
+        }
+        let content_type = resp
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.split(';').next())
+            .unwrap_or("")
+            .trim();
+        if !content_type.eq_ignore_ascii_case("text/event-stream") {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "{} returned content type '{}', expected 'text/event-stream'",
+                    crate::redact::url(u.as_str()),
+                    content_type
+                ),
+            ));
+        }
+        wire_debug!(
+            "subscribed to event stream {}",
+            crate::redact::url(u.as_str())
+        );
+        Ok(Some(resp.into_body()))
+    }
+
+    fn is_retryable(&self, e: &io::Error) -> bool {
+        match e.get_ref().and_then(|inner| inner.downcast_ref::<Error>()) {
+            Some(Error::Status(status, _)) => self.policy.is_retryable_status(*status),
+            _ => retry::is_retryable_error(e),
+        }
+    }
+
+    /// Returns the server's reconnection delay, backed off by the policy
+    /// after failed attempts.
+    fn reconnect_delay(&self) -> Duration {
+        let backoff = self.policy.delay(self.failures.max(1));
+        match self.parser.retry {
+            Some(d) if self.failures == 0 => d,
+            Some(d) => d.max(backoff),
+            None => backoff,
+        }
+    }
+
+    fn disconnected(&mut self) {
+        self.body = None;
+        self.parser.reset();
+        self.wait = Some(self.reconnect_delay());
+    }
+}
+
+/// RUST_LOG=debug cargo test --lib -- sse::test_parser --exact --show-output
+#[test]
+fn test_parser() {
+    let mut p = Parser::default();
+    let event = |event: &str, data: &str, id: Option<&str>| Event {
+        event: event.to_string(),
+        data: data.to_string(),
+        id: id.map(|s| s.to_string()),
+    };
+
+    // lines split across chunks, with mixed line endings
+    assert!(p.feed(b"\xEF\xBB\xBFdata: hel").is_empty());
+    assert!(p.feed(b"lo\r").is_empty());
+    assert_eq!(p.feed(b"\n\r\n"), vec![event("message", "hello", None)]);
+
+    assert_eq!(
+        p.feed(b": keep-alive\nevent: update\ndata:a\ndata:  b\nid: 7\nretry: 1500\n\n"),
+        vec![event("update", "a\n b", Some("7"))]
+    );
+    assert_eq!(p.retry, Some(Duration::from_millis(1500)));
+
+    // no data, invalid retry, and an empty data field
+    assert!(p.feed(b"event: ping\nretry: 1s\n\n").is_empty());
+    assert_eq!(p.retry, Some(Duration::from_millis(1500)));
+    assert_eq!(p.feed(b"data\r\r"), vec![event("message", "", Some("7"))]);
+
+    // the incomplete event is discarded on reconnects, the ID is kept
+    assert!(p.feed(b"data: lost\n").is_empty());
+    p.reset();
+    assert_eq!(
+        p.feed(b"data: x\n\n"),
+        vec![event("message", "x", Some("7"))]
+    );
+}
+
+/// RUST_LOG=debug cargo test --lib -- sse::test_get_sse --exact --show-output
+#[tokio::test]
+async fn test_get_sse() {
+    use futures_util::StreamExt;
+
+    use crate::testing::Stub;
+
+    let server = crate::testing::MockServer::start().await.unwrap();
+    let stub = |body: &'static str| {
+        Stub::new(Method::GET, "/events")
+            .with_header("accept", "text/event-stream")
+            .respond(200, body)
+            .respond_header("content-type", "text/event-stream; charset=utf-8")
+    };
+    server.register(stub("retry: 10\nid: 1\ndata: a\n\ndata: incomplete"));
+    server.register(stub("id: 2\nevent: b\ndata: b\n\n").with_header(LAST_EVENT_ID, "1"));
+    server.register(
+        Stub::new(Method::GET, "/events")
+            .with_header(LAST_EVENT_ID, "2")
+            .respond(204, ""),
+    );
+
+    let manager = Manager::new().unwrap();
+    let events: Vec<_> = manager
+        .get_sse(&server.url(), "/events")
+        .map(|ev| ev.unwrap())
+        .collect()
+        .await;
+    assert_eq!(
+        events,
+        vec![
+            Event {
+                event: "message".to_string(),
+                data: "a".to_string(),
+                id: Some("1".to_string()),
+            },
+            Event {
+                event: "b".to_string(),
+                data: "b".to_string(),
+                id: Some("2".to_string()),
+            },
+        ]
+    );
+    server.assert_received(Method::GET, "/events").times(3);
+
+    // retryable status codes until the attempts run out
+    let policy = RetryPolicy {
+        max_attempts: 2,
+        base_delay: Duration::from_millis(1),
+        ..Default::default()
+    };
+    server.stub(Method::GET, "/busy", 503, "busy");
+    let ret: Vec<_> = manager
+        .get_sse_with_retry(&server.url(), "/busy", Some("9"), &policy)
+        .collect()
+        .await;
+    assert_eq!(ret.len(), 1);
+    match Error::from(ret.into_iter().next().unwrap().unwrap_err()) {
+        Error::Status(status, body) => {
+            assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+            assert_eq!(body, "busy");
+        }
+        e => panic!("unexpected {:?}", e),
+    }
+    server
+        .assert_received(Method::GET, "/busy")
+        .with_header(LAST_EVENT_ID, "9")
+        .times(2);
+
+    // not an event stream
+    server.stub(Method::GET, "/json", 200, "{}");
+    let ret: Vec<_> = manager
+        .get_sse_with_retry(&server.url(), "/json", None, &policy)
+        .collect()
+        .await;
+    assert_eq!(ret.len(), 1);
+    assert_eq!(
+        ret.into_iter().next().unwrap().unwrap_err().kind(),
+        ErrorKind::InvalidData
+    );
+    server.assert_received(Method::GET, "/json").once();
+}
@@ -0,0 +1,265 @@
+//! Stalled-transfer detection, like curl's "--speed-limit"/"--speed-time":
+//! a body read fails as soon as the throughput stays below a minimum rate
+//! for a while, rather than only at the overall request timeout.
+
+use std::{
+    error, fmt,
+    io::{self, Error, ErrorKind},
+    time::Duration,
+};
+
+use bytes::BytesMut;
+use futures_util::{Stream, StreamExt, TryStreamExt};
+use hyper::{
+    body::{Bytes, HttpBody},
+    Body,
+};
+use tokio::time::{self, Instant};
+
+/// Minimum transfer rate of a response body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LowSpeedLimit {
+    /// Minimum average bytes per second over "time".
+    pub bytes_per_sec: u64,
+    /// How long the transfer may stay below "bytes_per_sec".
+    pub time: Duration,
+}
+
+impl LowSpeedLimit {
+    pub fn new(bytes_per_sec: u64, time: Duration) -> Self {
+        Self {
+            bytes_per_sec,
+            time,
+        }
+    }
+}
+
+/// Error of a transfer slower than its "LowSpeedLimit", returned as the
+/// inner error of a "TimedOut" "io::Error" (see "is_stalled").
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransferStalled {
+    /// Bytes received during the slow period.
+    pub bytes: u64,
+    pub elapsed: Duration,
+    /// Bytes of the body received so far.
+    pub total: u64,
+    pub limit: LowSpeedLimit,
+}
+
+impl fmt::Display for TransferStalled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "transfer stalled: {} bytes in {:?} is below {} bytes/sec ({} bytes received)",
+            self.bytes, self.elapsed, self.limit.bytes_per_sec, self.total
+        )
+    }
+}
+
+impl error::Error for TransferStalled {}
+
+/// Returns true if the error is a "TransferStalled".
+pub fn is_stalled(e: &io::Error) -> bool {
+    matches!(
+        crate::error::Error::typed(e),
+        Some(crate::error::Error::Stalled(_))
+    )
+}
+
+/// Measures the throughput of a body in windows of "LowSpeedLimit::time",
+/// failing at the end of the first window below the limit.
+#[derive(Debug, Clone)]
+pub struct SpeedMonitor {
+    limit: LowSpeedLimit,
+    window_start: Instant,
+    window_bytes: u64,
+    total: u64,
+}
+
+impl SpeedMonitor {
+    pub fn new(limit: LowSpeedLimit) -> Self {
+        Self {
+            limit,
+            window_start: Instant::now(),
+            window_bytes: 0,
+            total: 0,
+        }
+    }
+
+    /// Counts received bytes, failing if the current window ended below
+    /// the limit.
+    pub fn record(&mut self, n: usize) -> io::Result<()> {
+        self.window_bytes += n as u64;
+        self.total += n as u64;
+        self.check()
+    }
+
+    /// Bytes received so far.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    fn check(&mut self) -> io::Result<()> {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.window_start);
+        if elapsed < self.limit.time {
+            return Ok(());
+        }
+        let rate = self.window_bytes as f64 / elapsed.as_secs_f64();
+        if rate < self.limit.bytes_per_sec as f64 {
+            return Err(crate::error::Error::Stalled(TransferStalled {
+                bytes: self.window_bytes,
+                elapsed,
+                total: self.total,
+                limit: self.limit,
+            })
+            .into());
+        }
+        self.window_start = now;
+        self.window_bytes = 0;
+        Ok(())
+    }
+
+    /// Reads the next chunk of the body, failing once the transfer stalls
+    /// even if no data arrives at all.
+    pub async fn next_chunk(&mut self, body: &mut Body) -> io::Result<Option<Bytes>> {
+        let mut chunks = TryStreamExt::map_err(body, |e| {
+            Error::new(ErrorKind::Other, format!("failed to read response {}", e))
+        });
+        self.next_from(&mut chunks).await
+    }
+
+    /// Same as "next_chunk", but reads from any stream of chunks (e.g.,
+    /// "reqwest::Response::bytes_stream").
+    pub async fn next_from<S>(&mut self, chunks: &mut S) -> io::Result<Option<Bytes>>
+    where
+        S: Stream<Item = io::Result<Bytes>> + Unpin,
+    {
+        loop {
+            let deadline = self.window_start + self.limit.time;
+            match time::timeout_at(deadline, chunks.next()).await {
+                Ok(Some(Ok(chunk))) => {
+                    self.record(chunk.len())?;
+                    return Ok(Some(chunk));
+                }
+                Ok(Some(Err(e))) => return Err(e),
+                Ok(None) => return Ok(None),
+                // the window ended without data; fails unless the window
+                // was fast enough, in which case a new one starts
+                Err(_) => self.check()?,
+            }
+        }
+    }
+}
+
+/// Reads the whole body, failing once the transfer stalls.
+pub(crate) async fn collect(mut body: Body, limit: LowSpeedLimit) -> io::Result<Bytes> {
+    let mut monitor = SpeedMonitor::new(limit);
+    let mut buf =
+        BytesMut::with_capacity(HttpBody::size_hint(&body).lower().min(1024 * 1024) as usize);
+    while let Some(chunk) = monitor.next_chunk(&mut body).await? {
+        buf.extend_from_slice(&chunk);
+    }
+    Ok(buf.freeze())
+}
+
+/// RUST_LOG=debug cargo test --lib -- stall::test_speed_monitor --exact --show-output
+#[tokio::test]
+async fn test_speed_monitor() {
+    let limit = LowSpeedLimit::new(1000, Duration::from_millis(200));
+
+    // fast enough, across several windows
+    let (mut tx, body) = Body::channel();
+    tokio::spawn(async move {
+        for _ in 0..5 {
+            tx.send_data(Bytes::from(vec![b'a'; 100])).await.unwrap();
+            time::sleep(Duration::from_millis(50)).await;
+        }
+    });
+    assert_eq!(collect(body, limit).await.unwrap().len(), 500);
+
+    // a trickle below the limit
+    let (mut tx, mut body) = Body::channel();
+    tokio::spawn(async move {
+        for _ in 0..20 {
+            if tx.send_data(Bytes::from_static(b"a")).await.is_err() {
+                return;
+            }
+            time::sleep(Duration::from_millis(50)).await;
+        }
+    });
+    let mut monitor = SpeedMonitor::new(limit);
+    let e = loop {
+        match monitor.next_chunk(&mut body).await {
+            Ok(Some(_)) => {}
+            Ok(None) => panic!("unexpected end of body"),
+            Err(e) => break e,
+        }
+    };
+    assert_eq!(e.kind(), ErrorKind::TimedOut);
+    assert!(is_stalled(&e));
+    assert!(monitor.total() < 10);
+
+    // no data at all, with the sender kept open
+    let (_tx, body) = Body::channel();
+    let started = Instant::now();
+    let e = collect(body, limit).await.unwrap_err();
+    assert!(is_stalled(&e));
+    assert!(started.elapsed() < Duration::from_secs(1));
+    assert!(e.to_string().starts_with("transfer stalled: 0 bytes"));
+
+    assert!(!is_stalled(&Error::new(ErrorKind::TimedOut, "x")));
+}
+
+/// RUST_LOG=debug cargo test --lib -- stall::test_manager_low_speed_limit --exact --show-output
+#[tokio::test]
+async fn test_manager_low_speed_limit() {
+    use tokio::{io::AsyncWriteExt, net::TcpListener};
+
+    // sends the headers and a few bytes, then hangs
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        stream
+            .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 1000000\r\n\r\nhello")
+            .await
+            .unwrap();
+        time::sleep(Duration::from_secs(30)).await;
+    });
+
+    let manager = crate::Manager::builder()
+        .timeout(Duration::from_secs(20))
+        .low_speed_limit(LowSpeedLimit::new(1024, Duration::from_millis(300)))
+        .build()
+        .unwrap();
+    let started = Instant::now();
+    let req = crate::create_get(format!("http://{}", addr), "/").unwrap();
+    let e = manager.read_response(req).await.unwrap_err();
+    assert!(is_stalled(&e));
+    assert!(started.elapsed() < Duration::from_secs(5));
+
+    // slow but steady bodies outlast the timeout
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        stream
+            .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 10\r\n\r\n")
+            .await
+            .unwrap();
+        for _ in 0..10 {
+            stream.write_all(b"a").await.unwrap();
+            time::sleep(Duration::from_millis(100)).await;
+        }
+    });
+    let manager = crate::Manager::builder()
+        .timeout(Duration::from_millis(500))
+        .connect_timeout(Duration::from_millis(500))
+        .low_speed_limit(LowSpeedLimit::new(1, Duration::from_millis(300)))
+        .build()
+        .unwrap();
+    let req = crate::create_get(format!("http://{}", addr), "/").unwrap();
+    let resp = manager.read_response(req).await.unwrap();
+    assert_eq!(resp.body().as_ref(), b"aaaaaaaaaa");
+}
@@ -0,0 +1,245 @@
+//! "tail -f" for files served over HTTP (e.g., logs), using range requests.
+
+use std::{
+    io::{self, Error, ErrorKind},
+    time::Duration,
+};
+
+use futures_util::{stream, Stream};
+use hyper::{body::Bytes, StatusCode};
+
+use crate::{
+    logging::{wire_debug, wire_warn},
+    range, Manager,
+};
+
+struct Tail {
+    manager: Manager,
+    url: String,
+    path: String,
+    /// None until the end of the file is known.
+    offset: Option<u64>,
+    poll_interval: Duration,
+    /// Whether the last poll returned no data (or failed), so that the
+    /// next one waits for "poll_interval".
+    idle: bool,
+}
+
+impl Manager {
+    /// Follows the remote file from its current end, yielding the bytes
+    /// appended since, as "tail -f" does. See "tail_remote_from".
+    pub fn tail_remote(
+        &self,
+        url: &str,
+        path: &str,
+        poll_interval: Duration,
+    ) -> impl Stream<Item = io::Result<Bytes>> + Send + 'static {
+        self.tail(url, path, None, poll_interval)
+    }
+
+    /// Follows the remote file from the offset, polling with
+    /// "Range: bytes={offset}-" every "poll_interval" while there is no
+    /// new data. A file that shrinks below the offset is assumed to be
+    /// truncated (or rotated), and is read again from the start. Failed
+    /// polls yield an error and the stream keeps polling, so callers
+    /// decide when to give up.
+    pub fn tail_remote_from(
+        &self,
+        url: &str,
+        path: &str,
+        offset: u64,
+        poll_interval: Duration,
+    ) -> impl Stream<Item = io::Result<Bytes>> + Send + 'static {
+        self.tail(url, path, Some(offset), poll_interval)
+    }
+
+    fn tail(
+        &self,
+        url: &str,
+        path: &str,
+        offset: Option<u64>,
+        poll_interval: Duration,
+    ) -> impl Stream<Item = io::Result<Bytes>> + Send + 'static {
+        let t = Tail {
+            manager: self.clone(),
+            url: url.to_string(),
+            path: path.to_string(),
+            offset,
+            poll_interval,
+            idle: false,
+        };
+        stream::unfold(t, |mut t| async move {
+            loop {
+                if t.idle {
+                    tokio::time::sleep(t.poll_interval).await;
+                }
+                t.idle = true;
+
+                let offset = match t.offset {
+                    Some(o) => o,
+                    None => match t.end_offset().await {
+                        Ok(o) => {
+                            t.offset = Some(o);
+                            o
+                        }
+                        Err(e) => return Some((Err(e), t)),
+                    },
+                };
+                match t.poll(offset).await {
+                    Ok(Some(b)) => {
+                        t.idle = false;
+                        return Some((Ok(b), t));
+                    }
+                    Ok(None) => continue,
+                    Err(e) => return Some((Err(e), t)),
+                }
+            }
+        })
+    }
+}
+
+impl Tail {
+    /// Returns the current length of the file.
+    async fn end_offset(&self) -> io::Result<u64> {
+        let req = range::create_range_get(&self.url, &self.path, "0-0")?;
+        let resp = self.manager.read_response(req).await?;
+        let length = match resp.status() {
+            StatusCode::PARTIAL_CONTENT => range::single_part(&resp)?.0.total,
+            // an empty file
+            StatusCode::RANGE_NOT_SATISFIABLE => range::unsatisfied_length(resp.headers()),
+            StatusCode::OK => Some(resp.body().len() as u64),
+            s => return Err(self.unexpected(s)),
+        };
+        length.ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "{} did not report the length of the file",
+                    crate::redact::url(&self.url)
+                ),
+            )
+        })
+    }
+
+    /// Returns the bytes after the offset, if any, and advances the offset.
+    async fn poll(&mut self, offset: u64) -> io::Result<Option<Bytes>> {
+        let req = range::create_range_get(&self.url, &self.path, &format!("{}-", offset))?;
+        let resp = self.manager.read_response(req).await?;
+        let (start, data) = match resp.status() {
+            StatusCode::PARTIAL_CONTENT => {
+                let (r, data) = range::single_part(&resp)?;
+                if r.start != offset {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!(
+                            "requested bytes from {}, received {}-{}",
+                            offset, r.start, r.end
+                        ),
+                    ));
+                }
+                (offset, data)
+            }
+            StatusCode::RANGE_NOT_SATISFIABLE => match range::unsatisfied_length(resp.headers()) {
+                Some(len) if len < offset => {
+                    self.truncated(offset, len);
+                    return Ok(None);
+                }
+                // no new data
+                _ => return Ok(None),
+            },
+            // ranges not supported, so slice the whole file
+            StatusCode::OK => {
+                let body = resp.into_body();
+                let len = body.len() as u64;
+                if len < offset {
+                    self.truncated(offset, len);
+                    (0, body)
+                } else {
+                    (offset, body.slice(offset as usize..))
+                }
+            }
+            s => return Err(self.unexpected(s)),
+        };
+        self.offset = Some(start + data.len() as u64);
+        if data.is_empty() {
+            return Ok(None);
+        }
+        wire_debug!(
+            "tailed {} new bytes from {} at offset {}",
+            data.len(),
+            crate::redact::url(&self.url),
+            start
+        );
+        Ok(Some(data))
+    }
+
+    fn truncated(&mut self, offset: u64, len: u64) {
+        wire_warn!(
+            "{} shrank from {} to {} bytes, reading from the start",
+            crate::redact::url(&self.url),
+            offset,
+            len
+        );
+        self.offset = Some(0);
+    }
+
+    fn unexpected(&self, s: StatusCode) -> Error {
+        Error::new(
+            ErrorKind::Other,
+            format!(
+                "{} returned unexpected status code {} for a range request",
+                crate::redact::url(&self.url),
+                s
+            ),
+        )
+    }
+}
+
+/// RUST_LOG=debug cargo test --lib -- tail::test_tail_remote --exact --show-output
+#[tokio::test]
+async fn test_tail_remote() {
+    use futures_util::StreamExt;
+    use hyper::Method;
+
+    use crate::testing::Stub;
+
+    let server = crate::testing::MockServer::start().await.unwrap();
+    let stub = |range: &str, status: u16, body: &'static str, content_range: &str| {
+        Stub::new(Method::GET, "/log")
+            .with_header("range", range)
+            .respond(status, body)
+            .respond_header("content-range", content_range)
+    };
+    let manager = Manager::new().unwrap();
+    let poll = Duration::from_millis(10);
+
+    server.register(stub("bytes=0-", 206, "hello", "bytes 0-4/5"));
+    let mut s = Box::pin(manager.tail_remote_from(&server.url(), "/log", 0, poll));
+    assert_eq!(s.next().await.unwrap().unwrap(), "hello");
+
+    server.register(stub("bytes=5-", 206, " world", "bytes 5-10/11"));
+    assert_eq!(s.next().await.unwrap().unwrap(), " world");
+
+    // no new data
+    server.register(stub("bytes=11-", 416, "", "bytes */11"));
+    assert!(tokio::time::timeout(Duration::from_millis(100), s.next())
+        .await
+        .is_err());
+    assert!(server.received_count(Method::GET, "/log") > 3);
+
+    // truncated
+    server.register(stub("bytes=11-", 416, "", "bytes */3"));
+    server.register(stub("bytes=0-", 206, "new", "bytes 0-2/3"));
+    assert_eq!(s.next().await.unwrap().unwrap(), "new");
+
+    // from the current end
+    server.register(stub("bytes=0-0", 206, "n", "bytes 0-0/3"));
+    server.register(stub("bytes=3-", 206, "!", "bytes 3-3/4"));
+    let mut s = Box::pin(manager.tail_remote(&server.url(), "/log", poll));
+    assert_eq!(s.next().await.unwrap().unwrap(), "!");
+
+    // errors are yielded, and polling continues
+    let mut s = Box::pin(manager.tail_remote(&server.url(), "/missing", poll));
+    assert!(s.next().await.unwrap().is_err());
+    assert!(s.next().await.unwrap().is_err());
+}
@@ -0,0 +1,709 @@
+//! Local HTTP(S) servers for tests.
+//! Enabled for this crate's own tests, and for downstream crates via the
+//! "testing" feature.
+
+use std::{
+    convert::Infallible,
+    io::{self, Error, ErrorKind},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use hyper::{
+    body::Bytes, server::conn::Http, service::service_fn, Body, HeaderMap, Method, Request,
+    Response, StatusCode, Uri, Version,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+    task::JoinHandle,
+};
+#[cfg(feature = "native-tls")]
+use tokio_native_tls::{native_tls, TlsAcceptor};
+#[cfg(not(feature = "native-tls"))]
+use tokio_rustls::TlsAcceptor;
+
+use crate::tls::{ALPN_H2, ALPN_HTTP1};
+
+/// Request as received by the mock server.
+#[derive(Debug, Clone)]
+pub struct ReceivedRequest {
+    pub method: Method,
+    pub uri: Uri,
+    pub version: Version,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+type JsonPredicate = Arc<dyn Fn(&serde_json::Value) -> bool + Send + Sync>;
+
+/// Stubbed response with its request matchers.
+/// All matchers must hold for the stub to respond.
+///
+/// ```ignore
+/// server.register(
+///     Stub::new(Method::POST, "/rpc")
+///         .with_header_present("authorization")
+///         .with_query("chain", "c")
+///         .with_json_body(|v| v["method"] == "eth_blockNumber")
+///         .respond(200, r#"{"result": "0x1"}"#),
+/// );
+/// ```
+#[derive(Clone)]
+pub struct Stub {
+    method: Method,
+    path: String,
+    headers: Vec<(String, Option<String>)>,
+    query: Vec<(String, String)>,
+    json_body: Vec<JsonPredicate>,
+    status: StatusCode,
+    response_headers: Vec<(String, String)>,
+    body: Bytes,
+}
+
+impl std::fmt::Debug for Stub {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Stub")
+            .field("method", &self.method)
+            .field("path", &self.path)
+            .field("headers", &self.headers)
+            .field("query", &self.query)
+            .field("json_body", &self.json_body.len())
+            .field("status", &self.status)
+            .finish()
+    }
+}
+
+impl Stub {
+    /// Matches requests with exactly this method and path, responding
+    /// with an empty 200 unless "respond" is called.
+    pub fn new(method: Method, path: &str) -> Self {
+        Self {
+            method,
+            path: path.to_string(),
+            headers: Vec::new(),
+            query: Vec::new(),
+            json_body: Vec::new(),
+            status: StatusCode::OK,
+            response_headers: Vec::new(),
+            body: Bytes::new(),
+        }
+    }
+
+    /// Requires the header to be present, with any value.
+    pub fn with_header_present(mut self, name: &str) -> Self {
+        self.headers.push((name.to_string(), None));
+        self
+    }
+
+    /// Requires the header to be set to "value".
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers
+            .push((name.to_string(), Some(value.to_string())));
+        self
+    }
+
+    /// Requires the query parameter "key" to be set to "value".
+    pub fn with_query(mut self, key: &str, value: &str) -> Self {
+        self.query.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Requires the body to be valid JSON satisfying "pred".
+    pub fn with_json_body<F>(mut self, pred: F) -> Self
+    where
+        F: Fn(&serde_json::Value) -> bool + Send + Sync + 'static,
+    {
+        self.json_body.push(Arc::new(pred));
+        self
+    }
+
+    /// Sets the response status and body.
+    pub fn respond(mut self, status: u16, body: impl Into<Bytes>) -> Self {
+        self.status = StatusCode::from_u16(status).expect("invalid status code");
+        self.body = body.into();
+        self
+    }
+
+    /// Adds a response header (e.g., "location" for redirects).
+    pub fn respond_header(mut self, name: &str, value: &str) -> Self {
+        self.response_headers
+            .push((name.to_string(), value.to_string()));
+        self
+    }
+
+    fn matches(&self, req: &ReceivedRequest) -> bool {
+        if self.method != req.method || self.path != req.uri.path() {
+            return false;
+        }
+
+        for (name, value) in self.headers.iter() {
+            let mut values = req.headers.get_all(name.as_str()).iter();
+            let ok = match value {
+                Some(v) => values.any(|got| got.as_bytes() == v.as_bytes()),
+                None => values.next().is_some(),
+            };
+            if !ok {
+                return false;
+            }
+        }
+
+        if !self.query.is_empty() {
+            let pairs: Vec<(String, String)> =
+                url::form_urlencoded::parse(req.uri.query().unwrap_or("").as_bytes())
+                    .into_owned()
+                    .collect();
+            if !self.query.iter().all(|q| pairs.contains(q)) {
+                return false;
+            }
+        }
+
+        if !self.json_body.is_empty() {
+            let v = match serde_json::from_slice::<serde_json::Value>(&req.body) {
+                Ok(v) => v,
+                Err(_) => return false,
+            };
+            if !self.json_body.iter().all(|pred| pred(&v)) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    stubs: Vec<Stub>,
+    received: Vec<ReceivedRequest>,
+    accepted_connections: usize,
+}
+
+/// Mock server listening on localhost with an ephemeral port.
+/// Requests are answered by the latest matching stub (404 otherwise) and
+/// recorded for later inspection.
+/// The server is stopped when dropped.
+pub struct MockServer {
+    addr: SocketAddr,
+    cert_pem: Option<String>,
+    state: Arc<Mutex<State>>,
+    handle: JoinHandle<()>,
+}
+
+impl MockServer {
+    /// Starts a plain HTTP server.
+    pub async fn start() -> io::Result<Self> {
+        Self::start_inner("127.0.0.1:0", None).await
+    }
+
+    /// Starts a plain HTTP server on the address (e.g., "[::1]:0").
+    pub async fn start_on(addr: &str) -> io::Result<Self> {
+        Self::start_inner(addr, None).await
+    }
+
+    /// Starts an HTTPS server with a freshly generated self-signed
+    /// certificate valid for "localhost" and "127.0.0.1".
+    /// Trust "cert_pem" on the client side to talk to it.
+    pub async fn start_https() -> io::Result<Self> {
+        let cert = rcgen::generate_simple_self_signed(vec![
+            "localhost".to_string(),
+            "127.0.0.1".to_string(),
+        ])
+        .map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("failed to generate self-signed cert {}", e),
+            )
+        })?;
+        let cert_pem = cert.serialize_pem().map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("failed to serialize self-signed cert {}", e),
+            )
+        })?;
+        let key_pem = cert.serialize_private_key_pem();
+
+        let acceptor = tls_acceptor(&cert_pem, &key_pem)?;
+        Self::start_inner("127.0.0.1:0", Some((acceptor, cert_pem))).await
+    }
+
+    async fn start_inner(addr: &str, tls: Option<(TlsAcceptor, String)>) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        let addr = listener.local_addr()?;
+        let state = Arc::new(Mutex::new(State::default()));
+
+        let (acceptor, cert_pem) = match tls {
+            Some((acceptor, cert_pem)) => (Some(acceptor), Some(cert_pem)),
+            None => (None, None),
+        };
+
+        let accept_state = state.clone();
+        let handle = tokio::spawn(async move {
+            loop {
+                let (stream, _) = match listener.accept().await {
+                    Ok(conn) => conn,
+                    Err(e) => {
+                        log::warn!("mock server failed to accept {}", e);
+                        continue;
+                    }
+                };
+                accept_state.lock().unwrap().accepted_connections += 1;
+                let state = accept_state.clone();
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    match acceptor {
+                        Some(acceptor) => match acceptor.accept(stream).await {
+                            Ok(tls_stream) => serve(tls_stream, state).await,
+                            Err(e) => log::warn!("mock server failed TLS handshake {}", e),
+                        },
+                        None => serve(stream, state).await,
+                    }
+                });
+            }
+        });
+
+        log::info!("mock server listening on {}", addr);
+        Ok(Self {
+            addr,
+            cert_pem,
+            state,
+            handle,
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn port(&self) -> u16 {
+        self.addr.port()
+    }
+
+    /// Returns the base URL (e.g., "https://localhost:12345").
+    pub fn url(&self) -> String {
+        if self.cert_pem.is_some() {
+            format!("https://localhost:{}", self.addr.port())
+        } else {
+            format!("http://{}", self.addr)
+        }
+    }
+
+    /// Returns the PEM-encoded self-signed certificate, if serving HTTPS.
+    pub fn cert_pem(&self) -> Option<&str> {
+        self.cert_pem.as_deref()
+    }
+
+    /// Responds to "method" and "path" with the status and body.
+    /// Later stubs take precedence over earlier ones.
+    pub fn stub(&self, method: Method, path: &str, status: u16, body: impl Into<Bytes>) {
+        self.register(Stub::new(method, path).respond(status, body));
+    }
+
+    /// Registers a stub with request matchers.
+    /// Later stubs take precedence over earlier ones.
+    pub fn register(&self, stub: Stub) {
+        self.state.lock().unwrap().stubs.push(stub);
+    }
+
+    /// Returns all requests received so far, in arrival order.
+    pub fn received_requests(&self) -> Vec<ReceivedRequest> {
+        self.state.lock().unwrap().received.clone()
+    }
+
+    /// Returns the number of accepted TCP connections, to check
+    /// connection reuse.
+    pub fn accepted_connections(&self) -> usize {
+        self.state.lock().unwrap().accepted_connections
+    }
+
+    /// Returns the number of received requests matching "method" and "path".
+    pub fn received_count(&self, method: Method, path: &str) -> usize {
+        self.received_requests()
+            .iter()
+            .filter(|r| r.method == method && r.uri.path() == path)
+            .count()
+    }
+
+    /// Asserts that at least one request with "method" and "path" was
+    /// received, and returns an assertion that can be narrowed further.
+    /// Panics with the list of received requests on mismatch.
+    pub fn assert_received(&self, method: Method, path: &str) -> ReceivedAssertion {
+        let all = self.received_requests();
+        let matched: Vec<ReceivedRequest> = all
+            .iter()
+            .filter(|r| r.method == method && r.uri.path() == path)
+            .cloned()
+            .collect();
+        let assertion = ReceivedAssertion {
+            description: format!("{} {}", method, path),
+            all,
+            matched,
+        };
+        assertion.check_not_empty();
+        assertion
+    }
+
+    /// Asserts that requests with the given method and path were received
+    /// in this relative order (other requests may be interleaved).
+    pub fn assert_received_in_order(&self, expected: &[(Method, &str)]) {
+        let all = self.received_requests();
+        let mut it = all.iter();
+        for (method, path) in expected {
+            if !it.any(|r| r.method == *method && r.uri.path() == *path) {
+                panic!(
+                    "expected {} {} (in order {:?}) but received:\n{}",
+                    method,
+                    path,
+                    expected,
+                    format_received(&all)
+                );
+            }
+        }
+    }
+}
+
+/// Assertion over the received requests matching a method and path,
+/// narrowed by each chained call. Every call panics on mismatch.
+#[derive(Debug)]
+pub struct ReceivedAssertion {
+    description: String,
+    all: Vec<ReceivedRequest>,
+    matched: Vec<ReceivedRequest>,
+}
+
+impl ReceivedAssertion {
+    /// Keeps only requests with the header set to "value".
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.description
+            .push_str(&format!(" with header {}: {}", name, value));
+        self.matched.retain(|r| {
+            r.headers
+                .get_all(name)
+                .iter()
+                .any(|v| v.as_bytes() == value.as_bytes())
+        });
+        self.check_not_empty();
+        self
+    }
+
+    /// Keeps only requests whose body is JSON equal to "expected"
+    /// (object key order and whitespace are ignored).
+    pub fn with_json_body(mut self, expected: &serde_json::Value) -> Self {
+        self.description
+            .push_str(&format!(" with JSON body {}", expected));
+        self.matched.retain(|r| {
+            serde_json::from_slice::<serde_json::Value>(&r.body)
+                .map(|v| v == *expected)
+                .unwrap_or(false)
+        });
+        self.check_not_empty();
+        self
+    }
+
+    /// Asserts the exact number of matching requests.
+    pub fn times(self, n: usize) -> Self {
+        if self.matched.len() != n {
+            panic!(
+                "expected {} request(s) {} but matched {}; received:\n{}",
+                n,
+                self.description,
+                self.matched.len(),
+                format_received(&self.all)
+            );
+        }
+        self
+    }
+
+    /// Asserts exactly one matching request.
+    pub fn once(self) -> Self {
+        self.times(1)
+    }
+
+    /// Returns the matching requests.
+    pub fn requests(&self) -> &[ReceivedRequest] {
+        &self.matched
+    }
+
+    fn check_not_empty(&self) {
+        if self.matched.is_empty() {
+            panic!(
+                "expected request {} but none matched; received:\n{}",
+                self.description,
+                format_received(&self.all)
+            );
+        }
+    }
+}
+
+fn format_received(reqs: &[ReceivedRequest]) -> String {
+    if reqs.is_empty() {
+        return "  (none)".to_string();
+    }
+    reqs.iter()
+        .enumerate()
+        .map(|(i, r)| {
+            format!(
+                "  #{} {} {} ({} header(s), {}-byte body)",
+                i,
+                r.method,
+                r.uri,
+                r.headers.len(),
+                r.body.len()
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+/// Returns the TLS acceptor of the certificate, with the client's TLS
+/// backend (native-tls if enabled). HTTP/2 is offered via ALPN, and
+/// cleartext connections accept HTTP/2 with prior knowledge.
+#[cfg(feature = "native-tls")]
+fn tls_acceptor(cert_pem: &str, key_pem: &str) -> io::Result<TlsAcceptor> {
+    let identity = native_tls::Identity::from_pkcs8(cert_pem.as_bytes(), key_pem.as_bytes())
+        .map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("failed to load TLS identity {}", e),
+            )
+        })?;
+    let acceptor = native_tls::TlsAcceptor::builder(identity)
+        .accept_alpn(&[ALPN_H2, ALPN_HTTP1])
+        .build()
+        .map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("failed to create TLS acceptor {}", e),
+            )
+        })?;
+    Ok(TlsAcceptor::from(acceptor))
+}
+
+#[cfg(not(feature = "native-tls"))]
+fn tls_acceptor(cert_pem: &str, key_pem: &str) -> io::Result<TlsAcceptor> {
+    let certs = rustls_pemfile::certs(&mut cert_pem.as_bytes())?;
+    let key = rustls_pemfile::pkcs8_private_keys(&mut key_pem.as_bytes())?
+        .pop()
+        .ok_or_else(|| Error::new(ErrorKind::Other, "no private key in PEM"))?;
+    let mut config = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(
+            certs.into_iter().map(rustls::Certificate).collect(),
+            rustls::PrivateKey(key),
+        )
+        .map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("failed to create TLS acceptor {}", e),
+            )
+        })?;
+    config.alpn_protocols = vec![ALPN_H2.into(), ALPN_HTTP1.into()];
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+async fn serve<S>(stream: S, state: Arc<Mutex<State>>)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let svc = service_fn(move |req| handle(req, state.clone()));
+    if let Err(e) = Http::new().serve_connection(stream, svc).await {
+        log::warn!("mock server connection error {}", e);
+    }
+}
+
+async fn handle(
+    req: Request<Body>,
+    state: Arc<Mutex<State>>,
+) -> Result<Response<Body>, Infallible> {
+    let (parts, body) = req.into_parts();
+    let body = hyper::body::to_bytes(body).await.unwrap_or_default();
+
+    let received = ReceivedRequest {
+        method: parts.method,
+        uri: parts.uri,
+        version: parts.version,
+        headers: parts.headers,
+        body,
+    };
+
+    let mut state = state.lock().unwrap();
+    let matched = state
+        .stubs
+        .iter()
+        .rev()
+        .find(|s| s.matches(&received))
+        .cloned();
+    state.received.push(received);
+
+    let resp = match matched {
+        Some(stub) => {
+            let mut builder = Response::builder().status(stub.status);
+            for (k, v) in stub.response_headers.iter() {
+                builder = builder.header(k.as_str(), v.as_str());
+            }
+            builder.body(Body::from(stub.body))
+        }
+        None => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty()),
+    };
+    Ok(resp.unwrap())
+}
+
+/// RUST_LOG=debug cargo test --lib -- testing::test_mock_server_http --exact --show-output
+#[tokio::test]
+async fn test_mock_server_http() {
+    use std::time::Duration;
+
+    let server = MockServer::start().await.unwrap();
+    server.stub(Method::GET, "/health", 200, "ok");
+
+    let req = crate::create_get(server.url(), "/health").unwrap();
+    let out = crate::read_bytes(req, Duration::from_secs(5), false, true)
+        .await
+        .unwrap();
+    assert_eq!(out, "ok");
+
+    let req = crate::create_get(server.url(), "/missing").unwrap();
+    let ret = crate::read_bytes(req, Duration::from_secs(5), false, true).await;
+    assert!(ret.is_err());
+
+    let received = server.received_requests();
+    assert_eq!(received.len(), 2);
+    assert_eq!(received[0].uri.path(), "/health");
+    assert_eq!(received[1].uri.path(), "/missing");
+}
+
+/// RUST_LOG=debug cargo test --lib -- testing::test_mock_server_https --exact --show-output
+#[tokio::test]
+async fn test_mock_server_https() {
+    use std::time::Duration;
+
+    let server = MockServer::start_https().await.unwrap();
+    server.stub(Method::GET, "/health", 200, "ok");
+    assert!(server.url().starts_with("https://localhost:"));
+
+    // untrusted self-signed cert must be rejected by default
+    let req = crate::create_get(server.url(), "/health").unwrap();
+    let ret = crate::read_bytes(req, Duration::from_secs(5), true, true).await;
+    assert!(ret.is_err());
+
+    let cert = reqwest::Certificate::from_pem(server.cert_pem().unwrap().as_bytes()).unwrap();
+    let cli = reqwest::ClientBuilder::new()
+        .add_root_certificate(cert)
+        .build()
+        .unwrap();
+    let resp = cli
+        .get(format!("{}/health", server.url()))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.text().await.unwrap(), "ok");
+    assert_eq!(server.received_requests().len(), 1);
+}
+
+/// RUST_LOG=debug cargo test --lib -- testing::test_mock_server_assertions --exact --show-output
+#[tokio::test]
+async fn test_mock_server_assertions() {
+    use std::time::Duration;
+
+    let server = MockServer::start().await.unwrap();
+    server.stub(Method::POST, "/rpc", 200, "{}");
+
+    let req = crate::create_get(server.url(), "/health").unwrap();
+    let _ = crate::read_bytes(req, Duration::from_secs(5), false, false).await;
+    for _ in 0..2 {
+        let req =
+            crate::create_json_post(server.url(), "/rpc", r#"{"id": 1, "method": "x"}"#).unwrap();
+        crate::read_bytes(req, Duration::from_secs(5), false, true)
+            .await
+            .unwrap();
+    }
+
+    server.assert_received(Method::GET, "/health").once();
+    server
+        .assert_received(Method::POST, "/rpc")
+        .with_header("content-type", "application/json")
+        .with_json_body(&serde_json::json!({"method": "x", "id": 1}))
+        .times(2);
+    assert_eq!(server.received_count(Method::POST, "/rpc"), 2);
+    server.assert_received_in_order(&[(Method::GET, "/health"), (Method::POST, "/rpc")]);
+
+    let ret = std::panic::catch_unwind(|| {
+        server.assert_received_in_order(&[(Method::POST, "/rpc"), (Method::GET, "/health")])
+    });
+    assert!(ret.is_err());
+    let ret = std::panic::catch_unwind(|| {
+        server
+            .assert_received(Method::POST, "/rpc")
+            .with_header("authorization", "Bearer x");
+    });
+    assert!(ret.is_err());
+}
+
+/// RUST_LOG=debug cargo test --lib -- testing::test_mock_server_matchers --exact --show-output
+#[tokio::test]
+async fn test_mock_server_matchers() {
+    use std::time::Duration;
+
+    let server = MockServer::start().await.unwrap();
+    server.stub(Method::POST, "/rpc", 200, "fallback");
+    server.register(
+        Stub::new(Method::POST, "/rpc")
+            .with_header("content-type", "application/json")
+            .with_query("chain", "c")
+            .with_json_body(|v| v["method"] == "eth_blockNumber")
+            .respond(200, "block"),
+    );
+    server.register(
+        Stub::new(Method::GET, "/private")
+            .with_header_present("authorization")
+            .respond(200, "secret"),
+    );
+
+    let post = |path: &'static str, body: &'static str| {
+        let url = server.url();
+        async move {
+            let req = crate::create_json_post(&url, path, body).unwrap();
+            crate::read_bytes(req, Duration::from_secs(5), false, true).await
+        }
+    };
+    assert_eq!(
+        post("/rpc?chain=c", r#"{"method": "eth_blockNumber"}"#)
+            .await
+            .unwrap(),
+        "block"
+    );
+    assert_eq!(
+        post("/rpc?chain=x", r#"{"method": "eth_blockNumber"}"#)
+            .await
+            .unwrap(),
+        "fallback"
+    );
+    assert_eq!(
+        post("/rpc?chain=c", r#"{"method": "eth_chainId"}"#)
+            .await
+            .unwrap(),
+        "fallback"
+    );
+
+    let req = crate::create_get(server.url(), "/private").unwrap();
+    let ret = crate::read_bytes(req, Duration::from_secs(5), false, true).await;
+    assert!(ret.is_err());
+
+    let mut req = crate::create_get(server.url(), "/private").unwrap();
+    req.headers_mut()
+        .insert("authorization", "Bearer t".parse().unwrap());
+    let out = crate::read_bytes(req, Duration::from_secs(5), false, true)
+        .await
+        .unwrap();
+    assert_eq!(out, "secret");
+}
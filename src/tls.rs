@@ -0,0 +1,1333 @@
+//! TLS settings of the HTTPS connectors (see "ManagerBuilder::tls_config"
+//! and "read_bytes_with_tls"), and the connector over the TLS backend
+//! selected by the "native-tls" (default) and "rustls" features.
+
+#[cfg(not(any(feature = "native-tls", feature = "rustls")))]
+compile_error!("either the \"native-tls\" or \"rustls\" feature must be enabled");
+
+use std::{
+    fmt, fs,
+    future::Future,
+    io::{self, Error, ErrorKind},
+    path::Path,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hyper::{
+    client::connect::{Connected, Connection},
+    http::uri::Scheme,
+    service::Service,
+    Uri,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+#[cfg(feature = "native-tls")]
+use tokio_native_tls::native_tls;
+
+use crate::logging::wire_warn;
+
+/// TLS implementation of the connections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TlsBackend {
+    /// Platform TLS (OpenSSL, Secure Transport, or SChannel), with the
+    /// system root certificates.
+    #[cfg(feature = "native-tls")]
+    NativeTls,
+    /// rustls with the Mozilla root certificates ("webpki-roots"), for
+    /// builds without OpenSSL.
+    #[cfg(feature = "rustls")]
+    Rustls,
+}
+
+#[cfg(feature = "native-tls")]
+const DEFAULT_BACKEND: TlsBackend = TlsBackend::NativeTls;
+#[cfg(not(feature = "native-tls"))]
+const DEFAULT_BACKEND: TlsBackend = TlsBackend::Rustls;
+
+impl Default for TlsBackend {
+    /// "NativeTls" if enabled, otherwise "Rustls".
+    fn default() -> Self {
+        DEFAULT_BACKEND
+    }
+}
+
+/// Minimum TLS protocol version of the connections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum TlsVersion {
+    #[serde(rename = "1.2")]
+    Tls12,
+    /// Not supported by the native TLS backend on macOS.
+    #[serde(rename = "1.3")]
+    Tls13,
+}
+
+/// Client certificate and private key, presented to servers that require
+/// mutual TLS (e.g., services behind mTLS gateways).
+#[derive(Clone, PartialEq, Eq)]
+pub enum ClientIdentity {
+    /// PEM certificate (chain) and PKCS#8 PEM private key (or PKCS#1 and
+    /// SEC1 keys, with rustls).
+    Pem { cert: Vec<u8>, key: Vec<u8> },
+    /// DER-encoded PKCS#12 archive (".p12" or ".pfx") and its password.
+    /// Only supported by the native TLS backend.
+    Pkcs12 { der: Vec<u8>, password: String },
+}
+
+impl fmt::Debug for ClientIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientIdentity::Pem { cert, .. } => f
+                .debug_struct("Pem")
+                .field("cert", &format!("{} bytes", cert.len()))
+                .finish_non_exhaustive(),
+            ClientIdentity::Pkcs12 { der, .. } => f
+                .debug_struct("Pkcs12")
+                .field("der", &format!("{} bytes", der.len()))
+                .finish_non_exhaustive(),
+        }
+    }
+}
+
+impl ClientIdentity {
+    pub fn from_pem(cert: impl Into<Vec<u8>>, key: impl Into<Vec<u8>>) -> Self {
+        ClientIdentity::Pem {
+            cert: cert.into(),
+            key: key.into(),
+        }
+    }
+
+    pub fn from_pem_files(
+        cert_path: impl AsRef<Path>,
+        key_path: impl AsRef<Path>,
+    ) -> io::Result<Self> {
+        Ok(Self::from_pem(
+            read_file(cert_path.as_ref())?,
+            read_file(key_path.as_ref())?,
+        ))
+    }
+
+    pub fn from_pkcs12(der: impl Into<Vec<u8>>, password: &str) -> Self {
+        ClientIdentity::Pkcs12 {
+            der: der.into(),
+            password: password.to_string(),
+        }
+    }
+
+    pub fn from_pkcs12_file(path: impl AsRef<Path>, password: &str) -> io::Result<Self> {
+        Ok(Self::from_pkcs12(read_file(path.as_ref())?, password))
+    }
+
+    #[cfg(feature = "native-tls")]
+    fn to_native(&self) -> io::Result<native_tls::Identity> {
+        let ret = match self {
+            ClientIdentity::Pem { cert, key } => native_tls::Identity::from_pkcs8(cert, key),
+            ClientIdentity::Pkcs12 { der, password } => {
+                native_tls::Identity::from_pkcs12(der, password)
+            }
+        };
+        ret.map_err(|e| invalid_identity(&e))
+    }
+
+    /// Returns the DER certificate chain and the private key.
+    #[cfg(any(feature = "rustls", feature = "http3"))]
+    fn to_der(&self) -> io::Result<(Vec<Vec<u8>>, KeyDer)> {
+        let (cert, key) = match self {
+            ClientIdentity::Pem { cert, key } => (cert, key),
+            ClientIdentity::Pkcs12 { .. } => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "PKCS#12 client identity requires the native-tls backend",
+                ))
+            }
+        };
+        let certs =
+            rustls_pemfile::certs(&mut cert.as_slice()).map_err(|e| invalid_identity(&e))?;
+        let key = rustls_pemfile::read_all(&mut key.as_slice())
+            .map_err(|e| invalid_identity(&e))?
+            .into_iter()
+            .find_map(|item| match item {
+                rustls_pemfile::Item::PKCS8Key(k) => Some(KeyDer::Pkcs8(k)),
+                rustls_pemfile::Item::RSAKey(k) => Some(KeyDer::Pkcs1(k)),
+                rustls_pemfile::Item::ECKey(k) => Some(KeyDer::Sec1(k)),
+                _ => None,
+            });
+        match key {
+            Some(key) if !certs.is_empty() => Ok((certs, key)),
+            _ => Err(invalid_identity(&"no certificate or private key in PEM")),
+        }
+    }
+}
+
+/// DER private key of a PEM client identity.
+#[cfg(any(feature = "rustls", feature = "http3"))]
+enum KeyDer {
+    Pkcs8(Vec<u8>),
+    /// RSA key.
+    Pkcs1(Vec<u8>),
+    /// EC key.
+    Sec1(Vec<u8>),
+}
+
+#[cfg(feature = "rustls")]
+impl KeyDer {
+    fn into_inner(self) -> Vec<u8> {
+        match self {
+            KeyDer::Pkcs8(k) | KeyDer::Pkcs1(k) | KeyDer::Sec1(k) => k,
+        }
+    }
+}
+
+fn invalid_identity(e: &dyn fmt::Display) -> Error {
+    Error::new(
+        ErrorKind::InvalidInput,
+        format!("failed to load client identity {}", e),
+    )
+}
+
+/// Root certificate trusted in addition to (or instead of) the system
+/// store.
+#[derive(Clone, PartialEq, Eq)]
+enum RootCertificate {
+    /// One or more PEM certificates (e.g., a CA bundle).
+    Pem(Vec<u8>),
+    Der(Vec<u8>),
+}
+
+impl fmt::Debug for RootCertificate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RootCertificate::Pem(b) => write!(f, "Pem({} bytes)", b.len()),
+            RootCertificate::Der(b) => write!(f, "Der({} bytes)", b.len()),
+        }
+    }
+}
+
+impl RootCertificate {
+    #[cfg(feature = "native-tls")]
+    fn to_native(&self) -> io::Result<Vec<native_tls::Certificate>> {
+        let certs = match self {
+            RootCertificate::Pem(pem) => native_tls::Certificate::stack_from_pem(pem),
+            RootCertificate::Der(der) => native_tls::Certificate::from_der(der).map(|c| vec![c]),
+        }
+        .map_err(|e| invalid_root(&e))?;
+        if certs.is_empty() {
+            return Err(invalid_root(&"no certificate in PEM"));
+        }
+        Ok(certs)
+    }
+
+    /// Returns the DER certificates.
+    #[cfg(any(feature = "rustls", feature = "http3"))]
+    fn to_der(&self) -> io::Result<Vec<Vec<u8>>> {
+        let certs = match self {
+            RootCertificate::Pem(pem) => {
+                rustls_pemfile::certs(&mut pem.as_slice()).map_err(|e| invalid_root(&e))?
+            }
+            RootCertificate::Der(der) => vec![der.clone()],
+        };
+        if certs.is_empty() {
+            return Err(invalid_root(&"no certificate in PEM"));
+        }
+        Ok(certs)
+    }
+}
+
+fn invalid_root(e: &dyn fmt::Display) -> Error {
+    Error::new(
+        ErrorKind::InvalidInput,
+        format!("failed to load root certificate {}", e),
+    )
+}
+
+/// TLS settings, e.g.:
+///
+/// ```ignore
+/// let identity = ClientIdentity::from_pem_files("client.crt", "client.key")?;
+/// let tls = TlsConfig::new()
+///     .client_identity(identity)
+///     .add_root_pem_file("/etc/internal-ca.pem")?;
+/// let manager = Manager::builder().tls_config(tls).build()?;
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsConfig {
+    backend: TlsBackend,
+    client_identity: Option<ClientIdentity>,
+    root_certificates: Vec<RootCertificate>,
+    built_in_roots: bool,
+    danger_accept_invalid_certs: bool,
+    pins: Vec<[u8; 32]>,
+    min_version: Option<TlsVersion>,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self {
+            backend: TlsBackend::default(),
+            client_identity: None,
+            root_certificates: Vec::new(),
+            built_in_roots: true,
+            danger_accept_invalid_certs: false,
+            pins: Vec::new(),
+            min_version: None,
+        }
+    }
+}
+
+impl TlsConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Selects the TLS backend, if both features are enabled.
+    pub fn backend(mut self, backend: TlsBackend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    pub fn tls_backend(&self) -> TlsBackend {
+        self.backend
+    }
+
+    /// Trusts the PEM certificates (one or more, e.g., a CA bundle or a
+    /// self-signed server certificate).
+    pub fn add_root_pem(mut self, pem: impl Into<Vec<u8>>) -> Self {
+        self.root_certificates
+            .push(RootCertificate::Pem(pem.into()));
+        self
+    }
+
+    /// Trusts the DER-encoded certificate.
+    pub fn add_root_der(mut self, der: impl Into<Vec<u8>>) -> Self {
+        self.root_certificates
+            .push(RootCertificate::Der(der.into()));
+        self
+    }
+
+    pub fn add_root_pem_file(self, path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(self.add_root_pem(read_file(path.as_ref())?))
+    }
+
+    /// Trusts the PEM certificates of the ".pem", ".crt", and ".cer" files
+    /// in the directory (e.g., "/etc/ssl/internal"), in file name order.
+    pub fn add_root_pem_dir(mut self, dir: impl AsRef<Path>) -> io::Result<Self> {
+        let dir = dir.as_ref();
+        let mut paths = Vec::new();
+        for entry in fs::read_dir(dir)
+            .map_err(|e| Error::new(e.kind(), format!("failed to read {} {}", dir.display(), e)))?
+        {
+            let path = entry?.path();
+            let is_cert = matches!(
+                path.extension().and_then(|e| e.to_str()),
+                Some("pem" | "crt" | "cer")
+            );
+            if is_cert && path.is_file() {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        for path in paths {
+            self = self.add_root_pem_file(path)?;
+        }
+        Ok(self)
+    }
+
+    /// Trusts the built-in roots (the system store with native-tls, or the
+    /// Mozilla roots with rustls) in addition to the added roots (true by
+    /// default). If false, only the added roots are trusted.
+    pub fn built_in_roots(mut self, enable: bool) -> Self {
+        self.built_in_roots = enable;
+        self
+    }
+
+    /// Presents the client certificate to servers that request one.
+    pub fn client_identity(mut self, identity: ClientIdentity) -> Self {
+        self.client_identity = Some(identity);
+        self
+    }
+
+    /// Skips certificate and hostname verification ("curl --insecure").
+    /// Only for testing against self-signed endpoints (see "add_root_pem"
+    /// to trust them instead).
+    pub fn danger_accept_invalid_certs(mut self, accept: bool) -> Self {
+        self.danger_accept_invalid_certs = accept;
+        self
+    }
+
+    pub fn accepts_invalid_certs(&self) -> bool {
+        self.danger_accept_invalid_certs
+    }
+
+    /// Pins the server certificate, to defend against compromised CAs:
+    /// connections fail unless the SHA-256 of a certificate's
+    /// SubjectPublicKeyInfo (as "curl --pinnedpubkey") or of the whole DER
+    /// certificate matches one of the base64-encoded hashes, with or
+    /// without the "sha256//" prefix (see "spki_sha256"). With rustls, any
+    /// certificate of the presented chain may match (e.g., to pin an
+    /// intermediate CA); the native TLS backend only exposes the leaf. The
+    /// chain is still verified unless "danger_accept_invalid_certs" is set
+    /// (e.g., to pin a self-signed certificate).
+    pub fn pin_sha256(mut self, pins: &[&str]) -> io::Result<Self> {
+        for pin in pins.iter() {
+            let b64 = pin.strip_prefix("sha256//").unwrap_or(pin);
+            let hash: [u8; 32] = STANDARD
+                .decode(b64)
+                .ok()
+                .and_then(|h| h.try_into().ok())
+                .ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("invalid SHA-256 pin '{}'", pin),
+                    )
+                })?;
+            self.pins.push(hash);
+        }
+        Ok(self)
+    }
+
+    /// Refuses to negotiate protocol versions older than "version" (by
+    /// default, the backend decides, e.g., TLS 1.2 with rustls). QUIC
+    /// connections always use TLS 1.3.
+    pub fn min_version(mut self, version: TlsVersion) -> Self {
+        self.min_version = Some(version);
+        self
+    }
+
+    /// Returns the TLS connector of the backend with the settings applied,
+    /// skipping verification if "accept_invalid_certs" (which replaces
+    /// the config's own, e.g., for host overrides), and offering HTTP/2
+    /// via ALPN if "http2". Fails on invalid certificates or keys.
+    pub(crate) fn tls_connector(
+        &self,
+        accept_invalid_certs: bool,
+        http2: bool,
+    ) -> io::Result<TlsConnector> {
+        if accept_invalid_certs {
+            wire_warn!("building TLS connector with danger_accept_invalid_certs");
+        }
+        match self.backend {
+            #[cfg(feature = "native-tls")]
+            TlsBackend::NativeTls => self.native_connector(accept_invalid_certs, http2),
+            #[cfg(feature = "rustls")]
+            TlsBackend::Rustls => self.rustls_connector(accept_invalid_certs, http2),
+        }
+    }
+
+    #[cfg(feature = "native-tls")]
+    fn native_connector(
+        &self,
+        accept_invalid_certs: bool,
+        http2: bool,
+    ) -> io::Result<TlsConnector> {
+        let mut builder = native_tls::TlsConnector::builder();
+        if http2 {
+            builder.request_alpns(&[ALPN_H2, ALPN_HTTP1]);
+        }
+        if let Some(identity) = &self.client_identity {
+            builder.identity(identity.to_native()?);
+        }
+        for root in self.root_certificates.iter() {
+            for cert in root.to_native()? {
+                builder.add_root_certificate(cert);
+            }
+        }
+        match self.min_version {
+            Some(TlsVersion::Tls12) => {
+                builder.min_protocol_version(Some(native_tls::Protocol::Tlsv12));
+            }
+            Some(TlsVersion::Tls13) => {
+                builder.min_protocol_version(Some(native_tls::Protocol::Tlsv13));
+            }
+            None => {}
+        }
+        let tls = builder
+            .disable_built_in_roots(!self.built_in_roots)
+            .danger_accept_invalid_certs(accept_invalid_certs)
+            .danger_accept_invalid_hostnames(accept_invalid_certs)
+            .build()
+            .map_err(|e| {
+                Error::new(
+                    ErrorKind::Other,
+                    format!("failed to build TLS connector {}", e),
+                )
+            })?;
+        Ok(TlsConnector::NativeTls(tls.into()))
+    }
+
+    #[cfg(feature = "rustls")]
+    fn rustls_connector(
+        &self,
+        accept_invalid_certs: bool,
+        http2: bool,
+    ) -> io::Result<TlsConnector> {
+        let mut roots = rustls::RootCertStore::empty();
+        if self.built_in_roots {
+            roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|ta| {
+                rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
+                    ta.subject,
+                    ta.spki,
+                    ta.name_constraints,
+                )
+            }));
+        }
+        for root in self.root_certificates.iter() {
+            for der in root.to_der()? {
+                roots
+                    .add(&rustls::Certificate(der))
+                    .map_err(|e| invalid_root(&e))?;
+            }
+        }
+
+        let versions: &[&rustls::SupportedProtocolVersion] = match self.min_version {
+            Some(TlsVersion::Tls13) => &[&rustls::version::TLS13],
+            Some(TlsVersion::Tls12) | None => rustls::DEFAULT_VERSIONS,
+        };
+        let builder = rustls::ClientConfig::builder()
+            .with_safe_default_cipher_suites()
+            .with_safe_default_kx_groups()
+            .with_protocol_versions(versions)
+            .map_err(|e| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("failed to set TLS versions {}", e),
+                )
+            })?
+            .with_root_certificates(roots);
+        let mut config = match &self.client_identity {
+            Some(identity) => {
+                let (certs, key) = identity.to_der()?;
+                let certs = certs.into_iter().map(rustls::Certificate).collect();
+                let key = rustls::PrivateKey(key.into_inner());
+                builder
+                    .with_client_auth_cert(certs, key)
+                    .map_err(|e| invalid_identity(&e))?
+            }
+            None => builder.with_no_client_auth(),
+        };
+        if http2 {
+            config.alpn_protocols = vec![ALPN_H2.into(), ALPN_HTTP1.into()];
+        }
+        if accept_invalid_certs {
+            config
+                .dangerous()
+                .set_certificate_verifier(Arc::new(AcceptInvalidCerts));
+        }
+        Ok(TlsConnector::Rustls(Arc::new(config).into()))
+    }
+
+    /// Returns the TLS 1.3 config of QUIC connections with the settings
+    /// applied (see "tls_connector"), offering HTTP/3 via ALPN. QUIC always
+    /// uses rustls, regardless of the backend.
+    #[cfg(feature = "http3")]
+    pub(crate) fn quic_client_config(
+        &self,
+        accept_invalid_certs: bool,
+    ) -> io::Result<rustls_quic::ClientConfig> {
+        use rustls_quic::pki_types::{
+            CertificateDer, PrivateKeyDer, PrivatePkcs1KeyDer, PrivatePkcs8KeyDer,
+            PrivateSec1KeyDer,
+        };
+
+        let provider = Arc::new(rustls_quic::crypto::ring::default_provider());
+        let mut roots = rustls_quic::RootCertStore::empty();
+        if self.built_in_roots {
+            roots.extend(webpki_roots_quic::TLS_SERVER_ROOTS.iter().cloned());
+        }
+        for root in self.root_certificates.iter() {
+            for der in root.to_der()? {
+                roots
+                    .add(CertificateDer::from(der))
+                    .map_err(|e| invalid_root(&e))?;
+            }
+        }
+
+        let builder = rustls_quic::ClientConfig::builder_with_provider(provider.clone())
+            .with_protocol_versions(&[&rustls_quic::version::TLS13])
+            .map_err(|e| {
+                Error::new(
+                    ErrorKind::Other,
+                    format!("failed to build QUIC TLS config {}", e),
+                )
+            })?
+            .with_root_certificates(roots);
+        let mut config = match &self.client_identity {
+            Some(identity) => {
+                let (certs, key) = identity.to_der()?;
+                let certs = certs.into_iter().map(CertificateDer::from).collect();
+                let key = match key {
+                    KeyDer::Pkcs8(k) => PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(k)),
+                    KeyDer::Pkcs1(k) => PrivateKeyDer::Pkcs1(PrivatePkcs1KeyDer::from(k)),
+                    KeyDer::Sec1(k) => PrivateKeyDer::Sec1(PrivateSec1KeyDer::from(k)),
+                };
+                builder
+                    .with_client_auth_cert(certs, key)
+                    .map_err(|e| invalid_identity(&e))?
+            }
+            None => builder.with_no_client_auth(),
+        };
+        config.alpn_protocols = vec![ALPN_H3.into()];
+        if accept_invalid_certs {
+            wire_warn!("building QUIC TLS config with danger_accept_invalid_certs");
+            config
+                .dangerous()
+                .set_certificate_verifier(Arc::new(QuicAcceptInvalidCerts(provider)));
+        }
+        Ok(config)
+    }
+
+    pub(crate) fn pins(&self) -> &[[u8; 32]] {
+        &self.pins
+    }
+
+    /// Returns the HTTPS connector over "http" (see "tls_connector").
+    pub(crate) fn https_connector<C>(
+        &self,
+        http: C,
+        accept_invalid_certs: bool,
+        http2: bool,
+    ) -> io::Result<HttpsConnector<C>> {
+        Ok(HttpsConnector {
+            http,
+            tls: self.tls_connector(accept_invalid_certs, http2)?,
+            pins: Arc::new(self.pins.clone()),
+        })
+    }
+}
+
+/// ALPN protocol IDs.
+/// ref. https://www.iana.org/assignments/tls-extensiontype-values/tls-extensiontype-values.xhtml#alpn-protocol-ids
+pub(crate) const ALPN_H2: &str = "h2";
+pub(crate) const ALPN_HTTP1: &str = "http/1.1";
+#[cfg(feature = "http3")]
+pub(crate) const ALPN_H3: &str = "h3";
+
+/// Certificate verifier of "danger_accept_invalid_certs" with rustls,
+/// which still checks the handshake signatures.
+#[cfg(feature = "rustls")]
+struct AcceptInvalidCerts;
+
+#[cfg(feature = "rustls")]
+impl rustls::client::ServerCertVerifier for AcceptInvalidCerts {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &rustls::Certificate,
+        _intermediates: &[rustls::Certificate],
+        _server_name: &rustls::ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: std::time::SystemTime,
+    ) -> Result<rustls::client::ServerCertVerified, rustls::Error> {
+        Ok(rustls::client::ServerCertVerified::assertion())
+    }
+}
+
+/// "AcceptInvalidCerts" of QUIC connections, with the rustls version of
+/// quinn.
+#[cfg(feature = "http3")]
+#[derive(Debug)]
+struct QuicAcceptInvalidCerts(Arc<rustls_quic::crypto::CryptoProvider>);
+
+#[cfg(feature = "http3")]
+impl rustls_quic::client::danger::ServerCertVerifier for QuicAcceptInvalidCerts {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &rustls_quic::pki_types::CertificateDer<'_>,
+        _intermediates: &[rustls_quic::pki_types::CertificateDer<'_>],
+        _server_name: &rustls_quic::pki_types::ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: rustls_quic::pki_types::UnixTime,
+    ) -> Result<rustls_quic::client::danger::ServerCertVerified, rustls_quic::Error> {
+        Ok(rustls_quic::client::danger::ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls_quic::pki_types::CertificateDer<'_>,
+        dss: &rustls_quic::DigitallySignedStruct,
+    ) -> Result<rustls_quic::client::danger::HandshakeSignatureValid, rustls_quic::Error> {
+        rustls_quic::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls_quic::pki_types::CertificateDer<'_>,
+        dss: &rustls_quic::DigitallySignedStruct,
+    ) -> Result<rustls_quic::client::danger::HandshakeSignatureValid, rustls_quic::Error> {
+        rustls_quic::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls_quic::SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// TLS client of the selected backend.
+#[derive(Clone)]
+pub(crate) enum TlsConnector {
+    #[cfg(feature = "native-tls")]
+    NativeTls(tokio_native_tls::TlsConnector),
+    #[cfg(feature = "rustls")]
+    Rustls(tokio_rustls::TlsConnector),
+}
+
+impl fmt::Debug for TlsConnector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "native-tls")]
+            TlsConnector::NativeTls(_) => f.write_str("NativeTls"),
+            #[cfg(feature = "rustls")]
+            TlsConnector::Rustls(_) => f.write_str("Rustls"),
+        }
+    }
+}
+
+impl TlsConnector {
+    /// Runs the TLS handshake over the stream, verifying the certificate
+    /// for the host (a domain name or an IP address, with or without the
+    /// IPv6 brackets).
+    pub(crate) async fn connect<S>(&self, host: &str, stream: S) -> io::Result<TlsStream<S>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        match self {
+            #[cfg(feature = "native-tls")]
+            TlsConnector::NativeTls(tls) => tls
+                .connect(host, stream)
+                .await
+                .map(TlsStream::NativeTls)
+                .map_err(|e| Error::new(ErrorKind::Other, format!("failed TLS handshake {}", e))),
+            #[cfg(feature = "rustls")]
+            TlsConnector::Rustls(tls) => {
+                let name = rustls::ServerName::try_from(host).map_err(|e| {
+                    Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("invalid TLS server name '{}' {}", host, e),
+                    )
+                })?;
+                tls.connect(name, stream)
+                    .await
+                    .map(|s| TlsStream::Rustls(Box::new(s)))
+                    .map_err(|e| Error::new(e.kind(), format!("failed TLS handshake {}", e)))
+            }
+        }
+    }
+}
+
+/// TLS stream of either backend.
+pub(crate) enum TlsStream<S> {
+    #[cfg(feature = "native-tls")]
+    NativeTls(tokio_native_tls::TlsStream<S>),
+    #[cfg(feature = "rustls")]
+    Rustls(Box<tokio_rustls::client::TlsStream<S>>),
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> TlsStream<S> {
+    /// Returns the DER certificates presented by the server, leaf first
+    /// (only the leaf with the native TLS backend).
+    pub(crate) fn peer_certificates(&self) -> Vec<Vec<u8>> {
+        match self {
+            #[cfg(feature = "native-tls")]
+            TlsStream::NativeTls(s) => s
+                .get_ref()
+                .peer_certificate()
+                .ok()
+                .flatten()
+                .and_then(|c| c.to_der().ok())
+                .into_iter()
+                .collect(),
+            #[cfg(feature = "rustls")]
+            TlsStream::Rustls(s) => s
+                .get_ref()
+                .1
+                .peer_certificates()
+                .map(|certs| certs.iter().map(|c| c.0.clone()).collect())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Returns true if the server chose HTTP/2 via ALPN.
+    pub(crate) fn negotiated_h2(&self) -> bool {
+        let alpn = match self {
+            #[cfg(feature = "native-tls")]
+            TlsStream::NativeTls(s) => s.get_ref().negotiated_alpn().ok().flatten(),
+            #[cfg(feature = "rustls")]
+            TlsStream::Rustls(s) => s.get_ref().1.alpn_protocol().map(|p| p.to_vec()),
+        };
+        alpn.as_deref() == Some(ALPN_H2.as_bytes())
+    }
+
+    fn get_ref(&self) -> &S {
+        match self {
+            #[cfg(feature = "native-tls")]
+            TlsStream::NativeTls(s) => s.get_ref().get_ref().get_ref(),
+            #[cfg(feature = "rustls")]
+            TlsStream::Rustls(s) => s.get_ref().0,
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for TlsStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            #[cfg(feature = "native-tls")]
+            TlsStream::NativeTls(s) => Pin::new(s).poll_read(cx, buf),
+            #[cfg(feature = "rustls")]
+            TlsStream::Rustls(s) => Pin::new(s).poll_read(cx, buf),
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for TlsStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            #[cfg(feature = "native-tls")]
+            TlsStream::NativeTls(s) => Pin::new(s).poll_write(cx, buf),
+            #[cfg(feature = "rustls")]
+            TlsStream::Rustls(s) => Pin::new(s).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            #[cfg(feature = "native-tls")]
+            TlsStream::NativeTls(s) => Pin::new(s).poll_flush(cx),
+            #[cfg(feature = "rustls")]
+            TlsStream::Rustls(s) => Pin::new(s).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            #[cfg(feature = "native-tls")]
+            TlsStream::NativeTls(s) => Pin::new(s).poll_shutdown(cx),
+            #[cfg(feature = "rustls")]
+            TlsStream::Rustls(s) => Pin::new(s).poll_shutdown(cx),
+        }
+    }
+}
+
+/// Connection of the HTTPS connector: TLS for "https" URLs.
+pub(crate) enum MaybeHttpsStream<S> {
+    Http(S),
+    Https(TlsStream<S>),
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for MaybeHttpsStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MaybeHttpsStream::Http(s) => Pin::new(s).poll_read(cx, buf),
+            MaybeHttpsStream::Https(s) => Pin::new(s).poll_read(cx, buf),
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for MaybeHttpsStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            MaybeHttpsStream::Http(s) => Pin::new(s).poll_write(cx, buf),
+            MaybeHttpsStream::Https(s) => Pin::new(s).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MaybeHttpsStream::Http(s) => Pin::new(s).poll_flush(cx),
+            MaybeHttpsStream::Https(s) => Pin::new(s).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MaybeHttpsStream::Http(s) => Pin::new(s).poll_shutdown(cx),
+            MaybeHttpsStream::Https(s) => Pin::new(s).poll_shutdown(cx),
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Connection + Unpin> Connection for MaybeHttpsStream<S> {
+    fn connected(&self) -> Connected {
+        match self {
+            MaybeHttpsStream::Http(s) => s.connected(),
+            MaybeHttpsStream::Https(s) if s.negotiated_h2() => {
+                s.get_ref().connected().negotiated_h2()
+            }
+            MaybeHttpsStream::Https(s) => s.get_ref().connected(),
+        }
+    }
+}
+
+/// Returns the pin of the DER certificate: the base64-encoded SHA-256 of
+/// its SubjectPublicKeyInfo (e.g., for "TlsConfig::pin_sha256").
+pub fn spki_sha256(cert_der: &[u8]) -> io::Result<String> {
+    let spki = spki(cert_der).ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidData,
+            "failed to find SubjectPublicKeyInfo in certificate",
+        )
+    })?;
+    Ok(STANDARD.encode(Sha256::digest(spki)))
+}
+
+/// Returns the DER "SubjectPublicKeyInfo" of the DER certificate, the
+/// seventh element of "tbsCertificate" (after the optional version).
+/// ref. https://www.rfc-editor.org/rfc/rfc5280#section-4.1
+fn spki(cert_der: &[u8]) -> Option<&[u8]> {
+    let (_, cert, _) = der_next(cert_der)?;
+    let (_, tbs, _) = der_next(cert)?;
+    let mut rest = tbs;
+    let (tag, _, after_version) = der_next(rest)?;
+    if tag == 0xa0 {
+        rest = after_version;
+    }
+    // serial number, signature, issuer, validity, and subject
+    for _ in 0..5 {
+        rest = der_next(rest)?.2;
+    }
+    let (_, _, after) = der_next(rest)?;
+    Some(&rest[..rest.len() - after.len()])
+}
+
+/// Reads a DER element, returning its tag, contents, and the remaining
+/// bytes.
+pub(crate) fn der_next(b: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, b) = b.split_first()?;
+    let (&len, mut b) = b.split_first()?;
+    let len = if len < 0x80 {
+        len as usize
+    } else {
+        let n = (len & 0x7f) as usize;
+        if n == 0 || n > 4 || b.len() < n {
+            return None;
+        }
+        let len = b[..n].iter().fold(0usize, |l, &x| l << 8 | x as usize);
+        b = &b[n..];
+        len
+    };
+    if b.len() < len {
+        return None;
+    }
+    Some((tag, &b[..len], &b[len..]))
+}
+
+/// Error of a server certificate that matches none of the pins, returned
+/// as the inner error of the connection failure (see "Error::PinMismatch").
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PinMismatch {
+    pub host: String,
+    /// Pin of the presented (leaf) certificate, if it has one.
+    pub presented: Option<String>,
+}
+
+impl fmt::Display for PinMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "certificate of {} does not match the pinned hashes (presented sha256//{})",
+            self.host,
+            self.presented.as_deref().unwrap_or("unknown")
+        )
+    }
+}
+
+impl std::error::Error for PinMismatch {}
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// HTTPS connector of the clients, which checks the server certificates
+/// against the pins (if any) right after the handshake, before the request
+/// is sent.
+#[derive(Debug, Clone)]
+pub(crate) struct HttpsConnector<C> {
+    http: C,
+    tls: TlsConnector,
+    pins: Arc<Vec<[u8; 32]>>,
+}
+
+impl<C> Service<Uri> for HttpsConnector<C>
+where
+    C: Service<Uri>,
+    C::Response: AsyncRead + AsyncWrite + Connection + Unpin + Send + 'static,
+    C::Error: Into<BoxError>,
+    C::Future: Send + 'static,
+{
+    type Response = MaybeHttpsStream<C::Response>;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.http.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let is_https = uri.scheme() == Some(&Scheme::HTTPS);
+        let host = uri.host().unwrap_or("").to_string();
+        let connecting = self.http.call(uri);
+        let tls = self.tls.clone();
+        let pins = self.pins.clone();
+        Box::pin(async move {
+            let stream = connecting.await.map_err(Into::into)?;
+            if !is_https {
+                return Ok(MaybeHttpsStream::Http(stream));
+            }
+            let stream = tls.connect(&host, stream).await?;
+            if !pins.is_empty() {
+                check_pins(&host, &pins, &stream.peer_certificates())?;
+            }
+            Ok(MaybeHttpsStream::Https(stream))
+        })
+    }
+}
+
+/// Passes if any of the certificates matches a pin.
+pub(crate) fn check_pins(
+    host: &str,
+    pins: &[[u8; 32]],
+    certs_der: &[Vec<u8>],
+) -> Result<(), PinMismatch> {
+    for der in certs_der.iter() {
+        let cert_hash: [u8; 32] = Sha256::digest(der).into();
+        let spki_hash: Option<[u8; 32]> = spki(der).map(|s| Sha256::digest(s).into());
+        if pins
+            .iter()
+            .any(|p| *p == cert_hash || Some(*p) == spki_hash)
+        {
+            return Ok(());
+        }
+    }
+    Err(PinMismatch {
+        host: host.to_string(),
+        presented: certs_der
+            .first()
+            .and_then(|der| spki(der))
+            .map(|s| STANDARD.encode(Sha256::digest(s))),
+    })
+}
+
+fn read_file(path: &Path) -> io::Result<Vec<u8>> {
+    fs::read(path)
+        .map_err(|e| Error::new(e.kind(), format!("failed to read {} {}", path.display(), e)))
+}
+
+/// RUST_LOG=debug cargo test --lib -- tls::test_client_identity --exact --show-output
+#[tokio::test]
+async fn test_client_identity() {
+    use hyper::{client::HttpConnector, Method};
+
+    let cert = rcgen::generate_simple_self_signed(vec!["client".to_string()]).unwrap();
+    let cert_pem = cert.serialize_pem().unwrap();
+    let key_pem = cert.serialize_private_key_pem();
+
+    let dir = std::env::temp_dir();
+    let (cert_path, key_path) = (
+        dir.join("http-manager-test-client.crt"),
+        dir.join("http-manager-test-client.key"),
+    );
+    fs::write(&cert_path, &cert_pem).unwrap();
+    fs::write(&key_path, &key_pem).unwrap();
+    let identity = ClientIdentity::from_pem_files(&cert_path, &key_path).unwrap();
+    assert_eq!(
+        identity,
+        ClientIdentity::from_pem(cert_pem.clone(), key_pem.clone())
+    );
+    assert!(!format!("{:?}", identity).contains("PRIVATE KEY"));
+    fs::remove_file(&cert_path).unwrap();
+    fs::remove_file(&key_path).unwrap();
+
+    let tls = TlsConfig::new().client_identity(identity);
+    tls.https_connector(HttpConnector::new(), false, false)
+        .unwrap();
+
+    let e = TlsConfig::new()
+        .client_identity(ClientIdentity::from_pem(cert_pem.clone(), "invalid"))
+        .https_connector(HttpConnector::new(), false, false)
+        .unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidInput);
+    let e =
+        ClientIdentity::from_pkcs12_file(dir.join("http-manager-nonexistent.p12"), "").unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::NotFound);
+
+    // the test server does not request client certificates, but accepts
+    // connections that present one
+    let server = crate::testing::MockServer::start_https().await.unwrap();
+    server.stub(Method::GET, "/", 200, "ok");
+    let manager = crate::Manager::builder()
+        .tls_config(tls.clone())
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap();
+    let req = crate::create_get(server.url(), "/").unwrap();
+    assert_eq!(manager.read_bytes(req, true).await.unwrap(), "ok");
+
+    let e = crate::Manager::builder()
+        .tls_config(TlsConfig::new().client_identity(ClientIdentity::from_pkcs12(vec![0u8; 4], "")))
+        .build()
+        .unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidInput);
+}
+
+/// RUST_LOG=debug cargo test --lib -- tls::test_root_certificates --exact --show-output
+#[tokio::test]
+async fn test_root_certificates() {
+    use std::time::Duration;
+
+    use hyper::{client::HttpConnector, Method};
+
+    let server = crate::testing::MockServer::start_https().await.unwrap();
+    server.stub(Method::GET, "/", 200, "ok");
+    let cert_pem = server.cert_pem().unwrap().to_string();
+
+    let dir = std::env::temp_dir().join("http-manager-test-roots");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("server.pem"), &cert_pem).unwrap();
+    fs::write(dir.join("README"), "not a certificate").unwrap();
+    let from_dir = TlsConfig::new().add_root_pem_dir(&dir).unwrap();
+    assert_eq!(from_dir, TlsConfig::new().add_root_pem(cert_pem.clone()));
+    fs::remove_dir_all(&dir).unwrap();
+
+    // the self-signed server is trusted without skipping verification
+    let tls = TlsConfig::new()
+        .add_root_pem(cert_pem.clone())
+        .built_in_roots(false);
+    let req = crate::create_get(server.url(), "/").unwrap();
+    let ret = crate::read_bytes_with_tls(req, Duration::from_secs(5), &tls, true).await;
+    assert_eq!(ret.unwrap(), "ok");
+
+    let manager = crate::Manager::builder().tls_config(tls).build().unwrap();
+    let req = crate::create_get(server.url(), "/").unwrap();
+    assert_eq!(manager.read_bytes(req, true).await.unwrap(), "ok");
+
+    // a different root is not enough
+    let other = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let tls = TlsConfig::new().add_root_der(other.serialize_der().unwrap());
+    let req = crate::create_get(server.url(), "/").unwrap();
+    assert!(
+        crate::read_bytes_with_tls(req, Duration::from_secs(5), &tls, true)
+            .await
+            .is_err()
+    );
+
+    let e = TlsConfig::new()
+        .add_root_pem("not a certificate")
+        .https_connector(HttpConnector::new(), false, false)
+        .unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidInput);
+}
+
+/// RUST_LOG=debug cargo test --lib -- tls::test_danger_accept_invalid_certs --exact --show-output
+#[tokio::test]
+async fn test_danger_accept_invalid_certs() {
+    use std::time::Duration;
+
+    use hyper::Method;
+
+    let server = crate::testing::MockServer::start_https().await.unwrap();
+    server.stub(Method::GET, "/", 200, "ok");
+
+    let tls = TlsConfig::new().danger_accept_invalid_certs(true);
+    assert!(tls.accepts_invalid_certs());
+    let req = crate::create_get(server.url(), "/").unwrap();
+    let ret = crate::read_bytes_with_tls(req, Duration::from_secs(5), &tls, true).await;
+    assert_eq!(ret.unwrap(), "ok");
+
+    let manager = crate::Manager::builder()
+        .tls_config(tls.clone())
+        .build()
+        .unwrap();
+    let req = crate::create_get(server.url(), "/").unwrap();
+    assert_eq!(manager.read_bytes(req, true).await.unwrap(), "ok");
+
+    // a host override enables verification again
+    let manager = crate::Manager::builder()
+        .tls_config(tls)
+        .host_override(
+            "localhost",
+            crate::HostOverride::new().danger_accept_invalid_certs(false),
+        )
+        .build()
+        .unwrap();
+    let req = crate::create_get(server.url(), "/").unwrap();
+    assert!(manager.read_bytes(req, true).await.is_err());
+}
+
+/// RUST_LOG=debug cargo test --lib -- tls::test_pin_sha256 --exact --show-output
+#[tokio::test]
+async fn test_pin_sha256() {
+    use std::time::Duration;
+
+    use hyper::Method;
+
+    let server = crate::testing::MockServer::start_https().await.unwrap();
+    server.stub(Method::GET, "/", 200, "ok");
+    let cert_der = pem_to_der(server.cert_pem().unwrap());
+    let pin = spki_sha256(&cert_der).unwrap();
+    let cert_pin = STANDARD.encode(Sha256::digest(&cert_der));
+    let other = STANDARD.encode([0u8; 32]);
+
+    // the self-signed certificate is trusted as a root, and pinned
+    let trusted = TlsConfig::new().add_root_pem(server.cert_pem().unwrap());
+    for pins in [vec![pin.as_str()], vec![other.as_str(), cert_pin.as_str()]] {
+        let tls = trusted.clone().pin_sha256(&pins).unwrap();
+        let req = crate::create_get(server.url(), "/").unwrap();
+        let ret = crate::read_bytes_with_tls(req, Duration::from_secs(5), &tls, true).await;
+        assert_eq!(ret.unwrap(), "ok");
+    }
+    let tls = trusted
+        .clone()
+        .pin_sha256(&[&format!("sha256//{}", pin)])
+        .unwrap();
+    let manager = crate::Manager::builder().tls_config(tls).build().unwrap();
+    let req = crate::create_get(server.url(), "/").unwrap();
+    assert_eq!(manager.read_bytes(req, true).await.unwrap(), "ok");
+
+    // a valid chain with a different key fails before the request is sent
+    let tls = trusted.pin_sha256(&[&other]).unwrap();
+    let manager = crate::Manager::builder().tls_config(tls).build().unwrap();
+    let req = crate::create_get(server.url(), "/").unwrap();
+    let e = manager.read_bytes(req, true).await.unwrap_err();
+    match crate::error::Error::from(e) {
+        crate::error::Error::PinMismatch(e) => {
+            assert_eq!(e.host, "localhost");
+            assert_eq!(e.presented, Some(pin.clone()));
+        }
+        e => panic!("unexpected {:?}", e),
+    }
+    // also over the connections of the probes
+    let req = crate::create_get(server.url(), "/").unwrap();
+    let e = manager
+        .send_with_informational(req, |_| {})
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        crate::error::Error::from(e),
+        crate::error::Error::PinMismatch(_)
+    ));
+    // only the three requests above reached the server
+    assert_eq!(server.received_requests().len(), 3);
+
+    assert!(TlsConfig::new().pin_sha256(&["not base64!"]).is_err());
+    assert!(TlsConfig::new()
+        .pin_sha256(&[&STANDARD.encode([0u8; 16])])
+        .is_err());
+    assert!(spki_sha256(b"not DER").is_err());
+}
+
+/// Decodes the first PEM block.
+#[cfg(test)]
+fn pem_to_der(pem: &str) -> Vec<u8> {
+    let b64: String = pem
+        .lines()
+        .skip_while(|l| !l.starts_with("-----BEGIN"))
+        .skip(1)
+        .take_while(|l| !l.starts_with("-----END"))
+        .collect();
+    STANDARD.decode(b64).unwrap()
+}
+
+/// RUST_LOG=debug cargo test --lib -- tls::test_backends --exact --show-output
+#[tokio::test]
+async fn test_backends() {
+    use std::time::Duration;
+
+    use hyper::Method;
+
+    let server = crate::testing::MockServer::start_https().await.unwrap();
+    server.stub(Method::GET, "/", 200, "ok");
+    let cert_pem = server.cert_pem().unwrap().to_string();
+    let pin = spki_sha256(&pem_to_der(&cert_pem)).unwrap();
+    assert_eq!(TlsConfig::new().tls_backend(), TlsBackend::default());
+
+    let backends = [
+        #[cfg(feature = "native-tls")]
+        TlsBackend::NativeTls,
+        #[cfg(feature = "rustls")]
+        TlsBackend::Rustls,
+    ];
+    for backend in backends {
+        let trusted = TlsConfig::new()
+            .backend(backend)
+            .add_root_pem(cert_pem.clone())
+            .built_in_roots(false);
+        let req = crate::create_get(server.url(), "/").unwrap();
+        let ret = crate::read_bytes_with_tls(req, Duration::from_secs(5), &trusted, true).await;
+        assert_eq!(ret.unwrap(), "ok", "{:?}", backend);
+
+        // untrusted, unless verification is skipped
+        let tls = TlsConfig::new().backend(backend);
+        let req = crate::create_get(server.url(), "/").unwrap();
+        assert!(
+            crate::read_bytes_with_tls(req, Duration::from_secs(5), &tls, true)
+                .await
+                .is_err(),
+            "{:?}",
+            backend
+        );
+        let manager = crate::Manager::builder()
+            .tls_config(tls.danger_accept_invalid_certs(true))
+            .build()
+            .unwrap();
+        let req = crate::create_get(server.url(), "/").unwrap();
+        assert_eq!(manager.read_bytes(req, true).await.unwrap(), "ok");
+
+        let manager = crate::Manager::builder()
+            .tls_config(trusted.clone().pin_sha256(&[&pin]).unwrap())
+            .build()
+            .unwrap();
+        let req = crate::create_get(server.url(), "/").unwrap();
+        assert_eq!(manager.read_bytes(req, true).await.unwrap(), "ok");
+
+        // the native TLS mock server stops at TLS 1.2, the rustls one
+        // speaks TLS 1.3
+        for (version, ok) in [
+            (TlsVersion::Tls12, true),
+            (TlsVersion::Tls13, cfg!(not(feature = "native-tls"))),
+        ] {
+            let tls = trusted.clone().min_version(version);
+            let req = crate::create_get(server.url(), "/").unwrap();
+            let ret = crate::read_bytes_with_tls(req, Duration::from_secs(5), &tls, true).await;
+            assert_eq!(ret.is_ok(), ok, "{:?} {:?}", backend, version);
+        }
+    }
+
+    #[cfg(feature = "rustls")]
+    {
+        let e = TlsConfig::new()
+            .backend(TlsBackend::Rustls)
+            .client_identity(ClientIdentity::from_pkcs12(vec![0u8; 4], ""))
+            .tls_connector(false, false)
+            .unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidInput);
+    }
+}
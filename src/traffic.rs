@@ -0,0 +1,214 @@
+//! Request/response size accounting, to attribute egress (and ingress)
+//! bytes to upstream services.
+//!
+//! Sizes are of the HTTP/1.1 message (request or status line, headers and
+//! body, as sent by "Manager"), not including TLS framing or HTTP/2 header
+//! compression.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+};
+
+use hyper::{body::HttpBody, header::HOST, HeaderMap, Request, Response, Uri};
+
+/// Bytes of a single request and its response, attached to the responses
+/// of "Manager" as an extension (see "Manager::transfer_size").
+///
+/// "Manager::send" only knows the response headers, so the body counts
+/// for the responses read by "Manager::fetch" or "Manager::read_response".
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TransferSize {
+    pub request_header_bytes: u64,
+    /// Exact for in-memory bodies, a lower bound for streaming bodies.
+    pub request_body_bytes: u64,
+    pub response_header_bytes: u64,
+    pub response_body_bytes: u64,
+}
+
+impl TransferSize {
+    pub fn sent(&self) -> u64 {
+        self.request_header_bytes + self.request_body_bytes
+    }
+
+    pub fn received(&self) -> u64 {
+        self.response_header_bytes + self.response_body_bytes
+    }
+}
+
+/// Aggregate counters of a single upstream host.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HostTraffic {
+    pub requests: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+}
+
+/// Aggregate counters per upstream host ("host" or "host:port"), shared
+/// by a manager and its clones.
+#[derive(Debug, Default)]
+pub struct TrafficCounters {
+    hosts: Mutex<HashMap<String, HostTraffic>>,
+}
+
+impl TrafficCounters {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts a request sent to the host.
+    pub fn record_request(&self, host: &str, sent: u64, received: u64) {
+        let mut hosts = self.hosts.lock().unwrap();
+        let t = hosts.entry(host.to_string()).or_default();
+        t.requests += 1;
+        t.bytes_sent += sent;
+        t.bytes_received += received;
+    }
+
+    /// Counts response bytes read after the request was recorded
+    /// (e.g., the body).
+    pub fn record_received(&self, host: &str, received: u64) {
+        let mut hosts = self.hosts.lock().unwrap();
+        hosts.entry(host.to_string()).or_default().bytes_received += received;
+    }
+
+    pub fn get(&self, host: &str) -> Option<HostTraffic> {
+        self.hosts.lock().unwrap().get(host).copied()
+    }
+
+    /// Returns the counters of all hosts, sorted by host.
+    pub fn snapshot(&self) -> BTreeMap<String, HostTraffic> {
+        self.hosts
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(h, t)| (h.clone(), *t))
+            .collect()
+    }
+
+    /// Returns the sum over all hosts.
+    pub fn total(&self) -> HostTraffic {
+        self.hosts
+            .lock()
+            .unwrap()
+            .values()
+            .fold(HostTraffic::default(), |mut sum, t| {
+                sum.requests += t.requests;
+                sum.bytes_sent += t.bytes_sent;
+                sum.bytes_received += t.bytes_received;
+                sum
+            })
+    }
+
+    pub fn reset(&self) {
+        self.hosts.lock().unwrap().clear();
+    }
+}
+
+/// Returns the counter key of the request URI, "host" or "host:port".
+pub(crate) fn host_key(uri: &Uri) -> String {
+    uri.authority()
+        .map(|a| {
+            a.as_str()
+                .rsplit('@')
+                .next()
+                .unwrap_or("")
+                .to_ascii_lowercase()
+        })
+        .unwrap_or_default()
+}
+
+fn headers_size(headers: &HeaderMap) -> u64 {
+    headers
+        .iter()
+        .map(|(k, v)| (k.as_str().len() + 2 + v.len() + 2) as u64)
+        .sum()
+}
+
+/// Returns the size of the request line and headers, including the
+/// "Host" header that the client adds if missing.
+pub(crate) fn request_head_size<B>(req: &Request<B>) -> u64 {
+    let target = req
+        .uri()
+        .path_and_query()
+        .map(|p| p.as_str().len())
+        .unwrap_or(1);
+    // "GET /path HTTP/1.1\r\n"
+    let mut n = (req.method().as_str().len() + 1 + target + 11) as u64;
+    n += headers_size(req.headers());
+    if !req.headers().contains_key(HOST) {
+        n += ("host: ".len() + host_key(req.uri()).len() + 2) as u64;
+    }
+    n + 2
+}
+
+/// Returns the size of the request body if known, or its lower bound.
+pub(crate) fn request_body_size<B: HttpBody>(req: &Request<B>) -> u64 {
+    let hint = req.body().size_hint();
+    hint.exact().unwrap_or_else(|| hint.lower())
+}
+
+/// Returns the size of the status line and headers.
+pub(crate) fn response_head_size<B>(resp: &Response<B>) -> u64 {
+    // "HTTP/1.1 200 OK\r\n"
+    let reason = resp.status().canonical_reason().unwrap_or("").len();
+    let n = (9 + 3 + 1 + reason + 2) as u64;
+    n + headers_size(resp.headers()) + 2
+}
+
+#[test]
+fn test_message_sizes() {
+    use hyper::{Body, StatusCode};
+
+    let req = Request::post("http://user:pw@Example.com:8080/a?b=c")
+        .header("x-a", "1")
+        .body(Body::from("hello"))
+        .unwrap();
+    assert_eq!(host_key(req.uri()), "example.com:8080");
+    // "POST /a?b=c HTTP/1.1\r\n" + "x-a: 1\r\n" + "host: example.com:8080\r\n" + "\r\n"
+    assert_eq!(request_head_size(&req), 22 + 8 + 24 + 2);
+    assert_eq!(request_body_size(&req), 5);
+
+    let resp = Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .header("content-length", "0")
+        .body(())
+        .unwrap();
+    // "HTTP/1.1 404 Not Found\r\n" + "content-length: 0\r\n" + "\r\n"
+    assert_eq!(response_head_size(&resp), 24 + 19 + 2);
+}
+
+/// RUST_LOG=debug cargo test --lib -- traffic::test_manager_traffic --exact --show-output
+#[tokio::test]
+async fn test_manager_traffic() {
+    use hyper::Method;
+
+    let server = crate::testing::MockServer::start().await.unwrap();
+    server.stub(Method::GET, "/a", 200, "hello");
+    server.stub(Method::POST, "/b", 201, "");
+
+    let manager = crate::Manager::new().unwrap();
+    let resp = manager
+        .read_response(crate::create_get(server.url(), "/a").unwrap())
+        .await
+        .unwrap();
+    let size = crate::Manager::transfer_size(&resp).unwrap();
+    assert_eq!(size.response_body_bytes, 5);
+    assert!(size.response_header_bytes > 0);
+    assert_eq!(size.request_body_bytes, 0);
+
+    let req = crate::create_json_post(server.url(), "/b", "{\"a\":1}".to_string()).unwrap();
+    let resp = manager.clone().read_response(req).await.unwrap();
+    let size2 = crate::Manager::transfer_size(&resp).unwrap();
+    assert_eq!(size2.request_body_bytes, 7);
+
+    let host = format!("127.0.0.1:{}", server.port());
+    let t = manager.traffic().get(&host).unwrap();
+    assert_eq!(t.requests, 2);
+    assert_eq!(t.bytes_sent, size.sent() + size2.sent());
+    assert_eq!(t.bytes_received, size.received() + size2.received());
+    assert_eq!(manager.traffic().total(), t);
+
+    manager.traffic().reset();
+    assert!(manager.traffic().snapshot().is_empty());
+}
@@ -0,0 +1,321 @@
+//! RFC 6570 URI templates, up to level 3.
+//! ref. <https://www.rfc-editor.org/rfc/rfc6570>
+
+use std::{
+    fmt::Write,
+    io::{self, Error, ErrorKind},
+};
+
+use url::Url;
+
+/// Parsed URI template (e.g., "/repos/{owner}/{repo}/releases{?per_page,page}").
+/// Supports simple, reserved ("+"), fragment ("#"), label ("."),
+/// path segment ("/"), path-style (";"), and form-style ("?", "&")
+/// expressions with string values. Level 4 modifiers (prefix ":n" and
+/// explode "*") are rejected at parse time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UriTemplate {
+    template: String,
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Expression { op: Operator, vars: Vec<String> },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operator {
+    Simple,
+    Reserved,
+    Fragment,
+    Label,
+    PathSegment,
+    PathStyle,
+    FormQuery,
+    FormContinuation,
+}
+
+impl Operator {
+    fn parse(c: char) -> Option<Self> {
+        match c {
+            '+' => Some(Operator::Reserved),
+            '#' => Some(Operator::Fragment),
+            '.' => Some(Operator::Label),
+            '/' => Some(Operator::PathSegment),
+            ';' => Some(Operator::PathStyle),
+            '?' => Some(Operator::FormQuery),
+            '&' => Some(Operator::FormContinuation),
+            _ => None,
+        }
+    }
+
+    /// Returns (first, separator, named, if-empty, allow-reserved)
+    /// as defined in RFC 6570 Appendix A.
+    fn rules(&self) -> (&'static str, &'static str, bool, &'static str, bool) {
+        match self {
+            Operator::Simple => ("", ",", false, "", false),
+            Operator::Reserved => ("", ",", false, "", true),
+            Operator::Fragment => ("#", ",", false, "", true),
+            Operator::Label => (".", ".", false, "", false),
+            Operator::PathSegment => ("/", "/", false, "", false),
+            Operator::PathStyle => (";", ";", true, "", false),
+            Operator::FormQuery => ("?", "&", true, "=", false),
+            Operator::FormContinuation => ("&", "&", true, "=", false),
+        }
+    }
+}
+
+impl UriTemplate {
+    pub fn new(template: &str) -> io::Result<Self> {
+        let mut parts = Vec::new();
+        let mut rest = template;
+        while !rest.is_empty() {
+            match rest.find('{') {
+                Some(start) => {
+                    if start > 0 {
+                        parts.push(Part::Literal(rest[..start].to_string()));
+                    }
+                    let end = rest[start..].find('}').ok_or_else(|| {
+                        Error::new(
+                            ErrorKind::InvalidInput,
+                            format!("unclosed expression in URI template '{}'", template),
+                        )
+                    })? + start;
+                    parts.push(parse_expression(&rest[start + 1..end], template)?);
+                    rest = &rest[end + 1..];
+                }
+                None => {
+                    if rest.contains('}') {
+                        return Err(Error::new(
+                            ErrorKind::InvalidInput,
+                            format!("unmatched '}}' in URI template '{}'", template),
+                        ));
+                    }
+                    parts.push(Part::Literal(rest.to_string()));
+                    rest = "";
+                }
+            }
+        }
+
+        Ok(Self {
+            template: template.to_string(),
+            parts,
+        })
+    }
+
+    /// Returns the variable names in the order they appear.
+    pub fn variables(&self) -> Vec<&str> {
+        let mut out = Vec::new();
+        for part in self.parts.iter() {
+            if let Part::Expression { vars, .. } = part {
+                out.extend(vars.iter().map(|v| v.as_str()));
+            }
+        }
+        out
+    }
+
+    /// Expands the template with the given variables.
+    /// Variables not in "vars" are undefined and omitted from the output.
+    pub fn expand(&self, vars: &[(&str, &str)]) -> String {
+        let mut out = String::with_capacity(self.template.len());
+        for part in self.parts.iter() {
+            match part {
+                Part::Literal(lit) => encode_into(&mut out, lit, true),
+                Part::Expression { op, vars: names } => {
+                    let (first, sep, named, if_empty, allow_reserved) = op.rules();
+                    let mut is_first = true;
+                    for name in names.iter() {
+                        let value = match vars.iter().find(|(k, _)| k == name) {
+                            Some((_, v)) => *v,
+                            None => continue,
+                        };
+                        out.push_str(if is_first { first } else { sep });
+                        is_first = false;
+
+                        if named {
+                            out.push_str(name);
+                            if value.is_empty() {
+                                out.push_str(if_empty);
+                                continue;
+                            }
+                            out.push('=');
+                        }
+                        encode_into(&mut out, value, allow_reserved);
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    /// Expands the template and joins the result to the base URL
+    /// (see "crate::join_uri").
+    pub fn expand_uri(&self, url: &str, vars: &[(&str, &str)]) -> io::Result<Url> {
+        crate::join_uri(url, &self.expand(vars))
+    }
+}
+
+impl std::fmt::Display for UriTemplate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.template)
+    }
+}
+
+fn parse_expression(expr: &str, template: &str) -> io::Result<Part> {
+    let mut chars = expr.chars();
+    let (op, body) = match chars.next().and_then(Operator::parse) {
+        Some(op) => (op, chars.as_str()),
+        None => (Operator::Simple, expr),
+    };
+
+    let mut vars = Vec::new();
+    for name in body.split(',') {
+        if name.ends_with('*') || name.contains(':') {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "unsupported level 4 modifier in '{}' of URI template '{}'",
+                    name, template
+                ),
+            ));
+        }
+        let valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '%');
+        if !valid {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "invalid variable name '{}' in URI template '{}'",
+                    name, template
+                ),
+            ));
+        }
+        vars.push(name.to_string());
+    }
+
+    Ok(Part::Expression { op, vars })
+}
+
+fn is_unreserved(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~')
+}
+
+fn is_reserved(b: u8) -> bool {
+    matches!(
+        b,
+        b':' | b'/'
+            | b'?'
+            | b'#'
+            | b'['
+            | b']'
+            | b'@'
+            | b'!'
+            | b'$'
+            | b'&'
+            | b'\''
+            | b'('
+            | b')'
+            | b'*'
+            | b'+'
+            | b','
+            | b';'
+            | b'='
+    )
+}
+
+/// Percent-encodes "s" into "out", keeping unreserved characters, and
+/// reserved characters and existing pct-encoded triplets if "allow_reserved".
+fn encode_into(out: &mut String, s: &str, allow_reserved: bool) {
+    let bytes = s.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        if is_unreserved(b) || (allow_reserved && is_reserved(b)) {
+            out.push(b as char);
+        } else if allow_reserved
+            && b == b'%'
+            && i + 2 < bytes.len()
+            && bytes[i + 1].is_ascii_hexdigit()
+            && bytes[i + 2].is_ascii_hexdigit()
+        {
+            out.push_str(&s[i..i + 3]);
+            i += 3;
+            continue;
+        } else {
+            let _ = write!(out, "%{:02X}", b);
+        }
+        i += 1;
+    }
+}
+
+/// RUST_LOG=debug cargo test --lib -- uri_template::test_uri_template --exact --show-output
+#[test]
+fn test_uri_template() {
+    // examples from RFC 6570 section 1.2
+    let vars = [
+        ("var", "value"),
+        ("hello", "Hello World!"),
+        ("path", "/foo/bar"),
+        ("empty", ""),
+        ("x", "1024"),
+        ("y", "768"),
+    ];
+    let cases = [
+        ("{var}", "value"),
+        ("{hello}", "Hello%20World%21"),
+        ("{+var}", "value"),
+        ("{+hello}", "Hello%20World!"),
+        ("{+path}/here", "/foo/bar/here"),
+        ("here?ref={+path}", "here?ref=/foo/bar"),
+        ("X{#var}", "X#value"),
+        ("X{#hello}", "X#Hello%20World!"),
+        ("map?{x,y}", "map?1024,768"),
+        ("{x,hello,y}", "1024,Hello%20World%21,768"),
+        ("{+x,hello,y}", "1024,Hello%20World!,768"),
+        ("{+path,x}/here", "/foo/bar,1024/here"),
+        ("{#x,hello,y}", "#1024,Hello%20World!,768"),
+        ("X{.var}", "X.value"),
+        ("X{.x,y}", "X.1024.768"),
+        ("{/var}", "/value"),
+        ("{/var,x}/here", "/value/1024/here"),
+        ("{;x,y}", ";x=1024;y=768"),
+        ("{;x,y,empty}", ";x=1024;y=768;empty"),
+        ("{?x,y}", "?x=1024&y=768"),
+        ("{?x,y,empty}", "?x=1024&y=768&empty="),
+        ("?fixed=yes{&x}", "?fixed=yes&x=1024"),
+        ("{&x,y,empty}", "&x=1024&y=768&empty="),
+        ("{?undef}", ""),
+        ("{?x,undef,y}", "?x=1024&y=768"),
+    ];
+    for (template, expected) in cases {
+        let t = UriTemplate::new(template).unwrap();
+        assert_eq!(t.expand(&vars), expected, "template {}", template);
+    }
+
+    let t = UriTemplate::new("/repos/{owner}/{repo}/releases{?per_page}").unwrap();
+    assert_eq!(t.variables(), vec!["owner", "repo", "per_page"]);
+    let u = t
+        .expand_uri(
+            "https://api.github.com",
+            &[
+                ("owner", "ava-labs"),
+                ("repo", "avalanchego"),
+                ("per_page", "5"),
+            ],
+        )
+        .unwrap();
+    assert_eq!(
+        u.as_str(),
+        "https://api.github.com/repos/ava-labs/avalanchego/releases?per_page=5"
+    );
+
+    assert!(UriTemplate::new("{var").is_err());
+    assert!(UriTemplate::new("var}").is_err());
+    assert!(UriTemplate::new("{var*}").is_err());
+    assert!(UriTemplate::new("{var:3}").is_err());
+    assert!(UriTemplate::new("{}").is_err());
+}
@@ -0,0 +1,343 @@
+//! Webhook delivery: signs the payload with HMAC-SHA256 and retries
+//! transient failures with exponential backoff and jitter.
+
+use std::{
+    io::{self, Error, ErrorKind},
+    sync::Mutex,
+    time::Duration,
+};
+
+use hmac::{Hmac, Mac};
+use hyper::{
+    body::Bytes,
+    header::{HeaderValue, CONTENT_TYPE},
+    Body, Method, Request,
+};
+use rand::Rng;
+use sha2::Sha256;
+
+use crate::{
+    clock::{Clock, TokioClock},
+    logging::wire_info,
+    retry::{self, RetryPolicy},
+    Manager,
+};
+
+/// Header with the hex-encoded HMAC-SHA256 of the body
+/// (e.g., "sha256=5d5d...").
+pub const SIGNATURE_HEADER: &str = "x-webhook-signature";
+/// Header with the delivery ID, the same for all attempts of a delivery,
+/// so that receivers can deduplicate retries.
+pub const DELIVERY_ID_HEADER: &str = "x-webhook-id";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WebhookConfig {
+    /// Timeout of each attempt.
+    pub timeout: Duration,
+    /// Retries of the retryable status codes, and of connection errors and
+    /// timeouts (see "retry::is_retryable_error"). Every attempt is sent,
+    /// since receivers deduplicate them by the delivery ID.
+    pub retry: RetryPolicy,
+    pub content_type: String,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        let mut retry_on_status = vec![429];
+        retry_on_status.extend(500..=599);
+        Self {
+            timeout: Duration::from_secs(10),
+            retry: RetryPolicy {
+                max_attempts: 5,
+                base_delay: Duration::from_millis(500),
+                max_delay: Duration::from_secs(30),
+                jitter: 0.5,
+                retry_on_status,
+                retry_non_idempotent: true,
+            },
+            content_type: "application/json".to_string(),
+        }
+    }
+}
+
+/// Result of a single delivery attempt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeliveryAttempt {
+    /// Set if a response was received.
+    pub status: Option<u16>,
+    /// Set if no response was received.
+    pub error: Option<String>,
+    pub latency: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeliveryReport {
+    pub delivery_id: String,
+    /// True if an attempt received a 2xx response.
+    pub delivered: bool,
+    pub attempts: Vec<DeliveryAttempt>,
+}
+
+/// Returns the signature header value of the body.
+pub fn sign(secret: &[u8], body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts any key length");
+    mac.update(body);
+    format!("sha256={:x}", mac.finalize().into_bytes())
+}
+
+/// Verifies the signature header value of the body in constant time
+/// (the receiver half of "sign").
+pub fn verify(secret: &[u8], body: &[u8], signature: &str) -> bool {
+    let hex = match signature.strip_prefix("sha256=") {
+        Some(h) => h,
+        None => return false,
+    };
+    let expected = match decode_hex(hex) {
+        Some(b) => b,
+        None => return false,
+    };
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts any key length");
+    mac.update(body);
+    mac.verify_slice(&expected).is_ok()
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Delivers the payload with a default manager and config.
+pub async fn deliver(
+    url: &str,
+    payload: impl Into<Bytes>,
+    secret: &[u8],
+) -> io::Result<DeliveryReport> {
+    Manager::new()?
+        .deliver_webhook(url, payload, secret, &WebhookConfig::default())
+        .await
+}
+
+impl Manager {
+    /// POSTs the signed payload, retrying connection errors, timeouts,
+    /// 429, and 5xx responses with the backoff of "WebhookConfig::retry".
+    /// Other responses and errors (e.g., DNS failures) are final. Fails
+    /// only on an invalid URL or one rejected by the manager policies;
+    /// delivery failures are reported in "DeliveryReport".
+    pub async fn deliver_webhook(
+        &self,
+        url: &str,
+        payload: impl Into<Bytes>,
+        secret: &[u8],
+        cfg: &WebhookConfig,
+    ) -> io::Result<DeliveryReport> {
+        self.deliver_webhook_with_clock(url, payload.into(), secret, cfg, &TokioClock)
+            .await
+    }
+
+    pub(crate) async fn deliver_webhook_with_clock(
+        &self,
+        url: &str,
+        payload: Bytes,
+        secret: &[u8],
+        cfg: &WebhookConfig,
+        clock: &dyn Clock,
+    ) -> io::Result<DeliveryReport> {
+        let content_type = HeaderValue::from_str(&cfg.content_type).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid content type '{}' {}", cfg.content_type, e),
+            )
+        })?;
+        let signature = sign(secret, &payload);
+        let delivery_id = format!("{:032x}", rand::thread_rng().gen::<u128>());
+
+        let delivery_id = delivery_id.as_str();
+        let attempts = Mutex::new(Vec::with_capacity(cfg.retry.max_attempts));
+        let ret = retry::run(&cfg.retry, true, clock, delivery_id, || {
+            let req = Request::builder()
+                .method(Method::POST)
+                .uri(url)
+                .header(CONTENT_TYPE, content_type.clone())
+                .header(SIGNATURE_HEADER, signature.as_str())
+                .header(DELIVERY_ID_HEADER, delivery_id)
+                .body(Body::from(payload.clone()))
+                .map_err(|e| {
+                    Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("failed to create webhook request {}", e),
+                    )
+                });
+            let attempts = &attempts;
+            async move {
+                let start = clock.now();
+                let ret = self.fetch(req?, cfg.timeout).await;
+                let attempt = match &ret {
+                    Ok(resp) => DeliveryAttempt {
+                        status: Some(resp.status().as_u16()),
+                        error: None,
+                        latency: clock.now() - start,
+                    },
+                    Err(e) => DeliveryAttempt {
+                        status: None,
+                        error: Some(e.to_string()),
+                        latency: clock.now() - start,
+                    },
+                };
+                attempts.lock().unwrap().push(attempt);
+                ret.map(|resp| (resp.status(), ()))
+            }
+        })
+        .await;
+        let delivered = match ret {
+            Ok(attempted) => attempted.value.0.is_success(),
+            // policy violations do not go away with retries
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    ErrorKind::InvalidInput | ErrorKind::PermissionDenied
+                ) =>
+            {
+                return Err(e)
+            }
+            Err(_) => false,
+        };
+        let report = DeliveryReport {
+            delivery_id: delivery_id.to_string(),
+            delivered,
+            attempts: attempts.into_inner().unwrap(),
+        };
+
+        wire_info!(
+            "webhook delivery {} to {} (delivered {}, attempts {})",
+            report.delivery_id,
+            crate::redact::url(url),
+            report.delivered,
+            report.attempts.len()
+        );
+        Ok(report)
+    }
+}
+
+#[test]
+fn test_sign_verify() {
+    // ref. RFC 4231 test case 2
+    let sig = sign(b"Jefe", b"what do ya want for nothing?");
+    assert_eq!(
+        sig,
+        "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+    );
+    assert!(verify(b"Jefe", b"what do ya want for nothing?", &sig));
+    assert!(!verify(b"Jefe", b"what do ya want?", &sig));
+    assert!(!verify(b"other", b"what do ya want for nothing?", &sig));
+    assert!(!verify(
+        b"Jefe",
+        b"what do ya want for nothing?",
+        "sha256=zz"
+    ));
+    assert!(!verify(b"Jefe", b"what do ya want for nothing?", "md5=00"));
+}
+
+/// RUST_LOG=debug cargo test --lib -- webhook::test_deliver_webhook --exact --show-output
+#[tokio::test]
+async fn test_deliver_webhook() {
+    use crate::clock::MockClock;
+
+    let server = crate::testing::MockServer::start().await.unwrap();
+    server.stub(Method::POST, "/hook", 503, "busy");
+    let url = format!("{}/hook", server.url());
+    let manager = Manager::new().unwrap();
+    let mut cfg = WebhookConfig::default();
+    cfg.retry.max_attempts = 4;
+    cfg.retry.jitter = 0.0;
+
+    let clock = MockClock::new();
+    let report = manager
+        .deliver_webhook_with_clock(&url, Bytes::from(r#"{"id":1}"#), b"s3cret", &cfg, &clock)
+        .await
+        .unwrap();
+    assert!(!report.delivered);
+    assert_eq!(report.attempts.len(), 4);
+    assert!(report.attempts.iter().all(|a| a.status == Some(503)));
+    assert_eq!(
+        clock.sleeps(),
+        vec![
+            Duration::from_millis(500),
+            Duration::from_secs(1),
+            Duration::from_secs(2)
+        ]
+    );
+
+    let received = server.received_requests();
+    assert_eq!(received.len(), 4);
+    for r in received.iter() {
+        let sig = r.headers.get(SIGNATURE_HEADER).unwrap().to_str().unwrap();
+        assert!(verify(b"s3cret", &r.body, sig));
+        assert_eq!(
+            r.headers.get(DELIVERY_ID_HEADER).unwrap(),
+            report.delivery_id.as_str()
+        );
+    }
+
+    // 4xx responses are final
+    server.stub(Method::POST, "/hook", 400, "bad");
+    let report = manager
+        .deliver_webhook_with_clock(&url, Bytes::from("{}"), b"s3cret", &cfg, &clock)
+        .await
+        .unwrap();
+    assert!(!report.delivered);
+    assert_eq!(report.attempts.len(), 1);
+
+    server.stub(Method::POST, "/hook", 200, "ok");
+    let report = deliver(&url, r#"{"id":2}"#, b"s3cret").await.unwrap();
+    assert!(report.delivered);
+    assert_eq!(report.attempts.len(), 1);
+
+    assert!(deliver("ftp://localhost/hook", "{}", b"s3cret")
+        .await
+        .is_err());
+
+    // jittered backoff, within the exponential delays
+    server.stub(Method::POST, "/hook", 429, "slow down");
+    let clock = MockClock::new();
+    cfg.retry.jitter = 0.5;
+    let report = manager
+        .deliver_webhook_with_clock(&url, Bytes::from("{}"), b"s3cret", &cfg, &clock)
+        .await
+        .unwrap();
+    assert_eq!(report.attempts.len(), 4);
+    for (d, max) in clock.sleeps().into_iter().zip([500, 1000, 2000]) {
+        let max = Duration::from_millis(max);
+        assert!(d >= max / 2 && d <= max, "{:?} not within {:?}", d, max);
+    }
+
+    // connection errors are retried
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let closed = format!("http://{}/hook", listener.local_addr().unwrap());
+    drop(listener);
+    let report = manager
+        .deliver_webhook_with_clock(&closed, Bytes::from("{}"), b"s3cret", &cfg, &clock)
+        .await
+        .unwrap();
+    assert!(!report.delivered);
+    assert_eq!(report.attempts.len(), 4);
+    assert!(report.attempts.iter().all(|a| a.error.is_some()));
+
+    // DNS failures are final
+    let report = manager
+        .deliver_webhook_with_clock(
+            "http://webhook.invalid/hook",
+            Bytes::from("{}"),
+            b"s3cret",
+            &cfg,
+            &clock,
+        )
+        .await
+        .unwrap();
+    assert!(!report.delivered);
+    assert_eq!(report.attempts.len(), 1);
+}
@@ -0,0 +1,206 @@
+//! WebSocket client (e.g., JSON-RPC subscriptions), opened over the
+//! manager's HTTP client, so it shares the TLS settings, timeouts, default
+//! headers, and host policies of the manager. The handshake requires
+//! HTTP/1.1: servers that negotiate HTTP/2 (see "ManagerBuilder::http2")
+//! reject the upgrade.
+//!
+//! ```ignore
+//! use futures_util::{SinkExt, StreamExt};
+//!
+//! let mut ws = manager.connect_ws("wss://rpc.example.com", "/ws").await?;
+//! ws.send(Message::Text(sub.to_string())).await?;
+//! while let Some(msg) = ws.next().await {
+//!     ...
+//! }
+//! ```
+
+use std::io::{self, Error, ErrorKind};
+
+use hyper::{
+    header::{CONNECTION, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY, SEC_WEBSOCKET_VERSION, UPGRADE},
+    upgrade::Upgraded,
+    Body, HeaderMap, Method, Request, StatusCode,
+};
+use tokio_tungstenite::{
+    tungstenite::{
+        handshake::{client::generate_key, derive_accept_key},
+        protocol::Role,
+    },
+    WebSocketStream,
+};
+
+pub use tokio_tungstenite::tungstenite::{protocol::WebSocketConfig, Message};
+
+use crate::{error, logging::wire_debug, IntoUrl, Manager};
+
+/// WebSocket connection: a "Stream" of received messages and a "Sink" of
+/// messages to send (see "futures_util::{StreamExt, SinkExt}"). Pings are
+/// answered automatically while the stream is polled.
+pub type WebSocket = WebSocketStream<Upgraded>;
+
+impl Manager {
+    /// Opens a WebSocket to the path joined to the URL (see "join_uri"),
+    /// with the "ws", "wss", "http", or "https" scheme.
+    pub async fn connect_ws(&self, url: impl IntoUrl, path: &str) -> io::Result<WebSocket> {
+        self.connect_ws_with_headers(url, path, HeaderMap::new(), None)
+            .await
+    }
+
+    /// Opens a WebSocket with the handshake headers (e.g.,
+    /// "Sec-WebSocket-Protocol" or "Authorization") and the message size
+    /// limits (tungstenite defaults if None).
+    pub async fn connect_ws_with_headers(
+        &self,
+        url: impl IntoUrl,
+        path: &str,
+        headers: HeaderMap,
+        config: Option<WebSocketConfig>,
+    ) -> io::Result<WebSocket> {
+        let mut u = crate::join_uri(url, path)?;
+        let scheme = match u.scheme() {
+            "ws" | "http" => "http",
+            "wss" | "https" => "https",
+            s => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("scheme '{}' is not a WebSocket scheme", s),
+                ))
+            }
+        };
+        u.set_scheme(scheme)
+            .map_err(|_| error::Error::UrlParse(format!("failed to set scheme of '{}'", u)))?;
+
+        let key = generate_key();
+        let mut req = Request::builder()
+            .method(Method::GET)
+            .uri(u.as_str())
+            .header(CONNECTION, "Upgrade")
+            .header(UPGRADE, "websocket")
+            .header(SEC_WEBSOCKET_VERSION, "13")
+            .header(SEC_WEBSOCKET_KEY, &key)
+            .body(Body::empty())
+            .map_err(|e| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("failed to create WebSocket request {}", e),
+                )
+            })?;
+        req.headers_mut().extend(headers);
+
+        let timeout_dur = self.timeout_for(req.uri());
+        let resp = self.send_with_timeout(req, timeout_dur).await?;
+        if resp.status() != StatusCode::SWITCHING_PROTOCOLS {
+            let status = resp.status();
+            let body = crate::read_body_bytes(resp.into_body(), timeout_dur, None)
+                .await
+                .unwrap_or_default();
+            return Err(error::Error::Status(status, body).into());
+        }
+        let accept = resp.headers().get(SEC_WEBSOCKET_ACCEPT);
+        if accept.map(|v| v.as_bytes()) != Some(derive_accept_key(key.as_bytes()).as_bytes()) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "invalid WebSocket handshake response from {} (Sec-WebSocket-Accept {:?})",
+                    crate::redact::url(u.as_str()),
+                    accept
+                ),
+            ));
+        }
+
+        let upgraded = hyper::upgrade::on(resp).await.map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("failed to upgrade to WebSocket {}", e),
+            )
+        })?;
+        wire_debug!("opened WebSocket to {}", crate::redact::url(u.as_str()));
+        Ok(WebSocketStream::from_raw_socket(upgraded, Role::Client, config).await)
+    }
+}
+
+/// RUST_LOG=debug cargo test --features ws --lib -- ws::test_connect_ws --exact --show-output
+#[tokio::test]
+async fn test_connect_ws() {
+    use std::sync::{Arc, Mutex};
+
+    use futures_util::{SinkExt, StreamExt};
+    use tokio_tungstenite::tungstenite::handshake::server;
+
+    // echoes messages, recording the handshake path and token
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let handshakes = Arc::new(Mutex::new(Vec::new()));
+    let recorded = handshakes.clone();
+    tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        // the handshake error response is large, but only built on rejects
+        #[allow(clippy::result_large_err)]
+        let callback = |req: &server::Request, resp: server::Response| {
+            let token = req.headers().get("x-token").cloned();
+            recorded
+                .lock()
+                .unwrap()
+                .push((req.uri().path().to_string(), token));
+            Ok(resp)
+        };
+        let mut ws = tokio_tungstenite::accept_hdr_async(stream, callback)
+            .await
+            .unwrap();
+        while let Some(Ok(msg)) = ws.next().await {
+            if msg.is_text() || msg.is_binary() {
+                ws.send(msg).await.unwrap();
+            }
+        }
+    });
+
+    let manager = Manager::builder().build().unwrap();
+    let mut headers = HeaderMap::new();
+    headers.insert("x-token", "t0k3n".parse().unwrap());
+    let mut ws = manager
+        .connect_ws_with_headers(
+            format!("ws://127.0.0.1:{}/v1/", port).as_str(),
+            "rpc",
+            headers,
+            None,
+        )
+        .await
+        .unwrap();
+    ws.send(Message::Text("{\"id\":1}".to_string()))
+        .await
+        .unwrap();
+    assert_eq!(
+        ws.next().await.unwrap().unwrap(),
+        Message::Text("{\"id\":1}".to_string())
+    );
+    ws.send(Message::Binary(vec![1, 2, 3])).await.unwrap();
+    assert_eq!(
+        ws.next().await.unwrap().unwrap(),
+        Message::Binary(vec![1, 2, 3])
+    );
+    ws.close(None).await.unwrap();
+    assert_eq!(
+        *handshakes.lock().unwrap(),
+        vec![("/v1/rpc".to_string(), Some("t0k3n".parse().unwrap()))]
+    );
+
+    // servers that do not upgrade
+    let server = crate::testing::MockServer::start().await.unwrap();
+    server.stub(Method::GET, "/rpc", 404, "not found");
+    let e = manager.connect_ws(server.url(), "/rpc").await.unwrap_err();
+    match error::Error::from(e) {
+        error::Error::Status(status, body) => {
+            assert_eq!(status, StatusCode::NOT_FOUND);
+            assert_eq!(body, "not found");
+        }
+        e => panic!("unexpected {:?}", e),
+    }
+    let received = server.assert_received(Method::GET, "/rpc").once();
+    assert_eq!(received.requests()[0].headers[UPGRADE], "websocket");
+
+    let e = manager
+        .connect_ws("ftp://127.0.0.1", "/")
+        .await
+        .unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidInput);
+}